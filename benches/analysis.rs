@@ -23,13 +23,27 @@ pub fn criterion_benchmark(c: &mut Criterion) {
     debug!("config={:?}", &config);
 
     c.bench_function("performance::compute", |b| {
-        b.iter(|| performance::compute(&connection, black_box(4), Utc::now()))
+        b.iter(|| performance::compute(&connection, black_box(4), Utc::now(), &Default::default()))
     });
     c.bench_function("portfolio::compute<RealtimePrice>", |b| {
-        b.iter(|| portfolio::compute::<RealtimePrice>(&connection, black_box(4), Utc::now()))
+        b.iter(|| {
+            portfolio::compute::<RealtimePrice>(
+                &connection,
+                black_box(4),
+                Utc::now(),
+                &Default::default(),
+            )
+        })
     });
     c.bench_function("portfolio::compute<HistoricalPrice>", |b| {
-        b.iter(|| portfolio::compute::<HistoricalPrice>(&connection, black_box(4), Utc::now()))
+        b.iter(|| {
+            portfolio::compute::<HistoricalPrice>(
+                &connection,
+                black_box(4),
+                Utc::now(),
+                &Default::default(),
+            )
+        })
     });
 }
 
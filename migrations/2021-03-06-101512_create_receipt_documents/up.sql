@@ -0,0 +1,10 @@
+CREATE TABLE receipt_documents (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  hash BYTEA NOT NULL,
+  content BYTEA NOT NULL,
+  receipt_numbers BIGINT[] NOT NULL,
+  created TIMESTAMPTZ NOT NULL,
+  UNIQUE (user_id, hash)
+)
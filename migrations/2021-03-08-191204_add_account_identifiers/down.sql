@@ -0,0 +1,4 @@
+ALTER TABLE accounts
+DROP COLUMN depot_number,
+DROP COLUMN clearing_account_number,
+DROP COLUMN broker;
@@ -0,0 +1,4 @@
+ALTER TABLE accounts
+ADD depot_number TEXT,
+ADD clearing_account_number TEXT,
+ADD broker TEXT;
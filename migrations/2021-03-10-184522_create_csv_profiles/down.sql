@@ -0,0 +1 @@
+DROP TABLE csv_profiles
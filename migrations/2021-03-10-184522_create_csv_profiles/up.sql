@@ -0,0 +1,7 @@
+CREATE TABLE csv_profiles (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  mapping TEXT NOT NULL,
+  UNIQUE(user_id, name)
+)
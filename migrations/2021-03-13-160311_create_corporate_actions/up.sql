@@ -0,0 +1,12 @@
+CREATE TABLE corporate_actions (
+  id SERIAL PRIMARY KEY,
+  isin TEXT NOT NULL,
+  date TIMESTAMPTZ NOT NULL,
+  kind TEXT NOT NULL,
+  ratio DOUBLE PRECISION NOT NULL,
+  target_isin TEXT,
+  cost_fraction DOUBLE PRECISION,
+  comments TEXT NOT NULL
+);
+
+CREATE INDEX corporate_actions_isin ON corporate_actions (isin)
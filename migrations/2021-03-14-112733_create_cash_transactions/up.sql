@@ -0,0 +1,8 @@
+CREATE TABLE cash_transactions (
+  id SERIAL PRIMARY KEY,
+  account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+  date TIMESTAMPTZ NOT NULL,
+  kind TEXT NOT NULL,
+  amount BIGINT NOT NULL,
+  comments TEXT NOT NULL
+)
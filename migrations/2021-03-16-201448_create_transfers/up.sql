@@ -0,0 +1,11 @@
+CREATE TABLE transfers (
+  id SERIAL PRIMARY KEY,
+  from_account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+  to_account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+  isin TEXT NOT NULL,
+  date TIMESTAMPTZ NOT NULL,
+  units DOUBLE PRECISION NOT NULL,
+  comments TEXT NOT NULL,
+  CHECK (from_account_id <> to_account_id),
+  CHECK (units > 0)
+)
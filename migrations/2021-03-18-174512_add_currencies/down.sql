@@ -0,0 +1,11 @@
+DROP TABLE exchange_rates;
+
+ALTER TABLE transactions
+DROP COLUMN currency,
+DROP COLUMN exchange_rate;
+
+ALTER TABLE users
+DROP COLUMN reporting_currency;
+
+ALTER TABLE accounts
+DROP COLUMN currency;
@@ -0,0 +1,16 @@
+ALTER TABLE accounts
+ADD currency TEXT NOT NULL DEFAULT 'EUR';
+
+ALTER TABLE users
+ADD reporting_currency TEXT NOT NULL DEFAULT 'EUR';
+
+ALTER TABLE transactions
+ADD currency TEXT,
+ADD exchange_rate DOUBLE PRECISION;
+
+CREATE TABLE exchange_rates (
+  currency TEXT NOT NULL,
+  date DATE NOT NULL,
+  rate DOUBLE PRECISION NOT NULL,
+  PRIMARY KEY (currency, date)
+)
@@ -0,0 +1,3 @@
+DROP TABLE stock_tags;
+DROP TABLE transaction_tags;
+DROP TABLE tags
@@ -0,0 +1,4 @@
+ALTER TABLE transactions
+DROP COLUMN savings_plan_id;
+
+DROP TABLE savings_plans
@@ -0,0 +1,17 @@
+CREATE TABLE savings_plans (
+  id SERIAL PRIMARY KEY,
+  account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+  isin TEXT NOT NULL,
+  amount BIGINT NOT NULL,
+  interval_months INTEGER NOT NULL DEFAULT 1,
+  execution_day INTEGER NOT NULL,
+  start_date DATE NOT NULL,
+  end_date DATE,
+  comments TEXT NOT NULL DEFAULT '',
+  CHECK (amount > 0),
+  CHECK (interval_months > 0),
+  CHECK (execution_day BETWEEN 1 AND 31)
+);
+
+ALTER TABLE transactions
+ADD savings_plan_id INTEGER REFERENCES savings_plans(id) ON DELETE SET NULL
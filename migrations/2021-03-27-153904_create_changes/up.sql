@@ -0,0 +1,17 @@
+CREATE TABLE changes (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  date TIMESTAMPTZ NOT NULL DEFAULT now(),
+  source TEXT NOT NULL,
+  table_name TEXT NOT NULL,
+  record_id INTEGER NOT NULL,
+  action TEXT NOT NULL,
+  before TEXT,
+  after TEXT,
+  parent_id INTEGER REFERENCES changes(id) ON DELETE CASCADE,
+  reverted_by INTEGER REFERENCES changes(id) ON DELETE SET NULL,
+  CHECK (source IN ('web', 'cli', 'receipt', 'import')),
+  CHECK (action IN ('insert', 'update', 'delete'))
+);
+
+CREATE INDEX changes_user_id_idx ON changes(user_id, date)
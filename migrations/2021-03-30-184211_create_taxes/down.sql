@@ -0,0 +1,3 @@
+DROP TABLE tax_allowances;
+
+DROP TABLE base_rates
@@ -0,0 +1,20 @@
+-- Basiszins in percent, published by the Bundesfinanzministerium at the beginning of each year
+CREATE TABLE base_rates (
+  year INTEGER PRIMARY KEY,
+  rate DOUBLE PRECISION NOT NULL
+);
+
+INSERT INTO base_rates (year, rate) VALUES
+  (2018, 0.87),
+  (2019, 0.52),
+  (2020, 0.07),
+  (2021, -0.45);
+
+-- Sparerpauschbetrag (or the part of it that is assigned to this depot) in cents
+CREATE TABLE tax_allowances (
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  year INTEGER NOT NULL,
+  amount BIGINT NOT NULL,
+  PRIMARY KEY (user_id, year),
+  CHECK (amount >= 0)
+)
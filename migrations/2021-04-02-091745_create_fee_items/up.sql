@@ -0,0 +1,10 @@
+-- itemised fees of a transaction; their sum may be less than transactions.fees, the rest is unspecified
+CREATE TABLE fee_items (
+  id SERIAL PRIMARY KEY,
+  transaction_id INTEGER NOT NULL REFERENCES transactions(id) ON DELETE CASCADE,
+  category TEXT NOT NULL CHECK (category IN ('commission', 'exchange', 'thirdParty', 'fxSpread', 'other')),
+  amount BIGINT NOT NULL CHECK (amount <= 0),
+  description TEXT NOT NULL DEFAULT ''
+);
+
+CREATE INDEX fee_items_transaction_id ON fee_items (transaction_id);
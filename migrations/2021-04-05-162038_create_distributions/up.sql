@@ -0,0 +1,30 @@
+-- details of a dividend or fund distribution, the transaction (units = 0) holds the net amount
+CREATE TABLE distributions (
+  id SERIAL PRIMARY KEY,
+  transaction_id INTEGER NOT NULL UNIQUE REFERENCES transactions(id) ON DELETE CASCADE,
+  ex_date DATE,
+  pay_date DATE NOT NULL,
+  amount_per_unit DOUBLE PRECISION NOT NULL CHECK (amount_per_unit >= 0), -- gross, in `currency`
+  currency TEXT NOT NULL,
+  units DOUBLE PRECISION NOT NULL CHECK (units > 0), -- held on the ex-date
+  exchange_rate DOUBLE PRECISION, -- units of the account's currency per unit of `currency`
+  taxes BIGINT NOT NULL DEFAULT 0 CHECK (taxes <= 0), -- withheld, in cents of the account's currency
+  period_start DATE,
+  period_end DATE
+);
+
+-- dividends imported from receipts so far only have their details in the comments
+INSERT INTO distributions (transaction_id, pay_date, amount_per_unit, currency, units, period_start, period_end)
+SELECT id,
+  date::date,
+  replace(replace(m[4], '.', ''), ',', '.')::DOUBLE PRECISION,
+  m[3],
+  m[5]::DOUBLE PRECISION,
+  to_date(m[1], 'DD.MM.YYYY'),
+  to_date(m[2], 'DD.MM.YYYY')
+FROM (
+  SELECT id, date, regexp_match(comments, 'Ausschüttung für (\d\d\.\d\d\.\d{4}) - (\d\d\.\d\d\.\d{4}), ([A-Z]{3}) ([\d\.]+,\d+) pro Stück, ([\d\.]+) Stück im Besitz') AS m
+  FROM transactions
+  WHERE units = 0
+) AS t
+WHERE m IS NOT NULL AND m[5]::DOUBLE PRECISION > 0;
@@ -0,0 +1,12 @@
+-- target weight of a stock or of all stocks with a tag in the portfolio of a user
+CREATE TABLE allocation_targets (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  isin TEXT,
+  tag_id INTEGER REFERENCES tags(id) ON DELETE CASCADE,
+  weight DOUBLE PRECISION NOT NULL,
+  UNIQUE (user_id, isin),
+  UNIQUE (user_id, tag_id),
+  CHECK ((isin IS NULL) <> (tag_id IS NULL)),
+  CHECK (weight > 0 AND weight <= 1)
+)
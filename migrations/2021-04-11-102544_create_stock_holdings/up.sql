@@ -0,0 +1,24 @@
+-- largest positions of a fund (as fractions of the fund), normalized from stock_infos.holdings
+CREATE TABLE stock_holdings (
+  id SERIAL PRIMARY KEY,
+  isin CHAR(12) NOT NULL REFERENCES stock_infos(isin) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  holding_isin TEXT,
+  weight DOUBLE PRECISION NOT NULL,
+  CHECK (weight >= 0)
+);
+
+CREATE INDEX stock_holdings_isin ON stock_holdings (isin);
+CREATE INDEX stock_holdings_holding_isin ON stock_holdings (holding_isin);
+
+INSERT INTO stock_holdings (isin, name, holding_isin, weight)
+SELECT s.isin,
+  h.value->'instrument'->>'name',
+  NULLIF(h.value->'instrument'->>'isin', ''),
+  (h.value->>'investmentPct')::DOUBLE PRECISION / 100
+FROM stock_infos s
+CROSS JOIN LATERAL jsonb_array_elements(s.holdings::jsonb) WITH ORDINALITY AS h(value, position)
+WHERE s.holdings LIKE '[%'
+  AND h.value->'instrument'->>'name' IS NOT NULL
+  AND (h.value->>'investmentPct')::DOUBLE PRECISION >= 0
+ORDER BY s.isin, h.position
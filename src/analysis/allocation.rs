@@ -0,0 +1,329 @@
+use crate::analysis::{portfolio, tax, Options};
+use crate::holdings;
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+
+// for parts of a breakdown that are not known, as in the web interface
+pub const UNKNOWN: &str = "Unbekannt";
+
+// share of the portfolio in a country, industry, ...
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Exposure {
+    pub name: String,
+    pub value: f64,
+    pub share: f64, // of the value of all positions
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Allocation {
+    pub currency: String, // reporting currency of the user
+    pub value: f64,       // of all positions with a price
+    pub cash: Option<f64>,
+    pub countries: Vec<Exposure>,
+    pub industries: Vec<Exposure>,
+    pub currencies: Vec<Exposure>,
+    pub instruments: Vec<Exposure>,
+    pub holdings: Vec<Exposure>,
+    pub unvalued: Vec<String>, // isins of positions without a price, these are left out
+}
+
+// entry of the serialized breakdowns of StockInfo
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BreakdownEntry {
+    name_breakdown: String,
+    investment_pct: f64,
+}
+
+// onvista's name of the country of an ISIN prefix (the domicile of the issuer)
+fn country(isin: &str) -> String {
+    let name = match isin.get(0..2).unwrap_or_default() {
+        "AT" => "Österreich",
+        "AU" => "Australien",
+        "BE" => "Belgien",
+        "CA" => "Kanada",
+        "CH" => "Schweiz",
+        "CN" => "China",
+        "DE" => "Deutschland",
+        "DK" => "Dänemark",
+        "ES" => "Spanien",
+        "FI" => "Finnland",
+        "FR" => "Frankreich",
+        "GB" => "Großbritannien",
+        "HK" => "Hongkong",
+        "IE" => "Irland",
+        "IT" => "Italien",
+        "JP" => "Japan",
+        "KR" => "Südkorea",
+        "LU" => "Luxemburg",
+        "NL" => "Niederlande",
+        "NO" => "Norwegen",
+        "SE" => "Schweden",
+        "TW" => "Taiwan",
+        "US" => "Vereinigte Staaten",
+        _ => UNKNOWN,
+    };
+    name.to_string()
+}
+
+// onvista's label for the kind of a single instrument in the instrument breakdowns of funds
+fn instrument(kind: &str) -> String {
+    match kind {
+        "Aktie" => "Aktien",
+        k => k,
+    }
+    .to_string()
+}
+
+// onvista uses different names for some countries
+fn normalize(name: &str) -> String {
+    match name {
+        "Vereinigte Staaten von Amerika" | "USA" => "Vereinigte Staaten".to_string(),
+        _ => name.to_string(),
+    }
+}
+
+// parts of a breakdown as fractions, the rest (up to 100%) is unknown
+fn fractions(entries: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    let total = entries.iter().map(|(_, p)| p).sum::<f64>();
+    let scale = if total > 100.0 { 100.0 / total } else { 1.0 };
+
+    let mut result = entries
+        .into_iter()
+        .map(|(n, p)| (n, p * scale / 100.0))
+        .collect::<Vec<_>>();
+    if total < 99.5 {
+        result.push((UNKNOWN.to_string(), 1.0 - total / 100.0));
+    }
+
+    result
+}
+
+fn breakdown(s: Option<&String>) -> Option<Vec<(String, f64)>> {
+    let entries = serde_json::from_str::<Vec<BreakdownEntry>>(s?).ok()?;
+    if entries.is_empty() {
+        return None;
+    }
+
+    Some(fractions(
+        entries
+            .into_iter()
+            .map(|e| (normalize(&e.name_breakdown), e.investment_pct))
+            .collect(),
+    ))
+}
+
+// exposures sorted by value, largest first
+fn exposures(values: HashMap<String, f64>, total: f64) -> Vec<Exposure> {
+    let mut result = values
+        .into_iter()
+        .map(|(name, value)| Exposure {
+            share: if total != 0.0 { value / total } else { 0.0 },
+            name,
+            value,
+        })
+        .collect::<Vec<_>>();
+    result.sort_by(|a, b| b.value.total_cmp(&a.value));
+
+    result
+}
+
+// country, industry, currency, instrument and holding of a single share (or ETC): it counts fully for
+// the country of its ISIN. Its sector is unknown: the onvista stock pages that stock_infos is filled
+// from do not provide one.
+fn single(isin: &str, info: Option<&StockInfo>) -> [Vec<(String, f64)>; 5] {
+    let unknown = || vec![(UNKNOWN.to_string(), 1.0)];
+
+    [
+        vec![(country(isin), 1.0)],
+        unknown(),
+        info.and_then(|i| i.currency.clone())
+            .map(|c| vec![(c, 1.0)])
+            .unwrap_or_else(unknown),
+        info.map(|i| vec![(instrument(&i.kind), 1.0)])
+            .unwrap_or_else(unknown),
+        vec![(
+            info.map(|i| i.title.clone())
+                .unwrap_or_else(|| isin.to_string()),
+            1.0,
+        )],
+    ]
+}
+
+// look-through allocation of the current portfolio: the breakdowns of funds weighted by the value of
+// their positions; see `single` for shares.
+pub fn compute(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+    options: &Options,
+) -> Result<Allocation, Box<dyn Error>> {
+    let p = portfolio::compute::<RealtimePrice>(connection, user_id, date, options)?;
+    let infos = stock_infos::table
+        .filter(stock_infos::isin.eq_any(p.stocks.iter().map(|s| &s.isin).collect::<Vec<_>>()))
+        .load::<StockInfo>(connection)?
+        .into_iter()
+        .map(|s| (s.isin.clone(), s))
+        .collect::<HashMap<_, _>>();
+
+    let mut fund_holdings: HashMap<String, Vec<(String, f64)>> = HashMap::new();
+    for h in holdings::load(connection, &infos.keys().cloned().collect::<Vec<_>>())? {
+        fund_holdings
+            .entry(h.isin)
+            .or_default()
+            .push((h.name, h.weight * 100.0));
+    }
+
+    let mut countries = HashMap::new();
+    let mut industries = HashMap::new();
+    let mut currencies = HashMap::new();
+    let mut instruments = HashMap::new();
+    let mut holding_values = HashMap::new();
+    let mut unvalued = Vec::new();
+    let mut total = 0.0;
+
+    for position in p.stocks.iter().filter(|s| s.units.abs() > 1e-8) {
+        let value = match position.value {
+            Some(v) => v,
+            None => {
+                unvalued.push(position.isin.clone());
+                continue;
+            }
+        };
+        total += value;
+
+        let info = infos.get(&position.isin);
+        let unknown = || vec![(UNKNOWN.to_string(), 1.0)];
+        let is_fund = info.map(tax::is_fund).unwrap_or(false);
+
+        let parts = if is_fund {
+            [
+                breakdown(info.and_then(|i| i.country_breakdown.as_ref())).unwrap_or_else(unknown),
+                breakdown(info.and_then(|i| i.industry_breakdown.as_ref())).unwrap_or_else(unknown),
+                breakdown(info.and_then(|i| i.currency_breakdown.as_ref())).unwrap_or_else(unknown),
+                breakdown(info.and_then(|i| i.instrument_breakdown.as_ref()))
+                    .unwrap_or_else(unknown),
+                fund_holdings
+                    .get(&position.isin)
+                    .map(|hs| fractions(hs.clone()))
+                    .unwrap_or_else(unknown),
+            ]
+        } else {
+            single(&position.isin, info)
+        };
+
+        let [cs, is, cus, ins, hs] = parts;
+        for (target, part) in [
+            (&mut countries, cs),
+            (&mut industries, is),
+            (&mut currencies, cus),
+            (&mut instruments, ins),
+            (&mut holding_values, hs),
+        ] {
+            for (name, fraction) in part {
+                *target.entry(name).or_insert(0.0) += fraction * value;
+            }
+        }
+    }
+
+    Ok(Allocation {
+        currency: p.currency,
+        value: total,
+        cash: p.cash,
+        countries: exposures(countries, total),
+        industries: exposures(industries, total),
+        currencies: exposures(currencies, total),
+        instruments: exposures(instruments, total),
+        holdings: exposures(holding_values, total),
+        unvalued,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn share() -> StockInfo {
+        StockInfo {
+            isin: "US0378331005".to_string(),
+            wkn: "865985".to_string(),
+            title: "Apple".to_string(),
+            kind: "Aktie".to_string(),
+            company: "Apple".to_string(),
+            fonds_type: None,
+            focus: None,
+            persistent: false,
+            onvista_url: String::new(),
+            last_historical_update: None,
+            last_realtime_update: None,
+            industry_breakdown: None,
+            instrument_breakdown: None,
+            country_breakdown: None,
+            currency_breakdown: None,
+            holdings: None,
+            launch_date: None,
+            currency: Some("USD".to_string()),
+            management_type: None,
+            payout_type: None,
+            ter: None,
+            description: None,
+            benchmark_index: None,
+            instrument_id: None,
+        }
+    }
+
+    fn names(parts: &[(String, f64)]) -> Vec<&str> {
+        parts.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    #[test]
+    fn shares_use_the_labels_of_fund_breakdowns() {
+        let info = share();
+        let [cs, is, cus, ins, hs] = single(&info.isin, Some(&info));
+
+        assert_eq!(names(&cs), vec!["Vereinigte Staaten"]);
+        assert_eq!(names(&cus), vec!["USD"]);
+        assert_eq!(names(&ins), vec!["Aktien"]);
+        assert_eq!(names(&hs), vec!["Apple"]);
+        // not known for shares
+        assert_eq!(names(&is), vec![UNKNOWN]);
+    }
+
+    #[test]
+    fn shares_without_info() {
+        let [cs, _, cus, ins, hs] = single("DE0007164600", None);
+
+        assert_eq!(names(&cs), vec!["Deutschland"]);
+        assert_eq!(names(&cus), vec![UNKNOWN]);
+        assert_eq!(names(&ins), vec![UNKNOWN]);
+        assert_eq!(names(&hs), vec!["DE0007164600"]);
+    }
+
+    #[test]
+    fn exposures_tolerate_nan() {
+        let values = vec![
+            ("a".to_string(), 1.0),
+            ("b".to_string(), f64::NAN),
+            ("c".to_string(), 3.0),
+        ]
+        .into_iter()
+        .collect();
+        let es = exposures(values, 4.0);
+
+        assert_eq!(es.len(), 3);
+        let finite = es
+            .iter()
+            .filter(|e| e.value.is_finite())
+            .map(|e| e.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(finite, vec!["c", "a"]);
+    }
+}
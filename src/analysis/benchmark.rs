@@ -0,0 +1,171 @@
+use crate::analysis::cash;
+use crate::analysis::performance::PositionPerformance;
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// instrument that the cash flows of a portfolio are replayed into
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Benchmark {
+    pub isin: String,
+    pub title: String,
+    pub index: Option<String>, // benchmark index of the chosen ETF
+}
+
+// performance of the shadow portfolio that bought and sold the benchmark instead
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkPerformance {
+    pub benchmark: Benchmark,
+    #[serde(flatten)]
+    pub performance: PositionPerformance,
+}
+
+// the instrument `isin`, or the instrument tracking its benchmark index if it is an ETF
+// whose index is itself in the database (with the index's name as title)
+pub fn resolve(connection: &PgConnection, isin: &str) -> Result<Benchmark, Box<dyn Error>> {
+    let stock = stock_infos::table
+        .find(isin)
+        .first::<StockInfo>(connection)
+        .optional()?
+        .ok_or("unknown benchmark")?;
+
+    let tracked = match stock.benchmark_index.as_ref() {
+        Some(index) => stock_infos::table
+            .filter(stock_infos::title.eq(index))
+            .filter(stock_infos::isin.ne(isin))
+            .first::<StockInfo>(connection)
+            .optional()?,
+        None => None,
+    };
+
+    Ok(match tracked {
+        Some(t) => Benchmark {
+            isin: t.isin,
+            title: t.title,
+            index: stock.benchmark_index,
+        },
+        None => Benchmark {
+            isin: stock.isin,
+            title: stock.title,
+            index: stock.benchmark_index,
+        },
+    })
+}
+
+// money that entered or left the portfolio (transaction convention: paying in is negative).
+// Without cash these are purchases and sales, with cash only deposits and withdrawals;
+// dividends and synthetic transactions (spin-offs, transfers) are not replayed.
+pub fn flows(ts: &[Transaction], cts: Option<&[CashTransaction]>) -> Vec<(DateTime<Utc>, i64)> {
+    let mut result = match cts {
+        Some(cts) => cts
+            .iter()
+            .filter(|ct| cash::is_external(&ct.kind))
+            .map(|ct| (ct.date, -ct.amount))
+            .collect::<Vec<_>>(),
+        None => ts
+            .iter()
+            .filter(|t| t.synthetic.is_none() && t.units != 0.0)
+            .map(|t| (t.date, t.amount + t.fees))
+            .collect::<Vec<_>>(),
+    };
+    result.sort_by_key(|(d, _)| *d);
+
+    result
+}
+
+// synthetic transactions of the shadow portfolio: every flow buys or sells the benchmark
+// at the last closing price on or before its day. Flows before the first known price
+// are replayed on the day of that price instead.
+pub fn replay(
+    isin: &str,
+    flows: &[(DateTime<Utc>, i64)],
+    closing: &BTreeMap<NaiveDate, f64>,
+) -> Vec<Transaction> {
+    flows
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (date, amount))| {
+            let day = date.with_timezone(&Local).date().naive_local();
+            let (date, price) = match closing.range(..=day).last() {
+                Some((_, p)) => (*date, *p),
+                None => {
+                    let (first, p) = closing.iter().next()?;
+                    (
+                        Local
+                            .from_local_date(first)
+                            .single()?
+                            .and_hms(0, 0, 0)
+                            .with_timezone(&Utc),
+                        *p,
+                    )
+                }
+            };
+            if price <= 0.0 {
+                return None;
+            }
+
+            Some(Transaction {
+                id: -(i as i32) - 1,
+                account_id: 0,
+                isin: isin.to_string(),
+                date,
+                units: -*amount as f64 / 100.0 / price,
+                amount: *amount,
+                fees: 0,
+                onvista_exchange_id: None,
+                comments: "benchmark".to_string(),
+                exchange: None,
+                receipt_number: None,
+                currency: None,
+                exchange_rate: None,
+                savings_plan_id: None,
+                synthetic: Some(Synthetic::Benchmark),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closing() -> BTreeMap<NaiveDate, f64> {
+        vec![
+            (NaiveDate::from_ymd(2020, 3, 2), 100.0),
+            (NaiveDate::from_ymd(2020, 3, 6), 125.0),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn replay_uses_the_last_price_before_the_flow() {
+        let date = Local.ymd(2020, 3, 5).and_hms(12, 0, 0).with_timezone(&Utc);
+        let bts = replay("B", &[(date, -100000)], &closing());
+
+        assert_eq!(bts.len(), 1);
+        assert_eq!(bts[0].date, date);
+        assert!((bts[0].units - 10.0).abs() < 1e-9);
+        assert_eq!(bts[0].synthetic, Some(Synthetic::Benchmark));
+    }
+
+    #[test]
+    fn replay_moves_flows_before_the_first_price() {
+        let date = Local.ymd(2020, 2, 20).and_hms(12, 0, 0).with_timezone(&Utc);
+        let bts = replay("B", &[(date, -100000)], &closing());
+
+        assert_eq!(
+            bts[0].date.with_timezone(&Local).date().naive_local(),
+            NaiveDate::from_ymd(2020, 3, 2)
+        );
+        assert!((bts[0].units - 10.0).abs() < 1e-9);
+        assert!(replay("B", &[(date, -100000)], &BTreeMap::new()).is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+pub const DEPOSIT: &str = "deposit";
+pub const WITHDRAWAL: &str = "withdrawal";
+pub const INTEREST: &str = "interest";
+pub const FEE: &str = "fee";
+pub const DIVIDEND: &str = "dividend"; // only for dividends that are not recorded as a transaction of the stock
+pub const KINDS: [&str; 5] = [DEPOSIT, WITHDRAWAL, INTEREST, FEE, DIVIDEND];
+
+// kind of BalanceEntry that was caused by a security transaction
+pub const TRANSACTION: &str = "transaction";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BalanceEntry {
+    pub date: DateTime<Utc>,
+    pub kind: String,
+    pub cash_transaction_id: Option<i32>,
+    pub transaction_id: Option<i32>,
+    pub amount: f64,
+    pub balance: f64, // after this entry
+    pub comments: String,
+}
+
+// money that enters or leaves the portfolio, as opposed to money that the portfolio earned or spent itself
+pub fn is_external(kind: &str) -> bool {
+    kind == DEPOSIT || kind == WITHDRAWAL
+}
+
+pub fn load(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+    account_id: Option<i32>,
+) -> Result<Vec<CashTransaction>, Box<dyn Error>> {
+    Ok(cash_transactions::table
+        .inner_join(accounts::table)
+        .filter(accounts::user_id.eq(user_id))
+        .filter(cash_transactions::date.le(date))
+        .order(cash_transactions::date.asc())
+        .load::<(CashTransaction, Account)>(connection)?
+        .into_iter()
+        .map(|(ct, _)| ct)
+        .filter(|ct| account_id.map(|id| ct.account_id == id).unwrap_or(true))
+        .collect())
+}
+
+// cash at the end of `day` (local time): cash transactions plus the money spent on or
+// earned with securities (purchases, sales and dividends all go through the clearing account).
+// Synthetic transactions (e.g. spin-offs and transfers) do not move money.
+pub fn balance(cts: &[CashTransaction], ts: &[Transaction], day: NaiveDate) -> f64 {
+    let cash = cts
+        .iter()
+        .filter(|ct| ct.date.with_timezone(&Local).date().naive_local() <= day)
+        .map(|ct| ct.amount)
+        .sum::<i64>();
+    let securities = ts
+        .iter()
+        .filter(|t| t.synthetic.is_none())
+        .filter(|t| t.date.with_timezone(&Local).date().naive_local() <= day)
+        .map(|t| t.amount + t.fees)
+        .sum::<i64>();
+
+    (cash + securities) as f64 / 100.0
+}
+
+// deposits and withdrawals in (start, end] from the point of view of the investor
+// (i.e. with the same sign convention as transactions: paying money in is negative)
+pub fn external_flows(
+    cts: &[CashTransaction],
+    start: Option<NaiveDate>,
+    end: NaiveDate,
+) -> Vec<(DateTime<Utc>, f64)> {
+    cts.iter()
+        .filter(|ct| is_external(&ct.kind))
+        .filter(|ct| {
+            let d = ct.date.with_timezone(&Local).date().naive_local();
+            start.map(|s| d > s).unwrap_or(true) && d <= end
+        })
+        .map(|ct| (ct.date, -ct.amount as f64 / 100.0))
+        .collect()
+}
+
+// all movements of the clearing account of an account, oldest first
+pub fn running_balance(cts: &[CashTransaction], ts: &[Transaction]) -> Vec<BalanceEntry> {
+    let mut entries = cts
+        .iter()
+        .map(|ct| BalanceEntry {
+            date: ct.date,
+            kind: ct.kind.clone(),
+            cash_transaction_id: Some(ct.id),
+            transaction_id: None,
+            amount: ct.amount as f64 / 100.0,
+            balance: 0.0,
+            comments: ct.comments.clone(),
+        })
+        .chain(
+            ts.iter()
+                .filter(|t| t.synthetic.is_none())
+                .map(|t| BalanceEntry {
+                    date: t.date,
+                    kind: TRANSACTION.to_string(),
+                    cash_transaction_id: None,
+                    transaction_id: Some(t.id),
+                    amount: (t.amount + t.fees) as f64 / 100.0,
+                    balance: 0.0,
+                    comments: t.isin.clone(),
+                }),
+        )
+        .collect::<Vec<_>>();
+    entries.sort_by_key(|e| e.date);
+
+    let mut balance = 0.0;
+    for e in entries.iter_mut() {
+        balance += e.amount;
+        e.balance = balance;
+    }
+
+    entries
+}
@@ -0,0 +1,296 @@
+use crate::analysis::{lots, transfers};
+use crate::data::exchange_comparison;
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+
+pub const SPLIT: &str = "split";
+pub const REVERSE_SPLIT: &str = "reverseSplit";
+pub const BONUS: &str = "bonus";
+pub const SPIN_OFF: &str = "spinOff";
+pub const KINDS: [&str; 4] = [SPLIT, REVERSE_SPLIT, BONUS, SPIN_OFF];
+
+// price jumps that are this close to an integer ratio are reported as possible splits
+const SPLIT_TOLERANCE: f64 = 0.05;
+
+// jump in the historical prices of a stock that looks like a split nobody recorded
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitCandidate {
+    pub isin: String,
+    pub date: NaiveDate,
+    pub closing_before: f64,
+    pub closing_after: f64,
+    pub ratio: f64, // suggested ratio for a corporate action on `date`
+}
+
+// load the corporate actions that happened until `date` for the isins in `ts`
+// and apply them together with the user's transfers, see `apply_actions`.
+pub fn apply(
+    connection: &PgConnection,
+    user_id: i32,
+    ts: Vec<Transaction>,
+    date: DateTime<Utc>,
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let isins = ts
+        .iter()
+        .map(|t| t.isin.clone())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+
+    let actions = corporate_actions::table
+        .filter(corporate_actions::isin.eq_any(&isins))
+        .filter(corporate_actions::date.le(date))
+        .order(corporate_actions::date.asc())
+        .load::<CorporateAction>(connection)?;
+    let trs = transfers::load_recorded(connection, user_id, date)?;
+
+    Ok(apply_actions(ts, &trs, &actions))
+}
+
+// restate transactions in units after the given actions: units before a split are multiplied
+// with its ratio (onvista's historical prices are split-adjusted, so this keeps values consistent),
+// spin-offs add synthetic transactions that move part of the cost basis to the new isin: the units
+// of the new isin are bought for it, the lots of the old isin lose it (see lots::compute).
+// `transfers` (with units as recorded) decide which account holds the lots at a spin-off.
+pub fn apply_actions(
+    mut ts: Vec<Transaction>,
+    transfers: &[Transfer],
+    actions: &[CorporateAction],
+) -> Vec<Transaction> {
+    let mut trs = transfers.to_vec();
+
+    for a in actions.iter() {
+        if a.kind == SPIN_OFF {
+            let target = match &a.target_isin {
+                Some(t) => t,
+                None => continue,
+            };
+
+            let before = ts
+                .iter()
+                .filter(|t| t.isin == a.isin && t.date < a.date)
+                .cloned()
+                .collect::<Vec<_>>();
+            let trs_before = trs
+                .iter()
+                .filter(|tr| tr.isin == a.isin && tr.date < a.date)
+                .cloned()
+                .collect::<Vec<_>>();
+            let open = lots::compute(&before, &trs_before).open;
+
+            let mut account_ids = open.iter().map(|l| l.account_id).collect::<Vec<_>>();
+            account_ids.sort_unstable();
+            account_ids.dedup();
+
+            for account_id in account_ids {
+                let (units, cost) = open
+                    .iter()
+                    .filter(|l| l.account_id == account_id)
+                    .fold((0.0, 0.0), |(u, c), l| (u + l.units, c + l.cost));
+                let moved = (cost * a.cost_fraction.unwrap_or(0.0) * 100.0).round() as i64;
+
+                let synthetic = Transaction {
+                    id: -a.id,
+                    account_id,
+                    isin: a.isin.clone(),
+                    date: a.date,
+                    units: 0.0,
+                    amount: moved,
+                    fees: 0,
+                    onvista_exchange_id: None,
+                    comments: format!("Abspaltung {}", target),
+                    exchange: None,
+                    receipt_number: None,
+                    currency: None,
+                    exchange_rate: None,
+                    savings_plan_id: None,
+                    synthetic: Some(Synthetic::SpinOff),
+                };
+
+                ts.push(Transaction {
+                    isin: target.clone(),
+                    units: units * a.ratio,
+                    amount: -moved,
+                    comments: format!("Abspaltung aus {}", a.isin),
+                    ..synthetic.clone()
+                });
+                ts.push(synthetic);
+            }
+        } else {
+            for t in ts
+                .iter_mut()
+                .filter(|t| t.isin == a.isin && t.date < a.date)
+            {
+                t.units *= a.ratio;
+            }
+            for tr in trs
+                .iter_mut()
+                .filter(|tr| tr.isin == a.isin && tr.date < a.date)
+            {
+                tr.units *= a.ratio;
+            }
+        }
+    }
+
+    ts.sort_by(|a, b| a.date.cmp(&b.date));
+    ts
+}
+
+// look for overnight jumps in the historical prices of all stocks that appear in transactions
+// which are close to an integer ratio and not explained by a recorded corporate action
+pub fn find_split_candidates(
+    connection: &PgConnection,
+) -> Result<Vec<SplitCandidate>, Box<dyn Error>> {
+    let isins = transactions::table
+        .select(transactions::isin)
+        .distinct()
+        .load::<String>(connection)?;
+    let actions = corporate_actions::table.load::<CorporateAction>(connection)?;
+
+    let mut candidates = Vec::new();
+    for isin in isins.iter() {
+        let ex = match stock_exchanges::table
+            .filter(stock_exchanges::isin.eq(isin))
+            .load::<StockExchange>(connection)?
+            .into_iter()
+            .min_by(exchange_comparison)
+        {
+            Some(ex) => ex,
+            None => continue,
+        };
+
+        let prices = historical_prices::table
+            .filter(historical_prices::onvista_record_id.eq(ex.onvista_record_id))
+            .order(historical_prices::date.asc())
+            .load::<HistoricalPrice>(connection)?;
+
+        for w in prices.windows(2) {
+            if w[0].closing <= 0.0 || w[1].closing <= 0.0 {
+                continue;
+            }
+
+            let jump = w[0].closing / w[1].closing;
+            let factor = if jump > 1.0 { jump } else { 1.0 / jump };
+            let rounded = factor.round();
+            if rounded < 2.0 || (factor - rounded).abs() / rounded > SPLIT_TOLERANCE {
+                continue;
+            }
+
+            let recorded = actions.iter().any(|a| {
+                a.isin == *isin && (a.date.naive_utc().date() - w[1].date).num_days().abs() <= 5
+            });
+            if recorded {
+                continue;
+            }
+
+            candidates.push(SplitCandidate {
+                isin: isin.clone(),
+                date: w[1].date,
+                closing_before: w[0].closing,
+                closing_after: w[1].closing,
+                ratio: if jump > 1.0 { rounded } else { 1.0 / rounded },
+            });
+        }
+    }
+
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn transaction(id: i32, account_id: i32, day: u32, units: f64, amount: i64) -> Transaction {
+        Transaction {
+            id,
+            account_id,
+            isin: "A".to_string(),
+            date: Utc.ymd(2020, 1, day).and_hms(12, 0, 0),
+            units,
+            amount,
+            fees: 0,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: None,
+        }
+    }
+
+    // 1 unit of B per 2 units of A, which takes 20% of the cost basis
+    fn spin_off(day: u32) -> CorporateAction {
+        CorporateAction {
+            id: 1,
+            isin: "A".to_string(),
+            date: Utc.ymd(2020, 1, day).and_hms(0, 0, 0),
+            kind: SPIN_OFF.to_string(),
+            ratio: 0.5,
+            target_isin: Some("B".to_string()),
+            cost_fraction: Some(0.2),
+            comments: String::new(),
+        }
+    }
+
+    fn lot<'a>(lots: &'a lots::Lots, isin: &str) -> &'a lots::Lot {
+        lots.open.iter().find(|l| l.isin == isin).unwrap()
+    }
+
+    #[test]
+    fn spin_off_moves_cost_basis() {
+        let ts = vec![transaction(1, 1, 1, 10.0, -100000)];
+        let ts = apply_actions(ts, &[], &[spin_off(5)]);
+        let lots = lots::compute(&ts, &[]);
+
+        assert_eq!(lots.open.len(), 2);
+        assert!((lot(&lots, "A").units - 10.0).abs() < 1e-9);
+        assert!((lot(&lots, "A").cost - 800.0).abs() < 1e-6);
+        assert!((lot(&lots, "B").units - 5.0).abs() < 1e-9);
+        assert!((lot(&lots, "B").cost - 200.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sale_after_spin_off_uses_reduced_cost() {
+        let ts = vec![
+            transaction(1, 1, 1, 10.0, -100000),
+            transaction(2, 1, 10, -10.0, 90000),
+        ];
+        let ts = apply_actions(ts, &[], &[spin_off(5)]);
+        let lots = lots::compute(&ts, &[]);
+
+        assert_eq!(lots.sales.len(), 1);
+        assert!((lots.sales[0].cost - 800.0).abs() < 1e-6);
+        assert!((lots.sales[0].gain - 100.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn spin_off_after_transfer() {
+        let ts = vec![transaction(1, 1, 1, 10.0, -100000)];
+        let trs = vec![Transfer {
+            id: 1,
+            from_account_id: 1,
+            to_account_id: 2,
+            isin: "A".to_string(),
+            date: Utc.ymd(2020, 1, 3).and_hms(12, 0, 0),
+            units: 10.0,
+            comments: String::new(),
+        }];
+        let ts = apply_actions(ts, &trs, &[spin_off(5)]);
+        let lots = lots::compute(&ts, &trs);
+
+        assert_eq!(lots.open.len(), 2);
+        assert!(lots.open.iter().all(|l| l.account_id == 2));
+        assert!((lot(&lots, "A").cost - 800.0).abs() < 1e-6);
+        assert!((lot(&lots, "B").cost - 200.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::analysis::price::{EitherPrice, Price, PriceMap};
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use diesel::prelude::*;
+use log::info;
+use std::collections::HashMap;
+use std::error::Error;
+
+// onvista's prices (and therefore the values of all positions) are in EUR
+pub const EUR: &str = "EUR";
+
+// reference rates of all currencies in units per EUR, oldest first
+pub struct Rates(HashMap<String, Vec<(NaiveDate, f64)>>);
+
+impl Rates {
+    // only the rates of the given currencies
+    pub fn load(connection: &PgConnection, currencies: &[String]) -> Result<Rates, Box<dyn Error>> {
+        let mut rates: HashMap<String, Vec<(NaiveDate, f64)>> = HashMap::new();
+        for r in exchange_rates::table
+            .filter(exchange_rates::currency.eq_any(currencies))
+            .order(exchange_rates::date.asc())
+            .load::<ExchangeRate>(connection)?
+        {
+            rates.entry(r.currency).or_default().push((r.date, r.rate));
+        }
+
+        Ok(Rates(rates))
+    }
+
+    // units of `currency` per EUR on `day`, or on the last day before that has a rate (weekends, holidays)
+    pub fn per_eur(&self, currency: &str, day: NaiveDate) -> Option<f64> {
+        if currency == EUR {
+            return Some(1.0);
+        }
+
+        let rs = self.0.get(currency)?;
+        match rs.binary_search_by_key(&day, |(d, _)| *d) {
+            Ok(i) => Some(rs[i].1),
+            Err(0) => None,
+            Err(i) => Some(rs[i - 1].1),
+        }
+    }
+
+    pub fn convert(&self, value: f64, from: &str, to: &str, day: NaiveDate) -> Option<f64> {
+        if from == to {
+            return Some(value);
+        }
+
+        Some(value / self.per_eur(from, day)? * self.per_eur(to, day)?)
+    }
+}
+
+// insert (or update) the reference rates of a csv file in the format of the ECB's eurofxref-hist.csv,
+// i.e. a column 'Date' (%Y-%m-%d) and one column of rates per currency
+pub fn import_rates(connection: &PgConnection, data: &[u8]) -> Result<usize, Box<dyn Error>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data);
+    let headers = rdr.headers()?.clone();
+
+    let mut rates = Vec::new();
+    for record in rdr.records() {
+        let record = record?;
+        let day = NaiveDate::parse_from_str(record.get(0).unwrap_or(""), "%Y-%m-%d")?;
+
+        for (c, r) in headers.iter().zip(record.iter()).skip(1) {
+            // missing values are 'N/A' or empty
+            if let (false, Ok(r)) = (c.is_empty(), r.parse::<f64>()) {
+                rates.push(ExchangeRate {
+                    currency: c.to_uppercase(),
+                    date: day,
+                    rate: r,
+                });
+            }
+        }
+    }
+
+    let mut count = 0;
+    for chunk in rates.chunks(5000) {
+        count += diesel::insert_into(exchange_rates::table)
+            .values(chunk)
+            .on_conflict((exchange_rates::currency, exchange_rates::date))
+            .do_update()
+            .set(exchange_rates::rate.eq(diesel::pg::upsert::excluded(exchange_rates::rate)))
+            .execute(connection)?;
+    }
+    info!("Imported {} exchange rates", count);
+
+    Ok(count)
+}
+
+// restates transactions and prices in the reporting currency of a user
+pub struct Conversion {
+    rates: Rates,
+    account_currencies: HashMap<i32, String>,
+    pub reporting_currency: String,
+}
+
+impl Conversion {
+    pub fn load(connection: &PgConnection, user_id: i32) -> Result<Conversion, Box<dyn Error>> {
+        let user = users::table.find(user_id).first::<User>(connection)?;
+        let account_currencies = accounts::table
+            .filter(accounts::user_id.eq(user_id))
+            .load::<Account>(connection)?
+            .into_iter()
+            .map(|a| (a.id, a.currency))
+            .collect::<HashMap<_, _>>();
+
+        let mut currencies = transactions::table
+            .inner_join(accounts::table)
+            .filter(accounts::user_id.eq(user_id))
+            .select(transactions::currency)
+            .distinct()
+            .load::<Option<String>>(connection)?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        currencies.extend(account_currencies.values().cloned());
+        currencies.push(user.reporting_currency.clone());
+        currencies.sort();
+        currencies.dedup();
+
+        Ok(Conversion {
+            rates: Rates::load(connection, &currencies)?,
+            account_currencies,
+            reporting_currency: user.reporting_currency,
+        })
+    }
+
+    fn account_currency(&self, account_id: i32) -> &str {
+        self.account_currencies
+            .get(&account_id)
+            .map(|c| c.as_str())
+            .unwrap_or(EUR)
+    }
+
+    // factor that converts `currency` into the reporting currency on the (local) day of `date`
+    fn factor(&self, currency: &str, date: DateTime<Utc>) -> Result<f64, Box<dyn Error>> {
+        let day = date.with_timezone(&Local).date().naive_local();
+
+        self.rates
+            .convert(1.0, currency, &self.reporting_currency, day)
+            .ok_or_else(|| {
+                format!(
+                    "there is no exchange rate from {} to {} for {}",
+                    currency, self.reporting_currency, day
+                )
+                .into()
+            })
+    }
+
+    // amounts in foreign currencies are converted into the account's currency with the rate of the
+    // settlement (if it is known), and from there into the reporting currency with the reference rate of the day
+    pub fn transaction_factor(&self, t: &Transaction) -> Result<f64, Box<dyn Error>> {
+        let account_currency = self.account_currency(t.account_id);
+        let currency = t.currency.as_deref().unwrap_or(account_currency);
+
+        match t.exchange_rate {
+            Some(r) if currency != account_currency => {
+                Ok(r * self.factor(account_currency, t.date)?)
+            }
+            _ => self.factor(currency, t.date),
+        }
+    }
+
+    pub fn transactions(&self, ts: Vec<Transaction>) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        ts.into_iter()
+            .map(|mut t| {
+                let factor = self.transaction_factor(&t)?;
+                t.amount = (t.amount as f64 * factor).round() as i64;
+                t.fees = (t.fees as f64 * factor).round() as i64;
+                t.currency = Some(self.reporting_currency.clone());
+                t.exchange_rate = None;
+                Ok(t)
+            })
+            .collect()
+    }
+
+    // cash transactions are always in the account's currency
+    pub fn cash_transactions(
+        &self,
+        cts: Vec<CashTransaction>,
+    ) -> Result<Vec<CashTransaction>, Box<dyn Error>> {
+        cts.into_iter()
+            .map(|mut ct| {
+                let factor = self.factor(self.account_currency(ct.account_id), ct.date)?;
+                ct.amount = (ct.amount as f64 * factor).round() as i64;
+                Ok(ct)
+            })
+            .collect()
+    }
+
+    pub fn prices<T: Price>(&self, mut prices: PriceMap<T>) -> Result<PriceMap<T>, Box<dyn Error>> {
+        for ds in prices.values_mut() {
+            let factor = self.factor(EUR, ds.price.date())?;
+            ds.price.scale(factor);
+        }
+
+        Ok(prices)
+    }
+
+    pub fn either_prices(
+        &self,
+        mut prices: Vec<EitherPrice>,
+    ) -> Result<Vec<EitherPrice>, Box<dyn Error>> {
+        for p in prices.iter_mut() {
+            let factor = self.factor(EUR, p.date())?;
+            p.scale(factor);
+        }
+
+        Ok(prices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2021, 3, d)
+    }
+
+    fn conversion(reporting_currency: &str) -> Conversion {
+        let rates = vec![
+            ("USD".to_string(), vec![(day(1), 1.2), (day(3), 1.25)]),
+            ("CHF".to_string(), vec![(day(1), 1.1)]),
+        ];
+        Conversion {
+            rates: Rates(rates.into_iter().collect()),
+            account_currencies: vec![(1, EUR.to_string()), (2, "USD".to_string())]
+                .into_iter()
+                .collect(),
+            reporting_currency: reporting_currency.to_string(),
+        }
+    }
+
+    fn transaction(
+        account_id: i32,
+        currency: Option<&str>,
+        exchange_rate: Option<f64>,
+    ) -> Transaction {
+        Transaction {
+            id: 1,
+            account_id,
+            isin: "US0378331005".to_string(),
+            date: Local.ymd(2021, 3, 2).and_hms(12, 0, 0).with_timezone(&Utc),
+            units: 10.0,
+            amount: -120000,
+            fees: -1200,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: currency.map(|c| c.to_string()),
+            exchange_rate,
+            savings_plan_id: None,
+            synthetic: None,
+        }
+    }
+
+    #[test]
+    fn rates() {
+        let r = conversion(EUR).rates;
+        assert_eq!(r.per_eur(EUR, day(1)), Some(1.0));
+        assert_eq!(r.per_eur("USD", day(1)), Some(1.2));
+        // the last known rate before the day
+        assert_eq!(r.per_eur("USD", day(2)), Some(1.2));
+        assert_eq!(r.per_eur("USD", day(5)), Some(1.25));
+        assert_eq!(r.per_eur("USD", NaiveDate::from_ymd(2021, 2, 28)), None);
+        assert_eq!(r.per_eur("GBP", day(1)), None);
+
+        assert_eq!(r.convert(120.0, "USD", EUR, day(1)), Some(100.0));
+        assert_eq!(r.convert(5.0, "GBP", "GBP", day(1)), Some(5.0));
+        assert!((r.convert(120.0, "USD", "CHF", day(1)).unwrap() - 110.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn transactions_in_the_reporting_currency() {
+        let fx = conversion(EUR);
+        let ts = fx
+            .transactions(vec![
+                transaction(1, None, None),
+                // paid in USD from a EUR account at the rate of the settlement
+                transaction(1, Some("USD"), Some(0.8)),
+                // USD account, converted with the reference rate
+                transaction(2, None, None),
+            ])
+            .unwrap();
+
+        assert_eq!(ts[0].amount, -120000);
+        assert_eq!(ts[1].amount, -96000);
+        assert_eq!(ts[1].fees, -960);
+        assert_eq!(ts[2].amount, -100000);
+        assert!(ts.iter().all(|t| t.currency.as_deref() == Some(EUR)));
+
+        let fx = conversion("USD");
+        let ts = fx.transactions(vec![transaction(1, None, None)]).unwrap();
+        assert_eq!(ts[0].amount, -144000);
+
+        let fx = conversion("GBP");
+        assert!(fx.transactions(vec![transaction(1, None, None)]).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+use crate::analysis::currency;
+use crate::fees::{self, UNSPECIFIED};
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{Datelike, Local};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+// fees of the transactions of an account (or all accounts of a broker) in a year
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeSummary {
+    pub account_id: Option<i32>, // not set for summaries of a broker
+    pub name: String,            // of the account or broker
+    pub year: i32,
+    pub transactions: usize,               // purchases and sales
+    pub volume: f64,                       // sum of the absolute amounts
+    pub fees: f64,                         // positive
+    pub fee_ratio: f64,                    // fees / volume
+    pub categories: BTreeMap<String, f64>, // fees per category, including the unspecified rest
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeReport {
+    pub accounts: Vec<FeeSummary>,
+    pub brokers: Vec<FeeSummary>,
+}
+
+impl FeeSummary {
+    fn new(account_id: Option<i32>, name: &str, year: i32) -> FeeSummary {
+        FeeSummary {
+            account_id,
+            name: name.to_string(),
+            year,
+            transactions: 0,
+            volume: 0.0,
+            fees: 0.0,
+            fee_ratio: 0.0,
+            categories: BTreeMap::new(),
+        }
+    }
+
+    // `t` is converted into the reporting currency, `items` are its fees per category (negative)
+    fn add(&mut self, t: &Transaction, items: &[(String, f64)]) {
+        self.transactions += 1;
+        self.volume += (t.amount as f64 / 100.0).abs();
+        self.fees -= t.fees as f64 / 100.0;
+
+        let mut rest = -t.fees as f64 / 100.0;
+        for (c, a) in items.iter() {
+            *self.categories.entry(c.clone()).or_insert(0.0) -= a;
+            rest += a;
+        }
+        if rest.abs() >= 0.005 {
+            *self
+                .categories
+                .entry(UNSPECIFIED.to_string())
+                .or_insert(0.0) += rest;
+        }
+    }
+
+    fn finish(mut self) -> FeeSummary {
+        let round = |x: f64| (x * 100.0).round() / 100.0;
+
+        self.fee_ratio = if self.volume > 0.0 {
+            self.fees / self.volume
+        } else {
+            0.0
+        };
+        self.volume = round(self.volume);
+        self.fees = round(self.fees);
+        for v in self.categories.values_mut() {
+            *v = round(*v);
+        }
+        self
+    }
+}
+
+// fees of all purchases and sales of a user per account and broker and year,
+// converted into the user's reporting currency
+pub fn compute(
+    connection: &PgConnection,
+    user_id: i32,
+    year: Option<i32>,
+) -> Result<FeeReport, Box<dyn Error>> {
+    let accs = accounts::table
+        .filter(accounts::user_id.eq(user_id))
+        .order(accounts::id.asc())
+        .load::<Account>(connection)?;
+    let ts = Transaction::belonging_to(&accs)
+        .filter(transactions::units.ne(0.0))
+        .order(transactions::date.asc())
+        .load::<Transaction>(connection)?;
+    let items = fees::list(connection, &ts.iter().map(|t| t.id).collect::<Vec<_>>())?;
+
+    let fx = currency::Conversion::load(connection, user_id)?;
+    let converted = fx.transactions(ts.clone())?;
+
+    let mut by_account: BTreeMap<(i32, i32), FeeSummary> = BTreeMap::new();
+    let mut by_broker: BTreeMap<(String, i32), FeeSummary> = BTreeMap::new();
+    let acc_by_id = accs.iter().map(|a| (a.id, a)).collect::<HashMap<_, _>>();
+
+    for (t, c) in ts.iter().zip(converted.iter()) {
+        let y = t.date.with_timezone(&Local).year();
+        if year.map(|year| year != y).unwrap_or(false) {
+            continue;
+        }
+
+        // items are in the currency of the transaction, like its fees
+        let factor = fx.transaction_factor(t)?;
+        let t_items = items
+            .iter()
+            .filter(|i| i.transaction_id == t.id)
+            .map(|i| (i.category.clone(), i.amount as f64 * factor / 100.0))
+            .collect::<Vec<_>>();
+
+        let acc = acc_by_id[&t.account_id];
+        by_account
+            .entry((acc.id, y))
+            .or_insert_with(|| FeeSummary::new(Some(acc.id), &acc.name, y))
+            .add(c, &t_items);
+
+        let broker = acc.broker.clone().unwrap_or_default();
+        by_broker
+            .entry((broker.clone(), y))
+            .or_insert_with(|| FeeSummary::new(None, &broker, y))
+            .add(c, &t_items);
+    }
+
+    Ok(FeeReport {
+        accounts: by_account.into_values().map(|s| s.finish()).collect(),
+        brokers: by_broker.into_values().map(|s| s.finish()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn transaction(amount: i64, fees: i64) -> Transaction {
+        Transaction {
+            id: 1,
+            account_id: 1,
+            isin: "IE00B4L5Y983".to_string(),
+            date: Utc::now(),
+            units: 10.0,
+            amount,
+            fees,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: None,
+        }
+    }
+
+    #[test]
+    fn categories_and_unspecified_rest() {
+        let mut s = FeeSummary::new(Some(1), "depot", 2021);
+        s.add(
+            &transaction(-100000, -1290),
+            &[
+                (fees::COMMISSION.to_string(), -9.9),
+                (fees::EXCHANGE.to_string(), -1.5),
+            ],
+        );
+        s.add(
+            &transaction(50000, -990),
+            &[(fees::COMMISSION.to_string(), -9.9)],
+        );
+        let s = s.finish();
+
+        assert_eq!(s.transactions, 2);
+        assert_eq!(s.volume, 1500.0);
+        assert_eq!(s.fees, 22.8);
+        assert!((s.fee_ratio - 22.8 / 1500.0).abs() < 1e-12);
+        assert_eq!(s.categories[fees::COMMISSION], 19.8);
+        assert_eq!(s.categories[fees::EXCHANGE], 1.5);
+        assert_eq!(s.categories[UNSPECIFIED], 1.5);
+    }
+
+    #[test]
+    fn fully_itemised_fees_have_no_rest() {
+        let mut s = FeeSummary::new(None, "broker", 2021);
+        s.add(
+            &transaction(-100000, -1000),
+            &[
+                (fees::COMMISSION.to_string(), -7.5),
+                (fees::THIRD_PARTY.to_string(), -2.5),
+            ],
+        );
+        s.add(&transaction(-100000, 0), &[]);
+        let s = s.finish();
+
+        assert_eq!(s.fees, 10.0);
+        assert_eq!(s.categories.len(), 2);
+        assert!(!s.categories.contains_key(UNSPECIFIED));
+    }
+}
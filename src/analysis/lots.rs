@@ -0,0 +1,379 @@
+use crate::models::*;
+
+use chrono::{DateTime, Datelike, Local, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+// (remaining part of a) purchase that has not been sold yet
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lot {
+    pub transaction_id: i32,
+    pub account_id: i32,
+    pub isin: String,
+    pub date: DateTime<Utc>,
+    pub units: f64,
+    pub cost: f64, // cost basis of the remaining units including fees, positive
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sale {
+    pub transaction_id: i32,
+    pub account_id: i32,
+    pub isin: String,
+    pub date: DateTime<Utc>,
+    pub units: f64,    // positive
+    pub proceeds: f64, // amount + fees
+    pub cost: f64,     // cost basis of the lots that were sold
+    pub gain: f64,
+    pub lots: Vec<LotSale>,
+}
+
+// part of a lot that was consumed by a sale
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LotSale {
+    pub transaction_id: i32,
+    pub units: f64,
+    pub cost: f64,
+}
+
+// part of a lot that was moved to another account by a transfer
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MovedLot {
+    pub transfer_id: i32,
+    pub transaction_id: i32, // purchase the lot originates from
+    pub from_account_id: i32,
+    pub to_account_id: i32,
+    pub isin: String,
+    pub date: DateTime<Utc>,     // of the transfer
+    pub acquired: DateTime<Utc>, // of the purchase
+    pub units: f64,
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lots {
+    pub open: Vec<Lot>,
+    pub sales: Vec<Sale>,
+    pub moved: Vec<MovedLot>,
+}
+
+// units below this are considered to be rounding errors
+const EPSILON: f64 = 1e-6;
+
+// match sales against purchases first in, first out (separately for each account and isin,
+// like German tax law requires). Transfers move the oldest lots to the target account, keeping
+// their acquisition date and cost basis. Transactions and transfers have to be sorted by date.
+pub fn compute(ts: &[Transaction], transfers: &[Transfer]) -> Lots {
+    let mut open: HashMap<(i32, &str), VecDeque<Lot>> = HashMap::new();
+    let mut sales = Vec::new();
+    let mut moved = Vec::new();
+
+    let mut transfers = transfers.iter().peekable();
+    for t in ts.iter() {
+        while let Some(tr) = transfers.next_if(|tr| tr.date <= t.date) {
+            moved.extend(transfer(&mut open, tr));
+        }
+
+        let queue = open.entry((t.account_id, &t.isin)).or_default();
+
+        if t.units > EPSILON {
+            queue.push_back(Lot {
+                transaction_id: t.id,
+                account_id: t.account_id,
+                isin: t.isin.clone(),
+                date: t.date,
+                units: t.units,
+                cost: -(t.amount + t.fees) as f64 / 100.0,
+            });
+        } else if t.units < -EPSILON {
+            let (lots, remaining) = take(queue, -t.units);
+            if remaining > EPSILON {
+                warn!(
+                    "sale {} of {} sells {} more units than were bought",
+                    t.id, t.isin, remaining
+                );
+            }
+
+            let proceeds = (t.amount + t.fees) as f64 / 100.0;
+            let cost = lots.iter().map(|l| l.cost).sum::<f64>();
+            sales.push(Sale {
+                transaction_id: t.id,
+                account_id: t.account_id,
+                isin: t.isin.clone(),
+                date: t.date,
+                units: -t.units,
+                proceeds,
+                cost,
+                gain: proceeds - cost,
+                lots: lots
+                    .into_iter()
+                    .map(|l| LotSale {
+                        transaction_id: l.transaction_id,
+                        units: l.units,
+                        cost: l.cost,
+                    })
+                    .collect(),
+            });
+        } else if t.synthetic == Some(Synthetic::SpinOff) {
+            // the part of the cost basis that moves to the spun-off stock
+            reduce_cost(queue, (t.amount + t.fees) as f64 / 100.0);
+        }
+    }
+
+    for tr in transfers {
+        moved.extend(transfer(&mut open, tr));
+    }
+
+    let mut open = open
+        .values_mut()
+        .flat_map(|q| q.drain(..))
+        .collect::<Vec<_>>();
+    open.sort_by(|a, b| {
+        a.date
+            .cmp(&b.date)
+            .then(a.transaction_id.cmp(&b.transaction_id))
+    });
+
+    Lots { open, sales, moved }
+}
+
+// remove `units` from the front of the queue, returns the removed (parts of) lots
+// and the units that could not be removed because the queue ran empty
+fn take(queue: &mut VecDeque<Lot>, units: f64) -> (Vec<Lot>, f64) {
+    let mut remaining = units;
+    let mut taken = Vec::new();
+
+    while remaining > EPSILON {
+        let lot = match queue.front_mut() {
+            Some(l) => l,
+            None => break,
+        };
+
+        let units = remaining.min(lot.units);
+        let cost = lot.cost * units / lot.units;
+        taken.push(Lot {
+            units,
+            cost,
+            ..lot.clone()
+        });
+
+        lot.units -= units;
+        lot.cost -= cost;
+        remaining -= units;
+        if lot.units <= EPSILON {
+            queue.pop_front();
+        }
+    }
+
+    (taken, remaining)
+}
+
+// lower the cost of the lots in proportion to their cost
+fn reduce_cost(queue: &mut VecDeque<Lot>, amount: f64) {
+    let total = queue.iter().map(|l| l.cost).sum::<f64>();
+    if total.abs() > EPSILON {
+        for l in queue.iter_mut() {
+            l.cost -= amount * l.cost / total;
+        }
+    }
+}
+
+fn transfer<'a>(
+    open: &mut HashMap<(i32, &'a str), VecDeque<Lot>>,
+    tr: &'a Transfer,
+) -> Vec<MovedLot> {
+    let (taken, remaining) = take(
+        open.entry((tr.from_account_id, &tr.isin)).or_default(),
+        tr.units,
+    );
+    if remaining > EPSILON {
+        warn!(
+            "transfer {} of {} moves {} more units than were in the account",
+            tr.id, tr.isin, remaining
+        );
+    }
+
+    let target = open.entry((tr.to_account_id, &tr.isin)).or_default();
+    let mut result = Vec::new();
+    for l in taken {
+        result.push(MovedLot {
+            transfer_id: tr.id,
+            transaction_id: l.transaction_id,
+            from_account_id: tr.from_account_id,
+            to_account_id: tr.to_account_id,
+            isin: tr.isin.clone(),
+            date: tr.date,
+            acquired: l.date,
+            units: l.units,
+            cost: l.cost,
+        });
+        target.push_back(Lot {
+            account_id: tr.to_account_id,
+            ..l
+        });
+    }
+
+    // keep the target queue ordered by acquisition date so that FIFO still holds
+    target.make_contiguous().sort_by_key(|l| l.date);
+
+    result
+}
+
+// sum of realized gains per calendar year (local time)
+pub fn realized_by_year(sales: &[Sale]) -> BTreeMap<i32, f64> {
+    let mut result = BTreeMap::new();
+
+    for s in sales.iter() {
+        *result
+            .entry(s.date.with_timezone(&Local).year())
+            .or_insert(0.0) += s.gain;
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn transaction(id: i32, account_id: i32, month: u32, units: f64, amount: i64) -> Transaction {
+        Transaction {
+            id,
+            account_id,
+            isin: "A".to_string(),
+            date: Local
+                .ymd(2020, month, 15)
+                .and_hms(12, 0, 0)
+                .with_timezone(&Utc),
+            units,
+            amount,
+            fees: -100,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: None,
+        }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn sale_across_lots() {
+        // 10 units for 1000 + 1, then 10 units for 2000 + 1; selling 15 takes all of the first lot
+        let ts = vec![
+            transaction(1, 1, 1, 10.0, -100000),
+            transaction(2, 1, 2, 10.0, -200000),
+            transaction(3, 1, 3, -15.0, 300100),
+        ];
+        let lots = compute(&ts, &[]);
+
+        assert_eq!(lots.sales.len(), 1);
+        let s = &lots.sales[0];
+        assert_eq!(
+            s.lots.iter().map(|l| l.transaction_id).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert!(close(s.lots[1].units, 5.0));
+        assert!(close(s.cost, 1001.0 + 1000.5));
+        assert!(close(s.proceeds, 3000.0));
+        assert!(close(s.gain, 3000.0 - 2001.5));
+
+        assert_eq!(lots.open.len(), 1);
+        assert_eq!(lots.open[0].transaction_id, 2);
+        assert!(close(lots.open[0].units, 5.0));
+        assert!(close(lots.open[0].cost, 1000.5));
+    }
+
+    #[test]
+    fn accounts_are_matched_separately() {
+        let ts = vec![
+            transaction(1, 1, 1, 10.0, -100000),
+            transaction(2, 2, 2, 10.0, -200000),
+            transaction(3, 2, 3, -10.0, 300100),
+        ];
+        let lots = compute(&ts, &[]);
+
+        assert_eq!(lots.sales[0].lots[0].transaction_id, 2);
+        assert_eq!(lots.open.len(), 1);
+        assert_eq!(lots.open[0].transaction_id, 1);
+    }
+
+    #[test]
+    fn selling_more_than_held() {
+        // the missing units have no cost basis
+        let ts = vec![
+            transaction(1, 1, 1, 10.0, -100000),
+            transaction(2, 1, 2, -12.0, 120100),
+        ];
+        let lots = compute(&ts, &[]);
+
+        let s = &lots.sales[0];
+        assert!(close(s.units, 12.0));
+        assert!(close(s.lots.iter().map(|l| l.units).sum::<f64>(), 10.0));
+        assert!(close(s.cost, 1001.0));
+        assert!(lots.open.is_empty());
+    }
+
+    #[test]
+    fn transferred_lots_keep_date_and_cost() {
+        let ts = vec![
+            transaction(1, 1, 1, 10.0, -100000),
+            transaction(2, 2, 3, 10.0, -300000),
+            transaction(3, 2, 5, -5.0, 250100),
+        ];
+        let transfers = vec![Transfer {
+            id: 7,
+            from_account_id: 1,
+            to_account_id: 2,
+            isin: "A".to_string(),
+            date: Local.ymd(2020, 4, 1).and_hms(12, 0, 0).with_timezone(&Utc),
+            units: 10.0,
+            comments: String::new(),
+        }];
+        let lots = compute(&ts, &transfers);
+
+        assert_eq!(lots.moved.len(), 1);
+        assert_eq!(lots.moved[0].transaction_id, 1);
+        assert_eq!(lots.moved[0].acquired, ts[0].date);
+        assert!(close(lots.moved[0].cost, 1001.0));
+
+        // the moved lot is older than the purchase in the target account, so it is sold first
+        let s = &lots.sales[0];
+        assert_eq!(s.lots[0].transaction_id, 1);
+        assert!(close(s.cost, 500.5));
+        assert!(lots.open.iter().all(|l| l.account_id == 2));
+        assert!(close(lots.open.iter().map(|l| l.units).sum::<f64>(), 15.0));
+    }
+
+    #[test]
+    fn gains_per_year() {
+        let mut ts = vec![
+            transaction(1, 1, 1, 10.0, -100000),
+            transaction(2, 1, 6, -5.0, 60100),
+            transaction(3, 1, 7, -5.0, 40100),
+        ];
+        ts[2].date = Local.ymd(2021, 7, 15).and_hms(12, 0, 0).with_timezone(&Utc);
+        let lots = compute(&ts, &[]);
+        let by_year = realized_by_year(&lots.sales);
+
+        assert_eq!(
+            by_year.keys().copied().collect::<Vec<_>>(),
+            vec![2020, 2021]
+        );
+        assert!(close(by_year[&2020], 600.0 - 500.5));
+        assert!(close(by_year[&2021], 400.0 - 500.5));
+    }
+}
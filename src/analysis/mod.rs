@@ -1,5 +1,124 @@
+pub mod allocation;
+pub mod benchmark;
+pub mod cash;
+pub mod corporate_actions;
+pub mod currency;
+pub mod fees;
 pub mod irr;
+pub mod lots;
+pub mod overlap;
 pub mod performance;
 pub mod plots;
 pub mod portfolio;
 pub mod price;
+pub mod rebalancing;
+pub mod returns;
+pub mod risk;
+pub mod tax;
+pub mod transfers;
+
+use crate::models::*;
+use crate::schema::*;
+use crate::tags;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use std::collections::HashSet;
+use std::error::Error;
+
+// settings that apply to portfolio and performance computations
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub include_cash: bool, // include clearing account balances, only deposits and withdrawals count as invested
+    pub account_id: Option<i32>, // restrict to a single account (transfers count as sale and purchase at cost)
+    pub tag: Option<String>, // restrict to transactions with this tag (directly or through their stock), without cash
+    pub benchmark: Option<String>, // isin of an instrument to replay the cash flows into (performance only)
+}
+
+// what the analyses of a user start from, see `load`
+pub struct Data {
+    pub fx: currency::Conversion,
+    pub transactions: Vec<Transaction>, // sorted by date
+    pub lots: lots::Lots,
+    pub cash: Option<Vec<CashTransaction>>, // only set if cash is included
+}
+
+// the transactions of the user until `date` in the reporting currency, restated after corporate actions
+// and with transfers applied, restricted as `options` say
+pub fn load(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+    options: &Options,
+) -> Result<Data, Box<dyn Error>> {
+    let ts = transactions::table
+        .inner_join(accounts::table)
+        .filter(accounts::user_id.eq(user_id))
+        .filter(transactions::date.le(date))
+        .order(transactions::date.asc())
+        .load::<(Transaction, Account)>(connection)?
+        .into_iter()
+        .map(|(a, _)| a)
+        .collect::<Vec<_>>();
+    let fx = currency::Conversion::load(connection, user_id)?;
+    let ts = fx.transactions(ts)?;
+    let ts = corporate_actions::apply(connection, user_id, ts, date)?;
+    let (ts, lots) = transfers::apply(connection, user_id, ts, date, options.account_id)?;
+
+    // restricting only now keeps lots and transfers of all transactions intact;
+    // cash cannot carry tags, so it is left out
+    let (ts, lots) = match options.tag.as_deref() {
+        Some(tag) => restrict(&tags::transaction_ids(connection, user_id, tag)?, ts, lots),
+        None => (ts, lots),
+    };
+    let cash = if options.include_cash && options.tag.is_none() {
+        Some(fx.cash_transactions(cash::load(connection, user_id, date, options.account_id)?)?)
+    } else {
+        None
+    };
+
+    Ok(Data {
+        fx,
+        transactions: ts,
+        lots,
+        cash,
+    })
+}
+
+// keep the transactions with the given (sorted) ids and what was derived from them: the transfer legs
+// of their stocks, the spin-off legs of their stocks and their lots and sales
+fn restrict(
+    ids: &[i32],
+    ts: Vec<Transaction>,
+    mut lots: lots::Lots,
+) -> (Vec<Transaction>, lots::Lots) {
+    let tagged = |id: &i32| ids.binary_search(id).is_ok();
+    let isins = ts
+        .iter()
+        .filter(|t| t.synthetic.is_none() && tagged(&t.id))
+        .map(|t| t.isin.clone())
+        .collect::<HashSet<_>>();
+    // both legs of a spin-off have the same id, the one of the parent stock decides
+    let spin_offs = ts
+        .iter()
+        .filter(|t| t.synthetic == Some(Synthetic::SpinOff) && isins.contains(&t.isin))
+        .map(|t| t.id)
+        .collect::<HashSet<_>>();
+
+    let ts = ts
+        .into_iter()
+        .filter(|t| match t.synthetic {
+            None => tagged(&t.id),
+            Some(Synthetic::SpinOff) => spin_offs.contains(&t.id),
+            Some(_) => isins.contains(&t.isin),
+        })
+        .collect();
+
+    // lots come from purchases or spin-offs, whose ids do not overlap
+    let source = |id: &i32| tagged(id) || spin_offs.contains(id);
+    lots.open.retain(|l| source(&l.transaction_id));
+    lots.moved.retain(|m| source(&m.transaction_id));
+    lots.sales.retain(|s| tagged(&s.transaction_id));
+
+    (ts, lots)
+}
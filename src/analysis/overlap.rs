@@ -0,0 +1,235 @@
+use crate::analysis::{portfolio, Options};
+use crate::holdings;
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+// common holdings of two funds
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Overlap {
+    pub a: String, // isins of the funds
+    pub b: String,
+    pub overlap: f64,        // sum of the smaller weights of all common holdings
+    pub common: Vec<String>, // names of the common holdings, largest overlap first
+}
+
+// how much of a company is held directly and through funds
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompanyExposure {
+    pub name: String,
+    pub isin: Option<String>,
+    pub value: f64,
+    pub share: f64,                     // of the value of all positions
+    pub sources: BTreeMap<String, f64>, // value per held position
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlapReport {
+    pub currency: String,
+    pub value: f64,         // of all positions with a price
+    pub funds: Vec<String>, // held positions with known holdings
+    pub overlaps: Vec<Overlap>,
+    pub companies: Vec<CompanyExposure>,
+    pub unvalued: Vec<String>, // isins of positions without a price, these are left out
+}
+
+type Names = HashMap<String, (String, Option<String>)>; // name and isin of every company key
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+// isins of companies by their name, from the holdings that carry one and the titles of known stocks
+fn known_isins(hs: &[StockHolding], titles: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut result = titles
+        .iter()
+        .map(|(isin, title)| (normalize(title), isin.clone()))
+        .collect::<HashMap<_, _>>();
+    for h in hs.iter() {
+        if let Some(isin) = h.holding_isin.as_ref() {
+            result.insert(normalize(&h.name), isin.clone());
+        }
+    }
+
+    result
+}
+
+// weights per company of every fund: holdings are the same company if their isins match, a holding
+// without an isin gets the one of its name if that is known and is matched by its name otherwise
+fn fund_weights(
+    hs: Vec<StockHolding>,
+    titles: &HashMap<String, String>,
+) -> (BTreeMap<String, HashMap<String, f64>>, Names) {
+    let known = known_isins(&hs, titles);
+
+    let mut funds: BTreeMap<String, HashMap<String, f64>> = BTreeMap::new();
+    let mut names = HashMap::new();
+    for h in hs {
+        let isin = h
+            .holding_isin
+            .clone()
+            .or_else(|| known.get(&normalize(&h.name)).cloned());
+        let k = isin.clone().unwrap_or_else(|| normalize(&h.name));
+        names
+            .entry(k.clone())
+            .or_insert_with(|| (h.name.clone(), isin));
+        *funds.entry(h.isin).or_default().entry(k).or_insert(0.0) += h.weight;
+    }
+
+    (funds, names)
+}
+
+// pairwise overlap of the held funds and the look-through exposure to every company.
+// Only the largest holdings of a fund are known, so both are lower bounds.
+pub fn compute(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+    options: &Options,
+) -> Result<OverlapReport, Box<dyn Error>> {
+    let p = portfolio::compute::<RealtimePrice>(connection, user_id, date, options)?;
+
+    let mut values = BTreeMap::new();
+    let mut unvalued = Vec::new();
+    for s in p.stocks.iter().filter(|s| s.units.abs() > 1e-8) {
+        match s.value {
+            Some(v) => {
+                values.insert(s.isin.clone(), v);
+            }
+            None => unvalued.push(s.isin.clone()),
+        }
+    }
+    let isins = values.keys().cloned().collect::<Vec<_>>();
+    let total = values.values().sum::<f64>();
+
+    // all known stocks, so that holdings without an isin can be matched by their name
+    let titles = stock_infos::table
+        .select((stock_infos::isin, stock_infos::title))
+        .load::<(String, String)>(connection)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let (funds, mut names) = fund_weights(holdings::load(connection, &isins)?, &titles);
+
+    let fund_isins = funds.keys().cloned().collect::<Vec<_>>();
+    let mut overlaps = Vec::new();
+    for (i, a) in fund_isins.iter().enumerate() {
+        for b in fund_isins.iter().skip(i + 1) {
+            let mut common = funds[a]
+                .iter()
+                .filter_map(|(k, wa)| funds[b].get(k).map(|wb| (k, wa.min(*wb))))
+                .collect::<Vec<_>>();
+            common.sort_by(|x, y| y.1.total_cmp(&x.1));
+
+            overlaps.push(Overlap {
+                a: a.clone(),
+                b: b.clone(),
+                overlap: common.iter().map(|(_, w)| w).sum(),
+                common: common.iter().map(|(k, _)| names[*k].0.clone()).collect(),
+            });
+        }
+    }
+    overlaps.sort_by(|x, y| y.overlap.total_cmp(&x.overlap));
+
+    // direct positions count fully, funds with their holdings
+    let mut companies: HashMap<String, CompanyExposure> = HashMap::new();
+    for (isin, value) in values.iter() {
+        let parts = match funds.get(isin) {
+            Some(hs) => hs
+                .iter()
+                .map(|(k, w)| (k.clone(), w * value))
+                .collect::<Vec<_>>(),
+            None => {
+                let title = titles.get(isin).cloned().unwrap_or_else(|| isin.clone());
+                names
+                    .entry(isin.clone())
+                    .or_insert_with(|| (title, Some(isin.clone())));
+                vec![(isin.clone(), *value)]
+            }
+        };
+
+        for (k, v) in parts {
+            let c = companies
+                .entry(k.clone())
+                .or_insert_with(|| CompanyExposure {
+                    name: names[&k].0.clone(),
+                    isin: names[&k].1.clone(),
+                    value: 0.0,
+                    share: 0.0,
+                    sources: BTreeMap::new(),
+                });
+            c.value += v;
+            *c.sources.entry(isin.clone()).or_insert(0.0) += v;
+        }
+    }
+
+    let mut companies = companies
+        .into_values()
+        .map(|c| CompanyExposure {
+            share: if total != 0.0 { c.value / total } else { 0.0 },
+            ..c
+        })
+        .collect::<Vec<_>>();
+    companies.sort_by(|x, y| y.value.total_cmp(&x.value));
+
+    Ok(OverlapReport {
+        currency: p.currency,
+        value: total,
+        funds: fund_isins,
+        overlaps,
+        companies,
+        unvalued,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holding(fund: &str, name: &str, isin: Option<&str>, weight: f64) -> StockHolding {
+        StockHolding {
+            id: 0,
+            isin: fund.to_string(),
+            name: name.to_string(),
+            holding_isin: isin.map(|i| i.to_string()),
+            weight,
+        }
+    }
+
+    #[test]
+    fn holdings_are_merged_by_resolved_isin() {
+        let hs = vec![
+            holding("A", "Apple Inc.", Some("US0378331005"), 0.05),
+            holding("A", "Nestle SA", None, 0.02),
+            holding("B", "APPLE INC. ", None, 0.03),
+            holding("B", "Microsoft", None, 0.04),
+            holding("B", "Nestle SA", None, 0.01),
+        ];
+        let titles = vec![("US5949181045".to_string(), "Microsoft".to_string())]
+            .into_iter()
+            .collect();
+        let (funds, names) = fund_weights(hs, &titles);
+
+        // by the isin of another fund's holding
+        assert_eq!(funds["A"]["US0378331005"], 0.05);
+        assert_eq!(funds["B"]["US0378331005"], 0.03);
+        // by the title of a known stock
+        assert_eq!(funds["B"]["US5949181045"], 0.04);
+        // by the name only
+        assert_eq!(funds["A"]["nestle sa"], 0.02);
+        assert_eq!(funds["B"]["nestle sa"], 0.01);
+
+        assert_eq!(
+            names["US0378331005"],
+            ("Apple Inc.".to_string(), Some("US0378331005".to_string()))
+        );
+        assert_eq!(names["nestle sa"].1, None);
+    }
+}
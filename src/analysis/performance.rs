@@ -1,15 +1,15 @@
+use crate::analysis::benchmark::{self, Benchmark, BenchmarkPerformance};
 use crate::analysis::price::{DataSource, EitherPrice, Price, PriceMap};
-use crate::analysis::{irr, price};
+use crate::analysis::returns::{self, DailyValues};
+use crate::analysis::{self, cash, irr, price, Data, Options};
 use crate::models::*;
-use crate::schema::*;
 
 use chrono::offset::TimeZone;
 use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc, Weekday};
-use diesel::prelude::*;
 use log::debug;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::iter::successors;
 
@@ -32,7 +32,11 @@ pub struct PortfolioPerformance {
     pub end: PortfolioSnapshot,
     pub irr_annual: Option<f64>,
     pub irr_period: Option<f64>,
+    pub twr_annual: Option<f64>, // time-weighted return, chain-linked from daily closing prices
+    pub twr_period: Option<f64>,
+    pub modified_dietz: Option<f64>, // for the period
     pub positions: HashMap<String, PositionPerformance>,
+    pub benchmark: Option<BenchmarkPerformance>, // only set if a benchmark was chosen
 }
 
 #[derive(Deserialize, Serialize)]
@@ -42,6 +46,9 @@ pub struct PositionPerformance {
     pub end: PositionSnapshot,
     pub irr_annual: Option<f64>,
     pub irr_period: Option<f64>,
+    pub twr_annual: Option<f64>,
+    pub twr_period: Option<f64>,
+    pub modified_dietz: Option<f64>,
     pub transactions: Vec<Transaction>,
 }
 
@@ -51,6 +58,7 @@ pub struct PortfolioSnapshot {
     pub invested: f64,
     pub fees: f64, // these are included in `invested`
     pub value: Option<f64>,
+    pub cash: Option<f64>, // only set if cash is included (it is then also part of `value`)
 }
 
 #[derive(Deserialize, Serialize)]
@@ -68,17 +76,14 @@ pub fn compute(
     connection: &diesel::PgConnection,
     user_id: i32,
     date: DateTime<Utc>,
+    options: &Options,
 ) -> Result<Vec<PortfolioPerformance>, Box<dyn Error>> {
-    // read all transactions for the user from db
-    let ts = transactions::table
-        .inner_join(accounts::table)
-        .filter(accounts::user_id.eq(user_id))
-        .filter(transactions::date.le(date))
-        .order(transactions::date.asc())
-        .load::<(Transaction, Account)>(connection)?
-        .into_iter()
-        .map(|(a, _)| a)
-        .collect::<Vec<_>>();
+    let Data {
+        fx,
+        transactions: ts,
+        cash: cts,
+        ..
+    } = analysis::load(connection, user_id, date, options)?;
 
     // collect isins that appear in the transactions
     let mut isins = ts
@@ -91,9 +96,21 @@ pub fn compute(
     isins.sort();
     let isins = isins;
 
+    // the benchmark needs prices as well
+    let benchmark = match options.benchmark.as_deref() {
+        Some(isin) => Some(benchmark::resolve(connection, isin)?),
+        None => None,
+    };
+    let mut price_isins = isins.clone();
+    if let Some(b) = benchmark.as_ref() {
+        if !price_isins.contains(&b.isin) {
+            price_isins.push(b.isin.clone());
+        }
+    }
+
     // find suitable price information
     let current_prices: PriceMap<RealtimePrice> =
-        price::find(connection, &isins, date, 4 * 24, 4 * 24)?;
+        fx.prices(price::find(connection, &price_isins, date, 4 * 24, 4 * 24)?)?;
 
     // assemble dates for which we need to get HistoricalPrices
     let mut current_day = current_prices
@@ -222,15 +239,57 @@ pub fn compute(
     // query db for prices on these dates
     debug!(
         "Querying prices for {} isins and {} dates",
-        isins.len(),
+        price_isins.len(),
         dates.len()
     );
-    let prices = Price::find_multiple(connection, &isins, &utc_date_times, 4 * 24, 4 * 24)?;
+    let prices = Price::find_multiple(connection, &price_isins, &utc_date_times, 4 * 24, 4 * 24)?;
+    let prices = prices
+        .into_iter()
+        .map(|p| fx.prices(p))
+        .collect::<Result<Vec<_>, _>>()?;
     let prices = dates.into_iter().zip(prices).collect();
 
+    // daily values of every position (and the portfolio) for the time-weighted returns
+    let first_day = jobs.iter().map(|(_, x, _)| *x).min().unwrap_or(prev_day);
+    let today = Local::today().naive_local();
+    let closing = returns::closing_prices(connection, &fx, &price_isins, first_day, today)?;
+    let (daily, portfolio_daily) =
+        returns::portfolio(&ts, cts.as_deref(), &isins, &closing, first_day, today);
+
+    // shadow portfolio that put the same money into the benchmark
+    let empty = BTreeMap::new();
+    let shadow = benchmark.map(|b| {
+        let bts = benchmark::replay(
+            &b.isin,
+            &benchmark::flows(&ts, cts.as_deref()),
+            closing.get(&b.isin).unwrap_or(&empty),
+        );
+        let d = DailyValues::position(
+            &bts.iter().collect::<Vec<_>>(),
+            closing.get(&b.isin),
+            first_day,
+            today,
+        );
+        (b, bts, d)
+    });
+
     Ok(jobs
         .into_iter()
-        .map(|(k, st, en)| compute_performance(&ts, &isins, &current_prices, &prices, k, st, en))
+        .map(|(k, st, en)| {
+            compute_performance(
+                &ts,
+                cts.as_deref(),
+                &isins,
+                &current_prices,
+                &prices,
+                &daily,
+                &portfolio_daily,
+                shadow.as_ref(),
+                k,
+                st,
+                en,
+            )
+        })
         .collect::<Vec<_>>())
 }
 
@@ -239,6 +298,7 @@ fn compute_position(
     ts: &[Transaction],
     start_price: Option<DataSource<HistoricalPrice>>,
     end_price: Option<DataSource<EitherPrice>>,
+    daily: Option<&DailyValues>,
     start: NaiveDate,
     end: NaiveDate,
 ) -> PositionPerformance {
@@ -293,6 +353,31 @@ fn compute_position(
         data_source: start_price.clone().map(EitherPrice::wrap_historical),
     };
 
+    let (twr_annual, twr_period) = match (daily, start_snapshot.value, end_snapshot.value) {
+        (Some(d), Some(sv), Some(ev)) => annualize(d.time_weighted(start, sv, end, ev), start, end),
+        _ => (None, None),
+    };
+
+    let modified_dietz = match (start_snapshot.value, end_snapshot.value) {
+        (Some(sv), Some(ev)) => returns::modified_dietz(
+            sv,
+            ev,
+            &relevant_ts
+                .iter()
+                .map(|t| (t.date, -(t.amount + t.fees) as f64 / 100.0))
+                .collect::<Vec<_>>(),
+            start_price
+                .as_ref()
+                .map(|sp| sp.price.date())
+                .unwrap_or_else(|| noon(start)),
+            end_price
+                .as_ref()
+                .map(|ep| ep.price.date())
+                .unwrap_or_else(|| noon(end)),
+        ),
+        _ => None,
+    };
+
     let (irr_annual, irr_period) = if start_price.is_none() && prior_units != 0.0 {
         (None, None)
     } else if let Some(ep) = end_price {
@@ -320,7 +405,7 @@ fn compute_position(
             start_price
                 .as_ref()
                 .map(|sp| sp.price.date())
-                .unwrap_or_else(|| DateTime::<Utc>::from_utc(start.and_hms(12, 0, 0), Utc)),
+                .unwrap_or_else(|| noon(start)),
         );
         let year = chrono::Duration::days(365);
 
@@ -348,14 +433,22 @@ fn compute_position(
         transactions: relevant_ts,
         irr_annual,
         irr_period,
+        twr_annual,
+        twr_period,
+        modified_dietz,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn compute_performance(
     ts: &[Transaction],
+    cts: Option<&[CashTransaction]>,
     isins: &[String],
     current_prices: &PriceMap<RealtimePrice>,
     prices: &HashMap<NaiveDate, PriceMap<HistoricalPrice>>,
+    daily: &HashMap<String, DailyValues>,
+    portfolio_daily: &DailyValues,
+    shadow: Option<&(Benchmark, Vec<Transaction>, DailyValues)>,
     kind: PerformanceKind,
     start: NaiveDate,
     end: Option<NaiveDate>,
@@ -366,46 +459,47 @@ fn compute_performance(
 
     // possible way of optimizing performance: filter for 'transactions <= end' here, and not in compute_position ?
 
-    let positions = if let Some(end) = end {
-        isins
-            .iter()
-            .map(|isin| {
-                (
-                    isin.clone(),
-                    compute_position(
-                        isin.clone(),
-                        &ts,
-                        start_prices.get(isin).cloned(),
-                        end_historical_prices
-                            .and_then(|p| p.get(isin).cloned().map(EitherPrice::wrap_historical)),
-                        start,
-                        end,
-                    ),
-                )
-            })
-            .collect::<HashMap<_, _>>()
-    } else {
-        isins
-            .iter()
-            .map(|isin| {
-                (
-                    isin.clone(),
-                    compute_position(
-                        isin.clone(),
-                        &ts,
-                        start_prices.get(isin).cloned(),
-                        current_prices
-                            .get(isin)
-                            .cloned()
-                            .map(EitherPrice::wrap_realtime),
-                        start,
-                        Local::today().naive_local(),
-                    ),
-                )
-            })
-            .collect::<HashMap<_, _>>()
+    let end_date = end.unwrap_or_else(|| Local::today().naive_local());
+    let end_price = |isin: &String| match end {
+        Some(_) => end_historical_prices
+            .and_then(|p| p.get(isin).cloned().map(EitherPrice::wrap_historical)),
+        None => current_prices
+            .get(isin)
+            .cloned()
+            .map(EitherPrice::wrap_realtime),
     };
 
+    let positions = isins
+        .iter()
+        .map(|isin| {
+            (
+                isin.clone(),
+                compute_position(
+                    isin.clone(),
+                    &ts,
+                    start_prices.get(isin).cloned(),
+                    end_price(isin),
+                    daily.get(isin),
+                    start,
+                    end_date,
+                ),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let benchmark = shadow.map(|(b, bts, d)| BenchmarkPerformance {
+        benchmark: b.clone(),
+        performance: compute_position(
+            b.isin.clone(),
+            bts,
+            start_prices.get(&b.isin).cloned(),
+            end_price(&b.isin),
+            Some(d),
+            start,
+            end_date,
+        ),
+    });
+
     let (p_invested, p_value, p_fees, invested, value, fees) = positions.values().fold(
         (0.0, Some(0.0), 0.0, 0.0, Some(0.0), 0.0),
         |(api, apv, apf, ai, av, af), p| {
@@ -420,11 +514,36 @@ fn compute_performance(
         },
     );
 
+    // with cash, money only enters or leaves the portfolio through deposits and withdrawals
+    let (p_cash, cash) = match cts {
+        Some(cts) => (
+            Some(cash::balance(cts, ts, start)),
+            Some(cash::balance(cts, ts, end_date)),
+        ),
+        None => (None, None),
+    };
+    let (p_invested, invested) = match cts {
+        Some(cts) => (
+            cash::external_flows(cts, None, start)
+                .iter()
+                .map(|(_, x)| x)
+                .sum(),
+            cash::external_flows(cts, None, end_date)
+                .iter()
+                .map(|(_, x)| x)
+                .sum(),
+        ),
+        None => (p_invested, invested),
+    };
+    let p_value = p_value.map(|v| v + p_cash.unwrap_or(0.0));
+    let value = value.map(|v| v + cash.unwrap_or(0.0));
+
     let end_snapshot = PortfolioSnapshot {
-        date: end.unwrap_or_else(|| Local::today().naive_local()),
+        date: end_date,
         invested,
         fees,
         value,
+        cash,
     };
 
     let start_snapshot = PortfolioSnapshot {
@@ -432,6 +551,7 @@ fn compute_performance(
         invested: p_invested,
         fees: p_fees,
         value: p_value,
+        cash: p_cash,
     };
 
     let (irr_annual, irr_period) = if (p_value.is_none()) || value.is_none() {
@@ -461,12 +581,19 @@ fn compute_performance(
             })
         }));
 
-        // relevant transactions
-        ts.extend(positions.values().flat_map(|p| {
-            p.transactions
-                .iter()
-                .map(|t| (t.date, (t.amount + t.fees) as f64 / 100.0))
-        }));
+        if let Some(cts) = cts {
+            // cash at the start and end, and everything that was paid in or out in between
+            ts.push((noon(start), -p_cash.unwrap_or(0.0)));
+            ts.push((noon(end_date), cash.unwrap_or(0.0)));
+            ts.extend(cash::external_flows(cts, Some(start), end_date));
+        } else {
+            // relevant transactions
+            ts.extend(positions.values().flat_map(|p| {
+                p.transactions
+                    .iter()
+                    .map(|t| (t.date, (t.amount + t.fees) as f64 / 100.0))
+            }));
+        }
 
         let first_purchase = positions
             .values()
@@ -499,12 +626,63 @@ fn compute_performance(
         }
     };
 
+    let (twr_annual, twr_period) = match (p_value, value) {
+        (Some(sv), Some(ev)) => annualize(
+            portfolio_daily.time_weighted(start, sv, end_date, ev),
+            start,
+            end_date,
+        ),
+        _ => (None, None),
+    };
+
+    let modified_dietz = match (p_value, value) {
+        (Some(sv), Some(ev)) => {
+            let flows = match cts {
+                Some(cts) => cash::external_flows(cts, Some(start), end_date)
+                    .into_iter()
+                    .map(|(d, x)| (d, -x))
+                    .collect::<Vec<_>>(),
+                None => positions
+                    .values()
+                    .flat_map(|p| {
+                        p.transactions
+                            .iter()
+                            .map(|t| (t.date, -(t.amount + t.fees) as f64 / 100.0))
+                    })
+                    .collect::<Vec<_>>(),
+            };
+            returns::modified_dietz(sv, ev, &flows, noon(start), noon(end_date))
+        }
+        _ => None,
+    };
+
     PortfolioPerformance {
         kind,
         start: start_snapshot,
         end: end_snapshot,
         irr_annual,
         irr_period,
+        twr_annual,
+        twr_period,
+        modified_dietz,
         positions,
+        benchmark,
+    }
+}
+
+fn noon(d: NaiveDate) -> DateTime<Utc> {
+    DateTime::<Utc>::from_utc(d.and_hms(12, 0, 0), Utc)
+}
+
+// (annual, period) from a return for the period between the two days
+fn annualize(r: Option<f64>, start: NaiveDate, end: NaiveDate) -> (Option<f64>, Option<f64>) {
+    let period_length = end - start;
+    if period_length > Duration::zero() {
+        (
+            r.map(|x| irr::convert(x, period_length, Duration::days(365))),
+            r,
+        )
+    } else {
+        (None, r)
     }
 }
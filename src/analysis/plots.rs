@@ -1,4 +1,6 @@
+use crate::analysis::benchmark::{self, Benchmark};
 use crate::analysis::price::EitherPrice;
+use crate::analysis::{cash, corporate_actions, currency, returns};
 use crate::data::exchange_comparison;
 use crate::models::*;
 use crate::schema::*;
@@ -15,7 +17,7 @@ use std::collections::HashSet;
 use std::error::Error;
 use std::iter::successors;
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct PortfolioPlotDataPoint {
     date: DateTime<Utc>,
     invested: f64,
@@ -26,6 +28,14 @@ pub struct PortfolioPlotDataPoint {
 pub struct PortfolioPlot {
     points: Vec<PortfolioPlotDataPoint>,
     exchanges: Vec<StockExchange>,
+    benchmark: Option<BenchmarkPlot>,
+}
+
+// shadow portfolio that put the same money into the benchmark
+#[derive(Deserialize, Serialize)]
+pub struct BenchmarkPlot {
+    benchmark: Benchmark,
+    points: Vec<PortfolioPlotDataPoint>,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -200,6 +210,8 @@ pub fn compute_portfolio_plot(
     start_date: NaiveDate,
     end_date: NaiveDate,
     source_selection: DataSourceSelection,
+    include_cash: bool,
+    benchmark: Option<&str>,
 ) -> Result<PortfolioPlot, Box<dyn Error>> {
     // read all transactions up to end_time from db
     let ts = transactions::table
@@ -211,6 +223,25 @@ pub fn compute_portfolio_plot(
         .into_iter()
         .map(|(a, _)| a)
         .collect::<Vec<_>>();
+    let fx = currency::Conversion::load(connection, user_id)?;
+    let ts = fx.transactions(ts)?;
+    let ts = corporate_actions::apply(
+        connection,
+        user_id,
+        ts,
+        Utc.from_utc_date(&end_date).and_hms(18, 0, 0),
+    )?;
+    // with cash, only deposits and withdrawals count as invested (see performance::compute)
+    let cts = if include_cash {
+        Some(fx.cash_transactions(cash::load(
+            connection,
+            user_id,
+            Utc.from_utc_date(&end_date).and_hms(18, 0, 0),
+            None,
+        )?)?)
+    } else {
+        None
+    };
 
     // collect ISINs in the transactions
     let isins = ts.iter().map(|t| &t.isin).cloned().collect::<HashSet<_>>();
@@ -230,8 +261,22 @@ pub fn compute_portfolio_plot(
         exs.entry(isin).or_insert(None);
     }
 
+    let benchmark = match benchmark {
+        Some(isin) => Some(benchmark::resolve(connection, isin)?),
+        None => None,
+    };
+    let benchmark_exchange = match benchmark.as_ref() {
+        Some(b) => stock_exchanges::table
+            .filter(stock_exchanges::isin.eq(&b.isin))
+            .load::<StockExchange>(connection)?
+            .into_iter()
+            .min_by(exchange_comparison),
+        None => None,
+    };
+
     let record_ids = exs
         .values()
+        .chain(std::iter::once(&benchmark_exchange))
         .filter_map(|e| e.as_ref().map(|ee| ee.onvista_record_id))
         .collect::<Vec<_>>();
     let (dates, prices) = choose_and_query_points(
@@ -241,6 +286,7 @@ pub fn compute_portfolio_plot(
         end_date,
         source_selection,
     )?;
+    let prices = fx.either_prices(prices)?;
 
     let mut points = dates
         .into_iter()
@@ -251,56 +297,58 @@ pub fn compute_portfolio_plot(
         })
         .collect::<Vec<_>>();
 
-    for (isin, ex) in exs.iter() {
-        let mut invested = 0.0;
-        let mut units = 0.0;
+    let mut benchmark_points = points.clone();
 
-        let mut t_idx = 0;
+    for (isin, ex) in exs.iter() {
         let isin_ts = ts
             .iter()
             .filter(|t| &t.isin == isin)
             .collect::<Vec<&Transaction>>();
-
-        let mut p_idx = 0;
-        let isin_ps = if let Some(exx) = ex {
-            prices
-                .iter()
-                .filter(|p| p.onvista_record_id() == exx.onvista_record_id)
-                .collect::<Vec<&EitherPrice>>()
-        } else {
-            Vec::new()
-        };
-
-        let mut current_price = isin_ps.get(0);
-
-        // go through all points, search for the nearest price,
-        // calculate invested money and current value
+        add_position(&mut points, &isin_ts, &prices, ex.as_ref());
+    }
+    if let Some(cts) = cts.as_deref() {
         for p in points.iter_mut() {
-            while t_idx < isin_ts.len() && isin_ts[t_idx].date <= p.date {
-                let t = &isin_ts[t_idx];
-                units += t.units;
-                invested -= (t.amount + t.fees) as f64 / 100.0;
-
-                t_idx += 1;
-            }
-            p.invested += invested;
+            let day = p.date.with_timezone(&Local).date().naive_local();
+            p.value = p.value.map(|v| v + cash::balance(cts, &ts, day));
+            p.invested = cash::external_flows(cts, None, day)
+                .iter()
+                .map(|(_, x)| x)
+                .sum();
+        }
+    }
 
-            while p_idx < isin_ps.len() && isin_ps[p_idx].date() <= p.date {
-                current_price = isin_ps.get(p_idx);
-                p_idx += 1;
-            }
+    let benchmark = match benchmark {
+        Some(b) => {
+            let first = ts
+                .first()
+                .map(|t| t.date.with_timezone(&Local).date().naive_local())
+                .unwrap_or(start_date);
+            let closing = returns::closing_prices(
+                connection,
+                &fx,
+                std::slice::from_ref(&b.isin),
+                min(first, start_date),
+                end_date,
+            )?;
+            let bts = benchmark::replay(
+                &b.isin,
+                &benchmark::flows(&ts, cts.as_deref()),
+                closing.get(&b.isin).unwrap_or(&Default::default()),
+            );
+            add_position(
+                &mut benchmark_points,
+                &bts.iter().collect::<Vec<_>>(),
+                &prices,
+                benchmark_exchange.as_ref(),
+            );
 
-            if let Some(c_price) = current_price {
-                if (p.date - c_price.date()).num_days().abs() < 7 {
-                    p.value = p.value.map(|v| v + units * c_price.value());
-                } else if units.abs() > 1e-8 {
-                    p.value = None;
-                }
-            } else if units.abs() > 1e-8 {
-                p.value = None;
-            }
+            Some(BenchmarkPlot {
+                benchmark: b,
+                points: dedup_points(benchmark_points),
+            })
         }
-    }
+        None => None,
+    };
 
     Ok(PortfolioPlot {
         exchanges: exs
@@ -308,15 +356,73 @@ pub fn compute_portfolio_plot(
             .filter_map(|x| x.as_ref())
             .cloned()
             .collect::<Vec<_>>(),
-        points: points
-            .into_iter()
-            .dedup_by(|x, y| {
-                option_almost_eq(x.value, y.value) && (x.invested - y.invested).abs() < 0.01
-            })
-            .collect::<Vec<_>>(),
+        points: dedup_points(points),
+        benchmark,
     })
 }
 
+fn dedup_points(points: Vec<PortfolioPlotDataPoint>) -> Vec<PortfolioPlotDataPoint> {
+    points
+        .into_iter()
+        .dedup_by(|x, y| {
+            option_almost_eq(x.value, y.value) && (x.invested - y.invested).abs() < 0.01
+        })
+        .collect::<Vec<_>>()
+}
+
+// add invested money and value of a position (transactions `isin_ts`, oldest first) to the points
+fn add_position(
+    points: &mut [PortfolioPlotDataPoint],
+    isin_ts: &[&Transaction],
+    prices: &[EitherPrice],
+    ex: Option<&StockExchange>,
+) {
+    let mut invested = 0.0;
+    let mut units = 0.0;
+
+    let mut t_idx = 0;
+
+    let mut p_idx = 0;
+    let isin_ps = if let Some(exx) = ex {
+        prices
+            .iter()
+            .filter(|p| p.onvista_record_id() == exx.onvista_record_id)
+            .collect::<Vec<&EitherPrice>>()
+    } else {
+        Vec::new()
+    };
+
+    let mut current_price = isin_ps.get(0);
+
+    // go through all points, search for the nearest price,
+    // calculate invested money and current value
+    for p in points.iter_mut() {
+        while t_idx < isin_ts.len() && isin_ts[t_idx].date <= p.date {
+            let t = &isin_ts[t_idx];
+            units += t.units;
+            invested -= (t.amount + t.fees) as f64 / 100.0;
+
+            t_idx += 1;
+        }
+        p.invested += invested;
+
+        while p_idx < isin_ps.len() && isin_ps[p_idx].date() <= p.date {
+            current_price = isin_ps.get(p_idx);
+            p_idx += 1;
+        }
+
+        if let Some(c_price) = current_price {
+            if (p.date - c_price.date()).num_days().abs() < 7 {
+                p.value = p.value.map(|v| v + units * c_price.value());
+            } else if units.abs() > 1e-8 {
+                p.value = None;
+            }
+        } else if units.abs() > 1e-8 {
+            p.value = None;
+        }
+    }
+}
+
 pub fn compute_stock_plot(
     connection: &diesel::PgConnection,
     user_id: i32,
@@ -344,6 +450,14 @@ pub fn compute_stock_plot(
         .into_iter()
         .map(|(a, _)| a)
         .collect::<Vec<_>>();
+    let fx = currency::Conversion::load(connection, user_id)?;
+    let ts = fx.transactions(ts)?;
+    let ts = corporate_actions::apply(
+        connection,
+        user_id,
+        ts,
+        Utc.from_utc_date(&end_date).and_hms(18, 0, 0),
+    )?;
 
     let (dates, prices) = choose_and_query_points(
         connection,
@@ -352,6 +466,7 @@ pub fn compute_stock_plot(
         end_date,
         source_selection,
     )?;
+    let prices = fx.either_prices(prices)?;
 
     let mut points = dates
         .into_iter()
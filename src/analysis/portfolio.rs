@@ -1,20 +1,22 @@
+use crate::analysis::lots::{self, Lot, Sale};
 use crate::analysis::price::{DataSource, Price};
-use crate::analysis::{irr, price};
+use crate::analysis::{self, cash, irr, price, Data, Options};
 use crate::models::*;
-use crate::schema::*;
 
-use chrono::{DateTime, Utc};
-use diesel::prelude::*;
+use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 
 #[derive(Deserialize, Serialize)]
 pub struct Portfolio<T> {
+    pub currency: String, // reporting currency of the user, all amounts are in this currency
     pub invested: f64,
     pub value: Option<f64>,
     pub irr: Option<f64>,
+    pub cash: Option<f64>, // balance of the clearing accounts, only set if cash is included
+    pub realized_gains: BTreeMap<i32, f64>, // per calendar year
     pub stocks: Vec<Position<T>>,
 }
 
@@ -27,26 +29,26 @@ pub struct Position<T> {
     pub irr: Option<f64>,
     pub data_source: Option<DataSource<T>>,
     pub transactions: Vec<Transaction>,
+    pub lots: Vec<Lot>, // open lots, oldest first
+    pub sales: Vec<Sale>,
+    pub realized_gain: f64,
 }
 
 pub fn compute<T>(
     connection: &diesel::PgConnection,
     user_id: i32,
     date: DateTime<Utc>,
+    options: &Options,
 ) -> Result<Portfolio<T>, Box<dyn Error>>
 where
     T: Price + Sized,
 {
-    // read all transactions for the user from db
-    let ts = transactions::table
-        .inner_join(accounts::table)
-        .filter(accounts::user_id.eq(user_id))
-        .filter(transactions::date.le(date))
-        .order(transactions::date.asc())
-        .load::<(Transaction, Account)>(connection)?
-        .into_iter()
-        .map(|(a, _)| a)
-        .collect::<Vec<_>>();
+    let Data {
+        fx,
+        transactions: ts,
+        lots,
+        cash: cts,
+    } = analysis::load(connection, user_id, date, options)?;
 
     // collect isins that appear in the transactions
     let mut isins = ts
@@ -61,18 +63,33 @@ where
 
     // find suitable price information
     let mut prices: HashMap<String, DataSource<T>> =
-        price::find(connection, &isins, date, 4 * 24, 4 * 24)?;
+        fx.prices(price::find(connection, &isins, date, 4 * 24, 4 * 24)?)?;
+
+    let realized_gains = lots::realized_by_year(&lots.sales);
 
     let positions = isins
         .into_iter()
-        .map(|isin| compute_position(isin.clone(), &ts, prices.remove(&isin)))
+        .map(|isin| compute_position(isin.clone(), &ts, &lots, prices.remove(&isin)))
         .collect::<Vec<Position<T>>>();
 
+    // with cash, money only enters or leaves the portfolio through deposits and withdrawals
+    let day = date.with_timezone(&Local).date().naive_local();
+    let cash = cts.map(|cts| {
+        (
+            cash::balance(&cts, &ts, day),
+            cash::external_flows(&cts, None, day),
+        )
+    });
+
     // calculate total invested money and value
-    let invested = positions.iter().fold(0.0, |acc, p| acc + p.invested);
-    let value = positions
-        .iter()
-        .fold(Some(0.0), |acc, p| acc.and_then(|a| p.value.map(|x| a + x)));
+    let invested = match &cash {
+        Some((_, flows)) => flows.iter().map(|(_, x)| x).sum(),
+        None => positions.iter().fold(0.0, |acc, p| acc + p.invested),
+    };
+    let value = positions.iter().fold(
+        Some(cash.as_ref().map(|(b, _)| *b).unwrap_or(0.0)),
+        |acc, p| acc.and_then(|a| p.value.map(|x| a + x)),
+    );
 
     // calculate the total irr
     // (only possible if we can simulate sales for all positions, i.e. value != None)
@@ -84,11 +101,19 @@ where
                 .map(|s| (s.price.date(), p.units * s.price.value()))
         });
 
-        let ts = ts
-            .iter()
-            .map(|t| (t.date, (t.amount + t.fees) as f64 / 100.0))
-            .chain(sales)
-            .collect::<Vec<_>>();
+        let ts = match &cash {
+            Some((balance, flows)) => flows
+                .iter()
+                .cloned()
+                .chain(std::iter::once((date, *balance)))
+                .chain(sales)
+                .collect::<Vec<_>>(),
+            None => ts
+                .iter()
+                .map(|t| (t.date, (t.amount + t.fees) as f64 / 100.0))
+                .chain(sales)
+                .collect::<Vec<_>>(),
+        };
 
         irr::compute(&ts, chrono::Duration::days(365))
     } else {
@@ -96,16 +121,20 @@ where
     };
 
     Ok(Portfolio {
+        currency: fx.reporting_currency,
         invested,
         value,
         stocks: positions,
         irr,
+        cash: cash.map(|(b, _)| b),
+        realized_gains,
     })
 }
 
 fn compute_position<T>(
     isin: String,
     ts: &[Transaction],
+    lots: &lots::Lots,
     price: Option<DataSource<T>>,
 ) -> Position<T>
 where
@@ -122,6 +151,20 @@ where
 
     let value = price.as_ref().map(|x| units * x.price.value());
 
+    let open_lots = lots
+        .open
+        .iter()
+        .filter(|l| l.isin == isin)
+        .cloned()
+        .collect::<Vec<_>>();
+    let sales = lots
+        .sales
+        .iter()
+        .filter(|s| s.isin == isin)
+        .cloned()
+        .collect::<Vec<_>>();
+    let realized_gain = sales.iter().map(|s| s.gain).sum();
+
     let irr = if let Some(x) = price.as_ref() {
         // is guaranteed to have the same length as positions
         let sale = (x.price.date(), units * x.price.value());
@@ -145,5 +188,8 @@ where
         invested,
         irr,
         data_source: price,
+        lots: open_lots,
+        sales,
+        realized_gain,
     }
 }
@@ -34,6 +34,9 @@ pub trait Price {
     fn value(&self) -> f64;
     fn date(&self) -> DateTime<Utc>;
     fn onvista_record_id(&self) -> i32;
+
+    // multiply all prices with `factor`, e.g. to convert them into another currency
+    fn scale(&mut self, factor: f64);
 }
 
 pub fn find<T>(
@@ -58,6 +61,10 @@ impl Price for RealtimePrice {
         self.price
     }
 
+    fn scale(&mut self, factor: f64) {
+        self.price *= factor;
+    }
+
     fn date(&self) -> DateTime<Utc> {
         self.date
     }
@@ -171,6 +178,13 @@ impl Price for HistoricalPrice {
         self.closing
     }
 
+    fn scale(&mut self, factor: f64) {
+        self.opening *= factor;
+        self.closing *= factor;
+        self.high *= factor;
+        self.low *= factor;
+    }
+
     fn onvista_record_id(&self) -> i32 {
         self.onvista_record_id
     }
@@ -326,6 +340,15 @@ impl EitherPrice {
             EitherPrice::HistoricalPriceOpening(p) => p.opening,
         }
     }
+
+    pub fn scale(&mut self, factor: f64) {
+        match self {
+            EitherPrice::RealtimePrice(p) => p.scale(factor),
+            EitherPrice::HistoricalPrice(p) => p.scale(factor),
+            EitherPrice::HistoricalPriceOpening(p) => p.scale(factor),
+        }
+    }
+
     pub fn date(&self) -> DateTime<Utc> {
         match self {
             EitherPrice::RealtimePrice(p) => p.date(),
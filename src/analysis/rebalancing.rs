@@ -0,0 +1,366 @@
+use crate::analysis::price::{self, Price, PriceMap};
+use crate::analysis::{currency, portfolio, Options};
+use crate::models::*;
+use crate::schema::tags;
+use crate::targets;
+use crate::validation::{FieldError, ValidationErrors};
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+
+// how new money may be invested; amounts are in the reporting currency
+#[derive(Debug, Clone, Default)]
+pub struct Parameters {
+    pub cash: f64, // new money to invest
+    pub allow_sales: bool,
+    pub min_order: f64, // smallest amount of an order (without fees)
+    pub fixed_fee: f64, // per order
+    pub fee_rate: f64,  // of the amount of an order
+}
+
+impl Parameters {
+    pub fn check(&self) -> Result<(), ValidationErrors> {
+        let errors = [
+            ("cash", self.cash),
+            ("minOrder", self.min_order),
+            ("fixedFee", self.fixed_fee),
+            ("feeRate", self.fee_rate),
+        ]
+        .iter()
+        .filter(|(_, x)| !x.is_finite() || *x < 0.0)
+        .map(|(field, _)| FieldError {
+            field: field.to_string(),
+            message: "must be a number that is not negative".to_string(),
+        })
+        .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors { errors })
+        }
+    }
+}
+
+// current and resulting weight of a target
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TargetStatus {
+    pub target: AllocationTarget,
+    pub name: String,       // isin or name of the tag
+    pub isins: Vec<String>, // positions that count for this target
+    pub value: f64,
+    pub weight: f64,
+    pub deviation: f64, // weight - target weight
+    pub weight_after: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Order {
+    pub target_id: i32,
+    pub isin: Option<String>, // not set if no stock of the group is known
+    pub amount: f64,          // positive for purchases, negative for sales
+    pub fees: f64,            // positive
+    pub units: Option<f64>,   // at the current price
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rebalancing {
+    pub currency: String,
+    pub value: f64, // of all positions with a price, before the orders
+    pub cash: f64,  // new money
+    pub targets: Vec<TargetStatus>,
+    pub untargeted: f64, // value of the positions without a target
+    pub orders: Vec<Order>,
+    pub remaining_cash: f64,
+    pub unvalued: Vec<String>, // isins of positions without a price, these are left out
+}
+
+fn round(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+// purchases for the gaps (positive, indexed by target) that fit into `budget` including fees.
+// If the money does not suffice, all purchases are scaled down; orders below the minimum are dropped.
+fn purchases(gaps: &[(usize, f64)], budget: f64, params: &Parameters) -> Vec<(usize, f64)> {
+    let mut candidates = gaps
+        .iter()
+        .filter(|(_, g)| *g > 0.0)
+        .cloned()
+        .collect::<Vec<_>>();
+
+    while !candidates.is_empty() {
+        let available =
+            (budget - candidates.len() as f64 * params.fixed_fee) / (1.0 + params.fee_rate);
+        let needed = candidates.iter().map(|(_, g)| g).sum::<f64>();
+        let scale = if needed > 0.0 {
+            (available / needed).min(1.0)
+        } else {
+            0.0
+        };
+
+        let orders = candidates
+            .iter()
+            .map(|(i, g)| (*i, round(g * scale)))
+            .collect::<Vec<_>>();
+
+        // drop the smallest order that is too small and try again
+        match orders
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, a))| *a <= 0.0 || *a < params.min_order)
+            .min_by(|(_, (_, a)), (_, (_, b))| a.total_cmp(b))
+        {
+            Some((j, _)) => {
+                candidates.remove(j);
+            }
+            None => return orders,
+        }
+    }
+
+    Vec::new()
+}
+
+// deviation of the current portfolio from the allocation targets of the user and orders that bring it
+// closer to them. A position counts for the target of its isin, otherwise for the first target of a
+// tag that it carries.
+pub fn compute(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+    options: &Options,
+    params: &Parameters,
+) -> Result<Rebalancing, Box<dyn Error>> {
+    let p = portfolio::compute::<RealtimePrice>(connection, user_id, date, options)?;
+    let ts = targets::load(connection, user_id)?;
+    let members = targets::members(connection, &ts)?;
+    let tag_names = tags::table
+        .filter(tags::user_id.eq(user_id))
+        .load::<Tag>(connection)?
+        .into_iter()
+        .map(|t| (t.id, t.name))
+        .collect::<HashMap<_, _>>();
+
+    let mut values = HashMap::new();
+    let mut prices = HashMap::new();
+    let mut unvalued = Vec::new();
+    for s in p.stocks.iter().filter(|s| s.units.abs() > 1e-8) {
+        match s.value {
+            Some(v) => {
+                values.insert(s.isin.clone(), v);
+            }
+            None => unvalued.push(s.isin.clone()),
+        }
+        if let Some(ds) = s.data_source.as_ref() {
+            prices.insert(s.isin.clone(), ds.price.value());
+        }
+    }
+    let total = values.values().sum::<f64>();
+
+    // assign the positions to the targets
+    let mut isins = vec![Vec::new(); ts.len()];
+    let mut untargeted = 0.0;
+    let mut held = values.keys().cloned().collect::<Vec<_>>();
+    held.sort();
+    for isin in held {
+        let target = ts
+            .iter()
+            .position(|t| t.isin.as_ref() == Some(&isin))
+            .or_else(|| {
+                ts.iter().position(|t| {
+                    t.tag_id
+                        .and_then(|id| members.get(&id))
+                        .map(|m| m.contains(&isin))
+                        .unwrap_or(false)
+                })
+            });
+        match target {
+            Some(i) => isins[i].push(isin),
+            None => untargeted += values[&isin],
+        }
+    }
+    let current = isins
+        .iter()
+        .map(|is| is.iter().map(|i| values[i]).sum::<f64>())
+        .collect::<Vec<_>>();
+
+    // the stock that is bought or sold for a target: its isin or the largest position of the group
+    let order_isin = |i: usize| -> Option<String> {
+        ts[i].isin.clone().or_else(|| {
+            isins[i]
+                .iter()
+                .max_by(|a, b| values[*a].total_cmp(&values[*b]))
+                .cloned()
+                .or_else(|| {
+                    ts[i]
+                        .tag_id
+                        .and_then(|id| members.get(&id))
+                        .and_then(|m| m.first().cloned())
+                })
+        })
+    };
+
+    let after = total + params.cash;
+    let gaps = ts
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (i, t.weight * after - current[i]))
+        .collect::<Vec<_>>();
+
+    let mut orders = Vec::new();
+    let mut budget = params.cash;
+
+    if params.allow_sales {
+        for (i, gap) in gaps.iter() {
+            let isin = order_isin(*i);
+            let available = isin.as_ref().and_then(|x| values.get(x)).unwrap_or(&0.0);
+            let amount = round((-gap).min(*available));
+            if amount > 0.0 && amount >= params.min_order {
+                let fees = round(params.fixed_fee + params.fee_rate * amount);
+                budget += amount - fees;
+                orders.push((*i, isin, -amount, fees));
+            }
+        }
+    }
+
+    for (i, amount) in purchases(&gaps, budget, params) {
+        let fees = round(params.fixed_fee + params.fee_rate * amount);
+        budget -= amount + fees;
+        orders.push((i, order_isin(i), amount, fees));
+    }
+
+    // prices of stocks that are not held yet
+    let missing = orders
+        .iter()
+        .filter_map(|(_, isin, _, _)| isin.clone())
+        .filter(|isin| !prices.contains_key(isin))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        let fx = currency::Conversion::load(connection, user_id)?;
+        let found: PriceMap<RealtimePrice> =
+            fx.prices(price::find(connection, &missing, date, 4 * 24, 4 * 24)?)?;
+        for (isin, ds) in found {
+            prices.insert(isin, ds.price.value());
+        }
+    }
+
+    let fees = orders.iter().map(|(_, _, _, f)| f).sum::<f64>();
+    let total_after = total + params.cash - fees;
+    let weight = |v: f64, t: f64| if t != 0.0 { v / t } else { 0.0 };
+
+    Ok(Rebalancing {
+        currency: p.currency,
+        value: round(total),
+        cash: params.cash,
+        targets: ts
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let traded = orders
+                    .iter()
+                    .filter(|(j, _, _, _)| *j == i)
+                    .map(|(_, _, a, _)| a)
+                    .sum::<f64>();
+                TargetStatus {
+                    target: t.clone(),
+                    name: t
+                        .isin
+                        .clone()
+                        .or_else(|| t.tag_id.and_then(|id| tag_names.get(&id).cloned()))
+                        .unwrap_or_default(),
+                    isins: isins[i].clone(),
+                    value: round(current[i]),
+                    weight: weight(current[i], total),
+                    deviation: weight(current[i], total) - t.weight,
+                    weight_after: weight(current[i] + traded, total_after),
+                }
+            })
+            .collect(),
+        untargeted: round(untargeted),
+        orders: orders
+            .into_iter()
+            .map(|(i, isin, amount, fees)| Order {
+                target_id: ts[i].id,
+                units: isin
+                    .as_ref()
+                    .and_then(|x| prices.get(x))
+                    .filter(|p| **p > 0.0)
+                    .map(|p| amount / p),
+                isin,
+                amount,
+                fees,
+            })
+            .collect(),
+        remaining_cash: round(budget),
+        unvalued,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn purchases_are_scaled_down_to_the_budget() {
+        let params = Parameters::default();
+        let orders = purchases(&[(0, 600.0), (1, 400.0), (2, -100.0)], 500.0, &params);
+
+        assert_eq!(orders, vec![(0, 300.0), (1, 200.0)]);
+    }
+
+    #[test]
+    fn purchases_below_the_minimum_are_dropped() {
+        let params = Parameters {
+            min_order: 60.0,
+            ..Default::default()
+        };
+        // scaled down to 450 and 50, the second one is too small and its money goes to the first
+        let orders = purchases(&[(0, 900.0), (1, 100.0)], 500.0, &params);
+
+        assert_eq!(orders, vec![(0, 500.0)]);
+    }
+
+    #[test]
+    fn fees_are_paid_from_the_budget() {
+        let params = Parameters {
+            fixed_fee: 10.0,
+            ..Default::default()
+        };
+        // two orders would cost more in fees than there is
+        let orders = purchases(&[(0, 100.0), (1, 100.0)], 15.0, &params);
+        assert_eq!(orders, vec![(1, 5.0)]);
+
+        assert!(purchases(&[(0, 100.0)], 10.0, &params).is_empty());
+
+        let params = Parameters {
+            fee_rate: 0.01,
+            ..Default::default()
+        };
+        assert_eq!(purchases(&[(0, 200.0)], 101.0, &params), vec![(0, 100.0)]);
+    }
+
+    #[test]
+    fn parameters_must_not_be_negative() {
+        let params = Parameters {
+            cash: f64::NAN,
+            min_order: -1.0,
+            fee_rate: 0.01,
+            ..Default::default()
+        };
+        let fields = params
+            .check()
+            .unwrap_err()
+            .errors
+            .into_iter()
+            .map(|e| e.field)
+            .collect::<Vec<_>>();
+
+        assert_eq!(fields, vec!["cash", "minOrder"]);
+        assert!(Parameters::default().check().is_ok());
+    }
+}
@@ -0,0 +1,467 @@
+use crate::analysis::price::EitherPrice;
+use crate::analysis::{cash, currency};
+use crate::data::exchange_comparison;
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, Utc};
+use diesel::prelude::*;
+use itertools::Itertools;
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+// sub-periods that start with (almost) no capital are left out of the returns
+const EPSILON: f64 = 1e-6;
+
+// closing prices older than this are not used for the value of a position
+const MAX_PRICE_AGE: i64 = 7;
+
+// value at the end of every day and the money that went in during the day
+// (positive: paid into the position or portfolio, i.e. the opposite of the transaction convention)
+#[derive(Debug, Clone)]
+pub struct DailyValues {
+    first: NaiveDate,
+    values: Vec<Option<f64>>, // None if there was no price
+    flows: Vec<f64>,
+}
+
+impl DailyValues {
+    pub fn new(first: NaiveDate, last: NaiveDate) -> DailyValues {
+        let n = ((last - first).num_days() + 1).max(0) as usize;
+        DailyValues {
+            first,
+            values: vec![Some(0.0); n],
+            flows: vec![0.0; n],
+        }
+    }
+
+    fn index(&self, day: NaiveDate) -> Option<usize> {
+        let i = (day - self.first).num_days();
+        if i >= 0 && (i as usize) < self.values.len() {
+            Some(i as usize)
+        } else {
+            None
+        }
+    }
+
+    fn days(&self) -> impl Iterator<Item = NaiveDate> {
+        let first = self.first;
+        (0..self.values.len()).map(move |i| first + Duration::days(i as i64))
+    }
+
+    // a position: units at the end of every day times the last closing price before
+    pub fn position(
+        ts: &[&Transaction],
+        closing: Option<&BTreeMap<NaiveDate, f64>>,
+        first: NaiveDate,
+        last: NaiveDate,
+    ) -> DailyValues {
+        let mut result = DailyValues::new(first, last);
+        let mut units = ts
+            .iter()
+            .filter(|t| t.date.with_timezone(&Local).date().naive_local() < first)
+            .map(|t| t.units)
+            .sum::<f64>();
+
+        for (i, day) in result.days().enumerate().collect::<Vec<_>>() {
+            for t in ts
+                .iter()
+                .filter(|t| t.date.with_timezone(&Local).date().naive_local() == day)
+            {
+                units += t.units;
+                result.flows[i] -= (t.amount + t.fees) as f64 / 100.0;
+            }
+
+            result.values[i] = if units.abs() < EPSILON {
+                Some(0.0)
+            } else {
+                closing
+                    .and_then(|c| c.range(day - Duration::days(MAX_PRICE_AGE)..=day).last())
+                    .map(|(_, p)| units * p)
+            };
+        }
+
+        result
+    }
+
+    // one unit held all the time, e.g. of a benchmark
+    pub fn prices(
+        closing: Option<&BTreeMap<NaiveDate, f64>>,
+        first: NaiveDate,
+        last: NaiveDate,
+    ) -> DailyValues {
+        let mut result = DailyValues::new(first, last);
+        for (i, day) in result.days().enumerate().collect::<Vec<_>>() {
+            result.values[i] = closing
+                .and_then(|c| c.range(day - Duration::days(MAX_PRICE_AGE)..=day).last())
+                .map(|(_, p)| *p);
+        }
+
+        result
+    }
+
+    // sum of two series over the same days
+    pub fn add(&mut self, other: &DailyValues) {
+        for (i, day) in self.days().enumerate().collect::<Vec<_>>() {
+            let j = other.index(day);
+            self.values[i] = self.values[i]
+                .and_then(|v| j.map(|j| other.values[j].map(|o| v + o)).unwrap_or(Some(v)));
+            self.flows[i] += j.map(|j| other.flows[j]).unwrap_or(0.0);
+        }
+    }
+
+    pub fn add_value(&mut self, day: NaiveDate, value: f64) {
+        if let Some(i) = self.index(day) {
+            self.values[i] = self.values[i].map(|v| v + value);
+        }
+    }
+
+    pub fn set_flow(&mut self, day: NaiveDate, flow: f64) {
+        if let Some(i) = self.index(day) {
+            self.flows[i] = flow;
+        }
+    }
+
+    // chain-linked daily returns from the end of `start` to the end of `end`.
+    // `start_value` and `end_value` replace the values of the series at the boundaries
+    // (e.g. for realtime prices).
+    pub fn time_weighted(
+        &self,
+        start: NaiveDate,
+        start_value: f64,
+        end: NaiveDate,
+        end_value: f64,
+    ) -> Option<f64> {
+        let mut previous = start_value;
+        let mut growth = 1.0;
+
+        let mut day = start.succ();
+        while day <= end {
+            let i = self.index(day)?;
+            let value = if day == end {
+                end_value
+            } else {
+                self.values[i]?
+            };
+
+            if let Some(r) = sub_period(previous, value, self.flows[i]) {
+                growth *= 1.0 + r;
+            }
+
+            previous = value;
+            day = day.succ();
+        }
+
+        Some(growth - 1.0)
+    }
+
+    // returns between consecutive trading days (Monday to Friday) in (start, end], flows on weekends
+    // count for the following Monday. Days without a value or capital are left out.
+    pub fn trading_day_returns(&self, start: NaiveDate, end: NaiveDate) -> Vec<(NaiveDate, f64)> {
+        let mut result = Vec::new();
+        let mut previous = self.index(start).and_then(|i| self.values[i]);
+        let mut flow = 0.0;
+
+        let mut day = start.succ();
+        while let Some(i) = self.index(day).filter(|_| day <= end) {
+            flow += self.flows[i];
+
+            if day.weekday().number_from_monday() <= 5 {
+                let value = self.values[i];
+                if let (Some(p), Some(v)) = (previous, value) {
+                    if let Some(r) = sub_period(p, v, flow) {
+                        result.push((day, r));
+                    }
+                }
+
+                previous = value;
+                flow = 0.0;
+            }
+            day = day.succ();
+        }
+
+        result
+    }
+}
+
+// return of a sub-period with value `previous` at the start, `value` at the end and `flow` in between:
+// money paid in counts from the start, money taken out until the end of the sub-period
+fn sub_period(previous: f64, value: f64, flow: f64) -> Option<f64> {
+    let capital = previous + flow.max(0.0);
+    if capital.abs() > EPSILON {
+        Some((value - previous - flow) / capital)
+    } else {
+        None
+    }
+}
+
+// daily values of every position and of the whole portfolio, which also holds the cash if given
+pub fn portfolio(
+    ts: &[Transaction],
+    cts: Option<&[CashTransaction]>,
+    isins: &[String],
+    closing: &HashMap<String, BTreeMap<NaiveDate, f64>>,
+    first: NaiveDate,
+    last: NaiveDate,
+) -> (HashMap<String, DailyValues>, DailyValues) {
+    let positions = isins
+        .iter()
+        .map(|isin| {
+            let isin_ts = ts.iter().filter(|t| &t.isin == isin).collect::<Vec<_>>();
+            (
+                isin.clone(),
+                DailyValues::position(&isin_ts, closing.get(isin), first, last),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut total = DailyValues::new(first, last);
+    for d in positions.values() {
+        total.add(d);
+    }
+    if let Some(cts) = cts {
+        // with cash, only deposits and withdrawals are flows of the portfolio.
+        // Same as cash::balance and cash::external_flows for every day, but in a single pass
+        let local_day = |d: DateTime<Utc>| d.with_timezone(&Local).date().naive_local();
+        let mut movements = cts
+            .iter()
+            .map(|ct| (local_day(ct.date), ct.amount))
+            .chain(
+                ts.iter()
+                    .filter(|t| t.synthetic.is_none())
+                    .map(|t| (local_day(t.date), t.amount + t.fees)),
+            )
+            .collect::<Vec<_>>();
+        movements.sort_by_key(|(d, _)| *d);
+        let mut flows: HashMap<NaiveDate, i64> = HashMap::new();
+        for ct in cts.iter().filter(|ct| cash::is_external(&ct.kind)) {
+            *flows.entry(local_day(ct.date)).or_insert(0) += ct.amount;
+        }
+
+        let mut movements = movements.into_iter().peekable();
+        let mut balance = 0;
+        let mut day = first;
+        while day <= last {
+            while let Some((_, amount)) = movements.next_if(|(d, _)| *d <= day) {
+                balance += amount;
+            }
+            total.add_value(day, balance as f64 / 100.0);
+            total.set_flow(day, flows.get(&day).copied().unwrap_or(0) as f64 / 100.0);
+            day = day.succ();
+        }
+    }
+
+    (positions, total)
+}
+
+// (V1 - V0 - sum F_i) / (V0 + sum w_i F_i) with w_i the fraction of the period after the flow F_i.
+// `flows` are paid into the position or portfolio (positive) or taken out (negative).
+pub fn modified_dietz(
+    start_value: f64,
+    end_value: f64,
+    flows: &[(DateTime<Utc>, f64)],
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Option<f64> {
+    let length = (end - start).num_seconds() as f64;
+    if length <= 0.0 {
+        return None;
+    }
+
+    let net = flows.iter().map(|(_, f)| f).sum::<f64>();
+    let weighted = flows
+        .iter()
+        .map(|(d, f)| {
+            let w = (end - *d).num_seconds() as f64 / length;
+            w.clamp(0.0, 1.0) * f
+        })
+        .sum::<f64>();
+
+    let capital = start_value + weighted;
+    if capital.abs() > EPSILON {
+        Some((end_value - start_value - net) / capital)
+    } else {
+        None
+    }
+}
+
+// daily closing prices on the preferred exchange of every isin, converted into the reporting currency
+pub fn closing_prices(
+    connection: &PgConnection,
+    fx: &currency::Conversion,
+    isins: &[String],
+    first: NaiveDate,
+    last: NaiveDate,
+) -> Result<HashMap<String, BTreeMap<NaiveDate, f64>>, Box<dyn Error>> {
+    let exchanges = stock_exchanges::table
+        .filter(stock_exchanges::isin.eq_any(isins))
+        .order(stock_exchanges::isin.asc())
+        .load::<StockExchange>(connection)?
+        .into_iter()
+        .group_by(|se| se.isin.clone())
+        .into_iter()
+        .filter_map(|(_, es)| es.min_by(exchange_comparison))
+        .map(|e| (e.onvista_record_id, e.isin))
+        .collect::<HashMap<_, _>>();
+
+    let prices = historical_prices::table
+        .filter(historical_prices::onvista_record_id.eq_any(exchanges.keys().collect::<Vec<_>>()))
+        .filter(historical_prices::date.ge(first - Duration::days(MAX_PRICE_AGE)))
+        .filter(historical_prices::date.le(last))
+        .load::<HistoricalPrice>(connection)?
+        .into_iter()
+        .map(EitherPrice::HistoricalPrice)
+        .collect::<Vec<_>>();
+    debug!(
+        "loaded {} closing prices for {} exchanges",
+        prices.len(),
+        exchanges.len()
+    );
+
+    let mut result: HashMap<String, BTreeMap<NaiveDate, f64>> = HashMap::new();
+    for p in fx.either_prices(prices)? {
+        if let EitherPrice::HistoricalPrice(p) = p {
+            result
+                .entry(exchanges[&p.onvista_record_id].clone())
+                .or_default()
+                .insert(p.date, p.closing);
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd(2020, 6, d)
+    }
+
+    fn transaction(d: u32, units: f64, amount: i64) -> Transaction {
+        Transaction {
+            id: 1,
+            account_id: 1,
+            isin: "A".to_string(),
+            date: Local.ymd(2020, 6, d).and_hms(12, 0, 0).with_timezone(&Utc),
+            units,
+            amount,
+            fees: 0,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: None,
+        }
+    }
+
+    #[test]
+    fn time_weighted_with_full_sale() {
+        // bought at 100, sold everything at 121; the days without capital afterwards do not count
+        let ts = vec![transaction(2, 10.0, -100000), transaction(4, -10.0, 121000)];
+        let closing = vec![
+            (day(2), 100.0),
+            (day(3), 110.0),
+            (day(4), 121.0),
+            (day(5), 50.0),
+        ]
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+        let d = DailyValues::position(
+            &ts.iter().collect::<Vec<_>>(),
+            Some(&closing),
+            day(1),
+            day(6),
+        );
+
+        let r = d.time_weighted(day(1), 0.0, day(6), 0.0).unwrap();
+        assert!((r - 0.21).abs() < 1e-9);
+    }
+
+    #[test]
+    fn time_weighted_with_deposit() {
+        // +5% per day, with a deposit of 1000 on the second day
+        let mut d = DailyValues::new(day(1), day(3));
+        d.add_value(day(1), 1000.0);
+        d.add_value(day(2), 2100.0);
+        d.set_flow(day(2), 1000.0);
+        d.add_value(day(3), 2205.0);
+
+        let r = d.time_weighted(day(1), 1000.0, day(3), 2205.0).unwrap();
+        assert!((r - 0.1025).abs() < 1e-9);
+    }
+
+    #[test]
+    fn modified_dietz_with_deposit() {
+        let start = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let end = Utc.ymd(2020, 1, 11).and_hms(0, 0, 0);
+        let flows = vec![(Utc.ymd(2020, 1, 6).and_hms(0, 0, 0), 1000.0)];
+
+        // (2100 - 1000 - 1000) / (1000 + 0.5 * 1000)
+        let r = modified_dietz(1000.0, 2100.0, &flows, start, end).unwrap();
+        assert!((r - 100.0 / 1500.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn modified_dietz_with_full_sale() {
+        let start = Utc.ymd(2020, 1, 1).and_hms(0, 0, 0);
+        let end = Utc.ymd(2020, 1, 11).and_hms(0, 0, 0);
+        let flows = vec![(Utc.ymd(2020, 1, 6).and_hms(0, 0, 0), -1100.0)];
+
+        // (0 - 1000 + 1100) / (1000 - 0.5 * 1100)
+        let r = modified_dietz(1000.0, 0.0, &flows, start, end).unwrap();
+        assert!((r - 100.0 / 450.0).abs() < 1e-9);
+
+        assert!(modified_dietz(0.0, 0.0, &[], start, end).is_none());
+    }
+
+    #[test]
+    fn portfolio_with_cash() {
+        let cash = |id, d, kind: &str, amount| CashTransaction {
+            id,
+            account_id: 1,
+            date: Local.ymd(2020, 6, d).and_hms(9, 0, 0).with_timezone(&Utc),
+            kind: kind.to_string(),
+            amount,
+            comments: String::new(),
+        };
+        let cts = vec![
+            cash(1, 1, cash::DEPOSIT, 150000),
+            cash(2, 3, cash::INTEREST, 100),
+            cash(3, 4, cash::WITHDRAWAL, -20000),
+            cash(4, 4, cash::DEPOSIT, 5000),
+        ];
+        let ts = vec![transaction(2, 10.0, -100000), transaction(5, -10.0, 121000)];
+        let closing = (1..=6)
+            .map(|d| (day(d), 100.0 + d as f64))
+            .collect::<BTreeMap<_, _>>();
+        let closing = vec![("A".to_string(), closing)].into_iter().collect();
+
+        let (positions, total) = portfolio(
+            &ts,
+            Some(&cts),
+            &["A".to_string()],
+            &closing,
+            day(1),
+            day(6),
+        );
+        for d in 1..=6 {
+            let expected =
+                positions["A"].values[(d - 1) as usize].unwrap() + cash::balance(&cts, &ts, day(d));
+            assert!((total.values[(d - 1) as usize].unwrap() - expected).abs() < 1e-9);
+
+            let flow = cash::external_flows(&cts, Some(day(d).pred()), day(d))
+                .iter()
+                .map(|(_, x)| -x)
+                .sum::<f64>();
+            assert!((total.flows[(d - 1) as usize] - flow).abs() < 1e-9);
+        }
+        assert!((total.flows[3] + 150.0).abs() < 1e-9);
+    }
+}
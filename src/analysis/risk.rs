@@ -0,0 +1,317 @@
+use crate::analysis::benchmark::{self, Benchmark};
+use crate::analysis::returns::{self, DailyValues};
+use crate::analysis::{self, Data, Options};
+
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+// daily returns are taken on every weekday (see DailyValues::trading_day_returns), holidays included
+const TRADING_DAYS: f64 = 365.25 * 5.0 / 7.0;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Drawdown {
+    pub depth: f64, // negative, relative to the peak
+    pub peak: NaiveDate,
+    pub trough: NaiveDate,
+    pub recovery: Option<NaiveDate>, // not set if the peak has not been reached again
+}
+
+// statistics of the daily (time-weighted) returns of a position or the portfolio
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskMetrics {
+    pub days: usize,             // number of daily returns
+    pub volatility: Option<f64>, // annualised
+    pub max_drawdown: Option<Drawdown>,
+    pub current_drawdown: Option<f64>,
+    pub sharpe: Option<f64>,
+    pub sortino: Option<f64>,
+    pub beta: Option<f64>, // only set if a benchmark was chosen
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskReport {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub risk_free_rate: f64, // annual
+    pub benchmark: Option<Benchmark>,
+    pub portfolio: RiskMetrics,
+    pub positions: HashMap<String, RiskMetrics>,
+}
+
+// risk statistics from the end of `start` (default: the first transaction) until `date`.
+// `risk_free_rate` is annual, e.g. 0.01 for 1% p.a.
+pub fn compute(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+    options: &Options,
+    start: Option<NaiveDate>,
+    risk_free_rate: f64,
+) -> Result<RiskReport, Box<dyn Error>> {
+    let Data {
+        fx,
+        transactions: ts,
+        cash: cts,
+        ..
+    } = analysis::load(connection, user_id, date, options)?;
+
+    let mut isins = ts
+        .iter()
+        .map(|t| &t.isin)
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect::<Vec<_>>();
+    isins.sort();
+    let isins = isins;
+
+    let benchmark = match options.benchmark.as_deref() {
+        Some(isin) => Some(benchmark::resolve(connection, isin)?),
+        None => None,
+    };
+    let mut price_isins = isins.clone();
+    if let Some(b) = benchmark.as_ref() {
+        if !price_isins.contains(&b.isin) {
+            price_isins.push(b.isin.clone());
+        }
+    }
+
+    let end = date.with_timezone(&Local).date().naive_local();
+    let start = start.unwrap_or_else(|| {
+        ts.first()
+            .map(|t| t.date.with_timezone(&Local).date().naive_local().pred())
+            .unwrap_or(end)
+    });
+
+    let closing = returns::closing_prices(connection, &fx, &price_isins, start, end)?;
+    let (daily, portfolio_daily) =
+        returns::portfolio(&ts, cts.as_deref(), &isins, &closing, start, end);
+    let benchmark_returns = benchmark.as_ref().map(|b| {
+        DailyValues::prices(closing.get(&b.isin), start, end)
+            .trading_day_returns(start, end)
+            .into_iter()
+            .collect::<HashMap<_, _>>()
+    });
+
+    let series_metrics = |d: &DailyValues| {
+        metrics(
+            start,
+            &d.trading_day_returns(start, end),
+            risk_free_rate,
+            benchmark_returns.as_ref(),
+        )
+    };
+
+    Ok(RiskReport {
+        start,
+        end,
+        risk_free_rate,
+        portfolio: series_metrics(&portfolio_daily),
+        positions: daily
+            .iter()
+            .map(|(isin, d)| (isin.clone(), series_metrics(d)))
+            .collect(),
+        benchmark,
+    })
+}
+
+fn metrics(
+    start: NaiveDate,
+    returns: &[(NaiveDate, f64)],
+    risk_free_rate: f64,
+    benchmark: Option<&HashMap<NaiveDate, f64>>,
+) -> RiskMetrics {
+    let n = returns.len() as f64;
+    let rf = (1.0 + risk_free_rate).powf(1.0 / TRADING_DAYS) - 1.0;
+
+    let mean = returns.iter().map(|(_, r)| r).sum::<f64>() / n;
+    let volatility = if returns.len() > 1 {
+        let variance = returns.iter().map(|(_, r)| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        Some((variance * TRADING_DAYS).sqrt())
+    } else {
+        None
+    };
+    let downside = if returns.is_empty() {
+        None
+    } else {
+        let variance = returns
+            .iter()
+            .map(|(_, r)| (r - rf).min(0.0).powi(2))
+            .sum::<f64>()
+            / n;
+        Some((variance * TRADING_DAYS).sqrt())
+    };
+
+    // annualised excess return over the risk-free rate divided by the (downside) volatility
+    let excess = (mean - rf) * TRADING_DAYS;
+    let ratio = |x: Option<f64>| x.filter(|x| *x > 0.0).map(|x| excess / x);
+
+    let (max_drawdown, current_drawdown) = drawdowns(start, returns);
+
+    RiskMetrics {
+        days: returns.len(),
+        volatility,
+        max_drawdown,
+        current_drawdown,
+        sharpe: ratio(volatility),
+        sortino: ratio(downside),
+        beta: benchmark.and_then(|b| beta(returns, b)),
+    }
+}
+
+// maximum and current drawdown of the wealth index that starts at 1 at the end of `start`
+fn drawdowns(start: NaiveDate, returns: &[(NaiveDate, f64)]) -> (Option<Drawdown>, Option<f64>) {
+    if returns.is_empty() {
+        return (None, None);
+    }
+
+    let mut wealth = 1.0;
+    let mut peak = (start, 1.0);
+    let mut max: Option<(Drawdown, f64)> = None; // together with the wealth at its peak
+
+    for (day, r) in returns.iter() {
+        wealth *= 1.0 + r;
+
+        if wealth >= peak.1 {
+            peak = (*day, wealth);
+            if let Some((m, peak_wealth)) = max.as_mut() {
+                if m.recovery.is_none() && wealth >= *peak_wealth {
+                    m.recovery = Some(*day);
+                }
+            }
+        } else {
+            let depth = wealth / peak.1 - 1.0;
+            if max.as_ref().map(|(m, _)| depth < m.depth).unwrap_or(true) {
+                max = Some((
+                    Drawdown {
+                        depth,
+                        peak: peak.0,
+                        trough: *day,
+                        recovery: None,
+                    },
+                    peak.1,
+                ));
+            }
+        }
+    }
+
+    (max.map(|(m, _)| m), Some(wealth / peak.1 - 1.0))
+}
+
+// covariance with the benchmark's returns divided by their variance, on the days both have a return
+fn beta(returns: &[(NaiveDate, f64)], benchmark: &HashMap<NaiveDate, f64>) -> Option<f64> {
+    let pairs = returns
+        .iter()
+        .filter_map(|(d, r)| benchmark.get(d).map(|b| (*r, *b)))
+        .collect::<Vec<_>>();
+    if pairs.len() < 2 {
+        return None;
+    }
+
+    let n = pairs.len() as f64;
+    let mean_r = pairs.iter().map(|(r, _)| r).sum::<f64>() / n;
+    let mean_b = pairs.iter().map(|(_, b)| b).sum::<f64>() / n;
+    let covariance = pairs
+        .iter()
+        .map(|(r, b)| (r - mean_r) * (b - mean_b))
+        .sum::<f64>();
+    let variance = pairs.iter().map(|(_, b)| (b - mean_b).powi(2)).sum::<f64>();
+
+    if variance > 0.0 {
+        Some(covariance / variance)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn returns(rs: &[f64]) -> Vec<(NaiveDate, f64)> {
+        rs.iter()
+            .enumerate()
+            .map(|(i, r)| (NaiveDate::from_ymd(2020, 1, 2 + i as u32), *r))
+            .collect()
+    }
+
+    #[test]
+    fn drawdown_with_recovery() {
+        // 1.1, 0.88, 0.968, 1.1616
+        let rs = returns(&[0.1, -0.2, 0.1, 0.2]);
+        let (max, current) = drawdowns(NaiveDate::from_ymd(2020, 1, 1), &rs);
+        let max = max.unwrap();
+
+        assert!((max.depth + 0.2).abs() < 1e-9);
+        assert_eq!(max.peak, NaiveDate::from_ymd(2020, 1, 2));
+        assert_eq!(max.trough, NaiveDate::from_ymd(2020, 1, 3));
+        assert_eq!(max.recovery, Some(NaiveDate::from_ymd(2020, 1, 5)));
+        assert!(current.unwrap().abs() < 1e-9);
+    }
+
+    #[test]
+    fn drawdown_without_recovery() {
+        // 0.9, 0.72, 0.792
+        let rs = returns(&[-0.1, -0.2, 0.1]);
+        let (max, current) = drawdowns(NaiveDate::from_ymd(2020, 1, 1), &rs);
+        let max = max.unwrap();
+
+        assert!((max.depth + 0.28).abs() < 1e-9);
+        assert_eq!(max.peak, NaiveDate::from_ymd(2020, 1, 1));
+        assert_eq!(max.trough, NaiveDate::from_ymd(2020, 1, 3));
+        assert_eq!(max.recovery, None);
+        assert!((current.unwrap() + 0.208).abs() < 1e-9);
+    }
+
+    #[test]
+    fn no_drawdown_without_returns() {
+        let (max, current) = drawdowns(NaiveDate::from_ymd(2020, 1, 1), &[]);
+
+        assert!(max.is_none());
+        assert!(current.is_none());
+    }
+
+    #[test]
+    fn beta_of_leveraged_returns() {
+        let benchmark = returns(&[0.01, -0.02, 0.03, 0.0]);
+        let rs = benchmark
+            .iter()
+            .map(|(d, r)| (*d, 2.0 * r + 0.001))
+            .collect::<Vec<_>>();
+        let benchmark = benchmark.into_iter().collect::<HashMap<_, _>>();
+
+        assert!((beta(&rs, &benchmark).unwrap() - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn beta_needs_common_days() {
+        let benchmark = returns(&[0.01, 0.02])
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let rs = vec![(NaiveDate::from_ymd(2021, 1, 4), 0.01)];
+
+        assert!(beta(&rs, &benchmark).is_none());
+        assert!(beta(
+            &returns(&[0.01, 0.02]),
+            &returns(&[0.0, 0.0]).into_iter().collect()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn annualised_volatility() {
+        let rs = returns(&[0.01, -0.01, 0.01, -0.01]);
+        let m = metrics(NaiveDate::from_ymd(2020, 1, 1), &rs, 0.0, None);
+        let daily = (0.0004f64 / 3.0).sqrt();
+
+        assert_eq!(m.days, 4);
+        assert!((m.volatility.unwrap() - daily * TRADING_DAYS.sqrt()).abs() < 1e-9);
+        assert!(m.beta.is_none());
+    }
+}
@@ -0,0 +1,550 @@
+use crate::analysis::price::Price;
+use crate::analysis::{corporate_actions, currency, transfers};
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::offset::TimeZone;
+use chrono::{DateTime, Datelike, Local, Utc};
+use diesel::prelude::*;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+// German taxation of investment income (InvStG 2018): funds are taxed in advance with the
+// Vorabpauschale, part of their income is exempt (Teilfreistellung) and every user has a
+// tax-free allowance per year (Sparerpauschbetrag). All amounts are in EUR.
+
+// used for years without an entry in tax_allowances, in cents
+pub const SPARERPAUSCHBETRAG: i64 = 80100;
+
+// the Basisertrag is 70% of the Basiszins
+const BASE_RETURN_SHARE: f64 = 0.7;
+
+// units below this are considered to be rounding errors
+const EPSILON: f64 = 1e-6;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vorabpauschale {
+    pub isin: String,
+    pub year: i32, // considered received on the first business day of the following year
+    pub units: f64, // held at the end of the year
+    pub weighted_units: f64, // units bought during the year count 1/12 less for every month before their purchase
+    pub start_price: f64,
+    pub end_price: f64,
+    pub distributions: f64,
+    pub base_rate: f64, // in percent
+    pub base_return: f64,
+    pub amount: f64, // before partial exemption
+    pub partial_exemption: f64,
+    pub taxable: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxYear {
+    pub year: i32,
+    pub realized_gains: f64, // after partial exemption, earlier Vorabpauschalen are deducted
+    pub distributions: f64, // dividends and fund distributions before withheld taxes, after partial exemption
+    pub vorabpauschale: f64, // of the previous year, after partial exemption
+    pub taxable: f64,
+    pub allowance: f64,
+    pub allowance_used: f64,
+    pub allowance_left: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxReport {
+    pub years: Vec<TaxYear>,
+    pub vorabpauschalen: Vec<Vorabpauschale>,
+    pub partial_exemptions: BTreeMap<String, f64>, // per isin, only the ones that are not zero
+}
+
+pub fn is_fund(stock: &StockInfo) -> bool {
+    stock.kind == "ETF"
+}
+
+// Teilfreistellung (§ 20 InvStG) derived from onvista's fund type and investment focus
+pub fn partial_exemption(stock: &StockInfo) -> f64 {
+    if !is_fund(stock) {
+        return 0.0;
+    }
+
+    let fonds_type = stock.fonds_type.as_deref().unwrap_or("").to_lowercase();
+    let focus = stock.focus.as_deref().unwrap_or("").to_lowercase();
+
+    if fonds_type.contains("immobilien") {
+        // funds with a focus on foreign real estate
+        if focus.contains("welt") || focus.contains("ausland") || focus.contains("international") {
+            0.8
+        } else {
+            0.6
+        }
+    } else if fonds_type.contains("misch") {
+        0.15
+    } else if fonds_type.contains("aktien") || focus.starts_with("aktien") {
+        0.3
+    } else {
+        0.0
+    }
+}
+
+// allowance of every year in cents, an entry applies until the next one
+fn allowances(
+    connection: &PgConnection,
+    user_id: i32,
+) -> Result<BTreeMap<i32, i64>, Box<dyn Error>> {
+    Ok(tax_allowances::table
+        .filter(tax_allowances::user_id.eq(user_id))
+        .load::<TaxAllowance>(connection)?
+        .into_iter()
+        .map(|a| (a.year, a.amount))
+        .collect())
+}
+
+fn allowance_in(allowances: &BTreeMap<i32, i64>, year: i32) -> i64 {
+    allowances
+        .range(..=year)
+        .next_back()
+        .map(|(_, a)| *a)
+        .unwrap_or(SPARERPAUSCHBETRAG)
+}
+
+fn year_of(date: DateTime<Utc>) -> i32 {
+    date.with_timezone(&Local).year()
+}
+
+// share of a purchase in `year` that counts for the Vorabpauschale of that year
+fn purchase_weight(date: DateTime<Utc>, year: i32) -> f64 {
+    let d = date.with_timezone(&Local);
+    if d.year() < year {
+        1.0
+    } else {
+        (13 - d.month()) as f64 / 12.0
+    }
+}
+
+// distributions count before the taxes that were withheld from them (`withheld`: per transaction id,
+// negative, in the account's currency)
+fn gross(ts: Vec<(Transaction, Account)>, withheld: &HashMap<i32, i64>) -> Vec<Transaction> {
+    ts.into_iter()
+        .map(|(mut t, a)| {
+            if let Some(taxes) = withheld.get(&t.id) {
+                if t.units == 0.0
+                    && t.currency
+                        .as_ref()
+                        .map(|c| *c == a.currency)
+                        .unwrap_or(true)
+                {
+                    t.amount -= taxes;
+                }
+            }
+            t
+        })
+        .collect()
+}
+
+fn round(x: f64) -> f64 {
+    (x * 100.0).round() / 100.0
+}
+
+pub fn compute(connection: &PgConnection, user_id: i32) -> Result<TaxReport, Box<dyn Error>> {
+    let now = Utc::now();
+    let ts = transactions::table
+        .inner_join(accounts::table)
+        .filter(accounts::user_id.eq(user_id))
+        .filter(transactions::date.le(now))
+        .order(transactions::date.asc())
+        .load::<(Transaction, Account)>(connection)?;
+    let withheld = distributions::table
+        .filter(
+            distributions::transaction_id.eq_any(ts.iter().map(|(t, _)| t.id).collect::<Vec<_>>()),
+        )
+        .select((distributions::transaction_id, distributions::taxes))
+        .load::<(i32, i64)>(connection)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let ts = gross(ts, &withheld);
+
+    // taxes are always computed in EUR, no matter what the user reports in
+    let mut fx = currency::Conversion::load(connection, user_id)?;
+    fx.reporting_currency = currency::EUR.to_string();
+    let ts = fx.transactions(ts)?;
+    let ts = corporate_actions::apply(connection, user_id, ts, now)?;
+    // transfers do not change the units held, only the lots they are taken from
+    let (_, lots) = transfers::apply(connection, user_id, ts.clone(), now, None)?;
+
+    let first_year = match ts.first() {
+        Some(t) => year_of(t.date),
+        None => {
+            return Ok(TaxReport {
+                years: Vec::new(),
+                vorabpauschalen: Vec::new(),
+                partial_exemptions: BTreeMap::new(),
+            })
+        }
+    };
+    let current_year = Local::today().year();
+
+    let mut isins = ts.iter().map(|t| t.isin.clone()).collect::<Vec<_>>();
+    isins.sort();
+    isins.dedup();
+    let stocks = stock_infos::table
+        .filter(stock_infos::isin.eq_any(&isins))
+        .load::<StockInfo>(connection)?;
+    let exemptions = stocks
+        .iter()
+        .map(|s| (s.isin.clone(), partial_exemption(s)))
+        .collect::<HashMap<_, _>>();
+    let exemption = |isin: &str| exemptions.get(isin).cloned().unwrap_or(0.0);
+
+    let vorabpauschalen = compute_vorabpauschalen(
+        connection,
+        &fx,
+        &ts,
+        &stocks,
+        &exemptions,
+        first_year,
+        current_year,
+    )?;
+
+    // Vorabpauschale per weighted unit, deducted from the gains of later sales
+    let per_unit = vorabpauschalen
+        .iter()
+        .filter(|v| v.weighted_units > EPSILON)
+        .map(|v| ((v.isin.as_str(), v.year), v.amount / v.weighted_units))
+        .collect::<HashMap<_, _>>();
+    let purchases = ts.iter().map(|t| (t.id, t.date)).collect::<HashMap<_, _>>();
+
+    let mut gains: BTreeMap<i32, f64> = BTreeMap::new();
+    for s in lots.sales.iter() {
+        let sale_year = year_of(s.date);
+        let deduction: f64 = s
+            .lots
+            .iter()
+            .filter_map(|l| purchases.get(&l.transaction_id).map(|d| (l, *d)))
+            .map(|(l, acquired)| {
+                (year_of(acquired)..sale_year)
+                    .filter_map(|y| {
+                        per_unit
+                            .get(&(s.isin.as_str(), y))
+                            .map(|vp| vp * purchase_weight(acquired, y) * l.units)
+                    })
+                    .sum::<f64>()
+            })
+            .sum();
+
+        *gains.entry(sale_year).or_insert(0.0) += (s.gain - deduction) * (1.0 - exemption(&s.isin));
+    }
+
+    let mut distributions: BTreeMap<i32, f64> = BTreeMap::new();
+    for t in ts
+        .iter()
+        .filter(|t| t.units == 0.0 && t.synthetic.is_none())
+    {
+        *distributions.entry(year_of(t.date)).or_insert(0.0) +=
+            t.amount as f64 / 100.0 * (1.0 - exemption(&t.isin));
+    }
+
+    let allowances = allowances(connection, user_id)?;
+    let years = (first_year..=current_year)
+        .map(|y| {
+            let realized_gains = gains.get(&y).cloned().unwrap_or(0.0);
+            let distributions = distributions.get(&y).cloned().unwrap_or(0.0);
+            let vorabpauschale = vorabpauschalen
+                .iter()
+                .filter(|v| v.year == y - 1)
+                .map(|v| v.taxable)
+                .sum::<f64>();
+            let taxable = realized_gains + distributions + vorabpauschale;
+            let allowance = allowance_in(&allowances, y) as f64 / 100.0;
+            let allowance_used = taxable.max(0.0).min(allowance);
+
+            TaxYear {
+                year: y,
+                realized_gains: round(realized_gains),
+                distributions: round(distributions),
+                vorabpauschale: round(vorabpauschale),
+                taxable: round(taxable),
+                allowance,
+                allowance_used: round(allowance_used),
+                allowance_left: round(allowance - allowance_used),
+            }
+        })
+        .collect();
+
+    Ok(TaxReport {
+        years,
+        vorabpauschalen,
+        partial_exemptions: exemptions.into_iter().filter(|(_, e)| *e > 0.0).collect(),
+    })
+}
+
+// Vorabpauschale (§ 18 InvStG) of every fund for every complete year:
+// the Basisertrag (price at the beginning of the year * Basiszins * 0.7), but at most the increase
+// in value plus distributions, minus the distributions of the year
+fn compute_vorabpauschalen(
+    connection: &PgConnection,
+    fx: &currency::Conversion,
+    ts: &[Transaction],
+    stocks: &[StockInfo],
+    exemptions: &HashMap<String, f64>,
+    first_year: i32,
+    current_year: i32,
+) -> Result<Vec<Vorabpauschale>, Box<dyn Error>> {
+    let funds = stocks
+        .iter()
+        .filter(|s| is_fund(s))
+        .map(|s| s.isin.clone())
+        .collect::<Vec<_>>();
+    let years = (first_year..current_year).collect::<Vec<_>>();
+    if funds.is_empty() || years.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let base_rates = base_rates::table
+        .load::<BaseRate>(connection)?
+        .into_iter()
+        .map(|r| (r.year, r.rate))
+        .collect::<HashMap<_, _>>();
+
+    // closing prices at the end of the year before the first one and of every year after
+    let dates = (first_year - 1..current_year)
+        .map(|y| Local.ymd(y, 12, 31).and_hms(23, 59, 59).with_timezone(&Utc))
+        .collect::<Vec<_>>();
+    let prices = HistoricalPrice::find_multiple(connection, &funds, &dates, 7 * 24, 7 * 24)?
+        .into_iter()
+        .map(|p| fx.prices(p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut result = Vec::new();
+    for isin in funds.iter() {
+        let fund_ts = ts.iter().filter(|t| &t.isin == isin).collect::<Vec<_>>();
+
+        for (i, year) in years.iter().cloned().enumerate() {
+            let (units, weighted_units) = units_in(&fund_ts, year);
+            if units < EPSILON {
+                continue;
+            }
+
+            let base_rate = match base_rates.get(&year) {
+                Some(r) => *r,
+                None => {
+                    warn!(
+                        "There is no Basiszins for {}, skipping its Vorabpauschale",
+                        year
+                    );
+                    continue;
+                }
+            };
+            let (start_price, end_price) = match (
+                prices[i].get(isin).map(|ds| ds.price.value()),
+                prices[i + 1].get(isin).map(|ds| ds.price.value()),
+            ) {
+                (Some(s), Some(e)) => (s, e),
+                _ => {
+                    warn!(
+                        "Missing prices of {} around the turn of the year {}, skipping its Vorabpauschale",
+                        isin, year
+                    );
+                    continue;
+                }
+            };
+
+            let distributions = fund_ts
+                .iter()
+                .filter(|t| t.units == 0.0 && t.synthetic.is_none() && year_of(t.date) == year)
+                .map(|t| t.amount as f64 / 100.0)
+                .sum::<f64>();
+
+            let (base_return, amount) = vorabpauschale(
+                start_price,
+                end_price,
+                base_rate,
+                units,
+                weighted_units,
+                distributions,
+            );
+            let partial_exemption = exemptions.get(isin).cloned().unwrap_or(0.0);
+
+            result.push(Vorabpauschale {
+                isin: isin.clone(),
+                year,
+                units,
+                weighted_units,
+                start_price,
+                end_price,
+                distributions: round(distributions),
+                base_rate,
+                base_return: round(base_return),
+                amount: round(amount),
+                partial_exemption,
+                taxable: round(amount * (1.0 - partial_exemption)),
+            });
+        }
+    }
+
+    result.sort_by(|a, b| a.year.cmp(&b.year).then_with(|| a.isin.cmp(&b.isin)));
+    Ok(result)
+}
+
+// units of a fund held at the end of `year` and the share of them that counts for its Vorabpauschale
+fn units_in(ts: &[&Transaction], year: i32) -> (f64, f64) {
+    let units_before = ts
+        .iter()
+        .filter(|t| year_of(t.date) < year)
+        .map(|t| t.units)
+        .sum::<f64>()
+        .max(0.0);
+    let units = ts
+        .iter()
+        .filter(|t| year_of(t.date) <= year)
+        .map(|t| t.units)
+        .sum::<f64>();
+
+    // remaining units that were bought during the year are the latest purchases (FIFO)
+    let mut weighted_units = units.min(units_before);
+    let mut bought = units - weighted_units;
+    for t in ts
+        .iter()
+        .rev()
+        .filter(|t| year_of(t.date) == year && t.units > 0.0)
+    {
+        if bought < EPSILON {
+            break;
+        }
+        let u = bought.min(t.units);
+        weighted_units += u * purchase_weight(t.date, year);
+        bought -= u;
+    }
+
+    (units, weighted_units)
+}
+
+// Basisertrag and Vorabpauschale (before partial exemption) of a fund in a year
+fn vorabpauschale(
+    start_price: f64,
+    end_price: f64,
+    base_rate: f64,
+    units: f64,
+    weighted_units: f64,
+    distributions: f64,
+) -> (f64, f64) {
+    let base_return = start_price * base_rate.max(0.0) / 100.0 * BASE_RETURN_SHARE * weighted_units;
+    let increase = ((end_price - start_price) * units + distributions).max(0.0);
+
+    (
+        base_return,
+        (base_return.min(increase) - distributions).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(year: i32, month: u32, units: f64) -> Transaction {
+        Transaction {
+            id: 1,
+            account_id: 1,
+            isin: "A".to_string(),
+            date: Local
+                .ymd(year, month, 15)
+                .and_hms(12, 0, 0)
+                .with_timezone(&Utc),
+            units,
+            amount: 0,
+            fees: 0,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: None,
+        }
+    }
+
+    #[test]
+    fn vorabpauschale_is_the_base_return() {
+        // 100 * 1% * 0.7 * 10
+        let (base_return, amount) = vorabpauschale(100.0, 110.0, 1.0, 10.0, 10.0, 0.0);
+
+        assert!((base_return - 7.0).abs() < 1e-9);
+        assert!((amount - 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn vorabpauschale_is_limited_by_the_increase() {
+        let (_, amount) = vorabpauschale(100.0, 100.5, 1.0, 10.0, 10.0, 0.0);
+        assert!((amount - 5.0).abs() < 1e-9);
+
+        let (_, amount) = vorabpauschale(100.0, 90.0, 1.0, 10.0, 10.0, 0.0);
+        assert!(amount.abs() < 1e-9);
+
+        let (_, amount) = vorabpauschale(100.0, 110.0, -0.5, 10.0, 10.0, 0.0);
+        assert!(amount.abs() < 1e-9);
+    }
+
+    #[test]
+    fn distributions_reduce_the_vorabpauschale() {
+        let (_, amount) = vorabpauschale(100.0, 110.0, 1.0, 10.0, 10.0, 3.0);
+        assert!((amount - 4.0).abs() < 1e-9);
+
+        let (_, amount) = vorabpauschale(100.0, 110.0, 1.0, 10.0, 10.0, 8.0);
+        assert!(amount.abs() < 1e-9);
+    }
+
+    #[test]
+    fn taxed_distributions_count_gross() {
+        let account = Account {
+            id: 1,
+            user_id: 1,
+            name: String::new(),
+            iban: None,
+            depot_number: None,
+            clearing_account_number: None,
+            broker: None,
+            currency: "EUR".to_string(),
+        };
+        let mut distribution = transaction(2020, 9, 0.0);
+        distribution.id = 2;
+        distribution.amount = 4077;
+        let ts = vec![
+            (transaction(2020, 1, 10.0), account.clone()),
+            (distribution, account),
+        ];
+        let withheld = vec![(2, -923)].into_iter().collect();
+
+        let ts = gross(ts, &withheld);
+        assert_eq!(ts[0].amount, 0);
+        assert_eq!(ts[1].amount, 5000);
+
+        // the Vorabpauschale of the year is reduced by the gross distribution
+        let (_, amount) =
+            vorabpauschale(100.0, 110.0, 1.0, 10.0, 10.0, ts[1].amount as f64 / 100.0);
+        assert!(amount.abs() < 1e-9);
+    }
+
+    #[test]
+    fn purchases_count_from_their_month() {
+        let ts = vec![
+            transaction(2019, 1, 10.0),
+            transaction(2020, 7, 12.0), // counts for 6 of 12 months
+            transaction(2020, 9, -5.0),
+        ];
+        let ts = ts.iter().collect::<Vec<_>>();
+
+        let (units, weighted_units) = units_in(&ts, 2019);
+        assert!((units - 10.0).abs() < 1e-9);
+        assert!((weighted_units - 10.0).abs() < 1e-9);
+
+        // the sale is taken from the older units
+        let (units, weighted_units) = units_in(&ts, 2020);
+        assert!((units - 17.0).abs() < 1e-9);
+        assert!((weighted_units - 13.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::analysis::corporate_actions::SPIN_OFF;
+use crate::analysis::lots::{self, Lots, MovedLot};
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// transfers of the user until `date` as they were recorded
+pub fn load_recorded(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    Ok(transfers::table
+        .inner_join(accounts::table.on(accounts::id.eq(transfers::from_account_id)))
+        .filter(accounts::user_id.eq(user_id))
+        .filter(transfers::date.le(date))
+        .order(transfers::date.asc())
+        .load::<(Transfer, Account)>(connection)?
+        .into_iter()
+        .map(|(tr, _)| tr)
+        .collect::<Vec<_>>())
+}
+
+// transfers of the user until `date`, with units restated after corporate actions
+// (like corporate_actions::apply does for transactions)
+pub fn load(
+    connection: &PgConnection,
+    user_id: i32,
+    date: DateTime<Utc>,
+) -> Result<Vec<Transfer>, Box<dyn Error>> {
+    let mut trs = load_recorded(connection, user_id, date)?;
+
+    let isins = trs.iter().map(|tr| tr.isin.clone()).collect::<Vec<_>>();
+    let actions = corporate_actions::table
+        .filter(corporate_actions::isin.eq_any(&isins))
+        .filter(corporate_actions::date.le(date))
+        .filter(corporate_actions::kind.ne(SPIN_OFF))
+        .load::<CorporateAction>(connection)?;
+
+    for a in actions.iter() {
+        for tr in trs
+            .iter_mut()
+            .filter(|tr| tr.isin == a.isin && tr.date < a.date)
+        {
+            tr.units *= a.ratio;
+        }
+    }
+
+    Ok(trs)
+}
+
+// synthetic transactions for each transfer: the source account sells
+// the moved lots at their cost basis and the target account buys them at the same price,
+// so the portfolio as a whole is unaffected while each account keeps its invested money.
+pub fn legs(moved: &[MovedLot]) -> Vec<Transaction> {
+    let mut per_transfer: BTreeMap<i32, (&MovedLot, f64, f64)> = BTreeMap::new();
+    for m in moved.iter() {
+        let e = per_transfer.entry(m.transfer_id).or_insert((m, 0.0, 0.0));
+        e.1 += m.units;
+        e.2 += m.cost;
+    }
+
+    let mut ts = Vec::new();
+    for (id, (m, units, cost)) in per_transfer {
+        let cost = (cost * 100.0).round() as i64;
+        let out = Transaction {
+            id: -id,
+            account_id: m.from_account_id,
+            isin: m.isin.clone(),
+            date: m.date,
+            units: -units,
+            amount: cost,
+            fees: 0,
+            onvista_exchange_id: None,
+            comments: format!("Übertrag in Depot {}", m.to_account_id),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: Some(Synthetic::Transfer),
+        };
+
+        ts.push(Transaction {
+            account_id: m.to_account_id,
+            units,
+            amount: -cost,
+            comments: format!("Übertrag aus Depot {}", m.from_account_id),
+            ..out.clone()
+        });
+        ts.push(out);
+    }
+
+    ts
+}
+
+// compute the lots of `ts` including the user's transfers, add the transfer legs to `ts`
+// and restrict both to a single account if one is given
+pub fn apply(
+    connection: &PgConnection,
+    user_id: i32,
+    ts: Vec<Transaction>,
+    date: DateTime<Utc>,
+    account_id: Option<i32>,
+) -> Result<(Vec<Transaction>, Lots), Box<dyn Error>> {
+    let trs = load(connection, user_id, date)?;
+    let mut lots = lots::compute(&ts, &trs);
+
+    let mut ts = ts;
+    ts.extend(legs(&lots.moved));
+    ts.sort_by_key(|t| t.date);
+
+    if let Some(id) = account_id {
+        ts.retain(|t| t.account_id == id);
+        lots.open.retain(|l| l.account_id == id);
+        lots.sales.retain(|s| s.account_id == id);
+        lots.moved
+            .retain(|m| m.from_account_id == id || m.to_account_id == id);
+    }
+
+    Ok((ts, lots))
+}
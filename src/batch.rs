@@ -0,0 +1,276 @@
+use crate::history;
+use crate::models::*;
+use crate::schema::{accounts, transactions};
+use crate::validation::{FieldError, ValidationErrors, Validator};
+
+use diesel::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+// a single change of a batch, e.g. {"op": "update", "id": 3, "transaction": {...}}
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+pub enum Operation {
+    Create { transaction: NewTransaction },
+    Update { id: i32, transaction: Transaction }, // the id of `transaction` is ignored
+    Delete { id: i32 },
+}
+
+// outcome of an operation, in the order of the batch
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationResult {
+    pub op: String,
+    pub id: i32,
+    pub transaction: Option<Transaction>, // not set for deletions
+}
+
+impl Operation {
+    fn name(&self) -> &'static str {
+        match self {
+            Operation::Create { .. } => "create",
+            Operation::Update { .. } => "update",
+            Operation::Delete { .. } => "delete",
+        }
+    }
+}
+
+// check all operations up front, errors are reported with the index of their operation (e.g. "[2].amount")
+fn validate(
+    connection: &PgConnection,
+    user_id: i32,
+    operations: &[Operation],
+) -> Result<Vec<Option<Transaction>>, Box<dyn Error>> {
+    let account_ids = accounts::table
+        .filter(accounts::user_id.eq(user_id))
+        .select(accounts::id)
+        .load::<i32>(connection)?;
+
+    let ids = operations
+        .iter()
+        .filter_map(|op| match op {
+            Operation::Update { id, .. } | Operation::Delete { id } => Some(*id),
+            Operation::Create { .. } => None,
+        })
+        .collect::<Vec<_>>();
+    let existing = transactions::table
+        .filter(transactions::account_id.eq_any(&account_ids))
+        .filter(transactions::id.eq_any(&ids))
+        .load::<Transaction>(connection)?;
+
+    let validator = Validator::new(connection, account_ids)?;
+    let mut errors = Vec::new();
+    let mut before = Vec::new();
+
+    for (i, op) in operations.iter().enumerate() {
+        let mut error = |field: &str, message: &str| {
+            errors.push(FieldError {
+                field: format!("[{}].{}", i, field),
+                message: message.to_string(),
+            })
+        };
+
+        let t = match op {
+            Operation::Update { id, .. } | Operation::Delete { id } => {
+                let t = existing.iter().find(|t| t.id == *id).cloned();
+                if t.is_none() {
+                    error("id", "unknown transaction");
+                } else if ids.iter().filter(|other| *other == id).count() > 1 {
+                    error("id", "changed by more than one operation");
+                }
+                t
+            }
+            Operation::Create { .. } => None,
+        };
+
+        let new = match op {
+            Operation::Create { transaction } => Some(transaction.clone()),
+            Operation::Update { transaction, .. } => Some(NewTransaction::from(transaction)),
+            Operation::Delete { .. } => None,
+        };
+        if let Some(Err(e)) = new.map(|n| validator.check(&n)) {
+            for fe in e.errors.iter() {
+                error(&fe.field, &fe.message);
+            }
+        }
+
+        before.push(t);
+    }
+
+    if errors.is_empty() {
+        Ok(before)
+    } else {
+        Err(Box::new(ValidationErrors { errors }))
+    }
+}
+
+// validate all operations and apply them in a single database transaction: either all of them
+// succeed or none. Fails with ValidationErrors if one of the operations is invalid.
+pub fn apply(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    operations: &[Operation],
+) -> Result<Vec<OperationResult>, Box<dyn Error>> {
+    connection.transaction::<_, Box<dyn Error>, _>(|| {
+        let before = validate(connection, user_id, operations)?;
+
+        let mut results = Vec::new();
+        for (op, before) in operations.iter().zip(before.iter()) {
+            let result = match (op, before) {
+                (Operation::Create { transaction }, _) => {
+                    let t = diesel::insert_into(transactions::table)
+                        .values(transaction)
+                        .get_result::<Transaction>(connection)?;
+                    history::inserted(connection, user_id, source, std::slice::from_ref(&t))?;
+                    OperationResult {
+                        op: op.name().to_string(),
+                        id: t.id,
+                        transaction: Some(t),
+                    }
+                }
+                (Operation::Update { id, transaction }, Some(before)) => {
+                    let t = diesel::update(transactions::table.find(*id))
+                        .set(Transaction {
+                            id: *id,
+                            ..transaction.clone()
+                        })
+                        .get_result::<Transaction>(connection)?;
+                    history::updated(connection, user_id, source, before, &t)?;
+                    OperationResult {
+                        op: op.name().to_string(),
+                        id: *id,
+                        transaction: Some(t),
+                    }
+                }
+                (Operation::Delete { id }, Some(before)) => {
+                    history::deleted(connection, user_id, source, before)?;
+                    diesel::delete(transactions::table.find(*id)).execute(connection)?;
+                    OperationResult {
+                        op: op.name().to_string(),
+                        id: *id,
+                        transaction: None,
+                    }
+                }
+                (_, None) => return Err("transaction vanished during the batch".into()),
+            };
+            results.push(result);
+        }
+
+        info!(
+            "Applied a batch of {} operation(s) on transactions",
+            results.len()
+        );
+        Ok(results)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::users;
+    use chrono::{Duration, Utc};
+
+    fn purchase(account_id: i32, amount: i64) -> NewTransaction {
+        NewTransaction {
+            account_id,
+            isin: "IE00B4L5Y983".to_string(),
+            date: Utc::now() - Duration::days(1),
+            units: 10.0,
+            amount,
+            fees: -100,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+        }
+    }
+
+    // needs a database: DATABASE_URL=postgres://... cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn invalid_operations_apply_nothing() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let connection = crate::connect(&url).unwrap();
+
+        connection.test_transaction::<_, Box<dyn Error>, _>(|| {
+            let u = diesel::insert_into(users::table)
+                .values(&NewUser {
+                    name: "batch-test".to_string(),
+                    full_name: String::new(),
+                    hash: String::new(),
+                    reporting_currency: "EUR".to_string(),
+                })
+                .get_result::<User>(&connection)?;
+            let a = diesel::insert_into(accounts::table)
+                .values(&NewAccount {
+                    user_id: u.id,
+                    name: "depot".to_string(),
+                    iban: None,
+                    depot_number: None,
+                    clearing_account_number: None,
+                    broker: None,
+                    currency: "EUR".to_string(),
+                })
+                .get_result::<Account>(&connection)?;
+            let t = diesel::insert_into(transactions::table)
+                .values(&purchase(a.id, -70000))
+                .get_result::<Transaction>(&connection)?;
+            let count = || {
+                transactions::table
+                    .filter(transactions::account_id.eq(a.id))
+                    .count()
+                    .get_result::<i64>(&connection)
+            };
+
+            let ops = vec![
+                Operation::Create {
+                    transaction: purchase(a.id, -50000),
+                },
+                Operation::Create {
+                    transaction: purchase(a.id, 50000),
+                },
+                Operation::Delete { id: t.id },
+                Operation::Update {
+                    id: t.id,
+                    transaction: t.clone(),
+                },
+                Operation::Delete { id: -1 },
+            ];
+            let e = apply(&connection, u.id, history::CLI, &ops).unwrap_err();
+            let fields = e
+                .downcast_ref::<ValidationErrors>()
+                .expect("not a validation error")
+                .errors
+                .iter()
+                .map(|e| e.field.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(fields, vec!["[1].amount", "[2].id", "[3].id", "[4].id"]);
+            assert_eq!(count()?, 1);
+
+            // without the invalid ones, everything is applied
+            let results = apply(
+                &connection,
+                u.id,
+                history::CLI,
+                &[ops[0].clone(), ops[2].clone()],
+            )?;
+            assert_eq!(
+                results.iter().map(|r| r.op.as_str()).collect::<Vec<_>>(),
+                vec!["create", "delete"]
+            );
+            assert_eq!(count()?, 1);
+            assert!(transactions::table
+                .find(t.id)
+                .first::<Transaction>(&connection)
+                .optional()?
+                .is_none());
+
+            Ok(())
+        });
+    }
+}
@@ -44,6 +44,8 @@ fn main() {
             cli::stock::handle(pool, sub_matches).await;
         } else if let Some(sub_matches) = matches.subcommand_matches("transaction") {
             cli::transaction::handle(&connection, sub_matches);
+        } else if let Some(sub_matches) = matches.subcommand_matches("savings-plan") {
+            cli::savings_plan::handle(&connection, sub_matches);
         } else if let Some(sub_matches) = matches.subcommand_matches("account") {
             cli::account::handle(&connection, sub_matches);
         } else if let Some(sub_matches) = matches.subcommand_matches("data") {
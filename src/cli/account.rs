@@ -1,11 +1,15 @@
+use crate::analysis::cash;
+use crate::history;
 use crate::models::*;
 use crate::schema::accounts::dsl::*;
 
+use chrono::{Local, NaiveDate, TimeZone, Utc};
 use clap::ArgMatches;
 use clap::{App, Arg, ArgGroup, SubCommand};
 use diesel::prelude::*;
 use log::info;
 use prettytable::{cell, row, Table};
+use std::error::Error;
 use std::io;
 
 pub fn build() -> App<'static, 'static> {
@@ -25,9 +29,35 @@ pub fn build() -> App<'static, 'static> {
                 .help("update account details"),
         )
         .arg(Arg::with_name("list").long("list").help("list accounts"))
+        .arg(
+            Arg::with_name("cash")
+                .long("cash")
+                .value_name("id")
+                .help("show the cash balance of an account"),
+        )
+        .arg(
+            Arg::with_name("add-cash")
+                .long("add-cash")
+                .value_name("id")
+                .help("add a cash transaction (deposit, withdrawal, ...) to an account"),
+        )
+        .arg(
+            Arg::with_name("remove-cash")
+                .long("remove-cash")
+                .value_name("id")
+                .help("remove cash transaction"),
+        )
         .group(
             ArgGroup::with_name("action")
-                .args(&["add", "update", "remove", "list"])
+                .args(&[
+                    "add",
+                    "update",
+                    "remove",
+                    "list",
+                    "cash",
+                    "add-cash",
+                    "remove-cash",
+                ])
                 .required(true),
         )
 }
@@ -64,6 +94,15 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             "IBAN can only be empty or exactly 22 characters long!"
         );
 
+        let s_depot = read_optional("Please enter the depot number of the new account");
+        let s_clearing =
+            read_optional("Please enter the clearing account number of the new account");
+        let s_broker = read_optional("Please enter the broker of the new account (e.g. onvista)");
+        let s_currency =
+            read_optional("Please enter the currency of the new account (default: EUR)")
+                .map(|c| c.to_uppercase())
+                .unwrap_or_else(|| String::from("EUR"));
+
         let a = NewAccount {
             name: s_name.to_string(),
             iban: if s_iban.is_empty() {
@@ -72,25 +111,31 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
                 Some(s_iban)
             },
             user_id: s_uid,
+            depot_number: s_depot,
+            clearing_account_number: s_clearing,
+            broker: s_broker,
+            currency: s_currency,
         };
 
-        let a: Account = diesel::insert_into(crate::schema::accounts::table)
-            .values(&a)
-            .get_result(connection)
+        let a = connection
+            .transaction::<_, Box<dyn Error>, _>(|| {
+                let a: Account = diesel::insert_into(crate::schema::accounts::table)
+                    .values(&a)
+                    .get_result(connection)?;
+                history::inserted(connection, s_uid, history::CLI, std::slice::from_ref(&a))?;
+                Ok(a)
+            })
             .expect("Error saving new account");
 
         info!("Created account {:?}", a);
     } else if let Some(s_aid) = sub_matches.value_of("update") {
         let s_aid: i32 = s_aid.parse().expect("Could not parse account id!");
-        assert!(
-            crate::schema::accounts::table
-                .find(s_aid)
-                .execute(connection)
-                .expect("Error loading accounts")
-                > 0,
-            "there is no account with id '{}'!",
-            s_aid
-        );
+        let before = crate::schema::accounts::table
+            .find(s_aid)
+            .first::<Account>(connection)
+            .optional()
+            .expect("Error loading accounts")
+            .unwrap_or_else(|| panic!("there is no account with id '{}'!", s_aid));
 
         let mut s_name = String::new();
         println!("Please enter a new name for the account");
@@ -107,16 +152,29 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             "IBAN can only be empty or exactly 22 characters long!"
         );
 
-        let a = diesel::update(accounts.find(s_aid))
-            .set((
-                name.eq(s_name),
-                iban.eq(if s_iban.is_empty() {
-                    None
-                } else {
-                    Some(s_iban)
-                }),
-            ))
-            .get_result::<Account>(connection)
+        let s_depot = read_optional("Please enter a new depot number for the account");
+        let s_clearing =
+            read_optional("Please enter a new clearing account number for the account");
+        let s_broker = read_optional("Please enter a new broker for the account");
+
+        let a = connection
+            .transaction::<_, Box<dyn Error>, _>(|| {
+                let a = diesel::update(accounts.find(s_aid))
+                    .set((
+                        name.eq(s_name),
+                        iban.eq(if s_iban.is_empty() {
+                            None
+                        } else {
+                            Some(s_iban)
+                        }),
+                        depot_number.eq(s_depot),
+                        clearing_account_number.eq(s_clearing),
+                        broker.eq(s_broker),
+                    ))
+                    .get_result::<Account>(connection)?;
+                history::updated(connection, a.user_id, history::CLI, &before, &a)?;
+                Ok(a)
+            })
             .unwrap_or_else(|_| panic!("Unable to update account {}", s_aid));
 
         info!("Updated account {:?}", a);
@@ -145,8 +203,12 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
         //   .expect("Unable to delete associated transactions");
 
         // delete account
-        diesel::delete(accounts.find(&s_aid))
-            .execute(connection)
+        connection
+            .transaction::<_, Box<dyn Error>, _>(|| {
+                history::deleted(connection, a.user_id, history::CLI, &a)?;
+                diesel::delete(accounts.find(&s_aid)).execute(connection)?;
+                Ok(())
+            })
             .unwrap_or_else(|_| panic!("Unable to delete account {}", &s_aid));
 
         info!("deleted account '{}'", s_aid);
@@ -156,7 +218,16 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             .expect("Error loading accounts");
 
         let mut table = Table::new();
-        table.add_row(row!["ID", "User ID", "IBAN", "Name", "# Transactions"]);
+        table.add_row(row![
+            "ID",
+            "User ID",
+            "IBAN",
+            "Depot",
+            "Clearing Account",
+            "Broker",
+            "Name",
+            "# Transactions"
+        ]);
 
         for a in accs.iter() {
             let transaction_count: i64 = Transaction::belonging_to(a)
@@ -168,13 +239,132 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
                 a.id,
                 a.user_id,
                 a.iban.as_ref().unwrap_or(&String::new()),
+                a.depot_number.as_ref().unwrap_or(&String::new()),
+                a.clearing_account_number.as_ref().unwrap_or(&String::new()),
+                a.broker.as_ref().unwrap_or(&String::new()),
                 a.name,
                 transaction_count
             ]);
         }
 
         table.printstd();
+    } else if let Some(s_aid) = sub_matches.value_of("cash") {
+        let s_aid: i32 = s_aid.parse().expect("Could not parse account id!");
+        let a = accounts
+            .find(s_aid)
+            .first::<Account>(connection)
+            .unwrap_or_else(|_| panic!("there is no account with id '{}'!", s_aid));
+
+        let cts = CashTransaction::belonging_to(&a)
+            .load::<CashTransaction>(connection)
+            .expect("Error loading cash transactions");
+        let ts = Transaction::belonging_to(&a)
+            .load::<Transaction>(connection)
+            .expect("Error loading transactions");
+
+        let mut table = Table::new();
+        table.add_row(row!["Date", "Kind", "ID", "Amount", "Balance", "Comments"]);
+
+        for e in cash::running_balance(&cts, &ts) {
+            table.add_row(row![
+                e.date.with_timezone(&Local).format("%Y-%m-%d"),
+                e.kind,
+                e.cash_transaction_id
+                    .or(e.transaction_id)
+                    .unwrap_or_default(),
+                format!("{:.2}", e.amount),
+                format!("{:.2}", e.balance),
+                e.comments
+            ]);
+        }
+
+        table.printstd();
+    } else if let Some(s_aid) = sub_matches.value_of("add-cash") {
+        let s_aid: i32 = s_aid.parse().expect("Could not parse account id!");
+        assert!(
+            accounts
+                .find(s_aid)
+                .execute(connection)
+                .expect("Error loading accounts")
+                > 0,
+            "there is no account with id '{}'!",
+            s_aid
+        );
+
+        let mut s_kind = String::new();
+        println!(
+            "Please enter the kind of the cash transaction ({})",
+            cash::KINDS.join(", ")
+        );
+        io::stdin().read_line(&mut s_kind).unwrap();
+        let s_kind = s_kind.trim_end();
+        assert!(
+            cash::KINDS.contains(&s_kind),
+            "Unknown kind of cash transaction"
+        );
+
+        let mut s_date = String::new();
+        println!("Please enter the date (YYYY-MM-DD)");
+        io::stdin().read_line(&mut s_date).unwrap();
+        let s_date = NaiveDate::parse_from_str(s_date.trim_end(), "%Y-%m-%d")
+            .expect("Could not parse date!");
+
+        let mut s_amount = String::new();
+        println!("Please enter the amount in cents (positive if money was credited)");
+        io::stdin().read_line(&mut s_amount).unwrap();
+        let s_amount: i64 = s_amount
+            .trim_end()
+            .parse()
+            .expect("Could not parse amount!");
+
+        let mut s_comments = String::new();
+        println!("Please enter comments");
+        io::stdin().read_line(&mut s_comments).unwrap();
+
+        let ct: CashTransaction = diesel::insert_into(crate::schema::cash_transactions::table)
+            .values(&NewCashTransaction {
+                account_id: s_aid,
+                date: Local
+                    .from_local_date(&s_date)
+                    .unwrap()
+                    .and_hms(0, 0, 0)
+                    .with_timezone(&Utc),
+                kind: s_kind.to_string(),
+                amount: s_amount,
+                comments: s_comments.trim_end().to_string(),
+            })
+            .get_result(connection)
+            .expect("Error saving new cash transaction");
+
+        info!("Created cash transaction {:?}", ct);
+    } else if let Some(s_ctid) = sub_matches.value_of("remove-cash") {
+        let s_ctid: i32 = s_ctid.parse().expect("Could not parse id!");
+
+        let row_count = diesel::delete(crate::schema::cash_transactions::table.find(s_ctid))
+            .execute(connection)
+            .unwrap_or_else(|_| panic!("Unable to delete cash transaction {}", s_ctid));
+        assert!(
+            row_count > 0,
+            "there is no cash transaction with id '{}'!",
+            s_ctid
+        );
+
+        info!("deleted cash transaction '{}'", s_ctid);
     } else {
         panic!("unexpected options for subcommand 'account'");
     }
 }
+
+// read a line from stdin, empty input means None
+fn read_optional(prompt: &str) -> Option<String> {
+    let mut s = String::new();
+    println!("{}", prompt);
+    io::stdin().read_line(&mut s).unwrap();
+    let s = s.trim();
+
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
@@ -36,9 +36,20 @@ pub fn build() -> App<'static, 'static> {
                 .long("import")
                 .help("read realtime and historic data from stdin"),
         )
+        .arg(
+            Arg::with_name("exchange-rates")
+                .long("exchange-rates")
+                .value_name("filename")
+                .help("import reference exchange rates (in the format of the ECB's eurofxref-hist.csv)"),
+        )
+        .arg(
+            Arg::with_name("base-rate")
+                .long("base-rate")
+                .help("set the Basiszins of a year (used for the Vorabpauschale of funds)"),
+        )
         .group(
             ArgGroup::with_name("action")
-                .args(&["clean", "fetch", "export", "import"])
+                .args(&["clean", "fetch", "export", "import", "exchange-rates", "base-rate"])
                 .required(true),
         )
 }
@@ -92,6 +103,40 @@ pub async fn handle(pool: Pool<ConnectionManager<PgConnection>>, sub_matches: &A
             serde_json::from_reader(io::stdin()).expect("Could not parse data input");
 
         p.write_to(&connection);
+    } else if let Some(fname) = sub_matches.value_of("exchange-rates") {
+        let data = std::fs::read(fname).expect("Could not read exchange rates");
+
+        crate::analysis::currency::import_rates(&connection, &data)
+            .expect("Could not import exchange rates");
+    } else if sub_matches.is_present("base-rate") {
+        use crate::schema::base_rates;
+
+        let mut s_year = String::new();
+        println!("Please enter the year");
+        io::stdin().read_line(&mut s_year).unwrap();
+        let s_year = s_year.trim().parse::<i32>().expect("Could not parse year");
+
+        let mut s_rate = String::new();
+        println!(
+            "Please enter the Basiszins of {} in percent (e.g. 0.07)",
+            s_year
+        );
+        io::stdin().read_line(&mut s_rate).unwrap();
+        let s_rate = s_rate.trim().parse::<f64>().expect("Could not parse rate");
+
+        let r = BaseRate {
+            year: s_year,
+            rate: s_rate,
+        };
+        diesel::insert_into(base_rates::table)
+            .values(&r)
+            .on_conflict(base_rates::year)
+            .do_update()
+            .set(base_rates::rate.eq(s_rate))
+            .execute(&connection)
+            .expect("Unable to save Basiszins");
+
+        info!("Set Basiszins of {} to {}%", s_year, s_rate);
     } else if sub_matches.is_present("clean") {
         use crate::schema::realtime_prices::dsl::*;
         let now = Utc::now();
@@ -1,11 +1,13 @@
+use crate::csv_import;
 use crate::schema::*;
 use crate::serialization::*;
 
 use clap::ArgMatches;
 use clap::{App, Arg, SubCommand};
 use diesel::prelude::*;
-use log::warn;
+use log::{info, warn};
 use std::io;
+use std::io::Read;
 
 pub fn build() -> App<'static, 'static> {
     SubCommand::with_name("import")
@@ -15,6 +17,28 @@ pub fn build() -> App<'static, 'static> {
                 .long("moneydb")
                 .help("import from moneydb json data"),
         )
+        .arg(
+            Arg::with_name("csv")
+                .long("csv")
+                .value_name("profile")
+                .requires("user")
+                .conflicts_with_all(&["moneydb", "clean"])
+                .help("import transactions from csv data using the given mapping profile"),
+        )
+        .arg(
+            Arg::with_name("save-profile")
+                .long("save-profile")
+                .value_name("profile")
+                .requires("user")
+                .conflicts_with_all(&["moneydb", "clean", "csv"])
+                .help("store the mapping for --csv read from stdin (json) under the given name"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .value_name("id")
+                .help("user id for --csv and --save-profile"),
+        )
         .arg(
             Arg::with_name("clean")
                 .long("clean")
@@ -35,7 +59,34 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
         warn!("removed all users, accounts and transactions.");
     }
 
-    if sub_matches.is_present("moneydb") {
+    if let Some(profile) = sub_matches.value_of("csv") {
+        let uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+
+        let mut data = Vec::new();
+        io::stdin()
+            .read_to_end(&mut data)
+            .expect("Could not read csv input");
+
+        let ts = csv_import::import(connection, uid, profile, &data)
+            .unwrap_or_else(|e| panic!("Error importing csv data: {}", e));
+        info!("imported {} transaction(s)", ts.len());
+    } else if let Some(profile) = sub_matches.value_of("save-profile") {
+        let uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+
+        let mapping: csv_import::CsvMapping =
+            serde_json::from_reader(io::stdin()).expect("Could not parse csv mapping");
+        let p = csv_import::save_profile(connection, uid, profile, &mapping)
+            .expect("Error saving csv profile");
+        info!("saved csv profile {:?}", p);
+    } else if sub_matches.is_present("moneydb") {
         let p: MoneyDBFormat =
             serde_json::from_reader(io::stdin()).expect("Could not parse moneydb input");
         // debug!("{:?}", p);
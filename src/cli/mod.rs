@@ -3,6 +3,7 @@ pub mod data;
 pub mod export;
 pub mod import;
 pub mod push;
+pub mod savings_plan;
 pub mod serve;
 pub mod stock;
 pub mod transaction;
@@ -37,6 +38,7 @@ pub fn build(multiple: bool) -> App<'static, 'static> {
         .subcommand(user::build())
         .subcommand(account::build())
         .subcommand(transaction::build())
+        .subcommand(savings_plan::build())
         .subcommand(push::build())
         .subcommand(stock::build())
         .subcommand(data::build())
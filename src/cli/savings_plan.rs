@@ -0,0 +1,260 @@
+use crate::history;
+use crate::models::*;
+use crate::savings_plans;
+use crate::schema::{accounts, savings_plans as plans, transactions};
+
+use chrono::{Local, NaiveDate};
+use clap::ArgMatches;
+use clap::{App, Arg, ArgGroup, SubCommand};
+use diesel::prelude::*;
+use log::info;
+use prettytable::{cell, row, Table};
+use std::io;
+
+pub fn build() -> App<'static, 'static> {
+    SubCommand::with_name("savings-plan")
+        .about("Savings Plan Management")
+        .arg(Arg::with_name("add").long("add").help("add savings plan"))
+        .arg(
+            Arg::with_name("remove")
+                .long("remove")
+                .value_name("id")
+                .help("remove savings plan"),
+        )
+        .arg(
+            Arg::with_name("end")
+                .long("end")
+                .value_name("id")
+                .help("set the end date of a savings plan"),
+        )
+        .arg(
+            Arg::with_name("list")
+                .long("list")
+                .requires("user")
+                .help("list savings plans"),
+        )
+        .arg(
+            Arg::with_name("check").long("check").requires("user").help(
+                "list the executions of all savings plans, including missed and changed ones",
+            ),
+        )
+        .arg(
+            Arg::with_name("link")
+                .long("link")
+                .requires("user")
+                .help("link existing transactions to their savings plans"),
+        )
+        .arg(
+            Arg::with_name("projection")
+                .long("projection")
+                .value_name("months")
+                .requires("user")
+                .help("show the planned contributions of the next months"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .value_name("id")
+                .help("user id for --list, --check, --link and --projection"),
+        )
+        .group(
+            ArgGroup::with_name("action")
+                .args(&[
+                    "add",
+                    "remove",
+                    "end",
+                    "list",
+                    "check",
+                    "link",
+                    "projection",
+                ])
+                .required(true),
+        )
+}
+
+pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
+    let s_uid = sub_matches
+        .value_of("user")
+        .map(|s| s.parse::<i32>().expect("Could not parse user id!"));
+
+    if sub_matches.is_present("add") {
+        let s_aid: i32 = read("Please enter the account id for the savings plan")
+            .parse()
+            .expect("Could not parse account id!");
+        assert!(
+            accounts::table
+                .find(s_aid)
+                .execute(connection)
+                .expect("Error loading accounts")
+                >= 1,
+            "there is no account with id '{}'!",
+            s_aid
+        );
+
+        let s_isin = read("Please enter the ISIN of the savings plan").to_uppercase();
+        assert!(s_isin.len() == 12, "ISINs always have a length of 12!");
+
+        let s_amount = (read("Please enter the amount per execution (including fees)")
+            .parse::<f64>()
+            .expect("Could not parse amount")
+            * 100.0)
+            .round() as i64;
+        assert!(s_amount > 0, "The amount has to be positive");
+
+        let s_interval: i32 =
+            read("Please enter the interval in months (1 = monthly, 3 = quarterly)")
+                .parse()
+                .expect("Could not parse interval");
+        let s_day: i32 = read("Please enter the day of the month the plan is executed on")
+            .parse()
+            .expect("Could not parse day");
+        let s_start = read_date("Please enter the first day of the plan (YYYY-MM-DD)")
+            .expect("A start date is required");
+        let s_end = read_date("Please enter the last day of the plan (YYYY-MM-DD, or leave blank)");
+        let s_comments = read("Any comments on this savings plan?");
+
+        let p: SavingsPlan = diesel::insert_into(plans::table)
+            .values(&NewSavingsPlan {
+                account_id: s_aid,
+                isin: s_isin,
+                amount: s_amount,
+                interval_months: s_interval,
+                execution_day: s_day,
+                start_date: s_start,
+                end_date: s_end,
+                comments: s_comments,
+            })
+            .get_result(connection)
+            .expect("Error saving new savings plan");
+
+        info!("Created savings plan {:?}", p);
+    } else if let Some(s_pid) = sub_matches.value_of("remove") {
+        let s_pid: i32 = s_pid.parse().expect("Could not parse savings plan id!");
+
+        let row_count = diesel::delete(plans::table.find(s_pid))
+            .execute(connection)
+            .unwrap_or_else(|_| panic!("Unable to delete savings plan {}", s_pid));
+        assert!(
+            row_count > 0,
+            "there is no savings plan with id '{}'!",
+            s_pid
+        );
+
+        info!("deleted savings plan '{}'", s_pid);
+    } else if let Some(s_pid) = sub_matches.value_of("end") {
+        let s_pid: i32 = s_pid.parse().expect("Could not parse savings plan id!");
+        let s_end = read_date("Please enter the last day of the plan (YYYY-MM-DD)");
+
+        let p = diesel::update(plans::table.find(s_pid))
+            .set(plans::end_date.eq(s_end))
+            .get_result::<SavingsPlan>(connection)
+            .unwrap_or_else(|_| panic!("there is no savings plan with id '{}'!", s_pid));
+
+        info!("Updated savings plan {:?}", p);
+    } else if sub_matches.is_present("list") {
+        let ps =
+            savings_plans::load(connection, s_uid.unwrap()).expect("Error loading savings plans");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "ID", "Acc ID", "ISIN", "Amount", "Interval", "Day", "Start", "End", "Comments"
+        ]);
+        for p in ps.iter() {
+            table.add_row(row![
+                p.id,
+                p.account_id,
+                p.isin,
+                format!("{:.2}", p.amount as f64 / 100.0),
+                p.interval_months,
+                p.execution_day,
+                p.start_date,
+                p.end_date.map(|d| d.to_string()).unwrap_or_default(),
+                p.comments
+            ]);
+        }
+
+        table.printstd();
+    } else if sub_matches.is_present("check") {
+        let statuses =
+            savings_plans::check(connection, s_uid.unwrap()).expect("Error checking savings plans");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "Plan",
+            "ISIN",
+            "Due",
+            "Status",
+            "Transaction",
+            "Amount"
+        ]);
+        for s in statuses.iter() {
+            for e in s.executions.iter() {
+                table.add_row(row![
+                    s.plan.id,
+                    s.plan.isin,
+                    e.due,
+                    e.status,
+                    e.transaction_id.map(|i| i.to_string()).unwrap_or_default(),
+                    e.amount.map(|a| format!("{:.2}", a)).unwrap_or_default()
+                ]);
+            }
+        }
+
+        table.printstd();
+    } else if sub_matches.is_present("link") {
+        let ts = transactions::table
+            .inner_join(accounts::table)
+            .filter(accounts::user_id.eq(s_uid.unwrap()))
+            .filter(transactions::savings_plan_id.is_null())
+            .load::<(Transaction, Account)>(connection)
+            .expect("Error loading transactions")
+            .into_iter()
+            .map(|(t, _)| t)
+            .collect();
+
+        savings_plans::link(connection, s_uid.unwrap(), history::CLI, ts)
+            .expect("Error linking transactions");
+    } else if let Some(s_months) = sub_matches.value_of("projection") {
+        let s_months: u32 = s_months.parse().expect("Could not parse months!");
+        let today = Local::today().naive_local();
+        let until = today + chrono::Duration::days((s_months as f64 * 30.44).round() as i64);
+
+        let ps =
+            savings_plans::load(connection, s_uid.unwrap()).expect("Error loading savings plans");
+        let cs = savings_plans::projection(&ps, today, until);
+
+        let mut table = Table::new();
+        table.add_row(row!["Date", "Plan", "ISIN", "Amount"]);
+        for c in cs.iter() {
+            table.add_row(row![
+                c.date,
+                c.savings_plan_id,
+                c.isin,
+                format!("{:.2}", c.amount)
+            ]);
+        }
+        table.printstd();
+
+        println!("Total: {:.2}", cs.iter().map(|c| c.amount).sum::<f64>());
+    } else {
+        panic!("unexpected options for subcommand 'savings-plan'");
+    }
+}
+
+fn read(prompt: &str) -> String {
+    let mut s = String::new();
+    println!("{}", prompt);
+    io::stdin().read_line(&mut s).unwrap();
+
+    s.trim().to_string()
+}
+
+// empty input means None
+fn read_date(prompt: &str) -> Option<NaiveDate> {
+    let s = read(prompt);
+    if s.is_empty() {
+        None
+    } else {
+        Some(NaiveDate::parse_from_str(&s, "%Y-%m-%d").expect("Could not parse date"))
+    }
+}
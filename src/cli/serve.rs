@@ -1,5 +1,6 @@
 use crate::cli::push::Config as PushConfig;
 use crate::data::*;
+use crate::inbox::{self, InboxConfig};
 use crate::models::*;
 use crate::push;
 use crate::schema::stock_infos::dsl::*;
@@ -49,6 +50,8 @@ pub struct Config {
     pub port: i64,
     pub address: String,
     pub application_server_key: String, // for push notifications
+    pub inboxes: Vec<InboxConfig>,      // directories that are watched for new receipts
+    pub admins: Vec<String>, // names of users that may change shared data, e.g. corporate actions
 }
 
 impl Default for Config {
@@ -58,6 +61,8 @@ impl Default for Config {
             port: 8383,
             address: "127.0.0.1".into(),
             application_server_key: String::new(),
+            inboxes: Vec::new(),
+            admins: Vec::new(),
         }
     }
 }
@@ -168,21 +173,44 @@ pub async fn handle(
         }
     });
 
+    let private_key_file = File::open(push_config.key_file);
+    let mut private_key = Vec::new();
+    if let Err(e) = private_key_file.and_then(|mut f| f.read_to_end(&mut private_key)) {
+        warn!(
+            "Error loading private key: {:?}, will not attempt to send notifications.",
+            e
+        );
+
+        private_key = Vec::new();
+    }
+
+    let inboxes = std::mem::take(&mut config.inboxes);
+    if !inboxes.is_empty() {
+        let inbox_pool = pool.clone();
+        let inbox_private_key = private_key.clone();
+
+        tokio::spawn(async move {
+            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+
+            loop {
+                interval.tick().await;
+
+                for i in inboxes.iter() {
+                    inbox::process(inbox_pool.clone(), i, &inbox_private_key)
+                        .await
+                        .unwrap_or_else(|e| {
+                            error!("Error processing inbox {}: {}", &i.directory, e)
+                        });
+                }
+            }
+        });
+    }
+
     tokio::spawn(async move {
         tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
 
-        let private_key_file = File::open(push_config.key_file);
-        let mut private_key = Vec::new();
-        if let Err(e) = private_key_file.and_then(|mut f| f.read_to_end(&mut private_key)) {
-            warn!(
-                "Error loading private key: {:?}, will not attempt to send notifications.",
-                e
-            );
-
-            private_key = Vec::new();
-        }
-
         loop {
             interval.tick().await;
 
@@ -205,6 +233,7 @@ pub async fn handle(
         log_level: level,
         database_url: database,
         application_server_key: config.application_server_key,
+        admins: config.admins,
     })
     .await;
 }
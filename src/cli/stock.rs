@@ -1,9 +1,11 @@
 use crate::add_missing_stocks;
+use crate::analysis::corporate_actions;
+use crate::holdings;
 use crate::models::*;
 use crate::onvista;
 use crate::schema::stock_infos::dsl::*;
 
-use chrono::{DateTime, NaiveDate, Utc};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
 use clap::ArgMatches;
 use clap::{App, Arg, ArgGroup, SubCommand};
 use diesel::{
@@ -45,9 +47,41 @@ pub fn build() -> App<'static, 'static> {
                 .long("update")
                 .help("update stock infos"),
         )
+        .arg(
+            Arg::with_name("add-action")
+                .long("add-action")
+                .value_name("isin")
+                .help("record a corporate action (split, spin-off, ...) of a stock"),
+        )
+        .arg(
+            Arg::with_name("remove-action")
+                .long("remove-action")
+                .value_name("id")
+                .help("remove a corporate action"),
+        )
+        .arg(
+            Arg::with_name("list-actions")
+                .long("list-actions")
+                .help("list recorded corporate actions"),
+        )
+        .arg(
+            Arg::with_name("check-splits")
+                .long("check-splits")
+                .help("look for price jumps that might be unrecorded splits"),
+        )
         .group(
             ArgGroup::with_name("action")
-                .args(&["add", "update", "remove", "list", "fetch"])
+                .args(&[
+                    "add",
+                    "update",
+                    "remove",
+                    "list",
+                    "fetch",
+                    "add-action",
+                    "remove-action",
+                    "list-actions",
+                    "check-splits",
+                ])
                 .required(true),
         )
 }
@@ -91,6 +125,7 @@ pub async fn handle(pool: Pool<ConnectionManager<PgConnection>>, sub_matches: &A
                         .values(&si)
                         .execute(&connection)
                         .expect("Error saving stock info");
+                    holdings::sync(&connection, &si).expect("Error saving holdings");
 
                     diesel::insert_into(crate::schema::stock_exchanges::table)
                         .values(&exs)
@@ -117,6 +152,7 @@ pub async fn handle(pool: Pool<ConnectionManager<PgConnection>>, sub_matches: &A
                 .set(new_info.clone())
                 .execute(&connection)
                 .expect("error updating stock info");
+            holdings::sync(&connection, &new_info).expect("error updating holdings");
 
             info!("updated stock info for {}", &info.isin);
             debug!("new info: {:?}", &new_info);
@@ -169,6 +205,139 @@ pub async fn handle(pool: Pool<ConnectionManager<PgConnection>>, sub_matches: &A
             table.add_row(row);
         }
 
+        table.printstd();
+    } else if let Some(isin_) = sub_matches.value_of("add-action") {
+        let isin_ = isin_.to_uppercase();
+        assert!(isin_.len() == 12, "ISINs always have a length of 12");
+
+        let mut s_kind = String::new();
+        println!(
+            "Please enter the kind of the corporate action ({})",
+            corporate_actions::KINDS.join(", ")
+        );
+        io::stdin().read_line(&mut s_kind).unwrap();
+        let s_kind = s_kind.trim_end();
+        assert!(
+            corporate_actions::KINDS.contains(&s_kind),
+            "Unknown kind of corporate action"
+        );
+
+        let mut s_date = String::new();
+        println!("Please enter the ex-date (YYYY-MM-DD)");
+        io::stdin().read_line(&mut s_date).unwrap();
+        let s_date = NaiveDate::parse_from_str(s_date.trim_end(), "%Y-%m-%d")
+            .expect("Could not parse date!");
+
+        let mut s_ratio = String::new();
+        println!("Please enter the number of units after the action per unit before (e.g. 4 for a 4:1 split)");
+        io::stdin().read_line(&mut s_ratio).unwrap();
+        let s_ratio: f64 = s_ratio.trim_end().parse().expect("Could not parse ratio!");
+
+        let (s_target, s_fraction) = if s_kind == corporate_actions::SPIN_OFF {
+            let mut s_target = String::new();
+            println!("Please enter the ISIN of the spun-off stock");
+            io::stdin().read_line(&mut s_target).unwrap();
+            let s_target = s_target.trim_end().to_uppercase();
+            assert!(s_target.len() == 12, "ISINs always have a length of 12");
+
+            let mut s_fraction = String::new();
+            println!(
+                "Please enter the fraction of the cost basis that moves to the new stock (0-1)"
+            );
+            io::stdin().read_line(&mut s_fraction).unwrap();
+            let s_fraction: f64 = s_fraction
+                .trim_end()
+                .parse()
+                .expect("Could not parse fraction!");
+
+            (Some(s_target), Some(s_fraction))
+        } else {
+            (None, None)
+        };
+
+        let mut s_comments = String::new();
+        println!("Please enter comments");
+        io::stdin().read_line(&mut s_comments).unwrap();
+
+        let a: CorporateAction = diesel::insert_into(crate::schema::corporate_actions::table)
+            .values(&NewCorporateAction {
+                isin: isin_,
+                date: Local
+                    .from_local_date(&s_date)
+                    .unwrap()
+                    .and_hms(0, 0, 0)
+                    .with_timezone(&Utc),
+                kind: s_kind.to_string(),
+                ratio: s_ratio,
+                target_isin: s_target,
+                cost_fraction: s_fraction,
+                comments: s_comments.trim_end().to_string(),
+            })
+            .get_result(&connection)
+            .expect("Error saving corporate action");
+
+        info!("Created corporate action {:?}", a);
+    } else if let Some(s_id) = sub_matches.value_of("remove-action") {
+        let s_id: i32 = s_id.parse().expect("Could not parse id!");
+
+        let row_count = diesel::delete(crate::schema::corporate_actions::table.find(s_id))
+            .execute(&connection)
+            .unwrap_or_else(|_| panic!("Unable to delete corporate action {}", s_id));
+        assert!(
+            row_count > 0,
+            "there is no corporate action with id '{}'!",
+            s_id
+        );
+
+        info!("deleted corporate action '{}'", s_id);
+    } else if sub_matches.is_present("list-actions") {
+        let actions = crate::schema::corporate_actions::table
+            .order(crate::schema::corporate_actions::date.asc())
+            .load::<CorporateAction>(&connection)
+            .expect("Error loading corporate actions");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "ID", "ISIN", "Date", "Kind", "Ratio", "Target", "Cost", "Comments"
+        ]);
+
+        for a in actions {
+            table.add_row(row![
+                a.id,
+                a.isin,
+                a.date.with_timezone(&Local).format("%Y-%m-%d"),
+                a.kind,
+                a.ratio,
+                a.target_isin.unwrap_or_default(),
+                a.cost_fraction.map(|f| f.to_string()).unwrap_or_default(),
+                a.comments
+            ]);
+        }
+
+        table.printstd();
+    } else if sub_matches.is_present("check-splits") {
+        let candidates = corporate_actions::find_split_candidates(&connection)
+            .expect("Error looking for splits");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "ISIN",
+            "Date",
+            "Closing Before",
+            "Closing After",
+            "Ratio"
+        ]);
+
+        for c in candidates {
+            table.add_row(row![
+                c.isin,
+                c.date,
+                c.closing_before,
+                c.closing_after,
+                c.ratio
+            ]);
+        }
+
         table.printstd();
     } else if sub_matches.is_present("fetch") {
         add_missing_stocks(pool).await;
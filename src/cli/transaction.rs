@@ -1,13 +1,21 @@
+use crate::analysis::fees::{self as fee_report, FeeSummary};
+use crate::distributions;
+use crate::fees;
+use crate::history;
 use crate::models::*;
 use crate::receipts;
+use crate::tags;
+use crate::validation::Validator;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use clap::ArgMatches;
 use clap::{App, Arg, ArgGroup, SubCommand};
 use diesel::prelude::*;
-use log::info;
+use log::{info, warn};
 use prettytable::{cell, row, Table};
+use std::error::Error;
 use std::io;
+use std::path::Path;
 
 pub fn build() -> App<'static, 'static> {
     SubCommand::with_name("transaction")
@@ -27,18 +35,37 @@ pub fn build() -> App<'static, 'static> {
                 .requires("user")
                 .help("import onvista receipt(s)"),
         )
+        .arg(
+            Arg::with_name("reparse")
+                .long("reparse")
+                .requires("user")
+                .help("parse stored receipts again and update their transactions"),
+        )
+        .arg(
+            Arg::with_name("extract")
+                .long("extract")
+                .value_name("filename")
+                .help("extract and anonymise the text of a receipt into a new entry of the receipt test corpus"),
+        )
+        .arg(
+            Arg::with_name("corpus")
+                .long("corpus")
+                .value_name("directory")
+                .default_value("tests/receipts")
+                .help("corpus directory for --extract"),
+        )
         .arg(
             Arg::with_name("account")
                 .long("account")
                 .value_name("id")
                 .help("account id for --list")
-                .conflicts_with_all(&["add", "remove", "receipts"]),
+                .conflicts_with_all(&["add", "remove", "receipts", "reparse"]),
         )
         .arg(
             Arg::with_name("user")
                 .long("user")
                 .value_name("id")
-                .help("user id for --receipts")
+                .help("user id for --receipts, --reparse, --tag-stock, --history, --revert, --fee-report and --distributions")
                 .conflicts_with_all(&["add", "remove", "list"]),
         )
         .arg(
@@ -46,9 +73,100 @@ pub fn build() -> App<'static, 'static> {
                 .long("list")
                 .help("list transactions"),
         )
+        .arg(
+            Arg::with_name("transfer")
+                .long("transfer")
+                .help("transfer units of a stock to another account, keeping their cost basis"),
+        )
+        .arg(
+            Arg::with_name("remove-transfer")
+                .long("remove-transfer")
+                .value_name("id")
+                .help("remove transfer"),
+        )
+        .arg(
+            Arg::with_name("list-transfers")
+                .long("list-transfers")
+                .help("list transfers"),
+        )
+        .arg(
+            Arg::with_name("tag")
+                .long("tag")
+                .value_name("id")
+                .help("add a tag to a transaction"),
+        )
+        .arg(
+            Arg::with_name("untag")
+                .long("untag")
+                .value_name("id")
+                .help("remove a tag from a transaction"),
+        )
+        .arg(
+            Arg::with_name("tag-stock")
+                .long("tag-stock")
+                .value_name("isin")
+                .requires("user")
+                .help("add a tag to all transactions of a stock"),
+        )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .requires("user")
+                .help("list the changes of transactions and accounts"),
+        )
+        .arg(
+            Arg::with_name("revert")
+                .long("revert")
+                .value_name("change id")
+                .requires("user")
+                .help("revert a change of a transaction or account"),
+        )
+        .arg(
+            Arg::with_name("fees")
+                .long("fees")
+                .value_name("id")
+                .help("itemise the fees of a transaction (commission, exchange fees, ...)"),
+        )
+        .arg(
+            Arg::with_name("fee-report")
+                .long("fee-report")
+                .requires("user")
+                .help("show the fees per account, broker and year"),
+        )
+        .arg(
+            Arg::with_name("distribution")
+                .long("distribution")
+                .value_name("id")
+                .help("enter the details (ex-date, amount per unit, taxes, ...) of a dividend"),
+        )
+        .arg(
+            Arg::with_name("distributions")
+                .long("distributions")
+                .requires("user")
+                .help("list dividends and distributions with their totals per stock and year"),
+        )
         .group(
             ArgGroup::with_name("action")
-                .args(&["add", "remove", "list", "receipts"])
+                .args(&[
+                    "add",
+                    "remove",
+                    "list",
+                    "receipts",
+                    "reparse",
+                    "extract",
+                    "transfer",
+                    "remove-transfer",
+                    "list-transfers",
+                    "tag",
+                    "untag",
+                    "tag-stock",
+                    "history",
+                    "revert",
+                    "fees",
+                    "fee-report",
+                    "distribution",
+                    "distributions",
+                ])
                 .required(true),
         )
 }
@@ -62,21 +180,18 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             .trim_end()
             .parse()
             .expect("Could not parse account id!");
-        assert!(
-            crate::schema::accounts::table
-                .find(&s_aid)
-                .execute(connection)
-                .expect("Error loading accounts")
-                >= 1,
-            "there is no account with id '{}'!",
-            &s_aid
-        );
+        let s_uid = crate::schema::accounts::table
+            .find(s_aid)
+            .select(crate::schema::accounts::user_id)
+            .first::<i32>(connection)
+            .optional()
+            .expect("Error loading accounts")
+            .unwrap_or_else(|| panic!("there is no account with id '{}'!", &s_aid));
 
         let mut s_isin = String::new();
         println!("Please enter the ISIN for the transaction");
         io::stdin().read_line(&mut s_isin).unwrap();
         let s_isin = s_isin.trim().to_uppercase();
-        assert!(s_isin.len() == 12, "ISINs always have a length of 12!");
 
         let mut s_exchange = String::new();
         println!("Please enter the onvista exchange ID for the transaction (or leave blank)");
@@ -113,6 +228,20 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
         io::stdin().read_line(&mut s_fees).unwrap();
         let s_fees = (s_fees.parse::<f64>().expect("Could not parse fees") * 100.0).round() as i64;
 
+        let mut s_currency = String::new();
+        println!("Please enter the currency of price and fees (or leave blank for the account's currency)");
+        io::stdin().read_line(&mut s_currency).unwrap();
+        let s_currency = Some(s_currency.trim().to_uppercase()).filter(|c| !c.is_empty());
+
+        let s_exchange_rate = if s_currency.is_some() {
+            let mut s_rate = String::new();
+            println!("Please enter the exchange rate used at settlement (units of the account's currency per unit of the transaction's currency, or leave blank)");
+            io::stdin().read_line(&mut s_rate).unwrap();
+            s_rate.trim().parse::<f64>().ok()
+        } else {
+            None
+        };
+
         let mut s_comments = String::new();
         println!("Any comments on this transaction?");
         io::stdin().read_line(&mut s_comments).unwrap();
@@ -129,26 +258,38 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             comments: s_comments,
             exchange: s_exchange_str,
             receipt_number: None,
+            currency: s_currency,
+            exchange_rate: s_exchange_rate,
+            savings_plan_id: None,
         };
+        if let Err(e) = Validator::load(connection, s_uid)
+            .expect("Error loading accounts")
+            .check(&t)
+        {
+            panic!("{}", e);
+        }
 
-        let t: Transaction = diesel::insert_into(crate::schema::transactions::table)
-            .values(&t)
-            .get_result(connection)
+        let t = connection
+            .transaction::<_, Box<dyn Error>, _>(|| {
+                let t: Transaction = diesel::insert_into(crate::schema::transactions::table)
+                    .values(&t)
+                    .get_result(connection)?;
+                history::inserted(connection, s_uid, history::CLI, std::slice::from_ref(&t))?;
+                Ok(t)
+            })
             .expect("Error saving new transaction");
 
         info!("Created transaction {:?}", t);
     } else if let Some(s_tid) = sub_matches.value_of("remove") {
         let s_tid: i32 = s_tid.parse().expect("Could not parse transaction id!");
 
-        assert!(
-            crate::schema::transactions::table
-                .find(&s_tid)
-                .execute(connection)
-                .expect("Error loading transaction")
-                > 0,
-            "there is no transaction with id '{}'!",
-            &s_tid
-        );
+        let (t, a) = crate::schema::transactions::table
+            .inner_join(crate::schema::accounts::table)
+            .filter(crate::schema::transactions::id.eq(s_tid))
+            .first::<(Transaction, Account)>(connection)
+            .optional()
+            .expect("Error loading transaction")
+            .unwrap_or_else(|| panic!("there is no transaction with id '{}'!", &s_tid));
 
         let mut confirmation = String::new();
         println!(
@@ -159,8 +300,13 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
         let confirmation = confirmation.trim_end(); // Remove the trailing newline
         assert_eq!(confirmation, s_tid.to_string(), "Confirmation failed");
 
-        diesel::delete(crate::schema::transactions::table.find(&s_tid))
-            .execute(connection)
+        connection
+            .transaction::<_, Box<dyn Error>, _>(|| {
+                history::deleted(connection, a.user_id, history::CLI, &t)?;
+                diesel::delete(crate::schema::transactions::table.find(&s_tid))
+                    .execute(connection)?;
+                Ok(())
+            })
             .unwrap_or_else(|_| panic!("Unable to delete transaction {}", &s_tid));
 
         info!("deleted transaction '{}'", s_tid);
@@ -172,8 +318,66 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             .parse()
             .expect("Could not parse user id!");
 
-        receipts::parse_files(connection, uid, &file_names)
-            .unwrap_or_else(|e| panic!("Error adding receipts: {:?}", e));
+        let mut result = receipts::parse_files(connection, uid, &file_names, false);
+        if let Err(e) = &result {
+            if let Some(depots) = e.downcast_ref::<receipts::UnknownDepots>() {
+                let mut confirmation = String::new();
+                println!(
+                    "There are no accounts for depot number(s) {}. Create them? [y/N]",
+                    depots.0.join(", ")
+                );
+                io::stdin().read_line(&mut confirmation).unwrap();
+
+                if confirmation.trim_end().eq_ignore_ascii_case("y") {
+                    result = receipts::parse_files(connection, uid, &file_names, true);
+                }
+            }
+        }
+
+        if let Err(e) = result {
+            if let Some(errors) = e.downcast_ref::<receipts::ReceiptErrors>() {
+                print_receipt_errors(&errors.0);
+            }
+            panic!("Error adding receipts: {}", e);
+        }
+    } else if sub_matches.is_present("reparse") {
+        let uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+
+        let ts = receipts::reparse(connection, uid)
+            .unwrap_or_else(|e| panic!("Error parsing stored receipts: {:?}", e));
+        info!("updated or re-inserted {} transaction(s)", ts.len());
+    } else if let Some(file_name) = sub_matches.value_of("extract") {
+        let buf = std::fs::read(file_name).expect("Could not read receipt");
+        let text =
+            receipts::anonymise(&receipts::extract_text(&buf).expect("Could not extract text"));
+
+        let path = Path::new(sub_matches.value_of("corpus").unwrap()).join(
+            Path::new(file_name)
+                .file_stem()
+                .expect("Could not determine file name"),
+        );
+        std::fs::write(path.with_extension("txt"), &text).expect("Could not write text");
+        info!("wrote {}", path.with_extension("txt").display());
+
+        // the expected output still has to be checked by hand
+        match receipts::parse_text(&text) {
+            Ok(ts) => {
+                std::fs::write(
+                    path.with_extension("json"),
+                    serde_json::to_string_pretty(&ts).unwrap(),
+                )
+                .expect("Could not write expected transactions");
+                info!(
+                    "wrote {}, please verify its contents",
+                    path.with_extension("json").display()
+                );
+            }
+            Err(e) => warn!("could not parse the anonymised text: {}", e),
+        }
     } else if sub_matches.is_present("list") {
         let ts = if let Some(s_aid) = sub_matches.value_of("account") {
             let s_aid: i32 = s_aid.parse().expect("Could not parse account id!");
@@ -225,8 +429,447 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             ]);
         }
 
+        table.printstd();
+    } else if sub_matches.is_present("transfer") {
+        let mut s_from = String::new();
+        println!("Please enter the id of the account the units are transferred from");
+        io::stdin().read_line(&mut s_from).unwrap();
+        let s_from: i32 = s_from.trim().parse().expect("Could not parse account id!");
+
+        let mut s_to = String::new();
+        println!("Please enter the id of the account the units are transferred to");
+        io::stdin().read_line(&mut s_to).unwrap();
+        let s_to: i32 = s_to.trim().parse().expect("Could not parse account id!");
+        assert_ne!(s_from, s_to, "Cannot transfer to the same account");
+
+        let accs = crate::schema::accounts::table
+            .filter(crate::schema::accounts::id.eq_any(vec![s_from, s_to]))
+            .load::<Account>(connection)
+            .expect("Error loading accounts");
+        assert!(accs.len() == 2, "Unknown account");
+        assert_eq!(
+            accs[0].user_id, accs[1].user_id,
+            "Cannot transfer between accounts of different users"
+        );
+
+        let mut s_isin = String::new();
+        println!("Please enter the ISIN of the transferred stock");
+        io::stdin().read_line(&mut s_isin).unwrap();
+        let s_isin = s_isin.trim().to_uppercase();
+        assert!(s_isin.len() == 12, "ISINs always have a length of 12!");
+
+        let mut s_date = String::new();
+        println!("Please enter the date of the transfer");
+        io::stdin().read_line(&mut s_date).unwrap();
+        let s_date: DateTime<Utc> = s_date.trim().parse().expect("Could not parse date");
+
+        let mut s_units = String::new();
+        println!("Please enter the amount of units transferred");
+        io::stdin().read_line(&mut s_units).unwrap();
+        let s_units: f64 = s_units.trim().parse().expect("Could not parse units");
+        assert!(s_units > 0.0, "Units have to be positive");
+
+        let mut s_comments = String::new();
+        println!("Any comments on this transfer?");
+        io::stdin().read_line(&mut s_comments).unwrap();
+
+        let tr: Transfer = diesel::insert_into(crate::schema::transfers::table)
+            .values(&NewTransfer {
+                from_account_id: s_from,
+                to_account_id: s_to,
+                isin: s_isin,
+                date: s_date,
+                units: s_units,
+                comments: s_comments.trim().to_string(),
+            })
+            .get_result(connection)
+            .expect("Error saving new transfer");
+
+        info!("Created transfer {:?}", tr);
+    } else if let Some(s_trid) = sub_matches.value_of("remove-transfer") {
+        let s_trid: i32 = s_trid.parse().expect("Could not parse transfer id!");
+
+        let row_count = diesel::delete(crate::schema::transfers::table.find(s_trid))
+            .execute(connection)
+            .unwrap_or_else(|_| panic!("Unable to delete transfer {}", s_trid));
+        assert!(row_count > 0, "there is no transfer with id '{}'!", s_trid);
+
+        info!("deleted transfer '{}'", s_trid);
+    } else if sub_matches.is_present("list-transfers") {
+        let trs = crate::schema::transfers::table
+            .order(crate::schema::transfers::date.desc())
+            .load::<Transfer>(connection)
+            .expect("Error loading transfers");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "ID", "From", "To", "Date", "ISIN", "Units", "Comments"
+        ]);
+        for tr in trs.iter() {
+            table.add_row(row![
+                tr.id,
+                tr.from_account_id,
+                tr.to_account_id,
+                tr.date,
+                tr.isin,
+                tr.units,
+                tr.comments
+            ]);
+        }
+
+        table.printstd();
+    } else if let Some(s_tid) = sub_matches
+        .value_of("tag")
+        .or(sub_matches.value_of("untag"))
+    {
+        let s_tid: i32 = s_tid.parse().expect("Could not parse transaction id!");
+        let (_, acc) = crate::schema::transactions::table
+            .inner_join(crate::schema::accounts::table)
+            .filter(crate::schema::transactions::id.eq(s_tid))
+            .first::<(Transaction, Account)>(connection)
+            .unwrap_or_else(|_| panic!("there is no transaction with id '{}'!", s_tid));
+
+        let s_tag = read_tag();
+        if sub_matches.is_present("tag") {
+            tags::tag_transaction(connection, acc.user_id, s_tid, &s_tag)
+                .expect("Could not tag transaction");
+            info!("tagged transaction {} with '{}'", s_tid, s_tag);
+        } else {
+            let row_count = tags::untag_transaction(connection, acc.user_id, s_tid, &s_tag)
+                .expect("Could not remove tag");
+            assert!(
+                row_count > 0,
+                "transaction {} is not tagged with '{}'",
+                s_tid,
+                s_tag
+            );
+            info!("removed tag '{}' from transaction {}", s_tag, s_tid);
+        }
+    } else if let Some(s_isin) = sub_matches.value_of("tag-stock") {
+        let s_uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+        let s_tag = read_tag();
+
+        tags::tag_stock(connection, s_uid, &s_isin.to_uppercase(), &s_tag)
+            .expect("Could not tag stock");
+        info!("tagged {} with '{}'", s_isin, s_tag);
+    } else if sub_matches.is_present("history") {
+        let s_uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+        let cs = history::list(connection, s_uid, None, None).expect("Error loading changes");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "ID",
+            "Date",
+            "Source",
+            "Table",
+            "Record",
+            "Action",
+            "Reverted By"
+        ]);
+        for c in cs.iter() {
+            table.add_row(row![
+                c.id,
+                c.date,
+                c.source,
+                c.table_name,
+                c.record_id,
+                c.action,
+                c.reverted_by.map(|r| r.to_string()).unwrap_or_default()
+            ]);
+        }
+
+        table.printstd();
+    } else if let Some(s_cid) = sub_matches.value_of("revert") {
+        let s_cid: i32 = s_cid.parse().expect("Could not parse change id!");
+        let s_uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+
+        let c = history::revert(connection, s_uid, history::CLI, s_cid)
+            .unwrap_or_else(|e| panic!("Unable to revert change {}: {}", s_cid, e));
+        info!("reverted change {} (recorded as change {})", s_cid, c.id);
+    } else if let Some(s_tid) = sub_matches.value_of("fees") {
+        let s_tid: i32 = s_tid.parse().expect("Could not parse transaction id!");
+        let t = crate::schema::transactions::table
+            .find(s_tid)
+            .first::<Transaction>(connection)
+            .optional()
+            .expect("Error loading transaction")
+            .unwrap_or_else(|| panic!("there is no transaction with id '{}'!", s_tid));
+
+        let current = fees::list(connection, &[s_tid]).expect("Error loading fee items");
+        println!(
+            "Fees of transaction {}: {:.2}",
+            s_tid,
+            t.fees as f64 / 100.0
+        );
+        for i in current.iter() {
+            println!(
+                "  {:<12} {:8.2} {}",
+                i.category,
+                i.amount as f64 / 100.0,
+                i.description
+            );
+        }
+
+        println!(
+            "Please enter the new items as '<category> <amount> [description]', one per line, followed by an empty line.\nCategories: {}",
+            fees::CATEGORIES.join(", ")
+        );
+        let mut items = Vec::new();
+        loop {
+            let mut line = String::new();
+            io::stdin().read_line(&mut line).unwrap();
+            let mut parts = line.trim().splitn(3, ' ');
+            let category = match parts.next() {
+                Some(c) if !c.is_empty() => c.to_string(),
+                _ => break,
+            };
+            let amount: f64 = parts
+                .next()
+                .and_then(|a| a.trim().parse().ok())
+                .expect("Could not parse amount");
+
+            items.push(NewFeeItem {
+                transaction_id: s_tid,
+                category,
+                amount: -(amount.abs() * 100.0).round() as i64,
+                description: parts.next().unwrap_or("").trim().to_string(),
+            });
+        }
+
+        let items = fees::replace(connection, &t, &items)
+            .unwrap_or_else(|e| panic!("Unable to save fee items: {}", e));
+        info!("Saved {} fee item(s) of transaction {}", items.len(), s_tid);
+    } else if sub_matches.is_present("fee-report") {
+        let s_uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+        let report =
+            fee_report::compute(connection, s_uid, None).expect("Error computing fee report");
+
+        print_fee_summaries("Account", &report.accounts);
+        print_fee_summaries("Broker", &report.brokers);
+    } else if let Some(s_tid) = sub_matches.value_of("distribution") {
+        let s_tid: i32 = s_tid.parse().expect("Could not parse transaction id!");
+        let t = crate::schema::transactions::table
+            .find(s_tid)
+            .first::<Transaction>(connection)
+            .optional()
+            .expect("Error loading transaction")
+            .unwrap_or_else(|| panic!("there is no transaction with id '{}'!", s_tid));
+        if let Some(d) = distributions::find(connection, s_tid).expect("Error loading distribution")
+        {
+            println!("Current details: {:?}", d);
+        }
+
+        let read_date = |prompt: &str| -> Option<NaiveDate> {
+            let mut s_date = String::new();
+            println!("{}", prompt);
+            io::stdin().read_line(&mut s_date).unwrap();
+            let s_date = s_date.trim();
+            if s_date.is_empty() {
+                None
+            } else {
+                Some(
+                    NaiveDate::parse_from_str(s_date, "%Y-%m-%d")
+                        .expect("Could not parse date (expected YYYY-MM-DD)"),
+                )
+            }
+        };
+
+        let s_ex_date = read_date("Please enter the ex-date (YYYY-MM-DD, or leave blank)");
+        let s_pay_date =
+            read_date("Please enter the pay date (or leave blank for the date of the transaction)")
+                .unwrap_or_else(|| distributions::pay_date(&t));
+
+        let mut s_currency = String::new();
+        println!("Please enter the currency of the distribution (or leave blank for EUR)");
+        io::stdin().read_line(&mut s_currency).unwrap();
+        let s_currency = Some(s_currency.trim().to_uppercase())
+            .filter(|c| !c.is_empty())
+            .unwrap_or_else(|| String::from("EUR"));
+
+        let mut s_amount = String::new();
+        println!("Please enter the gross amount per unit (in {})", s_currency);
+        io::stdin().read_line(&mut s_amount).unwrap();
+        let s_amount: f64 = s_amount.trim().parse().expect("Could not parse amount");
+
+        let mut s_units = String::new();
+        println!("Please enter the amount of units held on the ex-date");
+        io::stdin().read_line(&mut s_units).unwrap();
+        let s_units: f64 = s_units.trim().parse().expect("Could not parse units");
+
+        let s_exchange_rate = if s_currency != "EUR" {
+            let mut s_rate = String::new();
+            println!("Please enter the exchange rate used (units of the account's currency per unit of {}, or leave blank)", s_currency);
+            io::stdin().read_line(&mut s_rate).unwrap();
+            s_rate.trim().parse::<f64>().ok()
+        } else {
+            None
+        };
+
+        let mut s_taxes = String::new();
+        println!("Please enter the withheld taxes (or leave blank)");
+        io::stdin().read_line(&mut s_taxes).unwrap();
+        let s_taxes = -(s_taxes.trim().parse::<f64>().unwrap_or(0.0).abs() * 100.0).round() as i64;
+
+        let s_period_start = read_date(
+            "Please enter the start of the period the distribution is for (or leave blank)",
+        );
+        let s_period_end = read_date("Please enter the end of the period (or leave blank)");
+
+        let d = distributions::save(
+            connection,
+            &t,
+            &NewDistribution {
+                transaction_id: s_tid,
+                ex_date: s_ex_date,
+                pay_date: s_pay_date,
+                amount_per_unit: s_amount,
+                currency: s_currency,
+                units: s_units,
+                exchange_rate: s_exchange_rate,
+                taxes: s_taxes,
+                period_start: s_period_start,
+                period_end: s_period_end,
+            },
+        )
+        .unwrap_or_else(|e| panic!("Unable to save distribution: {}", e));
+        info!("Saved distribution {:?}", d);
+    } else if sub_matches.is_present("distributions") {
+        let s_uid: i32 = sub_matches
+            .value_of("user")
+            .unwrap()
+            .parse()
+            .expect("Could not parse user id!");
+        let ds = distributions::list(connection, s_uid, None, None)
+            .expect("Error loading distributions");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "Transaction",
+            "ISIN",
+            "Ex-Date",
+            "Pay Date",
+            "Per Unit",
+            "Units",
+            "Gross",
+            "Taxes",
+            "Net"
+        ]);
+        for d in ds.iter() {
+            table.add_row(row![
+                d.distribution.transaction_id,
+                d.isin,
+                d.distribution
+                    .ex_date
+                    .map(|e| e.to_string())
+                    .unwrap_or_default(),
+                d.distribution.pay_date,
+                format!(
+                    "{} {:.4}",
+                    d.distribution.currency, d.distribution.amount_per_unit
+                ),
+                format!("{:.3}", d.distribution.units),
+                format!("{:.2}", d.gross),
+                format!("{:.2}", d.distribution.taxes as f64 / 100.0),
+                format!("{:.2}", d.net)
+            ]);
+        }
+        table.printstd();
+
+        let mut table = Table::new();
+        table.add_row(row!["ISIN", "Year", "#", "Per Unit", "Taxes", "Net"]);
+        for s in distributions::summarize(&ds).iter() {
+            table.add_row(row![
+                s.isin,
+                s.year,
+                s.count,
+                format!("{} {:.4}", s.currency, s.amount_per_unit),
+                format!("{:.2}", s.taxes),
+                format!("{:.2}", s.net)
+            ]);
+        }
         table.printstd();
     } else {
         panic!("unexpected options for subcommand 'transaction'");
     }
 }
+
+fn read_tag() -> String {
+    let mut s_tag = String::new();
+    println!("Please enter the tag (e.g. retirement)");
+    io::stdin().read_line(&mut s_tag).unwrap();
+    let s_tag = s_tag.trim().to_string();
+    assert!(!s_tag.is_empty(), "Tags cannot be empty");
+
+    s_tag
+}
+
+fn print_fee_summaries(title: &str, summaries: &[FeeSummary]) {
+    let mut categories = summaries
+        .iter()
+        .flat_map(|s| s.categories.keys().cloned())
+        .collect::<Vec<_>>();
+    categories.sort();
+    categories.dedup();
+
+    let mut table = Table::new();
+    let mut header = row![title, "Year", "#", "Volume", "Fees", "Ratio"];
+    for c in categories.iter() {
+        header.add_cell(cell!(c));
+    }
+    table.add_row(header);
+
+    for s in summaries.iter() {
+        let mut r = row![
+            s.name,
+            s.year,
+            s.transactions,
+            format!("{:.2}", s.volume),
+            format!("{:.2}", s.fees),
+            format!("{:.2}%", s.fee_ratio * 100.0)
+        ];
+        for c in categories.iter() {
+            r.add_cell(cell!(s
+                .categories
+                .get(c)
+                .map(|v| format!("{:.2}", v))
+                .unwrap_or_default()));
+        }
+        table.add_row(r);
+    }
+
+    table.printstd();
+}
+
+fn print_receipt_errors(errors: &[receipts::ReceiptError]) {
+    let mut table = Table::new();
+    table.add_row(row!["File", "Page", "Type", "Field", "Error", "Snippet"]);
+
+    for e in errors.iter() {
+        table.add_row(row![
+            e.file.as_deref().unwrap_or(""),
+            e.page.map(|p| p.to_string()).unwrap_or_default(),
+            e.kind.map(|k| format!("{:?}", k)).unwrap_or_default(),
+            e.field.as_deref().unwrap_or(""),
+            e.message,
+            e.snippet.as_deref().unwrap_or("")
+        ]);
+    }
+
+    table.printstd();
+}
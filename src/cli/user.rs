@@ -30,10 +30,36 @@ pub fn build() -> App<'static, 'static> {
                 .value_name("name")
                 .help("update user name and password"),
         )
+        .arg(
+            Arg::with_name("currency")
+                .long("currency")
+                .value_name("name")
+                .help("set the currency that analyses of the user are reported in"),
+        )
+        .arg(
+            Arg::with_name("tax-allowance")
+                .long("tax-allowance")
+                .value_name("name")
+                .help("set the tax-free allowance (Sparerpauschbetrag) of the user from a given year on"),
+        )
+        .arg(
+            Arg::with_name("taxes")
+                .long("taxes")
+                .value_name("name")
+                .help("show taxable income, Vorabpauschalen and allowance usage per year"),
+        )
         .arg(Arg::with_name("list").long("list").help("list users"))
         .group(
             ArgGroup::with_name("action")
-                .args(&["add", "update", "remove", "list"])
+                .args(&[
+                    "add",
+                    "update",
+                    "remove",
+                    "currency",
+                    "tax-allowance",
+                    "taxes",
+                    "list",
+                ])
                 .required(true),
         )
 }
@@ -70,6 +96,7 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             name: uname.to_string(),
             full_name: fname.to_string(),
             hash: hash_password(password),
+            reporting_currency: String::from("EUR"),
         };
 
         let u: User = diesel::insert_into(crate::schema::users::table)
@@ -100,6 +127,127 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             .unwrap_or_else(|_| panic!("Unable to find user {}", uname));
 
         info!("Updated user {:?}", u);
+    } else if let Some(uname) = sub_matches.value_of("currency") {
+        let mut s_currency = String::new();
+        println!("Please enter the reporting currency of the user (e.g. EUR, USD, CHF)");
+        io::stdin().read_line(&mut s_currency).unwrap();
+        let s_currency = s_currency.trim().to_uppercase();
+        assert!(
+            s_currency.len() == 3,
+            "Currency codes always have a length of 3!"
+        );
+
+        let u = diesel::update(users.filter(name.eq(uname)))
+            .set(reporting_currency.eq(s_currency))
+            .get_result::<User>(connection)
+            .unwrap_or_else(|_| panic!("Unable to find user {}", uname));
+
+        info!("Updated user {:?}", u);
+    } else if let Some(uname) = sub_matches.value_of("tax-allowance") {
+        use crate::schema::tax_allowances;
+
+        let u = users
+            .filter(name.eq(uname))
+            .first::<User>(connection)
+            .unwrap_or_else(|_| panic!("Unable to find user {}", uname));
+
+        let mut s_year = String::new();
+        println!("Please enter the first year the allowance applies to");
+        io::stdin().read_line(&mut s_year).unwrap();
+        let s_year = s_year.trim().parse::<i32>().expect("Could not parse year");
+
+        let mut s_amount = String::new();
+        println!("Please enter the allowance in EUR (e.g. 801 or 1602)");
+        io::stdin().read_line(&mut s_amount).unwrap();
+        let s_amount = (s_amount
+            .trim()
+            .parse::<f64>()
+            .expect("Could not parse amount")
+            * 100.0)
+            .round() as i64;
+        assert!(s_amount >= 0, "The allowance must not be negative!");
+
+        let a = TaxAllowance {
+            user_id: u.id,
+            year: s_year,
+            amount: s_amount,
+        };
+        diesel::insert_into(tax_allowances::table)
+            .values(&a)
+            .on_conflict((tax_allowances::user_id, tax_allowances::year))
+            .do_update()
+            .set(tax_allowances::amount.eq(s_amount))
+            .execute(connection)
+            .expect("Unable to save allowance");
+
+        info!(
+            "Set allowance of {} from {} on to {:.2} EUR",
+            uname,
+            s_year,
+            s_amount as f64 / 100.0
+        );
+    } else if let Some(uname) = sub_matches.value_of("taxes") {
+        let u = users
+            .filter(name.eq(uname))
+            .first::<User>(connection)
+            .unwrap_or_else(|_| panic!("Unable to find user {}", uname));
+
+        let report =
+            crate::analysis::tax::compute(connection, u.id).expect("Unable to compute taxes");
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "ISIN",
+            "Year",
+            "Units",
+            "Start",
+            "End",
+            "Distributions",
+            "Basiszins",
+            "Vorabpauschale",
+            "Teilfreistellung",
+            "Taxable"
+        ]);
+        for v in report.vorabpauschalen.iter() {
+            table.add_row(row![
+                v.isin,
+                v.year,
+                format!("{:.3}", v.units),
+                format!("{:.2}", v.start_price),
+                format!("{:.2}", v.end_price),
+                format!("{:.2}", v.distributions),
+                format!("{:.2}%", v.base_rate),
+                format!("{:.2}", v.amount),
+                format!("{:.0}%", v.partial_exemption * 100.0),
+                format!("{:.2}", v.taxable)
+            ]);
+        }
+        table.printstd();
+
+        let mut table = Table::new();
+        table.add_row(row![
+            "Year",
+            "Realized Gains",
+            "Distributions",
+            "Vorabpauschale",
+            "Taxable",
+            "Allowance",
+            "Used",
+            "Left"
+        ]);
+        for y in report.years.iter() {
+            table.add_row(row![
+                y.year,
+                format!("{:.2}", y.realized_gains),
+                format!("{:.2}", y.distributions),
+                format!("{:.2}", y.vorabpauschale),
+                format!("{:.2}", y.taxable),
+                format!("{:.2}", y.allowance),
+                format!("{:.2}", y.allowance_used),
+                format!("{:.2}", y.allowance_left)
+            ]);
+        }
+        table.printstd();
     } else if let Some(uname) = sub_matches.value_of("remove") {
         // check if this user even exists
         assert!(
@@ -148,6 +296,7 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
             "ID",
             "Name",
             "Full Name",
+            "Currency",
             "# Accounts",
             "# Transactions"
         ]);
@@ -168,6 +317,7 @@ pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
                 u.id,
                 u.name,
                 u.full_name,
+                u.reporting_currency,
                 acs.len(),
                 transaction_count
             ]);
@@ -0,0 +1,479 @@
+use crate::history;
+use crate::models::*;
+use crate::receipts;
+use crate::savings_plans;
+use crate::schema::{accounts, csv_profiles, transactions};
+use crate::validation::Validator;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use diesel::prelude::*;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+// how the columns of a broker's csv export translate into transactions;
+// stored (serialized) in the csv_profiles table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsvMapping {
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default)]
+    pub decimal_comma: bool, // 1.234,56 instead of 1,234.56
+    pub date_format: String, // chrono format string, e.g. "%d.%m.%Y" or "%Y-%m-%d %H:%M:%S"
+    #[serde(default)]
+    pub skip_lines: usize, // lines before the header row
+    pub columns: CsvColumns,
+    #[serde(default)]
+    pub negate_amount: bool, // set if purchases are listed with positive amounts
+    #[serde(default)]
+    pub negate_fees: bool, // set if fees are listed with positive amounts
+    #[serde(default)]
+    pub units_sign_from_amount: bool, // set if sales are listed with positive units
+    pub account_id: Option<i32>, // fixed account for all rows
+    pub broker: Option<String>,  // used together with columns.account to find the account
+}
+
+// header names of the columns that contain the respective transaction fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsvColumns {
+    pub isin: String,
+    pub date: String,
+    pub units: String,
+    pub amount: String,
+    pub fees: Option<String>,
+    pub comments: Option<String>,
+    pub exchange: Option<String>,
+    pub receipt_number: Option<String>, // only numeric ids are used, others are ignored
+    pub account: Option<String>,        // depot or clearing account number, see Account::find
+    pub currency: Option<String>, // of amount and fees, if they are not in the account's currency
+    pub exchange_rate: Option<String>,
+}
+
+fn default_delimiter() -> char {
+    ';'
+}
+
+pub fn load_profile(
+    connection: &PgConnection,
+    uid: i32,
+    profile_name: &str,
+) -> Result<CsvMapping, Box<dyn Error>> {
+    let p = csv_profiles::table
+        .filter(csv_profiles::user_id.eq(uid))
+        .filter(csv_profiles::name.eq(profile_name))
+        .first::<CsvProfile>(connection)
+        .optional()?
+        .ok_or_else(|| format!("there is no csv profile '{}'", profile_name))?;
+
+    Ok(serde_json::from_str(&p.mapping)?)
+}
+
+// create a profile or replace the mapping of an existing one with the same name
+pub fn save_profile(
+    connection: &PgConnection,
+    uid: i32,
+    profile_name: &str,
+    mapping: &CsvMapping,
+) -> Result<CsvProfile, Box<dyn Error>> {
+    let mapping = serde_json::to_string(mapping)?;
+
+    Ok(diesel::insert_into(csv_profiles::table)
+        .values(&NewCsvProfile {
+            user_id: uid,
+            name: profile_name.to_string(),
+            mapping: mapping.clone(),
+        })
+        .on_conflict((csv_profiles::user_id, csv_profiles::name))
+        .do_update()
+        .set(csv_profiles::mapping.eq(mapping))
+        .get_result(connection)?)
+}
+
+// parse a csv file using the given profile and insert its transactions;
+// rows that already exist in the database are skipped.
+pub fn import(
+    connection: &PgConnection,
+    uid: i32,
+    profile_name: &str,
+    data: &[u8],
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let mapping = load_profile(connection, uid, profile_name)?;
+    let accs = accounts::table
+        .filter(accounts::user_id.eq(uid))
+        .load::<Account>(connection)?;
+
+    let ts = parse(&mapping, &accs, data)?;
+    let ts = remove_existing(connection, &accs, ts)?;
+    let validator = Validator::new(connection, accs.iter().map(|a| a.id).collect())?;
+    for t in ts.iter() {
+        validator
+            .check(t)
+            .map_err(|e| format!("{} ({} on {})", e, t.isin, t.date))?;
+    }
+
+    let inserted = diesel::insert_into(transactions::table)
+        .values(&ts)
+        .load::<Transaction>(connection)?;
+    info!("Inserted {} transactions into the database", inserted.len());
+    history::inserted(connection, uid, history::IMPORT, &inserted)?;
+    let inserted = savings_plans::link(connection, uid, history::IMPORT, inserted)?;
+
+    Ok(inserted)
+}
+
+fn parse(
+    mapping: &CsvMapping,
+    accs: &[Account],
+    data: &[u8],
+) -> Result<Vec<NewTransaction>, Box<dyn Error>> {
+    let mut delimiter = [0; 4];
+    mapping.delimiter.encode_utf8(&mut delimiter);
+    if mapping.delimiter.len_utf8() != 1 {
+        return Err("delimiter has to be a single byte character".into());
+    }
+
+    let data = data
+        .split(|b| *b == b'\n')
+        .skip(mapping.skip_lines)
+        .collect::<Vec<_>>()
+        .join(&b'\n');
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter[0])
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(data.as_slice());
+
+    let headers = rdr.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| format!("there is no column '{}'", name))
+    };
+    let optional_column = |name: &Option<String>| -> Result<Option<usize>, String> {
+        name.as_ref().map(|n| column(n)).transpose()
+    };
+
+    let c = &mapping.columns;
+    let c_isin = column(&c.isin)?;
+    let c_date = column(&c.date)?;
+    let c_units = column(&c.units)?;
+    let c_amount = column(&c.amount)?;
+    let c_fees = optional_column(&c.fees)?;
+    let c_comments = optional_column(&c.comments)?;
+    let c_exchange = optional_column(&c.exchange)?;
+    let c_receipt_number = optional_column(&c.receipt_number)?;
+    let c_account = optional_column(&c.account)?;
+    let c_currency = optional_column(&c.currency)?;
+    let c_exchange_rate = optional_column(&c.exchange_rate)?;
+
+    let mut ts = Vec::new();
+    for (i, record) in rdr.records().enumerate() {
+        let record = record?;
+        let line = i + mapping.skip_lines + 2;
+        let get = |c: usize| record.get(c).unwrap_or("");
+        let get_optional = |c: Option<usize>| c.map(get).filter(|s| !s.is_empty());
+
+        let account_id = match (mapping.account_id, c_account) {
+            (Some(id), _) => {
+                accs.iter()
+                    .find(|a| a.id == id)
+                    .ok_or_else(|| format!("there is no account with id {}", id))?
+                    .id
+            }
+            (None, Some(c)) => {
+                let broker = mapping
+                    .broker
+                    .as_deref()
+                    .ok_or("profile specifies an account column, but no broker")?;
+                Account::find(accs, broker, Some(get(c)), Some(get(c)))
+                    .ok_or_else(|| format!("line {}: cannot find account for '{}'", line, get(c)))?
+                    .id
+            }
+            (None, None) => return Err("profile does not specify an account".into()),
+        };
+
+        let mut amount = parse_cents(mapping, get(c_amount))
+            .map_err(|e| format!("line {}: amount: {}", line, e))?;
+        if mapping.negate_amount {
+            amount = -amount;
+        }
+
+        let mut fees = match c_fees {
+            Some(c) if !get(c).is_empty() => {
+                parse_cents(mapping, get(c)).map_err(|e| format!("line {}: fees: {}", line, e))?
+            }
+            _ => 0,
+        };
+        if mapping.negate_fees {
+            fees = -fees;
+        }
+
+        let mut units = parse_number(mapping, get(c_units))
+            .map_err(|e| format!("line {}: units: {}", line, e))?;
+        if mapping.units_sign_from_amount {
+            // rows without an amount are deliveries, e.g. of free shares
+            units = if amount > 0 {
+                -units.abs()
+            } else {
+                units.abs()
+            };
+        }
+
+        ts.push(NewTransaction {
+            account_id,
+            isin: get(c_isin).to_uppercase(),
+            date: parse_date(mapping, get(c_date))
+                .map_err(|e| format!("line {}: date: {}", line, e))?,
+            units,
+            amount,
+            fees,
+            onvista_exchange_id: None,
+            comments: get_optional(c_comments).unwrap_or_default().to_string(),
+            exchange: get_optional(c_exchange).map(|s| s.to_string()),
+            // some brokers use ids with letters, those cannot be used to detect duplicates
+            receipt_number: get_optional(c_receipt_number).and_then(|s| match s.parse() {
+                Ok(n) => Some(n),
+                Err(_) => {
+                    warn!("line {}: ignoring non-numeric receipt number '{}'", line, s);
+                    None
+                }
+            }),
+            currency: get_optional(c_currency).map(|s| s.to_uppercase()),
+            exchange_rate: c_exchange_rate
+                .and_then(|c| get_optional(Some(c)))
+                .map(|s| parse_number(mapping, s))
+                .transpose()
+                .map_err(|e| format!("line {}: exchange rate: {}", line, e))?,
+            savings_plan_id: None,
+        });
+    }
+
+    Ok(ts)
+}
+
+// removes transactions whose receipt number exists (like receipts::parse_mem does),
+// or, if they have none, that match an existing transaction in account, isin, date and amount.
+fn remove_existing(
+    connection: &PgConnection,
+    accs: &[Account],
+    ts: Vec<NewTransaction>,
+) -> Result<Vec<NewTransaction>, Box<dyn Error>> {
+    let account_ids = accs.iter().map(|a| a.id).collect::<Vec<_>>();
+    let ex_receipt_numbers = receipts::existing_receipt_numbers(
+        connection,
+        &account_ids,
+        &ts.iter().flat_map(|t| t.receipt_number).collect::<Vec<_>>(),
+    )?;
+
+    let isins = ts.iter().map(|t| t.isin.clone()).collect::<Vec<_>>();
+    let existing = transactions::table
+        .filter(transactions::account_id.eq_any(&account_ids))
+        .filter(transactions::isin.eq_any(&isins))
+        .load::<Transaction>(connection)?;
+
+    Ok(without_existing(ts, &ex_receipt_numbers, &existing))
+}
+
+fn without_existing(
+    ts: Vec<NewTransaction>,
+    ex_receipt_numbers: &[i64],
+    existing: &[Transaction],
+) -> Vec<NewTransaction> {
+    let orig_len = ts.len();
+    let ts = ts
+        .into_iter()
+        .filter(|t| match t.receipt_number {
+            Some(n) => !ex_receipt_numbers.contains(&n),
+            None => !existing.iter().any(|e| {
+                e.account_id == t.account_id
+                    && e.isin == t.isin
+                    && e.date == t.date
+                    && e.amount == t.amount
+            }),
+        })
+        .collect::<Vec<_>>();
+
+    if ts.len() < orig_len {
+        warn!("Removed {} of the parsed transactions from the list because they already exist in the database", orig_len - ts.len());
+    }
+
+    ts
+}
+
+fn parse_number(mapping: &CsvMapping, s: &str) -> Result<f64, Box<dyn Error>> {
+    let s = s.replace(' ', "");
+    let s = if mapping.decimal_comma {
+        s.replace(".", "").replace(",", ".")
+    } else {
+        s.replace(",", "")
+    };
+
+    Ok(s.trim_end_matches(|c: char| c.is_alphabetic() || c == '€')
+        .trim()
+        .parse()?)
+}
+
+fn parse_cents(mapping: &CsvMapping, s: &str) -> Result<i64, Box<dyn Error>> {
+    Ok((parse_number(mapping, s)? * 100.0).round() as i64)
+}
+
+// dates without time are interpreted as midnight in local time, like receipts
+fn parse_date(mapping: &CsvMapping, s: &str) -> Result<chrono::DateTime<Utc>, Box<dyn Error>> {
+    let date = match NaiveDateTime::parse_from_str(s, &mapping.date_format) {
+        Ok(d) => d,
+        Err(_) => NaiveDate::parse_from_str(s, &mapping.date_format)?.and_hms(0, 0, 0),
+    };
+
+    Ok(Local
+        .from_local_datetime(&date)
+        .earliest()
+        .ok_or("invalid local time")?
+        .with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(decimal_comma: bool, date_format: &str) -> CsvMapping {
+        CsvMapping {
+            delimiter: ';',
+            decimal_comma,
+            date_format: date_format.to_string(),
+            skip_lines: 0,
+            columns: CsvColumns {
+                isin: "ISIN".to_string(),
+                date: "Datum".to_string(),
+                units: "Stück".to_string(),
+                amount: "Betrag".to_string(),
+                fees: Some("Gebühren".to_string()),
+                comments: None,
+                exchange: None,
+                receipt_number: Some("Referenz".to_string()),
+                account: Some("Depot".to_string()),
+                currency: None,
+                exchange_rate: None,
+            },
+            negate_amount: false,
+            negate_fees: true,
+            units_sign_from_amount: true,
+            account_id: None,
+            broker: Some("comdirect".to_string()),
+        }
+    }
+
+    fn account(id: i32, depot_number: &str) -> Account {
+        Account {
+            id,
+            user_id: 1,
+            name: String::new(),
+            iban: None,
+            depot_number: Some(depot_number.to_string()),
+            clearing_account_number: None,
+            broker: Some("comdirect".to_string()),
+            currency: "EUR".to_string(),
+        }
+    }
+
+    #[test]
+    fn numbers() {
+        let m = mapping(true, "%d.%m.%Y");
+        assert_eq!(parse_number(&m, "1.234,56").unwrap(), 1234.56);
+        assert_eq!(parse_number(&m, "-1.234.567,8 €").unwrap(), -1234567.8);
+        assert_eq!(parse_number(&m, "12,5 EUR").unwrap(), 12.5);
+        assert_eq!(parse_cents(&m, "0,07").unwrap(), 7);
+        assert!(parse_number(&m, "").is_err());
+
+        let m = mapping(false, "%d.%m.%Y");
+        assert_eq!(parse_number(&m, "1,234.56 USD").unwrap(), 1234.56);
+        assert_eq!(parse_number(&m, "1 234.5").unwrap(), 1234.5);
+    }
+
+    #[test]
+    fn dates() {
+        let midnight = |y, m, d| Local.ymd(y, m, d).and_hms(0, 0, 0).with_timezone(&Utc);
+
+        let m = mapping(true, "%d.%m.%Y");
+        assert_eq!(parse_date(&m, "03.05.2021").unwrap(), midnight(2021, 5, 3));
+        assert!(parse_date(&m, "2021-05-03").is_err());
+
+        let m = mapping(true, "%Y-%m-%d %H:%M:%S");
+        assert_eq!(
+            parse_date(&m, "2021-05-03 09:30:00").unwrap(),
+            Local.ymd(2021, 5, 3).and_hms(9, 30, 0).with_timezone(&Utc)
+        );
+    }
+
+    #[test]
+    fn rows() {
+        let data = "Depot;Datum;ISIN;Stück;Betrag;Gebühren;Referenz\n\
+                    111;03.05.2021;ie00b4l5y983;10;-700,00;1,50;123\n\
+                    222;04.05.2021;US0378331005;5;1.100,00;2,00;A-7\n";
+        let accs = vec![account(1, "111"), account(2, "222")];
+        let ts = parse(&mapping(true, "%d.%m.%Y"), &accs, data.as_bytes()).unwrap();
+
+        assert_eq!(ts.len(), 2);
+        assert_eq!(ts[0].account_id, 1);
+        assert_eq!(ts[0].isin, "IE00B4L5Y983");
+        assert_eq!(ts[0].units, 10.0);
+        assert_eq!(ts[0].amount, -70000);
+        assert_eq!(ts[0].fees, -150);
+        assert_eq!(ts[0].receipt_number, Some(123));
+
+        // a sale with positive units, the receipt number is not numeric
+        assert_eq!(ts[1].account_id, 2);
+        assert_eq!(ts[1].units, -5.0);
+        assert_eq!(ts[1].amount, 110000);
+        assert_eq!(ts[1].receipt_number, None);
+    }
+
+    #[test]
+    fn rows_with_errors() {
+        let accs = vec![account(1, "111")];
+        let m = mapping(true, "%d.%m.%Y");
+
+        let data = "Depot;Datum;ISIN;Stück;Betrag;Gebühren;Referenz\n\
+                    333;03.05.2021;IE00B4L5Y983;10;-700,00;1,50;123\n";
+        let e = parse(&m, &accs, data.as_bytes()).unwrap_err();
+        assert!(e.to_string().starts_with("line 2:"), "{}", e);
+
+        let data = "Depot;Datum;ISIN;Stück;Betrag\n";
+        let e = parse(&m, &accs, data.as_bytes()).unwrap_err();
+        assert_eq!(e.to_string(), "there is no column 'Gebühren'");
+    }
+
+    #[test]
+    fn existing_transactions() {
+        let accs = vec![account(1, "111")];
+        let data = "Depot;Datum;ISIN;Stück;Betrag;Gebühren;Referenz\n\
+                    111;03.05.2021;IE00B4L5Y983;10;-700,00;1,50;123\n\
+                    111;04.05.2021;IE00B4L5Y983;10;-710,00;1,50;\n\
+                    111;05.05.2021;IE00B4L5Y983;10;-720,00;1,50;\n";
+        let ts = parse(&mapping(true, "%d.%m.%Y"), &accs, data.as_bytes()).unwrap();
+
+        let existing = Transaction {
+            id: 1,
+            account_id: 1,
+            isin: ts[1].isin.clone(),
+            date: ts[1].date,
+            units: ts[1].units,
+            amount: ts[1].amount,
+            fees: 0,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: None,
+        };
+        let ts = without_existing(ts, &[123], &[existing]);
+
+        assert_eq!(ts.len(), 1);
+        assert_eq!(ts[0].amount, -72000);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::models::*;
+use crate::schema::{accounts, distributions, transactions};
+use crate::validation::{FieldError, ValidationErrors};
+
+use chrono::{Datelike, Local};
+use diesel::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// a distribution together with the transaction it was booked as
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DistributionInfo {
+    pub distribution: Distribution,
+    pub isin: String,
+    pub account_id: i32,
+    pub gross: f64, // amount_per_unit * units, in the distribution's currency
+    pub net: f64,   // amount of the transaction, in the account's currency
+}
+
+// distributions of a stock in a year
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DistributionSummary {
+    pub isin: String,
+    pub year: i32,
+    pub count: usize,
+    pub amount_per_unit: f64, // sum of the gross amounts per unit
+    pub currency: String,
+    pub taxes: f64,
+    pub net: f64,
+}
+
+pub fn check(t: &Transaction, d: &NewDistribution) -> Result<(), ValidationErrors> {
+    let mut errors = Vec::new();
+    let mut error = |field: &str, message: &str| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        })
+    };
+
+    if t.units != 0.0 {
+        error("transactionId", "is not a dividend or distribution");
+    }
+    if !d.amount_per_unit.is_finite() || d.amount_per_unit < 0.0 {
+        error("amountPerUnit", "must not be negative");
+    }
+    if !d.units.is_finite() || d.units <= 0.0 {
+        error("units", "must be positive");
+    }
+    if d.currency.len() != 3 || !d.currency.chars().all(|c| c.is_ascii_uppercase()) {
+        error("currency", "must be a three-letter code (e.g. USD)");
+    }
+    if let Some(r) = d.exchange_rate {
+        if !r.is_finite() || r <= 0.0 {
+            error("exchangeRate", "must be positive");
+        }
+    }
+    if d.taxes > 0 {
+        error("taxes", "must not be positive");
+    }
+    if d.ex_date.map(|e| e > d.pay_date).unwrap_or(false) {
+        error("exDate", "must not be after the pay date");
+    }
+    if let (Some(s), Some(e)) = (d.period_start, d.period_end) {
+        if s > e {
+            error("periodEnd", "must not be before the start of the period");
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors { errors })
+    }
+}
+
+// insert or replace the distribution of a transaction (the transaction id of `d` is ignored)
+pub fn save(
+    connection: &PgConnection,
+    t: &Transaction,
+    d: &NewDistribution,
+) -> Result<Distribution, Box<dyn Error>> {
+    check(t, d)?;
+
+    let d = NewDistribution {
+        transaction_id: t.id,
+        ..d.clone()
+    };
+    let result = diesel::insert_into(distributions::table)
+        .values(&d)
+        .on_conflict(distributions::transaction_id)
+        .do_update()
+        .set(&d)
+        .get_result::<Distribution>(connection)?;
+    info!("Saved distribution {:?}", result);
+
+    Ok(result)
+}
+
+pub fn find(
+    connection: &PgConnection,
+    transaction_id: i32,
+) -> Result<Option<Distribution>, Box<dyn Error>> {
+    Ok(distributions::table
+        .filter(distributions::transaction_id.eq(transaction_id))
+        .first::<Distribution>(connection)
+        .optional()?)
+}
+
+// distributions of a user, newest first, optionally restricted to a stock or a year (of the pay date)
+pub fn list(
+    connection: &PgConnection,
+    uid: i32,
+    isin: Option<&str>,
+    year: Option<i32>,
+) -> Result<Vec<DistributionInfo>, Box<dyn Error>> {
+    let mut query = distributions::table
+        .inner_join(transactions::table.inner_join(accounts::table))
+        .filter(accounts::user_id.eq(uid))
+        .select((distributions::all_columns, transactions::all_columns))
+        .order(distributions::pay_date.desc())
+        .into_boxed();
+    if let Some(isin) = isin {
+        query = query.filter(transactions::isin.eq(isin.to_string()));
+    }
+
+    Ok(query
+        .load::<(Distribution, Transaction)>(connection)?
+        .into_iter()
+        .filter(|(d, _)| year.map(|y| d.pay_date.year() == y).unwrap_or(true))
+        .map(|(d, t)| DistributionInfo {
+            gross: d.amount_per_unit * d.units,
+            net: t.amount as f64 / 100.0,
+            isin: t.isin,
+            account_id: t.account_id,
+            distribution: d,
+        })
+        .collect())
+}
+
+// totals per stock and year (of the pay date)
+pub fn summarize(ds: &[DistributionInfo]) -> Vec<DistributionSummary> {
+    let mut result: BTreeMap<(String, i32), DistributionSummary> = BTreeMap::new();
+
+    for d in ds.iter() {
+        let year = d.distribution.pay_date.year();
+        let s = result
+            .entry((d.isin.clone(), year))
+            .or_insert_with(|| DistributionSummary {
+                isin: d.isin.clone(),
+                year,
+                count: 0,
+                amount_per_unit: 0.0,
+                currency: d.distribution.currency.clone(),
+                taxes: 0.0,
+                net: 0.0,
+            });
+
+        s.count += 1;
+        s.amount_per_unit += d.distribution.amount_per_unit;
+        s.taxes += d.distribution.taxes as f64 / 100.0;
+        s.net += d.net;
+    }
+
+    result.into_values().collect()
+}
+
+// the pay date of a transaction as used for distributions
+pub fn pay_date(t: &Transaction) -> chrono::NaiveDate {
+    t.date.with_timezone(&Local).date().naive_local()
+}
@@ -0,0 +1,172 @@
+use crate::models::*;
+use crate::schema::fee_items;
+use crate::validation::{FieldError, ValidationErrors};
+
+use diesel::prelude::*;
+use log::info;
+use std::error::Error;
+
+pub const COMMISSION: &str = "commission"; // broker's order fee
+pub const EXCHANGE: &str = "exchange"; // fees of the trading venue
+pub const THIRD_PARTY: &str = "thirdParty"; // e.g. brokerage of the Makler, foreign fees
+pub const FX_SPREAD: &str = "fxSpread"; // costs of the currency conversion
+pub const OTHER: &str = "other";
+pub const CATEGORIES: [&str; 5] = [COMMISSION, EXCHANGE, THIRD_PARTY, FX_SPREAD, OTHER];
+
+// the part of Transaction.fees that is not itemised
+pub const UNSPECIFIED: &str = "unspecified";
+
+// category of a fee from its label on a receipt (e.g. "Orderprovision", "Handelsplatzgebühr")
+pub fn classify(label: &str) -> &'static str {
+    let l = label.to_lowercase();
+
+    if l.contains("provision") && !l.contains("makler") {
+        COMMISSION
+    } else if l.contains("handelsplatz")
+        || l.contains("börse")
+        || l.contains("xetra")
+        || l.contains("transaktionsentgelt")
+    {
+        EXCHANGE
+    } else if l.contains("fremde") || l.contains("courtage") || l.contains("makler") {
+        THIRD_PARTY
+    } else if l.contains("devisen") || l.contains("umrechnung") || l.contains("währung") {
+        FX_SPREAD
+    } else {
+        OTHER
+    }
+}
+
+// fee items have to be negative and must not exceed the fees of their transaction
+pub fn check(t: &Transaction, items: &[NewFeeItem]) -> Result<(), ValidationErrors> {
+    let mut errors = Vec::new();
+    let mut error = |field: String, message: &str| {
+        errors.push(FieldError {
+            field,
+            message: message.to_string(),
+        })
+    };
+
+    for (i, item) in items.iter().enumerate() {
+        if !CATEGORIES.contains(&item.category.as_str()) {
+            error(format!("feeItems[{}].category", i), "unknown category");
+        }
+        if item.amount > 0 {
+            error(format!("feeItems[{}].amount", i), "must not be positive");
+        }
+    }
+    if items.iter().map(|i| i.amount).sum::<i64>() < t.fees {
+        error(
+            "feeItems".to_string(),
+            "must not add up to more than the fees of the transaction",
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors { errors })
+    }
+}
+
+pub fn list(
+    connection: &PgConnection,
+    transaction_ids: &[i32],
+) -> Result<Vec<FeeItem>, Box<dyn Error>> {
+    Ok(fee_items::table
+        .filter(fee_items::transaction_id.eq_any(transaction_ids))
+        .order(fee_items::id.asc())
+        .load::<FeeItem>(connection)?)
+}
+
+// replace the fee items of a transaction (the transaction ids of `items` are ignored)
+pub fn replace(
+    connection: &PgConnection,
+    t: &Transaction,
+    items: &[NewFeeItem],
+) -> Result<Vec<FeeItem>, Box<dyn Error>> {
+    check(t, items)?;
+
+    let items = items
+        .iter()
+        .map(|i| NewFeeItem {
+            transaction_id: t.id,
+            ..i.clone()
+        })
+        .collect::<Vec<_>>();
+
+    connection.transaction::<_, Box<dyn Error>, _>(|| {
+        diesel::delete(fee_items::table.filter(fee_items::transaction_id.eq(t.id)))
+            .execute(connection)?;
+        let result = diesel::insert_into(fee_items::table)
+            .values(&items)
+            .load::<FeeItem>(connection)?;
+        info!("Set {} fee item(s) of transaction {}", result.len(), t.id);
+
+        Ok(result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn item(category: &str, amount: i64) -> NewFeeItem {
+        NewFeeItem {
+            transaction_id: 0,
+            category: category.to_string(),
+            amount,
+            description: String::new(),
+        }
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(classify("Orderprovision"), COMMISSION);
+        assert_eq!(classify("Handelsplatzgebühr"), EXCHANGE);
+        assert_eq!(classify("Maklerprovision"), THIRD_PARTY);
+        assert_eq!(classify("Fremde Spesen"), THIRD_PARTY);
+        assert_eq!(classify("Währungsumrechnung"), FX_SPREAD);
+        assert_eq!(classify("Porto"), OTHER);
+    }
+
+    #[test]
+    fn items() {
+        let t = Transaction {
+            id: 1,
+            account_id: 1,
+            isin: "IE00B4L5Y983".to_string(),
+            date: Utc::now(),
+            units: 10.0,
+            amount: -100000,
+            fees: -1000,
+            onvista_exchange_id: None,
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
+            synthetic: None,
+        };
+        assert!(check(&t, &[item(COMMISSION, -750), item(EXCHANGE, -250)]).is_ok());
+
+        let fields = |items: &[NewFeeItem]| {
+            check(&t, items)
+                .unwrap_err()
+                .errors
+                .into_iter()
+                .map(|e| e.field)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            fields(&[item("brokerage", -100), item(OTHER, 100)]),
+            vec!["feeItems[0].category", "feeItems[1].amount"]
+        );
+        assert_eq!(
+            fields(&[item(COMMISSION, -750), item(EXCHANGE, -251)]),
+            vec!["feeItems"]
+        );
+    }
+}
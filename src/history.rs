@@ -0,0 +1,744 @@
+use crate::models::*;
+use crate::schema::{
+    accounts, cash_transactions, changes, distributions, fee_items, savings_plans,
+    transaction_tags, transactions, transfers,
+};
+
+use diesel::prelude::*;
+use log::info;
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+
+pub const WEB: &str = "web";
+pub const CLI: &str = "cli";
+pub const RECEIPT: &str = "receipt";
+pub const IMPORT: &str = "import";
+
+pub const INSERT: &str = "insert";
+pub const UPDATE: &str = "update";
+pub const DELETE: &str = "delete";
+
+// records that can be stored in a change
+pub trait Recorded: Serialize + DeserializeOwned + Sized {
+    const TABLE: &'static str;
+
+    fn record_id(&self) -> i32;
+    // insert the record again with its original id
+    fn restore(&self, connection: &PgConnection) -> QueryResult<usize>;
+}
+
+// records whose changes are tracked and can be reverted
+pub trait Audited: Recorded {
+    fn load(connection: &PgConnection, id: i32) -> QueryResult<Option<Self>>;
+    fn remove(connection: &PgConnection, id: i32) -> QueryResult<usize>;
+    // write all columns, including the ones that are NULL
+    fn overwrite(&self, connection: &PgConnection) -> QueryResult<usize>;
+    // record the rows that ON DELETE CASCADE removes along with this one as children of its deletion
+    fn record_dependents(
+        &self,
+        connection: &PgConnection,
+        user_id: i32,
+        source: &str,
+        parent_id: i32,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+// rows that are only deleted along with an audited record and restored when that is reverted
+pub trait Dependent: Recorded {
+    fn remove_row(&self, connection: &PgConnection) -> QueryResult<usize>;
+}
+
+impl Recorded for Transaction {
+    const TABLE: &'static str = "transactions";
+
+    fn record_id(&self) -> i32 {
+        self.id
+    }
+
+    fn restore(&self, connection: &PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(transactions::table)
+            .values(self)
+            .execute(connection)
+    }
+}
+
+impl Audited for Transaction {
+    fn load(connection: &PgConnection, id: i32) -> QueryResult<Option<Transaction>> {
+        transactions::table
+            .find(id)
+            .first::<Transaction>(connection)
+            .optional()
+    }
+
+    fn remove(connection: &PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::delete(transactions::table.find(id)).execute(connection)
+    }
+
+    fn overwrite(&self, connection: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::transactions::dsl::*;
+
+        diesel::update(transactions.find(self.id))
+            .set((
+                account_id.eq(self.account_id),
+                isin.eq(&self.isin),
+                date.eq(self.date),
+                units.eq(self.units),
+                amount.eq(self.amount),
+                fees.eq(self.fees),
+                onvista_exchange_id.eq(self.onvista_exchange_id),
+                comments.eq(&self.comments),
+                exchange.eq(&self.exchange),
+                receipt_number.eq(self.receipt_number),
+                currency.eq(&self.currency),
+                exchange_rate.eq(self.exchange_rate),
+                savings_plan_id.eq(self.savings_plan_id),
+            ))
+            .execute(connection)
+    }
+
+    fn record_dependents(
+        &self,
+        connection: &PgConnection,
+        user_id: i32,
+        source: &str,
+        parent_id: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        let items = fee_items::table
+            .filter(fee_items::transaction_id.eq(self.id))
+            .order(fee_items::id)
+            .load::<FeeItem>(connection)?;
+        dependents_deleted(connection, user_id, source, &items, parent_id)?;
+        let ds = distributions::table
+            .filter(distributions::transaction_id.eq(self.id))
+            .load::<Distribution>(connection)?;
+        dependents_deleted(connection, user_id, source, &ds, parent_id)?;
+        let tags = transaction_tags::table
+            .filter(transaction_tags::transaction_id.eq(self.id))
+            .load::<TransactionTag>(connection)?;
+        dependents_deleted(connection, user_id, source, &tags, parent_id)
+    }
+}
+
+impl Recorded for Account {
+    const TABLE: &'static str = "accounts";
+
+    fn record_id(&self) -> i32 {
+        self.id
+    }
+
+    fn restore(&self, connection: &PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(accounts::table)
+            .values(self)
+            .execute(connection)
+    }
+}
+
+impl Audited for Account {
+    fn load(connection: &PgConnection, id: i32) -> QueryResult<Option<Account>> {
+        accounts::table
+            .find(id)
+            .first::<Account>(connection)
+            .optional()
+    }
+
+    fn remove(connection: &PgConnection, id: i32) -> QueryResult<usize> {
+        diesel::delete(accounts::table.find(id)).execute(connection)
+    }
+
+    fn overwrite(&self, connection: &PgConnection) -> QueryResult<usize> {
+        use crate::schema::accounts::dsl::*;
+
+        diesel::update(accounts.find(self.id))
+            .set((
+                user_id.eq(self.user_id),
+                name.eq(&self.name),
+                iban.eq(&self.iban),
+                depot_number.eq(&self.depot_number),
+                clearing_account_number.eq(&self.clearing_account_number),
+                broker.eq(&self.broker),
+                currency.eq(&self.currency),
+            ))
+            .execute(connection)
+    }
+
+    fn record_dependents(
+        &self,
+        connection: &PgConnection,
+        user_id: i32,
+        source: &str,
+        parent_id: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        // savings plans first, they are referenced by the transactions when restoring
+        let plans = savings_plans::table
+            .filter(savings_plans::account_id.eq(self.id))
+            .order(savings_plans::id)
+            .load::<SavingsPlan>(connection)?;
+        dependents_deleted(connection, user_id, source, &plans, parent_id)?;
+        let ts = transactions::table
+            .filter(transactions::account_id.eq(self.id))
+            .order(transactions::id)
+            .load::<Transaction>(connection)?;
+        for t in ts.iter() {
+            let c = record(
+                connection,
+                user_id,
+                source,
+                DELETE,
+                t.id,
+                Some(t),
+                None,
+                Some(parent_id),
+            )?;
+            t.record_dependents(connection, user_id, source, c.id)?;
+        }
+        let cts = cash_transactions::table
+            .filter(cash_transactions::account_id.eq(self.id))
+            .order(cash_transactions::id)
+            .load::<CashTransaction>(connection)?;
+        dependents_deleted(connection, user_id, source, &cts, parent_id)?;
+        let trs = transfers::table
+            .filter(
+                transfers::from_account_id
+                    .eq(self.id)
+                    .or(transfers::to_account_id.eq(self.id)),
+            )
+            .order(transfers::id)
+            .load::<Transfer>(connection)?;
+        dependents_deleted(connection, user_id, source, &trs, parent_id)
+    }
+}
+
+macro_rules! dependent {
+    ($t:ty, $table:ident) => {
+        impl Recorded for $t {
+            const TABLE: &'static str = stringify!($table);
+
+            fn record_id(&self) -> i32 {
+                self.id
+            }
+
+            fn restore(&self, connection: &PgConnection) -> QueryResult<usize> {
+                diesel::insert_into($table::table)
+                    .values(self)
+                    .execute(connection)
+            }
+        }
+
+        impl Dependent for $t {
+            fn remove_row(&self, connection: &PgConnection) -> QueryResult<usize> {
+                diesel::delete($table::table.find(self.id)).execute(connection)
+            }
+        }
+    };
+}
+
+dependent!(FeeItem, fee_items);
+dependent!(Distribution, distributions);
+dependent!(CashTransaction, cash_transactions);
+dependent!(Transfer, transfers);
+dependent!(SavingsPlan, savings_plans);
+
+impl Recorded for TransactionTag {
+    const TABLE: &'static str = "transaction_tags";
+
+    // there is no id of its own
+    fn record_id(&self) -> i32 {
+        self.transaction_id
+    }
+
+    fn restore(&self, connection: &PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(transaction_tags::table)
+            .values(self)
+            .execute(connection)
+    }
+}
+
+impl Dependent for TransactionTag {
+    fn remove_row(&self, connection: &PgConnection) -> QueryResult<usize> {
+        diesel::delete(
+            transaction_tags::table
+                .filter(transaction_tags::transaction_id.eq(self.transaction_id))
+                .filter(transaction_tags::tag_id.eq(self.tag_id)),
+        )
+        .execute(connection)
+    }
+}
+
+fn to_json<T: Recorded>(record: Option<&T>) -> Result<Option<String>, Box<dyn Error>> {
+    Ok(record.map(serde_json::to_string).transpose()?)
+}
+
+fn from_json<T: Recorded>(s: &Option<String>) -> Result<Option<T>, Box<dyn Error>> {
+    Ok(s.as_deref().map(serde_json::from_str).transpose()?)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn record<T: Recorded>(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    action: &str,
+    record_id: i32,
+    before: Option<&T>,
+    after: Option<&T>,
+    parent_id: Option<i32>,
+) -> Result<Change, Box<dyn Error>> {
+    Ok(diesel::insert_into(changes::table)
+        .values(&NewChange {
+            user_id,
+            source: source.to_string(),
+            table_name: T::TABLE.to_string(),
+            record_id,
+            action: action.to_string(),
+            before: to_json(before)?,
+            after: to_json(after)?,
+            parent_id,
+        })
+        .get_result::<Change>(connection)?)
+}
+
+pub fn inserted<T: Audited>(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    records: &[T],
+) -> Result<(), Box<dyn Error>> {
+    for r in records.iter() {
+        record(
+            connection,
+            user_id,
+            source,
+            INSERT,
+            r.record_id(),
+            None,
+            Some(r),
+            None,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn updated<T: Audited>(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    before: &T,
+    after: &T,
+) -> Result<Change, Box<dyn Error>> {
+    record(
+        connection,
+        user_id,
+        source,
+        UPDATE,
+        after.record_id(),
+        Some(before),
+        Some(after),
+        None,
+    )
+}
+
+// has to be called before the record is deleted, so that the rows removed along with it can be recorded
+pub fn deleted<T: Audited>(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    before: &T,
+) -> Result<Change, Box<dyn Error>> {
+    let c = record(
+        connection,
+        user_id,
+        source,
+        DELETE,
+        before.record_id(),
+        Some(before),
+        None,
+        None,
+    )?;
+    before.record_dependents(connection, user_id, source, c.id)?;
+
+    Ok(c)
+}
+
+fn dependents_deleted<T: Dependent>(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    records: &[T],
+    parent_id: i32,
+) -> Result<(), Box<dyn Error>> {
+    for r in records.iter() {
+        record(
+            connection,
+            user_id,
+            source,
+            DELETE,
+            r.record_id(),
+            Some(r),
+            None,
+            Some(parent_id),
+        )?;
+    }
+
+    Ok(())
+}
+
+// changes of a user, newest first (optionally only the ones of a single record)
+pub fn list(
+    connection: &PgConnection,
+    user_id: i32,
+    table_name: Option<&str>,
+    record_id: Option<i32>,
+) -> Result<Vec<Change>, Box<dyn Error>> {
+    let mut query = changes::table
+        .filter(changes::user_id.eq(user_id))
+        .order((changes::date.desc(), changes::id.desc()))
+        .into_boxed();
+
+    if let Some(t) = table_name {
+        query = query.filter(changes::table_name.eq(t.to_string()));
+    }
+    if let Some(id) = record_id {
+        query = query.filter(changes::record_id.eq(id));
+    }
+
+    Ok(query.load::<Change>(connection)?)
+}
+
+// undo a change (and the ones that happened along with it); the revert itself is recorded as a new change
+pub fn revert(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    change_id: i32,
+) -> Result<Change, Box<dyn Error>> {
+    connection.transaction::<_, Box<dyn Error>, _>(|| {
+        let c = changes::table
+            .find(change_id)
+            .filter(changes::user_id.eq(user_id))
+            .first::<Change>(connection)
+            .optional()?
+            .ok_or_else(|| format!("there is no change with id {}", change_id))?;
+
+        let result = revert_change(connection, source, &c, None)?;
+        info!("Reverted change {} by change {}", c.id, result.id);
+
+        Ok(result)
+    })
+}
+
+fn revert_change(
+    connection: &PgConnection,
+    source: &str,
+    c: &Change,
+    parent_id: Option<i32>,
+) -> Result<Change, Box<dyn Error>> {
+    if let Some(r) = c.reverted_by {
+        return Err(format!("change {} has already been reverted by change {}", c.id, r).into());
+    }
+
+    // records that were deleted along with this one; they are restored after their parent,
+    // but have to be removed before it
+    let children = changes::table
+        .filter(changes::parent_id.eq(c.id))
+        .filter(changes::reverted_by.is_null())
+        .order(changes::id.asc())
+        .load::<Change>(connection)?;
+    let children_first = c.action == INSERT;
+
+    let mut reverted_children = Vec::new();
+    if children_first {
+        for child in children.iter() {
+            reverted_children.push(revert_change(connection, source, child, None)?.id);
+        }
+    }
+
+    let result = match c.table_name.as_str() {
+        "transactions" => revert_as::<Transaction>(connection, source, c, parent_id)?,
+        "accounts" => {
+            // transactions that were added to the account afterwards would be deleted along with it
+            // without a record, so they have to be removed first
+            if c.action == INSERT {
+                let count = transactions::table
+                    .filter(transactions::account_id.eq(c.record_id))
+                    .count()
+                    .get_result::<i64>(connection)?;
+                if count > 0 {
+                    return Err(format!(
+                        "account {} still has {} transactions",
+                        c.record_id, count
+                    )
+                    .into());
+                }
+            }
+            revert_as::<Account>(connection, source, c, parent_id)?
+        }
+        "fee_items" => revert_dependent::<FeeItem>(connection, source, c, parent_id)?,
+        "distributions" => revert_dependent::<Distribution>(connection, source, c, parent_id)?,
+        "transaction_tags" => revert_dependent::<TransactionTag>(connection, source, c, parent_id)?,
+        "cash_transactions" => {
+            revert_dependent::<CashTransaction>(connection, source, c, parent_id)?
+        }
+        "transfers" => revert_dependent::<Transfer>(connection, source, c, parent_id)?,
+        "savings_plans" => revert_dependent::<SavingsPlan>(connection, source, c, parent_id)?,
+        t => return Err(format!("changes of table '{}' cannot be reverted", t).into()),
+    };
+
+    diesel::update(changes::table.find(c.id))
+        .set(changes::reverted_by.eq(result.id))
+        .execute(connection)?;
+
+    if children_first {
+        diesel::update(changes::table.filter(changes::id.eq_any(reverted_children)))
+            .set(changes::parent_id.eq(result.id))
+            .execute(connection)?;
+    } else {
+        for child in children.iter() {
+            revert_change(connection, source, child, Some(result.id))?;
+        }
+    }
+
+    Ok(result)
+}
+
+fn does_not_exist<T: Recorded>(id: i32) -> String {
+    format!("{} {} does not exist", T::TABLE.trim_end_matches('s'), id)
+}
+
+fn revert_as<T: Audited>(
+    connection: &PgConnection,
+    source: &str,
+    c: &Change,
+    parent_id: Option<i32>,
+) -> Result<Change, Box<dyn Error>> {
+    let current = T::load(connection, c.record_id)?;
+    let missing = || format!("change {} does not contain the previous record", c.id);
+
+    match c.action.as_str() {
+        INSERT => {
+            let current = current.ok_or_else(|| does_not_exist::<T>(c.record_id))?;
+            let result = record(
+                connection,
+                c.user_id,
+                source,
+                DELETE,
+                c.record_id,
+                Some(&current),
+                None,
+                parent_id,
+            )?;
+            current.record_dependents(connection, c.user_id, source, result.id)?;
+            T::remove(connection, c.record_id)?;
+            Ok(result)
+        }
+        UPDATE => {
+            let before = from_json::<T>(&c.before)?.ok_or_else(missing)?;
+            let current = current.ok_or_else(|| does_not_exist::<T>(c.record_id))?;
+            // the record must not have been changed since, otherwise those changes would be lost
+            let after = c.after.as_deref().map(serde_json::from_str).transpose()?;
+            if Some(serde_json::to_value(&current)?) != after {
+                return Err(format!(
+                    "{} {} has been changed since change {}",
+                    T::TABLE.trim_end_matches('s'),
+                    c.record_id,
+                    c.id
+                )
+                .into());
+            }
+            before.overwrite(connection)?;
+            record(
+                connection,
+                c.user_id,
+                source,
+                UPDATE,
+                c.record_id,
+                Some(&current),
+                Some(&before),
+                parent_id,
+            )
+        }
+        DELETE => {
+            let before = from_json::<T>(&c.before)?.ok_or_else(missing)?;
+            if current.is_some() {
+                return Err(format!(
+                    "{} {} exists already",
+                    T::TABLE.trim_end_matches('s'),
+                    c.record_id
+                )
+                .into());
+            }
+            before.restore(connection)?;
+            record(
+                connection,
+                c.user_id,
+                source,
+                INSERT,
+                c.record_id,
+                None,
+                Some(&before),
+                parent_id,
+            )
+        }
+        a => Err(format!("unknown action '{}'", a).into()),
+    }
+}
+
+// dependent rows are only recorded along with their parent, so only deletions and their reverts occur
+fn revert_dependent<T: Dependent>(
+    connection: &PgConnection,
+    source: &str,
+    c: &Change,
+    parent_id: Option<i32>,
+) -> Result<Change, Box<dyn Error>> {
+    let missing = || format!("change {} does not contain the record", c.id);
+
+    match c.action.as_str() {
+        INSERT => {
+            let after = from_json::<T>(&c.after)?.ok_or_else(missing)?;
+            after.remove_row(connection)?;
+            record(
+                connection,
+                c.user_id,
+                source,
+                DELETE,
+                c.record_id,
+                Some(&after),
+                None,
+                parent_id,
+            )
+        }
+        DELETE => {
+            let before = from_json::<T>(&c.before)?.ok_or_else(missing)?;
+            before.restore(connection)?;
+            record(
+                connection,
+                c.user_id,
+                source,
+                INSERT,
+                c.record_id,
+                None,
+                Some(&before),
+                parent_id,
+            )
+        }
+        a => Err(format!(
+            "changes of table '{}' with action '{}' cannot be reverted",
+            T::TABLE,
+            a
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{tags, users};
+    use chrono::Utc;
+
+    // needs a database: DATABASE_URL=postgres://... cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn reverting_a_deletion_restores_fee_items_and_tags() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL is not set");
+        let connection = crate::connect(&url).unwrap();
+
+        connection.test_transaction::<_, Box<dyn Error>, _>(|| {
+            let u = diesel::insert_into(users::table)
+                .values(&NewUser {
+                    name: "history-test".to_string(),
+                    full_name: String::new(),
+                    hash: String::new(),
+                    reporting_currency: "EUR".to_string(),
+                })
+                .get_result::<User>(&connection)?;
+            let a = diesel::insert_into(accounts::table)
+                .values(&NewAccount {
+                    user_id: u.id,
+                    name: "depot".to_string(),
+                    iban: None,
+                    depot_number: None,
+                    clearing_account_number: None,
+                    broker: None,
+                    currency: "EUR".to_string(),
+                })
+                .get_result::<Account>(&connection)?;
+            let t = diesel::insert_into(transactions::table)
+                .values(&NewTransaction {
+                    account_id: a.id,
+                    isin: "IE00B4L5Y983".to_string(),
+                    date: Utc::now(),
+                    units: 10.0,
+                    amount: -70000,
+                    fees: -1000,
+                    onvista_exchange_id: None,
+                    comments: String::new(),
+                    exchange: None,
+                    receipt_number: None,
+                    currency: None,
+                    exchange_rate: None,
+                    savings_plan_id: None,
+                })
+                .get_result::<Transaction>(&connection)?;
+            let items = diesel::insert_into(fee_items::table)
+                .values(&vec![
+                    NewFeeItem {
+                        transaction_id: t.id,
+                        category: "commission".to_string(),
+                        amount: -750,
+                        description: String::new(),
+                    },
+                    NewFeeItem {
+                        transaction_id: t.id,
+                        category: "exchange".to_string(),
+                        amount: -250,
+                        description: String::new(),
+                    },
+                ])
+                .get_results::<FeeItem>(&connection)?;
+            let tag = diesel::insert_into(tags::table)
+                .values(&NewTag {
+                    user_id: u.id,
+                    name: "retirement".to_string(),
+                })
+                .get_result::<Tag>(&connection)?;
+            diesel::insert_into(transaction_tags::table)
+                .values(&TransactionTag {
+                    transaction_id: t.id,
+                    tag_id: tag.id,
+                })
+                .execute(&connection)?;
+
+            let c = deleted(&connection, u.id, CLI, &t)?;
+            diesel::delete(transactions::table.find(t.id)).execute(&connection)?;
+            let count = || {
+                fee_items::table
+                    .filter(fee_items::transaction_id.eq(t.id))
+                    .count()
+                    .get_result::<i64>(&connection)
+            };
+            assert_eq!(count()?, 0);
+
+            revert(&connection, u.id, CLI, c.id)?;
+            let restored = fee_items::table
+                .filter(fee_items::transaction_id.eq(t.id))
+                .order(fee_items::id)
+                .load::<FeeItem>(&connection)?;
+            assert_eq!(restored, items);
+            let tagged = transaction_tags::table
+                .filter(transaction_tags::transaction_id.eq(t.id))
+                .select(transaction_tags::tag_id)
+                .load::<i32>(&connection)?;
+            assert_eq!(tagged, vec![tag.id]);
+
+            // the same for the deletion of the account
+            let c = deleted(&connection, u.id, CLI, &a)?;
+            diesel::delete(accounts::table.find(a.id)).execute(&connection)?;
+            revert(&connection, u.id, CLI, c.id)?;
+            assert!(Transaction::load(&connection, t.id)?.is_some());
+            assert_eq!(count()?, 2);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,69 @@
+use crate::models::*;
+use crate::schema::stock_holdings;
+
+use diesel::prelude::*;
+use log::debug;
+use serde::Deserialize;
+use std::error::Error;
+
+#[derive(Deserialize)]
+struct Instrument {
+    name: String,
+    isin: Option<String>,
+}
+
+// entry of the serialized StockInfo.holdings
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    instrument: Instrument,
+    investment_pct: Option<f64>,
+}
+
+// holdings of a fund from its serialized list, largest first as given by onvista
+pub fn parse(info: &StockInfo) -> Vec<NewStockHolding> {
+    let entries = info
+        .holdings
+        .as_deref()
+        .and_then(|h| serde_json::from_str::<Vec<Entry>>(h).ok())
+        .unwrap_or_default();
+
+    entries
+        .into_iter()
+        .filter_map(|e| {
+            Some(NewStockHolding {
+                isin: info.isin.clone(),
+                name: e.instrument.name,
+                holding_isin: e.instrument.isin.filter(|i| !i.is_empty()),
+                weight: e.investment_pct.filter(|p| *p >= 0.0)? / 100.0,
+            })
+        })
+        .collect()
+}
+
+// replace the holdings of a stock with the ones of its (updated) info
+pub fn sync(connection: &PgConnection, info: &StockInfo) -> Result<usize, Box<dyn Error>> {
+    let hs = parse(info);
+
+    connection.transaction::<_, Box<dyn Error>, _>(|| {
+        diesel::delete(stock_holdings::table.filter(stock_holdings::isin.eq(&info.isin)))
+            .execute(connection)?;
+        let n = diesel::insert_into(stock_holdings::table)
+            .values(&hs)
+            .execute(connection)?;
+        debug!("Saved {} holding(s) of {}", n, info.isin);
+
+        Ok(n)
+    })
+}
+
+// holdings of the given funds, ordered by fund and weight
+pub fn load(
+    connection: &PgConnection,
+    isins: &[String],
+) -> Result<Vec<StockHolding>, Box<dyn Error>> {
+    Ok(stock_holdings::table
+        .filter(stock_holdings::isin.eq_any(isins))
+        .order((stock_holdings::isin.asc(), stock_holdings::weight.desc()))
+        .load::<StockHolding>(connection)?)
+}
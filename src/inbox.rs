@@ -0,0 +1,194 @@
+use crate::models::*;
+use crate::push;
+use crate::receipts;
+use crate::schema::push_subscriptions;
+
+use chrono::Local;
+use diesel::{
+    prelude::*,
+    r2d2::{ConnectionManager, Pool},
+};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+// directory that is watched for new receipts of a user;
+// imported files are moved to `processed/`, files that could not be imported to `failed/`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InboxConfig {
+    pub user_id: i32,
+    pub directory: String,
+}
+
+// files that were modified recently might still be written to by the sync client
+const MIN_AGE: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Summary {
+    files: usize,
+    transactions: usize,
+    failed: Vec<String>,
+}
+
+pub async fn process(
+    pool: Pool<ConnectionManager<PgConnection>>,
+    inbox: &InboxConfig,
+    private_key: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    // the database and the file system are used synchronously
+    let blocking_inbox = inbox.clone();
+    let notify = !private_key.is_empty();
+    let result = tokio::task::spawn_blocking(move || {
+        import(&pool, &blocking_inbox, notify).map_err(|e| e.to_string())
+    })
+    .await??;
+
+    if let Some((summary, subs)) = result {
+        if notify {
+            push::send_text_notifications(&subs, private_key, &summary.text()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// summary of an import and the push subscriptions to notify about it
+type Imported = (Summary, Vec<PushSubscription>);
+
+// import the new files of an inbox and load the push subscriptions of the user if `notify` is set;
+// nothing is returned if there were no new files
+fn import(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    inbox: &InboxConfig,
+    notify: bool,
+) -> Result<Option<Imported>, Box<dyn Error>> {
+    let dir = Path::new(&inbox.directory);
+    let files = find_new_files(dir)?;
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    info!(
+        "Found {} new file(s) in inbox {} of user {}",
+        files.len(),
+        &inbox.directory,
+        inbox.user_id
+    );
+
+    let connection = pool.get()?;
+    let mut summary = Summary::default();
+
+    for file in files.iter() {
+        let file_name = file
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let target = match receipts::parse_files(
+            &connection,
+            inbox.user_id,
+            &[&file.to_string_lossy()],
+            false,
+        ) {
+            Ok(ts) => {
+                info!("Imported {} transaction(s) from {}", ts.len(), &file_name);
+                summary.files += 1;
+                summary.transactions += ts.len();
+                "processed"
+            }
+            Err(e) => {
+                error!("Could not import {}: {}", &file_name, e);
+                summary.failed.push(file_name.clone());
+                "failed"
+            }
+        };
+
+        // the other files are still imported
+        if let Err(e) = move_file(file, &dir.join(target)) {
+            error!("Could not move {} to {}/: {}", &file_name, target, e);
+        }
+    }
+
+    let subs = if notify {
+        push_subscriptions::table
+            .filter(push_subscriptions::user_id.eq(inbox.user_id))
+            .load::<PushSubscription>(&connection)?
+    } else {
+        Vec::new()
+    };
+
+    Ok(Some((summary, subs)))
+}
+
+impl Summary {
+    fn text(&self) -> String {
+        let mut text = format!(
+            "{} Beleg(e) mit {} Transaktion(en) importiert",
+            self.files, self.transactions
+        );
+
+        if !self.failed.is_empty() {
+            text.push_str(&format!(
+                ", {} fehlgeschlagen: {}",
+                self.failed.len(),
+                self.failed.join(", ")
+            ));
+        }
+
+        text
+    }
+}
+
+fn find_new_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let now = SystemTime::now();
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+
+        let is_pdf = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.eq_ignore_ascii_case("pdf"))
+            .unwrap_or(false);
+        if !metadata.is_file() || !is_pdf {
+            continue;
+        }
+
+        let age = now
+            .duration_since(metadata.modified()?)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        if age < MIN_AGE {
+            debug!("Skipping {:?} because it was modified recently", &path);
+            continue;
+        }
+
+        files.push(path);
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn move_file(file: &Path, target_dir: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(target_dir)?;
+
+    let file_name = file.file_name().ok_or("invalid file name")?;
+    let mut target = target_dir.join(file_name);
+    if target.exists() {
+        warn!("{:?} already exists, appending a timestamp", &target);
+        target = target_dir.join(format!(
+            "{}-{}",
+            Local::now().format("%Y%m%d%H%M%S"),
+            file_name.to_string_lossy()
+        ));
+    }
+
+    fs::rename(file, &target)?;
+    Ok(())
+}
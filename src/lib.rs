@@ -1,12 +1,23 @@
 pub mod analysis;
+pub mod batch;
 pub mod cli;
+pub mod csv_import;
 pub mod data;
+pub mod distributions;
+pub mod fees;
+pub mod history;
+pub mod holdings;
+pub mod inbox;
 pub mod models;
 pub mod onvista;
 pub mod push;
 pub mod receipts;
+pub mod savings_plans;
 pub mod schema;
 pub mod serialization;
+pub mod tags;
+pub mod targets;
+pub mod validation;
 pub mod web;
 
 #[macro_use]
@@ -88,6 +99,7 @@ pub async fn add_missing_stocks(pool: Pool<ConnectionManager<PgConnection>>) {
                     .values(&si)
                     .execute(&connection)
                     .expect("Error saving stock info");
+                holdings::sync(&connection, &si).expect("Error saving holdings");
 
                 diesel::insert_into(crate::schema::stock_exchanges::table)
                     .values(&exs)
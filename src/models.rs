@@ -1,10 +1,15 @@
 use crate::schema::*;
 
 use chrono::{DateTime, NaiveDate, Utc};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_builder::AsChangeset;
 use serde::{Deserialize, Serialize};
 
 // grabbed periodically for relevant ISINs
-#[derive(Debug, Clone, Queryable, Insertable, Identifiable, Serialize, Deserialize, AsChangeset)]
+#[derive(
+    Debug, Clone, Queryable, Insertable, Identifiable, Serialize, Deserialize, AsChangeset,
+)]
 #[primary_key("isin")]
 #[serde(rename_all = "camelCase")]
 pub struct StockInfo {
@@ -87,6 +92,8 @@ pub struct User {
     pub name: String,
     pub full_name: String,
     pub hash: String,
+    #[serde(default = "default_currency")]
+    pub reporting_currency: String, // analyses convert all amounts into this currency
 }
 
 #[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
@@ -96,6 +103,8 @@ pub struct NewUser {
     pub name: String,
     pub full_name: String,
     pub hash: String,
+    #[serde(default = "default_currency")]
+    pub reporting_currency: String,
 }
 
 #[derive(
@@ -116,6 +125,48 @@ pub struct Account {
     pub user_id: i32,
     pub name: String,
     pub iban: Option<String>,
+    pub depot_number: Option<String>, // used to assign imported transactions to this account
+    pub clearing_account_number: Option<String>,
+    pub broker: Option<String>,
+    #[serde(default = "default_currency")]
+    pub currency: String, // of the clearing account
+}
+
+impl Account {
+    // find the account that imported data with the given identifiers belongs to;
+    // the depot number takes precedence, then the clearing account number (or, as a fallback, the end of the IBAN)
+    pub fn find<'a>(
+        accs: &'a [Account],
+        broker: &str,
+        depot_number: Option<&str>,
+        account_number: Option<&str>,
+    ) -> Option<&'a Account> {
+        let accs = accs
+            .iter()
+            .filter(|a| {
+                a.broker
+                    .as_ref()
+                    .map(|b| b.eq_ignore_ascii_case(broker))
+                    .unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(dn) = depot_number {
+            if let Some(a) = accs.iter().find(|a| a.depot_number.as_deref() == Some(dn)) {
+                return Some(a);
+            }
+        }
+
+        let an = account_number?;
+        accs.iter()
+            .filter(|a| a.depot_number.is_none() || depot_number.is_none())
+            .find(|a| match (&a.clearing_account_number, &a.iban) {
+                (Some(c), _) => c == an,
+                (None, Some(iban)) => iban.ends_with(an),
+                _ => false,
+            })
+            .copied()
+    }
 }
 
 #[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
@@ -125,6 +176,11 @@ pub struct NewAccount {
     pub user_id: i32,
     pub name: String,
     pub iban: Option<String>,
+    pub depot_number: Option<String>, // used to assign imported transactions to this account
+    pub clearing_account_number: Option<String>,
+    pub broker: Option<String>,
+    #[serde(default = "default_currency")]
+    pub currency: String,
 }
 
 #[derive(
@@ -143,17 +199,7 @@ pub struct PushSubscription {
     pub last_notification: Option<DateTime<Utc>>,
 }
 
-#[derive(
-    Debug,
-    Clone,
-    Queryable,
-    Insertable,
-    Associations,
-    Identifiable,
-    Serialize,
-    Deserialize,
-    AsChangeset,
-)]
+#[derive(Debug, Clone, Associations, Identifiable, Serialize, Deserialize, PartialEq)]
 #[belongs_to(Account, foreign_key = "account_id")]
 #[serde(rename_all = "camelCase")]
 pub struct Transaction {
@@ -168,6 +214,126 @@ pub struct Transaction {
     pub comments: String,
     pub exchange: Option<String>,
     pub receipt_number: Option<i64>,
+    pub currency: Option<String>, // of amount and fees, None -> currency of the account
+    pub exchange_rate: Option<f64>, // units of the account's currency per unit of `currency` at settlement
+    pub savings_plan_id: Option<i32>, // set for executions of a savings plan
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synthetic: Option<Synthetic>, // only set by analyses, never stored
+}
+
+// transactions that analyses derive from other records; their ids are negative, so they never clash
+// with stored transactions, but only unique within one kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Synthetic {
+    SpinOff,   // moves part of the cost basis from the parent to the spun-off stock
+    Transfer,  // moves lots between accounts
+    Benchmark, // purchase or sale of the benchmark in its shadow portfolio
+}
+
+// the columns of a transaction, Transaction is loaded and stored through it
+#[derive(Queryable, Insertable, AsChangeset)]
+#[table_name = "transactions"]
+pub struct TransactionRow {
+    id: i32,
+    account_id: i32,
+    isin: String,
+    date: DateTime<Utc>,
+    units: f64,
+    amount: i64,
+    fees: i64,
+    onvista_exchange_id: Option<i32>,
+    comments: String,
+    exchange: Option<String>,
+    receipt_number: Option<i64>,
+    currency: Option<String>,
+    exchange_rate: Option<f64>,
+    savings_plan_id: Option<i32>,
+}
+
+impl Transaction {
+    // transaction of a user, if it exists
+    pub fn find_owned(
+        connection: &PgConnection,
+        user_id: i32,
+        id: i32,
+    ) -> QueryResult<Option<Transaction>> {
+        transactions::table
+            .inner_join(accounts::table)
+            .filter(transactions::id.eq(id))
+            .filter(accounts::user_id.eq(user_id))
+            .select(transactions::all_columns)
+            .first::<Transaction>(connection)
+            .optional()
+    }
+}
+
+impl From<TransactionRow> for Transaction {
+    fn from(r: TransactionRow) -> Transaction {
+        Transaction {
+            id: r.id,
+            account_id: r.account_id,
+            isin: r.isin,
+            date: r.date,
+            units: r.units,
+            amount: r.amount,
+            fees: r.fees,
+            onvista_exchange_id: r.onvista_exchange_id,
+            comments: r.comments,
+            exchange: r.exchange,
+            receipt_number: r.receipt_number,
+            currency: r.currency,
+            exchange_rate: r.exchange_rate,
+            savings_plan_id: r.savings_plan_id,
+            synthetic: None,
+        }
+    }
+}
+
+impl From<&Transaction> for TransactionRow {
+    fn from(t: &Transaction) -> TransactionRow {
+        TransactionRow {
+            id: t.id,
+            account_id: t.account_id,
+            isin: t.isin.clone(),
+            date: t.date,
+            units: t.units,
+            amount: t.amount,
+            fees: t.fees,
+            onvista_exchange_id: t.onvista_exchange_id,
+            comments: t.comments.clone(),
+            exchange: t.exchange.clone(),
+            receipt_number: t.receipt_number,
+            currency: t.currency.clone(),
+            exchange_rate: t.exchange_rate,
+            savings_plan_id: t.savings_plan_id,
+        }
+    }
+}
+
+impl Queryable<transactions::SqlType, Pg> for Transaction {
+    type Row = <TransactionRow as Queryable<transactions::SqlType, Pg>>::Row;
+
+    fn build(row: Self::Row) -> Self {
+        <TransactionRow as Queryable<transactions::SqlType, Pg>>::build(row).into()
+    }
+}
+
+impl Insertable<transactions::table> for &Transaction {
+    type Values = <TransactionRow as Insertable<transactions::table>>::Values;
+
+    fn values(self) -> Self::Values {
+        TransactionRow::from(self).values()
+    }
+}
+
+impl AsChangeset for Transaction {
+    type Target = transactions::table;
+    type Changeset = <TransactionRow as AsChangeset>::Changeset;
+
+    fn as_changeset(self) -> Self::Changeset {
+        TransactionRow::from(&self).as_changeset()
+    }
 }
 
 #[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
@@ -184,4 +350,426 @@ pub struct NewTransaction {
     pub comments: String,
     pub exchange: Option<String>,
     pub receipt_number: Option<i64>,
+    pub currency: Option<String>,
+    pub exchange_rate: Option<f64>,
+    pub savings_plan_id: Option<i32>,
+}
+
+impl From<&Transaction> for NewTransaction {
+    fn from(t: &Transaction) -> NewTransaction {
+        NewTransaction {
+            account_id: t.account_id,
+            isin: t.isin.clone(),
+            date: t.date,
+            units: t.units,
+            amount: t.amount,
+            fees: t.fees,
+            onvista_exchange_id: t.onvista_exchange_id,
+            comments: t.comments.clone(),
+            exchange: t.exchange.clone(),
+            receipt_number: t.receipt_number,
+            currency: t.currency.clone(),
+            exchange_rate: t.exchange_rate,
+            savings_plan_id: t.savings_plan_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Queryable, Associations, Identifiable, Serialize, Deserialize)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptDocument {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    #[serde(skip)]
+    pub hash: Vec<u8>, // sha256 of content, computed by the database
+    #[serde(skip)]
+    pub content: Vec<u8>,
+    pub receipt_numbers: Vec<i64>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Associations,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
+    AsChangeset,
+)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[serde(rename_all = "camelCase")]
+pub struct CsvProfile {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub mapping: String, // serialized csv_import::CsvMapping
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "csv_profiles"]
+#[serde(rename_all = "camelCase")]
+pub struct NewCsvProfile {
+    pub user_id: i32,
+    pub name: String,
+    pub mapping: String,
+}
+
+// splits, reverse splits, bonus shares and spin-offs; not specific to a user
+#[derive(
+    Debug, Clone, Queryable, Identifiable, Insertable, Serialize, Deserialize, AsChangeset,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct CorporateAction {
+    pub id: i32,
+    pub isin: String,
+    pub date: DateTime<Utc>, // ex-date, transactions before it are adjusted
+    pub kind: String,        // see analysis::corporate_actions for possible values
+    pub ratio: f64, // units after the action per unit before (for spin-offs: units of target_isin per unit)
+    pub target_isin: Option<String>, // spin-offs only
+    pub cost_fraction: Option<f64>, // spin-offs only: part of the cost basis that moves to target_isin
+    pub comments: String,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "corporate_actions"]
+#[serde(rename_all = "camelCase")]
+pub struct NewCorporateAction {
+    pub isin: String,
+    pub date: DateTime<Utc>,
+    pub kind: String,
+    pub ratio: f64,
+    pub target_isin: Option<String>,
+    pub cost_fraction: Option<f64>,
+    pub comments: String,
+}
+
+// money that is moved into or out of the clearing account of an account without buying or selling securities
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Associations,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
+    AsChangeset,
+)]
+#[belongs_to(Account, foreign_key = "account_id")]
+#[serde(rename_all = "camelCase")]
+pub struct CashTransaction {
+    pub id: i32,
+    pub account_id: i32,
+    pub date: DateTime<Utc>,
+    pub kind: String, // see analysis::cash for possible values
+    pub amount: i64,  // in cents, positive sign -> money was credited
+    pub comments: String,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "cash_transactions"]
+#[serde(rename_all = "camelCase")]
+pub struct NewCashTransaction {
+    pub account_id: i32,
+    pub date: DateTime<Utc>,
+    pub kind: String,
+    pub amount: i64,
+    pub comments: String,
+}
+
+// units of a stock that were moved from one account to another, keeping their cost basis
+#[derive(
+    Debug, Clone, Queryable, Identifiable, Insertable, Serialize, Deserialize, AsChangeset,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct Transfer {
+    pub id: i32,
+    pub from_account_id: i32,
+    pub to_account_id: i32,
+    pub isin: String,
+    pub date: DateTime<Utc>,
+    pub units: f64,
+    pub comments: String,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "transfers"]
+#[serde(rename_all = "camelCase")]
+pub struct NewTransfer {
+    pub from_account_id: i32,
+    pub to_account_id: i32,
+    pub isin: String,
+    pub date: DateTime<Utc>,
+    pub units: f64,
+    pub comments: String,
+}
+
+// reference rate of a currency in units per EUR (like the ECB publishes them)
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeRate {
+    pub currency: String,
+    pub date: NaiveDate,
+    pub rate: f64,
+}
+
+fn default_currency() -> String {
+    String::from("EUR")
+}
+
+// user-defined label for transactions and stocks, e.g. "retirement"
+#[derive(
+    Debug, Clone, Queryable, Identifiable, Associations, Insertable, Serialize, Deserialize,
+)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "tags"]
+#[serde(rename_all = "camelCase")]
+pub struct NewTag {
+    pub user_id: i32,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionTag {
+    pub transaction_id: i32,
+    pub tag_id: i32,
+}
+
+// tags a stock for all transactions (of the tag's user) of it
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StockTag {
+    pub isin: String,
+    pub tag_id: i32,
+}
+
+// recurring purchase of a stock, e.g. 'Kauf Sparplan' at onvista
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Identifiable,
+    Associations,
+    Insertable,
+    Serialize,
+    Deserialize,
+    AsChangeset,
+)]
+#[belongs_to(Account, foreign_key = "account_id")]
+#[serde(rename_all = "camelCase")]
+pub struct SavingsPlan {
+    pub id: i32,
+    pub account_id: i32,
+    pub isin: String,
+    pub amount: i64, // cents per execution including fees, positive
+    pub interval_months: i32,
+    pub execution_day: i32, // day of month (capped at the length of the month)
+    pub start_date: NaiveDate,
+    pub end_date: Option<NaiveDate>,
+    pub comments: String,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "savings_plans"]
+#[serde(rename_all = "camelCase")]
+pub struct NewSavingsPlan {
+    pub account_id: i32,
+    pub isin: String,
+    pub amount: i64,
+    pub interval_months: i32,
+    pub execution_day: i32,
+    pub start_date: NaiveDate,
+    pub end_date: Option<NaiveDate>,
+    pub comments: String,
+}
+
+// audit log entry of an insert, update or delete of a transaction or account;
+// `before` and `after` are the json serializations of the record
+#[derive(Debug, Clone, Queryable, Identifiable, Associations, Serialize, Deserialize)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    pub id: i32,
+    pub user_id: i32,
+    pub date: DateTime<Utc>,
+    pub source: String, // web, cli, receipt or import
+    pub table_name: String,
+    pub record_id: i32,
+    pub action: String, // insert, update or delete
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub parent_id: Option<i32>, // set for records that were deleted together with their parent (e.g. an account)
+    pub reverted_by: Option<i32>,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "changes"]
+#[serde(rename_all = "camelCase")]
+pub struct NewChange {
+    pub user_id: i32,
+    pub source: String,
+    pub table_name: String,
+    pub record_id: i32,
+    pub action: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub parent_id: Option<i32>,
+}
+
+// Basiszins of a year in percent, used for the Vorabpauschale of funds
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BaseRate {
+    pub year: i32,
+    pub rate: f64,
+}
+
+// Sparerpauschbetrag of a user for a year in cents
+#[derive(Debug, Clone, Queryable, Insertable, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxAllowance {
+    pub user_id: i32,
+    pub year: i32,
+    pub amount: i64,
+}
+
+// part of the fees of a transaction, amount in cents (negative like Transaction.fees)
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Identifiable,
+    Associations,
+    Insertable,
+    Serialize,
+    Deserialize,
+    PartialEq,
+)]
+#[belongs_to(Transaction, foreign_key = "transaction_id")]
+#[serde(rename_all = "camelCase")]
+pub struct FeeItem {
+    pub id: i32,
+    pub transaction_id: i32,
+    pub category: String, // commission, exchange, thirdParty, fxSpread or other
+    pub amount: i64,
+    pub description: String, // label on the receipt, if any
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize, PartialEq)]
+#[table_name = "fee_items"]
+#[serde(rename_all = "camelCase")]
+pub struct NewFeeItem {
+    #[serde(default)]
+    pub transaction_id: i32, // taken from the url when items are sent to the api
+    pub category: String,
+    pub amount: i64,
+    #[serde(default)]
+    pub description: String,
+}
+
+// details of a dividend or fund distribution; the net amount is the one of its transaction
+#[derive(
+    Debug,
+    Clone,
+    Queryable,
+    Identifiable,
+    Associations,
+    Insertable,
+    Serialize,
+    Deserialize,
+    PartialEq,
+)]
+#[belongs_to(Transaction, foreign_key = "transaction_id")]
+#[serde(rename_all = "camelCase")]
+pub struct Distribution {
+    pub id: i32,
+    pub transaction_id: i32,
+    pub ex_date: Option<NaiveDate>,
+    pub pay_date: NaiveDate,
+    pub amount_per_unit: f64, // gross, in `currency`
+    pub currency: String,
+    pub units: f64,                 // held on the ex-date
+    pub exchange_rate: Option<f64>, // units of the account's currency per unit of `currency`
+    pub taxes: i64,                 // withheld, in cents of the account's currency (negative)
+    pub period_start: Option<NaiveDate>,
+    pub period_end: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Insertable, AsChangeset, Serialize, Deserialize, PartialEq)]
+#[table_name = "distributions"]
+#[changeset_options(treat_none_as_null = "true")]
+#[serde(rename_all = "camelCase")]
+pub struct NewDistribution {
+    #[serde(default, skip_serializing)]
+    pub transaction_id: i32, // taken from the url when sent to the api
+    pub ex_date: Option<NaiveDate>,
+    pub pay_date: NaiveDate,
+    pub amount_per_unit: f64,
+    pub currency: String,
+    pub units: f64,
+    pub exchange_rate: Option<f64>,
+    #[serde(default)]
+    pub taxes: i64,
+    pub period_start: Option<NaiveDate>,
+    pub period_end: Option<NaiveDate>,
+}
+
+// target weight (0 < weight <= 1) of a stock or of all stocks with a tag, exactly one of them is set
+#[derive(
+    Debug, Clone, Queryable, Identifiable, Associations, Insertable, Serialize, Deserialize,
+)]
+#[belongs_to(User, foreign_key = "user_id")]
+#[serde(rename_all = "camelCase")]
+pub struct AllocationTarget {
+    pub id: i32,
+    pub user_id: i32,
+    pub isin: Option<String>,
+    pub tag_id: Option<i32>,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "allocation_targets"]
+#[serde(rename_all = "camelCase")]
+pub struct NewAllocationTarget {
+    #[serde(default, skip_serializing)]
+    pub user_id: i32, // set from the session
+    pub isin: Option<String>,
+    pub tag_id: Option<i32>,
+    pub weight: f64,
+}
+
+// position of a fund, see StockInfo.holdings
+#[derive(Debug, Clone, Queryable, Identifiable, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StockHolding {
+    pub id: i32,
+    pub isin: String, // of the fund
+    pub name: String,
+    pub holding_isin: Option<String>,
+    pub weight: f64, // fraction of the fund
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "stock_holdings"]
+#[serde(rename_all = "camelCase")]
+pub struct NewStockHolding {
+    pub isin: String,
+    pub name: String,
+    pub holding_isin: Option<String>,
+    pub weight: f64,
 }
@@ -26,7 +26,7 @@ fn daily_notification_body(
     kind: PerformanceKind,
 ) -> Result<String, Box<dyn Error>> {
     let now = Utc::now();
-    let mut perf = performance::compute(connection, uid, now)?
+    let mut perf = performance::compute(connection, uid, now, &Default::default())?
         .into_iter()
         .find(|p| p.kind == kind)
         .ok_or("performance empty")?;
@@ -100,6 +100,25 @@ pub async fn send_welcome_notifications(
     Ok(())
 }
 
+pub async fn send_text_notifications(
+    subs: &[PushSubscription],
+    private_key: &[u8],
+    text: &str,
+) -> Result<(), Box<dyn Error>> {
+    let body = serde_json::to_string(&TextPayload {
+        text: text.to_string(),
+    })?;
+    for sub in subs.iter() {
+        if let Err(e) = send(private_key, sub.clone(), body.clone()).await {
+            error!("Failed to send notification to {}: {:?}", sub.endpoint, e);
+        } else {
+            info!("Sent notification to {}", sub.endpoint);
+        }
+    }
+
+    Ok(())
+}
+
 // TODO: also send weekly notifications
 // returns error only on serious problems
 pub async fn send_daily_notifications(
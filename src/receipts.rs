@@ -1,29 +1,185 @@
+use crate::distributions;
+use crate::fees;
+use crate::history;
 use crate::models::*;
+use crate::savings_plans;
+use crate::schema::{
+    distributions as distributions_table, fee_items, receipt_documents, transactions,
+};
+use crate::validation::Validator;
 
-use chrono::{Local, TimeZone, Utc};
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use diesel::prelude::*;
-use log::{debug, info, warn};
-use regex::Regex;
+use log::{debug, error, info, warn};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
 use std::error::Error;
+use std::hash::{Hash, Hasher};
 use std::str::FromStr;
 
 #[derive(Debug)]
 struct ParsedTransaction {
     content: NewTransaction,
+    fee_items: Vec<ParsedFee>,
+    distribution: Option<NewDistribution>,
+    depot_number: Option<String>,
     account_number: u64,
     over_the_counter: bool,
 }
 
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ReceiptKind {
+    Purchase,
+    Dividends,
+    Unknown,
+}
+
+// why a receipt (or one of its pages) could not be parsed
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptError {
+    pub file: Option<String>,
+    pub page: Option<usize>,
+    pub kind: Option<ReceiptKind>,
+    pub field: Option<String>,
+    pub message: String,
+    pub snippet: Option<String>, // extracted text around the expected position, long numbers masked
+}
+
+impl ReceiptError {
+    fn new(message: &str) -> ReceiptError {
+        ReceiptError {
+            file: None,
+            page: None,
+            kind: None,
+            field: None,
+            message: message.to_owned(),
+            snippet: None,
+        }
+    }
+
+    fn from_error<E: std::fmt::Display>(e: E) -> ReceiptError {
+        ReceiptError::new(&e.to_string())
+    }
+
+    fn in_file(self, file: &str) -> ReceiptError {
+        ReceiptError {
+            file: Some(file.to_owned()),
+            ..self
+        }
+    }
+}
+
+impl std::fmt::Display for ReceiptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file)?;
+        }
+        if let Some(page) = self.page {
+            write!(f, "page {}: ", page)?;
+        }
+        if let Some(kind) = self.kind {
+            write!(f, "{:?}: ", kind)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ReceiptError {}
+
+// all errors that occurred while parsing a set of files
+#[derive(Debug, Serialize)]
+pub struct ReceiptErrors(pub Vec<ReceiptError>);
+
+impl std::fmt::Display for ReceiptErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msgs = self.0.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", msgs.join("; "))
+    }
+}
+
+impl Error for ReceiptErrors {}
+
+// a fee as listed on a receipt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParsedFee {
+    pub category: String,
+    pub description: String,
+    pub amount: i64, // negative, in cents
+}
+
+// a parsed transaction with its date in local time, so that it does not depend on the time zone of the machine
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ParsedReceipt {
+    pub isin: String,
+    pub date: NaiveDateTime,
+    pub units: f64,
+    pub amount: i64,
+    pub fees: i64,
+    pub comments: String,
+    pub exchange: Option<String>,
+    pub receipt_number: Option<i64>,
+    pub depot_number: Option<String>,
+    pub account_number: u64,
+    pub over_the_counter: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fee_items: Vec<ParsedFee>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distribution: Option<NewDistribution>,
+}
+
+// depots that receipts were found for, but no matching account exists
+#[derive(Debug)]
+pub struct UnknownDepots(pub Vec<String>);
+
+impl std::fmt::Display for UnknownDepots {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Cannot find account for depot number(s) {}",
+            self.0.join(", ")
+        )
+    }
+}
+
+impl Error for UnknownDepots {}
+
+// value of Account.broker for accounts that receipts can be imported into
+pub const BROKER: &str = "onvista";
+
+sql_function!(fn sha256(x: diesel::sql_types::Bytea) -> diesel::sql_types::Bytea);
+
+// parse receipts and insert their transactions; if `create_accounts` is set,
+// an account is created for every depot number without a matching account.
 pub fn parse_mem(
     connection: &PgConnection,
     uid: i32,
     files: &[(String, Vec<u8>)],
+    create_accounts: bool,
 ) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let ts = files
+    let mut parsed = Vec::new();
+    let mut errors = Vec::new();
+    for (file_name, buf) in files.iter() {
+        match parse_receipt(buf) {
+            Ok(ts) => parsed.push(ts),
+            Err(e) => errors.push(e.in_file(file_name)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Box::new(ReceiptErrors(errors)));
+    }
+
+    let receipt_numbers = parsed
         .iter()
-        .map(|(file_name, buf)| parse_receipt(buf).map_err(|e| format!("{}: {}", file_name, e)))
-        .collect::<Result<Vec<Vec<ParsedTransaction>>, String>>()?;
-    let ts = ts.into_iter().flatten().collect::<Vec<_>>();
+        .map(|ts| {
+            ts.iter()
+                .flat_map(|t| t.content.receipt_number)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let ts = parsed.into_iter().flatten().collect::<Vec<_>>();
 
     let accs = crate::schema::accounts::table
         .filter(crate::schema::accounts::user_id.eq(uid))
@@ -33,27 +189,339 @@ pub fn parse_mem(
     // find out if one of the receipt ids already exists,
     // warn the user about them and remove them from the list
     let orig_len = ts.len();
-    let receipt_numbers = ts
-        .iter()
-        .flat_map(|t| t.content.receipt_number)
+    let ex_receipt_numbers = existing_receipt_numbers(
+        connection,
+        &account_ids,
+        &ts.iter()
+            .flat_map(|t| t.content.receipt_number)
+            .collect::<Vec<_>>(),
+    )?;
+    let ts = ts
+        .into_iter()
+        .filter(|t| {
+            t.content
+                .receipt_number
+                .map(|n| !ex_receipt_numbers.contains(&n))
+                .unwrap_or(true)
+        })
         .collect::<Vec<_>>();
-    let ex_receipt_numbers = crate::schema::transactions::table
-        .filter(crate::schema::transactions::dsl::receipt_number.eq_any(&receipt_numbers))
-        .filter(crate::schema::transactions::dsl::account_id.eq_any(&account_ids))
+
+    if ts.len() < orig_len {
+        warn!("Removed {} of the parsed transactions from the list because they already exist in the database", orig_len - ts.len());
+    }
+
+    // the transactions and their documents are stored together or not at all
+    connection.transaction::<_, Box<dyn Error>, _>(|| {
+        let ts = prepare(connection, uid, &accs, ts, create_accounts)?;
+        let inserted = diesel::insert_into(crate::schema::transactions::table)
+            .values(&ts.iter().map(|t| t.content.clone()).collect::<Vec<_>>())
+            .load::<Transaction>(connection)?;
+        info!("Inserted {} transactions into the database", inserted.len());
+        insert_details(connection, &inserted, &ts)?;
+        history::inserted(connection, uid, history::RECEIPT, &inserted)?;
+        let inserted = savings_plans::link(connection, uid, history::RECEIPT, inserted)?;
+
+        for ((name, buf), numbers) in files.iter().zip(receipt_numbers) {
+            store_document(connection, uid, name, buf, numbers)?;
+        }
+
+        Ok(inserted)
+    })
+}
+
+pub fn parse_files(
+    connection: &PgConnection,
+    uid: i32,
+    file_names: &[&str],
+    create_accounts: bool,
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let files = file_names
+        .iter()
+        .map(|file_name| {
+            let buf = std::fs::read(file_name)?;
+
+            Ok((file_name.to_string(), buf))
+        })
+        .collect::<Result<Vec<(String, Vec<u8>)>, Box<dyn Error>>>()?;
+
+    parse_mem(connection, uid, &files, create_accounts)
+}
+
+// parse all stored documents of a user again and update the transactions they belong to;
+// transactions that do not exist (anymore) are inserted again.
+pub fn reparse(connection: &PgConnection, uid: i32) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let docs = receipt_documents::table
+        .filter(receipt_documents::user_id.eq(uid))
+        .order(receipt_documents::id.asc())
+        .load::<ReceiptDocument>(connection)?;
+    info!("Parsing {} stored documents again", docs.len());
+
+    // a failure leaves the stored documents and their transactions as they were
+    connection.transaction::<_, Box<dyn Error>, _>(|| {
+        let mut ts = Vec::new();
+        for doc in docs.iter() {
+            match parse_receipt(&doc.content) {
+                Ok(doc_ts) => {
+                    let numbers = doc_ts
+                        .iter()
+                        .flat_map(|t| t.content.receipt_number)
+                        .collect::<Vec<_>>();
+                    if numbers != doc.receipt_numbers {
+                        diesel::update(receipt_documents::table.find(doc.id))
+                            .set(receipt_documents::receipt_numbers.eq(numbers))
+                            .execute(connection)?;
+                    }
+
+                    ts.extend(doc_ts);
+                }
+                Err(e) => error!("{}", e.in_file(&doc.name)),
+            }
+        }
+
+        let accs = crate::schema::accounts::table
+            .filter(crate::schema::accounts::user_id.eq(uid))
+            .load::<Account>(connection)?;
+        let account_ids = accs.iter().map(|a| a.id).collect::<Vec<_>>();
+        let ts = prepare(connection, uid, &accs, ts, false)?;
+
+        let receipt_numbers = ts
+            .iter()
+            .flat_map(|t| t.content.receipt_number)
+            .collect::<Vec<_>>();
+        let existing = transactions::table
+            .filter(transactions::receipt_number.eq_any(&receipt_numbers))
+            .filter(transactions::account_id.eq_any(&account_ids))
+            .load::<Transaction>(connection)?;
+        let existing_fees = fees::list(
+            connection,
+            &existing.iter().map(|e| e.id).collect::<Vec<_>>(),
+        )?;
+
+        let mut result = Vec::new();
+        let mut missing = Vec::new();
+        for p in ts.into_iter() {
+            if let Some(e) = existing
+                .iter()
+                .find(|e| e.receipt_number == p.content.receipt_number)
+            {
+                let t = p.content.clone();
+                let updated = Transaction {
+                    id: e.id,
+                    account_id: e.account_id,
+                    comments: if t.comments.is_empty() {
+                        e.comments.clone()
+                    } else {
+                        t.comments
+                    },
+                    isin: t.isin,
+                    date: t.date,
+                    units: t.units,
+                    amount: t.amount,
+                    fees: t.fees,
+                    onvista_exchange_id: t.onvista_exchange_id,
+                    exchange: t.exchange,
+                    receipt_number: t.receipt_number,
+                    currency: t.currency,
+                    exchange_rate: t.exchange_rate,
+                    savings_plan_id: e.savings_plan_id,
+                    synthetic: None,
+                };
+
+                let updated = if &updated != e {
+                    info!("Updating transaction {:?} to {:?}", e, updated);
+                    let updated = diesel::update(transactions::table.find(e.id))
+                        .set(updated)
+                        .get_result::<Transaction>(connection)?;
+                    history::updated(connection, uid, history::RECEIPT, e, &updated)?;
+                    result.push(updated.clone());
+                    updated
+                } else {
+                    updated
+                };
+
+                update_details(connection, &updated, &p, &existing_fees)?;
+            } else {
+                missing.push(p);
+            }
+        }
+
+        if !missing.is_empty() {
+            warn!(
+                "Inserting {} transactions that are no longer in the database",
+                missing.len()
+            );
+            let inserted = diesel::insert_into(transactions::table)
+                .values(
+                    &missing
+                        .iter()
+                        .map(|t| t.content.clone())
+                        .collect::<Vec<_>>(),
+                )
+                .load::<Transaction>(connection)?;
+            insert_details(connection, &inserted, &missing)?;
+            history::inserted(connection, uid, history::RECEIPT, &inserted)?;
+            result.extend(inserted);
+        }
+
+        Ok(result)
+    })
+}
+
+pub fn find_document(
+    connection: &PgConnection,
+    uid: i32,
+    receipt_number: i64,
+) -> Result<Option<ReceiptDocument>, Box<dyn Error>> {
+    Ok(receipt_documents::table
+        .filter(receipt_documents::user_id.eq(uid))
+        .filter(receipt_documents::receipt_numbers.contains(vec![receipt_number]))
+        .order(receipt_documents::created.desc())
+        .first::<ReceiptDocument>(connection)
+        .optional()?)
+}
+
+// documents are deduplicated by their hash, storing one twice only updates name and receipt numbers
+fn store_document(
+    connection: &PgConnection,
+    uid: i32,
+    name: &str,
+    buf: &[u8],
+    receipt_numbers: Vec<i64>,
+) -> Result<(), Box<dyn Error>> {
+    diesel::insert_into(receipt_documents::table)
+        .values((
+            receipt_documents::user_id.eq(uid),
+            receipt_documents::name.eq(name),
+            receipt_documents::hash.eq(sha256(buf)),
+            receipt_documents::content.eq(buf),
+            receipt_documents::receipt_numbers.eq(&receipt_numbers),
+            receipt_documents::created.eq(Utc::now()),
+        ))
+        .on_conflict((receipt_documents::user_id, receipt_documents::hash))
+        .do_update()
+        .set((
+            receipt_documents::name.eq(name),
+            receipt_documents::receipt_numbers.eq(&receipt_numbers),
+        ))
+        .execute(connection)?;
+
+    debug!("Stored document {} ({} bytes)", name, buf.len());
+    Ok(())
+}
+
+// receipt numbers of transactions in the given accounts that already exist in the database
+pub fn existing_receipt_numbers(
+    connection: &PgConnection,
+    account_ids: &[i32],
+    receipt_numbers: &[i64],
+) -> Result<Vec<i64>, Box<dyn Error>> {
+    Ok(transactions::table
+        .filter(transactions::receipt_number.eq_any(receipt_numbers))
+        .filter(transactions::account_id.eq_any(account_ids))
         .load::<Transaction>(connection)?
         .iter()
         .flat_map(|t| t.receipt_number)
-        .map(Some)
+        .collect::<Vec<_>>())
+}
+
+fn new_fee_items(t: &Transaction, parsed: &[ParsedFee]) -> Vec<NewFeeItem> {
+    parsed
+        .iter()
+        .map(|f| NewFeeItem {
+            transaction_id: t.id,
+            category: f.category.clone(),
+            amount: f.amount,
+            description: f.description.clone(),
+        })
+        .collect()
+}
+
+// fee items and distributions of newly inserted transactions, `parsed` are the ones `ts` were inserted from
+fn insert_details(
+    connection: &PgConnection,
+    ts: &[Transaction],
+    parsed: &[ParsedTransaction],
+) -> Result<(), Box<dyn Error>> {
+    let items = ts
+        .iter()
+        .zip(parsed.iter())
+        .flat_map(|(t, p)| new_fee_items(t, &p.fee_items))
         .collect::<Vec<_>>();
-    let ts = ts
-        .into_iter()
-        .filter(|t| !ex_receipt_numbers.contains(&t.content.receipt_number))
+    diesel::insert_into(fee_items::table)
+        .values(&items)
+        .execute(connection)?;
+
+    let ds = ts
+        .iter()
+        .zip(parsed.iter())
+        .filter_map(|(t, p)| {
+            p.distribution.as_ref().map(|d| NewDistribution {
+                transaction_id: t.id,
+                ..d.clone()
+            })
+        })
         .collect::<Vec<_>>();
+    diesel::insert_into(distributions_table::table)
+        .values(&ds)
+        .execute(connection)?;
 
-    if ts.len() < orig_len {
-        warn!("Removed {} of the parsed transactions from the list because they already exist in the database", orig_len - ts.len());
+    Ok(())
+}
+
+// bring fee items and distribution of an existing transaction in line with its receipt
+fn update_details(
+    connection: &PgConnection,
+    t: &Transaction,
+    parsed: &ParsedTransaction,
+    existing_fees: &[FeeItem],
+) -> Result<(), Box<dyn Error>> {
+    let items = new_fee_items(t, &parsed.fee_items);
+    let current = existing_fees
+        .iter()
+        .filter(|i| i.transaction_id == t.id)
+        .map(|i| (i.category.as_str(), i.amount, i.description.as_str()))
+        .collect::<Vec<_>>();
+    let expected = items
+        .iter()
+        .map(|i| (i.category.as_str(), i.amount, i.description.as_str()))
+        .collect::<Vec<_>>();
+    if current != expected {
+        fees::replace(connection, t, &items)?;
     }
 
+    if let Some(d) = &parsed.distribution {
+        let d = NewDistribution {
+            transaction_id: t.id,
+            ..d.clone()
+        };
+        let current = distributions::find(connection, t.id)?.map(|c| NewDistribution {
+            transaction_id: c.transaction_id,
+            ex_date: c.ex_date,
+            pay_date: c.pay_date,
+            amount_per_unit: c.amount_per_unit,
+            currency: c.currency,
+            units: c.units,
+            exchange_rate: c.exchange_rate,
+            taxes: c.taxes,
+            period_start: c.period_start,
+            period_end: c.period_end,
+        });
+        if current.as_ref() != Some(&d) {
+            distributions::save(connection, t, &d)?;
+        }
+    }
+
+    Ok(())
+}
+
+// replace exchange names with ids and find the accounts of the parsed transactions
+fn prepare(
+    connection: &PgConnection,
+    uid: i32,
+    accs: &[Account],
+    ts: Vec<ParsedTransaction>,
+    create_accounts: bool,
+) -> Result<Vec<ParsedTransaction>, Box<dyn Error>> {
     // try to replace the exchange names with an id (unless they were traded over the counter)
     let isins = ts
         .iter()
@@ -67,54 +535,84 @@ pub fn parse_mem(
         .map(|t| replace_exchange(&exs, t))
         .collect::<Vec<_>>();
 
-    for mut t in ts.iter_mut() {
-        let account = accs
-            .iter()
-            .find(|a| {
-                a.iban
-                    .as_ref()
-                    .map(|iban| iban.ends_with(&format!("{}", t.account_number)))
-                    .unwrap_or(false)
-            })
-            .ok_or_else(|| {
-                format!(
-                    "Cannot find account for account number {}",
-                    t.account_number
+    let mut accs = accs.to_vec();
+    let mut unknown = Vec::new();
+    for t in ts.iter_mut() {
+        let account_number = t.account_number.to_string();
+        let account_id = Account::find(
+            &accs,
+            BROKER,
+            t.depot_number.as_deref(),
+            Some(&account_number),
+        )
+        .map(|a| a.id);
+
+        match (account_id, &t.depot_number) {
+            (Some(id), _) => t.content.account_id = id,
+            (None, Some(dn)) if create_accounts => {
+                let a: Account = diesel::insert_into(crate::schema::accounts::table)
+                    .values(&NewAccount {
+                        user_id: uid,
+                        name: format!("Depot {}", dn),
+                        iban: None,
+                        depot_number: Some(dn.clone()),
+                        clearing_account_number: Some(account_number),
+                        broker: Some(BROKER.to_owned()),
+                        currency: String::from("EUR"),
+                    })
+                    .get_result(connection)?;
+                history::inserted(connection, uid, history::RECEIPT, std::slice::from_ref(&a))?;
+                info!("Created account {:?}", a);
+
+                t.content.account_id = a.id;
+                accs.push(a);
+            }
+            (None, Some(dn)) => {
+                if !unknown.contains(dn) {
+                    unknown.push(dn.clone());
+                }
+            }
+            (None, None) => {
+                return Err(
+                    format!("Cannot find account for account number {}", account_number).into(),
                 )
-            })?;
+            }
+        }
+    }
 
-        t.content.account_id = account.id;
+    if !unknown.is_empty() {
+        return Err(Box::new(UnknownDepots(unknown)));
     }
 
-    let ts = ts.into_iter().map(|t| t.content).collect::<Vec<_>>();
-    let inserted = diesel::insert_into(crate::schema::transactions::table)
-        .values(&ts)
-        .load::<Transaction>(connection)?;
-    info!("Inserted {} transactions into the database", inserted.len());
+    let validator = Validator::new(connection, accs.iter().map(|a| a.id).collect())?;
+    let mut errors = Vec::new();
+    for t in ts.iter() {
+        if let Err(e) = validator.check(&t.content) {
+            errors.extend(e.errors.into_iter().map(|fe| ReceiptError {
+                field: Some(fe.field),
+                message: match t.content.receipt_number {
+                    Some(n) => format!("receipt {}: {}", n, fe.message),
+                    None => fe.message,
+                },
+                ..ReceiptError::new("")
+            }));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Box::new(ReceiptErrors(errors)));
+    }
 
-    Ok(inserted)
+    Ok(ts)
 }
 
-pub fn parse_files(
-    connection: &PgConnection,
-    uid: i32,
-    file_names: &[&str],
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let files = file_names
-        .iter()
-        .map(|file_name| {
-            let buf = std::fs::read(file_name)?;
-
-            Ok((file_name.to_string(), buf))
-        })
-        .collect::<Result<Vec<(String, Vec<u8>)>, Box<dyn Error>>>()?;
-
-    parse_mem(connection, uid, &files)
+fn parse_receipt(buf: &[u8]) -> Result<Vec<ParsedTransaction>, ReceiptError> {
+    parse_pages(&extract_text(buf)?)
 }
 
-fn parse_receipt(buf: &[u8]) -> Result<Vec<ParsedTransaction>, Box<dyn Error>> {
+// text of a receipt as seen by the parser
+pub fn extract_text(buf: &[u8]) -> Result<String, ReceiptError> {
     let mut buffer = Vec::<u8>::new();
-    let doc = lopdf::Document::load_mem(buf)?;
+    let doc = lopdf::Document::load_mem(buf).map_err(ReceiptError::from_error)?;
 
     pdf_extract::output_doc(
         &doc,
@@ -122,15 +620,43 @@ fn parse_receipt(buf: &[u8]) -> Result<Vec<ParsedTransaction>, Box<dyn Error>> {
             &mut buffer as &mut dyn std::io::Write,
         ))
         .as_mut(),
-    )?;
-    let body = std::str::from_utf8(&buffer)?
-        .to_owned()
-        .replace("Depot-Nr.", "Depot-Nr.Depot-Nr."); // Lookaheads are not supported by the regex engine
+    )
+    .map_err(ReceiptError::from_error)?;
+
+    Ok(std::str::from_utf8(&buffer)
+        .map_err(ReceiptError::from_error)?
+        .to_owned())
+}
+
+// parse the extracted text of a receipt, used for the corpus in tests/receipts
+pub fn parse_text(text: &str) -> Result<Vec<ParsedReceipt>, ReceiptError> {
+    Ok(parse_pages(text)?
+        .into_iter()
+        .map(|t| ParsedReceipt {
+            isin: t.content.isin,
+            date: t.content.date.with_timezone(&Local).naive_local(),
+            units: t.content.units,
+            amount: t.content.amount,
+            fees: t.content.fees,
+            comments: t.content.comments,
+            exchange: t.content.exchange,
+            receipt_number: t.content.receipt_number,
+            depot_number: t.depot_number,
+            account_number: t.account_number,
+            over_the_counter: t.over_the_counter,
+            fee_items: t.fee_items,
+            distribution: t.distribution,
+        })
+        .collect())
+}
+
+fn parse_pages(text: &str) -> Result<Vec<ParsedTransaction>, ReceiptError> {
+    let body = text.replace("Depot-Nr.", "Depot-Nr.Depot-Nr."); // Lookaheads are not supported by the regex engine
 
     let mut ts = Vec::new();
     let re_page = Regex::new(r"Depot-Nr\.[\s\S]+?(Depot-Nr\.|\z)").unwrap();
 
-    for page in re_page.find_iter(&body) {
+    for (i, page) in re_page.find_iter(&body).enumerate() {
         let s = page.as_str();
 
         let re_purchase =
@@ -138,16 +664,27 @@ fn parse_receipt(buf: &[u8]) -> Result<Vec<ParsedTransaction>, Box<dyn Error>> {
                 .unwrap();
         if s.contains("Erträgnisgutschrift") {
             debug!("Parsing as dividends");
-            ts.push(parse_dividends(&s)?);
+            ts.push(
+                parse_dividends(&s)
+                    .map_err(|e| e.into_receipt_error(s, i + 1, ReceiptKind::Dividends))?,
+            );
         } else if re_purchase.is_match(&s) {
             debug!("Parsing as purchase");
-            ts.push(parse_purchase(&s)?);
+            ts.push(
+                parse_purchase(&s)
+                    .map_err(|e| e.into_receipt_error(s, i + 1, ReceiptKind::Purchase))?,
+            );
         } else if s.contains("Steuerbelastung\naus Wertpapieren") {
             debug!("Ignoring page with tax information");
         } else if !s.contains("SEITENNUMMER=1\n") {
             debug!("Ignoring page that does not have page number 1");
         } else {
-            return Err("unknown receipt type".into());
+            return Err(ReceiptError {
+                page: Some(i + 1),
+                kind: Some(ReceiptKind::Unknown),
+                snippet: Some(snippet(s, 0)),
+                ..ReceiptError::new("unknown receipt type")
+            });
         }
     }
 
@@ -155,57 +692,191 @@ fn parse_receipt(buf: &[u8]) -> Result<Vec<ParsedTransaction>, Box<dyn Error>> {
         if body.contains("Steuerbelastung\naus Wertpapieren") {
             info!("file only contains tax information, ignoring.");
         } else {
-            return Err("no transaction pages found".into());
+            return Err(ReceiptError::new("no transaction pages found"));
         }
     }
 
     Ok(ts)
 }
 
-fn parse_dividends(s: &str) -> Result<ParsedTransaction, Box<dyn Error>> {
-    let re = Regex::new(r"ISIN\s+([A-Z]{2}[A-Z0-9]{10})\s").unwrap();
-    let isin = re.captures_iter(&s).next().ok_or("no isin match")?[1].to_owned();
+// a field of a receipt that could not be found or parsed
+struct FieldError {
+    field: &'static str,
+    anchor: &'static str, // label close to which the field is expected
+    message: String,
+}
+
+impl FieldError {
+    fn into_receipt_error(self, page_text: &str, page: usize, kind: ReceiptKind) -> ReceiptError {
+        ReceiptError {
+            page: Some(page),
+            kind: Some(kind),
+            field: Some(self.field.to_owned()),
+            snippet: page_text.find(self.anchor).map(|i| snippet(page_text, i)),
+            ..ReceiptError::new(&self.message)
+        }
+    }
+}
+
+fn capture<'t>(
+    s: &'t str,
+    re: &str,
+    field: &'static str,
+    anchor: &'static str,
+) -> Result<Captures<'t>, FieldError> {
+    Regex::new(re)
+        .unwrap()
+        .captures(s)
+        .ok_or_else(|| FieldError {
+            field,
+            anchor,
+            message: format!("no {} match", field),
+        })
+}
 
-    let re = Regex::new(r"Abrechnungs-Nr\.\s+(\d+)\s").unwrap();
-    let receipt_number = re
-        .captures_iter(&s)
-        .next()
-        .ok_or("no receipt number match")?[1]
-        .parse()?;
+fn parse_field<T>(value: &str, field: &'static str, anchor: &'static str) -> Result<T, FieldError>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| FieldError {
+        field,
+        anchor,
+        message: format!("cannot parse '{}': {}", redact(value), e),
+    })
+}
 
-    let re = Regex::new(r"Nominal\s+STK ([\d\.]+,\d+)\s").unwrap();
-    let cpt = re.captures_iter(&s).next().ok_or("no units match")?;
-    let units = parse_float(&cpt[1])?;
+fn parse_amount(value: &str, field: &'static str, anchor: &'static str) -> Result<f64, FieldError> {
+    parse_float(value).map_err(|e| FieldError {
+        field,
+        anchor,
+        message: format!("cannot parse '{}': {}", redact(value), e),
+    })
+}
 
-    let re = Regex::new(r"Ausschüttungsbetrag pro Stück\s+((EUR|USD) [\d\.]+,\d+)").unwrap();
-    let amount_per_unit = re
-        .captures_iter(&s)
-        .next()
-        .ok_or("no amount per unit match")?[1]
-        .to_owned();
+fn parse_date(
+    value: &str,
+    field: &'static str,
+    anchor: &'static str,
+) -> Result<NaiveDate, FieldError> {
+    NaiveDate::parse_from_str(value, "%d.%m.%Y").map_err(|e| FieldError {
+        field,
+        anchor,
+        message: format!("cannot parse '{}': {}", value, e),
+    })
+}
+
+fn parse_dividends(s: &str) -> Result<ParsedTransaction, FieldError> {
+    let isin = capture(s, r"ISIN\s+([A-Z]{2}[A-Z0-9]{10})\s", "isin", "ISIN")?[1].to_owned();
+
+    let receipt_number = parse_field(
+        &capture(
+            s,
+            r"Abrechnungs-Nr\.\s+(\d+)\s",
+            "receipt number",
+            "Abrechnungs-Nr.",
+        )?[1],
+        "receipt number",
+        "Abrechnungs-Nr.",
+    )?;
 
-    let re = Regex::new(r"Ausschüttung für\s+([\d\.]{10}\s-\s[\d\.]{10})").unwrap();
-    let period = &re.captures_iter(&s).next().ok_or("no period match")?[1];
+    let cpt = capture(s, r"Nominal\s+STK ([\d\.]+,\d+)\s", "units", "Nominal")?;
+    let units = parse_amount(&cpt[1], "units", "Nominal")?;
+
+    let cpt = capture(
+        s,
+        r"Ausschüttungsbetrag pro Stück\s+((EUR|USD) ([\d\.]+,\d+))",
+        "amount per unit",
+        "Ausschüttungsbetrag",
+    )?;
+    let amount_per_unit = cpt[1].to_owned();
+    let currency = cpt[2].to_owned();
+    let gross_per_unit = parse_amount(&cpt[3], "amount per unit", "Ausschüttungsbetrag")?;
+
+    let cpt = capture(
+        s,
+        r"Ausschüttung für\s+(([\d\.]{10})\s-\s([\d\.]{10}))",
+        "period",
+        "Ausschüttung für",
+    )?;
+    let period = &cpt[1];
+    let period_start = parse_date(&cpt[2], "period", "Ausschüttung für")?;
+    let period_end = parse_date(&cpt[3], "period", "Ausschüttung für")?;
+
+    let ex_date = match Regex::new(r"Ex-Tag\s+([\d\.]{10})\s").unwrap().captures(s) {
+        Some(cpt) => Some(parse_date(&cpt[1], "ex-date", "Ex-Tag")?),
+        None => None,
+    };
+    let pay_date = match Regex::new(r"Zahltag\s+([\d\.]{10})\s").unwrap().captures(s) {
+        Some(cpt) => Some(parse_date(&cpt[1], "pay date", "Zahltag")?),
+        None => None,
+    };
+
+    // e.g. 'Devisenkurs EUR / USD 1,1734', i.e. USD per EUR
+    let exchange_rate = match Regex::new(r"Devisenkurs EUR / [A-Z]{3} ([\d\.]+,\d+)")
+        .unwrap()
+        .captures(s)
+    {
+        Some(cpt) => Some(1.0 / parse_amount(&cpt[1], "exchange rate", "Devisenkurs")?),
+        None => None,
+    };
+
+    // withheld taxes, listed between the gross amount and the account details
+    let re_taxes = Regex::new(
+        r"(?m)^(Kapitalertragsteuer|Solidaritätszuschlag|Kirchensteuer|Quellensteuer)[^\n]*?EUR ([\d\.]+,\d{2})-?\s*$",
+    )
+    .unwrap();
+    let mut taxes = 0;
+    for cpt in re_taxes.captures_iter(s) {
+        taxes -= (parse_amount(&cpt[2], "taxes", "steuer")? * 100.0).round() as i64;
+    }
 
     let comments = format!(
         "Ausschüttung für {}, {} pro Stück, {:.3} Stück im Besitz",
         period, amount_per_unit, units
     );
 
-    let re = Regex::new(r"Konto-Nr\.\s+(\d+)\s").unwrap();
-    let account_number = re.captures_iter(&s).next().ok_or("no account match")?[1].parse()?;
+    let account_number = parse_field(
+        &capture(s, r"Konto-Nr\.\s+(\d+)\s", "account number", "Konto-Nr.")?[1],
+        "account number",
+        "Konto-Nr.",
+    )?;
 
-    let re = Regex::new(r"Betrag zu Ihren Gunsten\s+EUR ([\d\.]+,\d{2})").unwrap();
-    let amount = (parse_float(&re.captures_iter(&s).next().ok_or("no amount match")?[1])? * 100.0)
+    let amount = (parse_amount(
+        &capture(
+            s,
+            r"Betrag zu Ihren Gunsten\s+EUR ([\d\.]+,\d{2})",
+            "amount",
+            "Betrag zu Ihren Gunsten",
+        )?[1],
+        "amount",
+        "Betrag zu Ihren Gunsten",
+    )? * 100.0)
         .round() as i64;
 
-    let re = Regex::new(r"Wert\s+(\d\d)\.(\d\d)\.(\d{4})\s").unwrap();
-    let cpt = re.captures_iter(&s).next().ok_or("no date match")?;
+    let cpt = capture(s, r"Wert\s+(\d\d)\.(\d\d)\.(\d{4})\s", "date", "Wert")?;
     let date = Local
-        .ymd(cpt[3].parse()?, cpt[2].parse()?, cpt[1].parse()?)
+        .ymd(
+            parse_field(&cpt[3], "date", "Wert")?,
+            parse_field(&cpt[2], "date", "Wert")?,
+            parse_field(&cpt[1], "date", "Wert")?,
+        )
         .and_hms(0, 0, 0)
         .with_timezone(&Utc);
 
+    let distribution = NewDistribution {
+        transaction_id: 0, // set once the transaction is inserted
+        ex_date,
+        pay_date: pay_date.unwrap_or_else(|| date.with_timezone(&Local).date().naive_local()),
+        amount_per_unit: gross_per_unit,
+        exchange_rate: exchange_rate.filter(|_| currency != "EUR"),
+        currency,
+        units,
+        taxes,
+        period_start: Some(period_start),
+        period_end: Some(period_end),
+    };
+
     let t = NewTransaction {
         account_id: -1,
         isin,
@@ -217,57 +888,87 @@ fn parse_dividends(s: &str) -> Result<ParsedTransaction, Box<dyn Error>> {
         comments,
         exchange: None,
         receipt_number: Some(receipt_number),
+        currency: None,
+        exchange_rate: None,
+        savings_plan_id: None,
     };
 
     Ok(ParsedTransaction {
         content: t,
+        fee_items: Vec::new(),
+        distribution: Some(distribution),
         over_the_counter: true,
+        depot_number: parse_depot_number(s),
         account_number,
     })
 }
 
-fn parse_purchase(s: &str) -> Result<ParsedTransaction, Box<dyn Error>> {
-    let re = Regex::new(r"ISIN\s+([A-Z]{2}[A-Z0-9]{10})\s").unwrap();
-    let isin = re.captures_iter(&s).next().ok_or("no isin match")?[1].to_owned();
-
-    let re = Regex::new(r"Abrechnungs-Nr\.\s+(\d+)\s").unwrap();
-    let receipt_number = re
-        .captures_iter(&s)
-        .next()
-        .ok_or("no receipt number match")?[1]
-        .parse()?;
-
-    let re_units_price =
-        Regex::new(r"Nominal\s+STK ([\d\.]+,\d+)\s+Kurs\s+EUR ([\d\.]+,\d+)").unwrap();
-    let cpt = re_units_price
-        .captures_iter(&s)
-        .next()
-        .ok_or("no units/price match")?;
-    let units = parse_float(&cpt[1])?;
+fn parse_purchase(s: &str) -> Result<ParsedTransaction, FieldError> {
+    let isin = capture(s, r"ISIN\s+([A-Z]{2}[A-Z0-9]{10})\s", "isin", "ISIN")?[1].to_owned();
+
+    let receipt_number = parse_field(
+        &capture(
+            s,
+            r"Abrechnungs-Nr\.\s+(\d+)\s",
+            "receipt number",
+            "Abrechnungs-Nr.",
+        )?[1],
+        "receipt number",
+        "Abrechnungs-Nr.",
+    )?;
+
+    let cpt = capture(
+        s,
+        r"Nominal\s+STK ([\d\.]+,\d+)\s+Kurs\s+EUR ([\d\.]+,\d+)",
+        "units/price",
+        "Nominal",
+    )?;
+    let units = parse_amount(&cpt[1], "units/price", "Nominal")?;
     // let price = parse_float(&cpt[2])?;
 
-    let re = Regex::new(r"Kurswert\sEUR ([\d\.]+,\d{2})").unwrap();
-    let amount_no_fees = (parse_float(&re.captures_iter(&s).next().ok_or("no amount match")?[1])?
-        * 100.0)
+    let amount_no_fees = (parse_amount(
+        &capture(s, r"Kurswert\sEUR ([\d\.]+,\d{2})", "amount", "Kurswert")?[1],
+        "amount",
+        "Kurswert",
+    )? * 100.0)
         .round() as i64;
 
-    let re = Regex::new(r"Konto-Nr\.\s+(\d+)\s").unwrap();
-    let account_number = re.captures_iter(&s).next().ok_or("no account match")?[1].parse()?;
+    let account_number = parse_field(
+        &capture(s, r"Konto-Nr\.\s+(\d+)\s", "account number", "Konto-Nr.")?[1],
+        "account number",
+        "Konto-Nr.",
+    )?;
 
-    let re = Regex::new(r"Betrag zu Ihren Lasten\s+EUR ([\d\.]+,\d{2})").unwrap();
-    let amount = (parse_float(&re.captures_iter(&s).next().ok_or("no amount match")?[1])? * 100.0)
+    let amount = (parse_amount(
+        &capture(
+            s,
+            r"Betrag zu Ihren Lasten\s+EUR ([\d\.]+,\d{2})",
+            "total amount",
+            "Betrag zu Ihren Lasten",
+        )?[1],
+        "total amount",
+        "Betrag zu Ihren Lasten",
+    )? * 100.0)
         .round() as i64;
 
-    let re =
-Regex::new(r"Handelstag\s(\d\d)\.(\d\d)\.(\d{4})\s+Handelszeit\s(\d\d):(\d\d)\s+Handelsplatz\s(Börse|außerbörslich)\s(.+?)\s*\n").unwrap();
-    let cpt = re
-        .captures_iter(&s)
-        .next()
-        .ok_or("no date and place match")?;
+    let cpt = capture(
+        s,
+        r"Handelstag\s(\d\d)\.(\d\d)\.(\d{4})\s+Handelszeit\s(\d\d):(\d\d)\s+Handelsplatz\s(Börse|außerbörslich)\s(.+?)\s*\n",
+        "date and place",
+        "Handelstag",
+    )?;
 
     let date = Local
-        .ymd(cpt[3].parse()?, cpt[2].parse()?, cpt[1].parse()?)
-        .and_hms(cpt[4].parse()?, cpt[5].parse()?, 0)
+        .ymd(
+            parse_field(&cpt[3], "date and place", "Handelstag")?,
+            parse_field(&cpt[2], "date and place", "Handelstag")?,
+            parse_field(&cpt[1], "date and place", "Handelstag")?,
+        )
+        .and_hms(
+            parse_field(&cpt[4], "date and place", "Handelstag")?,
+            parse_field(&cpt[5], "date and place", "Handelstag")?,
+            0,
+        )
         .with_timezone(&Utc);
 
     let ex_type = cpt[6].to_owned();
@@ -277,26 +978,141 @@ Regex::new(r"Handelstag\s(\d\d)\.(\d\d)\.(\d{4})\s+Handelszeit\s(\d\d):(\d\d)\s+
         ex = m[1].to_owned();
     }
 
+    let fees = amount_no_fees - amount; // sign should be negative
+    let mut fee_items = parse_fee_items(s)?;
+    if fee_items.iter().map(|f| f.amount).sum::<i64>() < fees {
+        warn!(
+            "Itemised fees of receipt {} exceed its total fees, ignoring them",
+            receipt_number
+        );
+        fee_items.clear();
+    }
+
     let t = NewTransaction {
         account_id: -1,
         isin,
         date,
         units,
         amount: -amount_no_fees, // -units*price in cents (or simply the amount in case of dividends); does not include fees; negative sign -> gave money away.
-        fees: amount_no_fees - amount, // sign should be negative
+        fees,
         onvista_exchange_id: None,
         comments: String::new(),
         exchange: Some(ex),
         receipt_number: Some(receipt_number),
+        currency: None,
+        exchange_rate: None,
+        savings_plan_id: None,
     };
 
     Ok(ParsedTransaction {
         content: t,
+        fee_items,
+        distribution: None,
         over_the_counter: ex_type == "außerbörslich",
+        depot_number: parse_depot_number(s),
         account_number,
     })
 }
 
+// fees are listed as '<label> EUR <amount>' between the Kurswert and the account details
+fn parse_fee_items(s: &str) -> Result<Vec<ParsedFee>, FieldError> {
+    let section = match s.find("Kurswert") {
+        Some(i) => s[i..].split("Konto-Nr.").next().unwrap_or(""),
+        None => return Ok(Vec::new()),
+    };
+
+    let re = Regex::new(r"(?m)^(\S[^\n]*?)\s+EUR ([\d\.]+,\d{2})\s*$").unwrap();
+    re.captures_iter(section)
+        .filter(|cpt| !cpt[1].starts_with("Kurswert"))
+        .map(|cpt| {
+            let amount = parse_amount(&cpt[2], "fees", "Kurswert")?;
+
+            Ok(ParsedFee {
+                category: fees::classify(&cpt[1]).to_owned(),
+                description: cpt[1].to_owned(),
+                amount: -(amount * 100.0).round() as i64,
+            })
+        })
+        .collect()
+}
+
+// replace personal data in the extracted text of a receipt, keeping it parseable:
+// the address block is replaced by a placeholder, IBANs are zeroed and numbers with 5 or more digits
+// are replaced consistently by other numbers of the same length.
+pub fn anonymise(text: &str) -> String {
+    let re_iban = Regex::new(r"\b[A-Z]{2}\d{2}(\s?[A-Z0-9]{4}){3,7}(\s?[A-Z0-9]{1,3})?\b").unwrap();
+    let text = re_iban.replace_all(text, |c: &Captures| {
+        c[0].chars()
+            .enumerate()
+            .map(|(i, x)| match x {
+                _ if i < 2 || x.is_whitespace() => x,
+                _ => '0',
+            })
+            .collect::<String>()
+    });
+
+    // decimal places of prices are kept
+    let re_number = Regex::new(r"(?m)(^|[^\d,\.])(\d{5,})\b").unwrap();
+    let text = re_number.replace_all(&text, |c: &Captures| {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        c[2].hash(&mut hasher);
+        let mut h = hasher.finish();
+
+        let digits = (0..c[2].len())
+            .map(|i| {
+                let d = (h % 10) as u32;
+                h /= 10;
+                if h == 0 {
+                    h = 0x9e37_79b9_7f4a_7c15;
+                }
+                std::char::from_digit(if i == 0 && d == 0 { 1 } else { d }, 10).unwrap()
+            })
+            .collect::<String>();
+        format!("{}{}", &c[1], digits)
+    });
+
+    let re_address = Regex::new(r"(?m)^(Herrn?|Frau|Firma)\b.*\n(.*\n){0,2}").unwrap();
+    re_address
+        .replace_all(
+            &text,
+            "Herrn Max Mustermann\nMusterstraße 1\n12345 Musterstadt\n",
+        )
+        .into_owned()
+}
+
+// the depot number is not required, it only helps finding the right account
+fn parse_depot_number(s: &str) -> Option<String> {
+    let re = Regex::new(r"Depot-Nr\.\s+(\d+)\s").unwrap();
+    re.captures(s).map(|cpt| cpt[1].to_owned())
+}
+
+// part of the text around `pos`, with account numbers and other long numbers masked
+fn snippet(s: &str, pos: usize) -> String {
+    let start = s[..pos]
+        .char_indices()
+        .rev()
+        .nth(40)
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    let end = s[pos..]
+        .char_indices()
+        .nth(160)
+        .map(|(i, _)| pos + i)
+        .unwrap_or_else(|| s.len());
+
+    redact(&s[start..end])
+}
+
+fn redact(s: &str) -> String {
+    let re = Regex::new(r"[A-Z]{2}\d{2}(\s?[A-Z0-9]{4}){3,7}(\s?[A-Z0-9]{1,3})?|\d{5,}").unwrap();
+    re.replace_all(s, |c: &Captures| {
+        c[0].chars()
+            .map(|x| if x.is_whitespace() { x } else { 'X' })
+            .collect::<String>()
+    })
+    .into_owned()
+}
+
 fn parse_float(s: &str) -> Result<f64, <f64 as FromStr>::Err> {
     s.replace(".", "").replace(",", ".").parse()
 }
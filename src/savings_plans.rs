@@ -0,0 +1,252 @@
+use crate::history;
+use crate::models::*;
+use crate::schema::*;
+
+use chrono::{Datelike, Local, NaiveDate};
+use diesel::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+// executions may happen a few days away from the planned date (weekends, holidays)
+const TOLERANCE_DAYS: i64 = 5;
+
+pub const EXECUTED: &str = "executed";
+pub const CHANGED: &str = "changed"; // executed, but with a different amount
+pub const MISSED: &str = "missed";
+pub const PENDING: &str = "pending"; // due, but could still be executed
+pub const UNSCHEDULED: &str = "unscheduled"; // linked to the plan, but not near any due date
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Execution {
+    pub due: NaiveDate,
+    pub status: String,
+    pub transaction_id: Option<i32>,
+    pub amount: Option<f64>, // paid including fees, positive
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanStatus {
+    pub plan: SavingsPlan,
+    pub executions: Vec<Execution>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Contribution {
+    pub savings_plan_id: i32,
+    pub isin: String,
+    pub date: NaiveDate,
+    pub amount: f64,
+}
+
+pub fn load(connection: &PgConnection, uid: i32) -> Result<Vec<SavingsPlan>, Box<dyn Error>> {
+    Ok(savings_plans::table
+        .inner_join(accounts::table)
+        .filter(accounts::user_id.eq(uid))
+        .order(savings_plans::id.asc())
+        .load::<(SavingsPlan, Account)>(connection)?
+        .into_iter()
+        .map(|(p, _)| p)
+        .collect())
+}
+
+// planned execution dates in [start_date, until] (and before the end date of the plan)
+pub fn due_dates(plan: &SavingsPlan, until: NaiveDate) -> Vec<NaiveDate> {
+    let until = plan.end_date.map(|e| e.min(until)).unwrap_or(until);
+    let mut month = plan.start_date.year() * 12 + plan.start_date.month0() as i32;
+
+    let mut result = Vec::new();
+    loop {
+        let d = day_in_month(
+            month.div_euclid(12),
+            month.rem_euclid(12) as u32 + 1,
+            plan.execution_day as u32,
+        );
+        if d > until {
+            break;
+        }
+        if d >= plan.start_date {
+            result.push(d);
+        }
+
+        month += plan.interval_months.max(1);
+    }
+
+    result
+}
+
+// `day` of the given month, or its last day if the month is shorter
+fn day_in_month(year: i32, month: u32, day: u32) -> NaiveDate {
+    (1..=day.max(1))
+        .rev()
+        .find_map(|d| NaiveDate::from_ymd_opt(year, month, d))
+        .unwrap()
+}
+
+fn local_day(t: &Transaction) -> NaiveDate {
+    t.date.with_timezone(&Local).date().naive_local()
+}
+
+fn is_near_due_date(plan: &SavingsPlan, day: NaiveDate) -> bool {
+    due_dates(plan, day + chrono::Duration::days(TOLERANCE_DAYS))
+        .iter()
+        .any(|d| (*d - day).num_days().abs() <= TOLERANCE_DAYS)
+}
+
+// link purchases that are not linked yet to the plan of their account and stock
+// if they happened near one of its due dates; returns the updated transactions.
+// every link is recorded as a change from `source`
+pub fn link(
+    connection: &PgConnection,
+    user_id: i32,
+    source: &str,
+    ts: Vec<Transaction>,
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let account_ids = ts.iter().map(|t| t.account_id).collect::<Vec<_>>();
+    let plans = savings_plans::table
+        .filter(savings_plans::account_id.eq_any(&account_ids))
+        .load::<SavingsPlan>(connection)?;
+    if plans.is_empty() {
+        return Ok(ts);
+    }
+
+    let mut count = 0;
+    let ts = ts
+        .into_iter()
+        .map(|mut t| {
+            if t.units <= 0.0 || t.savings_plan_id.is_some() {
+                return Ok(t);
+            }
+
+            if let Some(p) = plans.iter().find(|p| {
+                p.account_id == t.account_id
+                    && p.isin == t.isin
+                    && is_near_due_date(p, local_day(&t))
+            }) {
+                let before = t.clone();
+                connection.transaction::<_, Box<dyn Error>, _>(|| {
+                    diesel::update(transactions::table.find(t.id))
+                        .set(transactions::savings_plan_id.eq(p.id))
+                        .execute(connection)?;
+                    t.savings_plan_id = Some(p.id);
+                    history::updated(connection, user_id, source, &before, &t)?;
+                    Ok(())
+                })?;
+                count += 1;
+            }
+
+            Ok(t)
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+    if count > 0 {
+        info!("Linked {} transactions to their savings plans", count);
+    }
+
+    Ok(ts)
+}
+
+// match the transactions linked to the plan with its due dates until `today`:
+// each due date gets the first unused execution within the tolerance
+pub fn executions(plan: &SavingsPlan, ts: &[Transaction], today: NaiveDate) -> Vec<Execution> {
+    let mut linked = ts
+        .iter()
+        .filter(|t| t.savings_plan_id == Some(plan.id))
+        .collect::<Vec<_>>();
+    linked.sort_by_key(|t| t.date);
+    let mut used = vec![false; linked.len()];
+
+    let mut result = Vec::new();
+    for due in due_dates(plan, today) {
+        let found = linked
+            .iter()
+            .enumerate()
+            .find(|(i, t)| !used[*i] && (local_day(t) - due).num_days().abs() <= TOLERANCE_DAYS);
+
+        result.push(match found {
+            Some((i, t)) => {
+                used[i] = true;
+                let paid = -(t.amount + t.fees);
+                Execution {
+                    due,
+                    status: if paid == plan.amount {
+                        EXECUTED
+                    } else {
+                        CHANGED
+                    }
+                    .to_string(),
+                    transaction_id: Some(t.id),
+                    amount: Some(paid as f64 / 100.0),
+                }
+            }
+            None => Execution {
+                due,
+                status: if (today - due).num_days() <= TOLERANCE_DAYS {
+                    PENDING
+                } else {
+                    MISSED
+                }
+                .to_string(),
+                transaction_id: None,
+                amount: None,
+            },
+        });
+    }
+
+    for (t, _) in linked.iter().zip(used).filter(|(_, u)| !u) {
+        result.push(Execution {
+            due: local_day(t),
+            status: UNSCHEDULED.to_string(),
+            transaction_id: Some(t.id),
+            amount: Some(-(t.amount + t.fees) as f64 / 100.0),
+        });
+    }
+
+    result.sort_by_key(|e| e.due);
+    result
+}
+
+pub fn check(connection: &PgConnection, uid: i32) -> Result<Vec<PlanStatus>, Box<dyn Error>> {
+    let plans = load(connection, uid)?;
+    let ts = transactions::table
+        .inner_join(accounts::table)
+        .filter(accounts::user_id.eq(uid))
+        .filter(
+            transactions::savings_plan_id.eq_any(plans.iter().map(|p| p.id).collect::<Vec<_>>()),
+        )
+        .select(transactions::all_columns)
+        .load::<Transaction>(connection)?;
+    let today = Local::today().naive_local();
+
+    Ok(plans
+        .into_iter()
+        .map(|p| PlanStatus {
+            executions: executions(&p, &ts, today),
+            plan: p,
+        })
+        .collect())
+}
+
+// planned contributions of all plans in (from, until], ordered by date
+pub fn projection(plans: &[SavingsPlan], from: NaiveDate, until: NaiveDate) -> Vec<Contribution> {
+    let mut result = plans
+        .iter()
+        .flat_map(|p| {
+            due_dates(p, until)
+                .into_iter()
+                .filter(move |d| *d > from)
+                .map(move |d| Contribution {
+                    savings_plan_id: p.id,
+                    isin: p.isin.clone(),
+                    date: d,
+                    amount: p.amount as f64 / 100.0,
+                })
+        })
+        .collect::<Vec<_>>();
+
+    result.sort_by_key(|c| c.date);
+    result
+}
@@ -4,6 +4,110 @@ table! {
         user_id -> Int4,
         name -> Text,
         iban -> Nullable<Text>,
+        depot_number -> Nullable<Text>,
+        clearing_account_number -> Nullable<Text>,
+        broker -> Nullable<Text>,
+        currency -> Text,
+    }
+}
+
+table! {
+    allocation_targets (id) {
+        id -> Int4,
+        user_id -> Int4,
+        isin -> Nullable<Text>,
+        tag_id -> Nullable<Int4>,
+        weight -> Float8,
+    }
+}
+
+table! {
+    base_rates (year) {
+        year -> Int4,
+        rate -> Float8,
+    }
+}
+
+table! {
+    cash_transactions (id) {
+        id -> Int4,
+        account_id -> Int4,
+        date -> Timestamptz,
+        kind -> Text,
+        amount -> Int8,
+        comments -> Text,
+    }
+}
+
+table! {
+    changes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        date -> Timestamptz,
+        source -> Text,
+        table_name -> Text,
+        record_id -> Int4,
+        action -> Text,
+        before -> Nullable<Text>,
+        after -> Nullable<Text>,
+        parent_id -> Nullable<Int4>,
+        reverted_by -> Nullable<Int4>,
+    }
+}
+
+table! {
+    corporate_actions (id) {
+        id -> Int4,
+        isin -> Text,
+        date -> Timestamptz,
+        kind -> Text,
+        ratio -> Float8,
+        target_isin -> Nullable<Text>,
+        cost_fraction -> Nullable<Float8>,
+        comments -> Text,
+    }
+}
+
+table! {
+    csv_profiles (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        mapping -> Text,
+    }
+}
+
+table! {
+    distributions (id) {
+        id -> Int4,
+        transaction_id -> Int4,
+        ex_date -> Nullable<Date>,
+        pay_date -> Date,
+        amount_per_unit -> Float8,
+        currency -> Text,
+        units -> Float8,
+        exchange_rate -> Nullable<Float8>,
+        taxes -> Int8,
+        period_start -> Nullable<Date>,
+        period_end -> Nullable<Date>,
+    }
+}
+
+table! {
+    exchange_rates (currency, date) {
+        currency -> Text,
+        date -> Date,
+        rate -> Float8,
+    }
+}
+
+table! {
+    fee_items (id) {
+        id -> Int4,
+        transaction_id -> Int4,
+        category -> Text,
+        amount -> Int8,
+        description -> Text,
     }
 }
 
@@ -39,6 +143,32 @@ table! {
     }
 }
 
+table! {
+    receipt_documents (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        hash -> Bytea,
+        content -> Bytea,
+        receipt_numbers -> Array<Int8>,
+        created -> Timestamptz,
+    }
+}
+
+table! {
+    savings_plans (id) {
+        id -> Int4,
+        account_id -> Int4,
+        isin -> Text,
+        amount -> Int8,
+        interval_months -> Int4,
+        execution_day -> Int4,
+        start_date -> Date,
+        end_date -> Nullable<Date>,
+        comments -> Text,
+    }
+}
+
 table! {
     stock_exchanges (onvista_record_id) {
         isin -> Bpchar,
@@ -50,6 +180,16 @@ table! {
     }
 }
 
+table! {
+    stock_holdings (id) {
+        id -> Int4,
+        isin -> Bpchar,
+        name -> Text,
+        holding_isin -> Nullable<Text>,
+        weight -> Float8,
+    }
+}
+
 table! {
     stock_infos (isin) {
         isin -> Bpchar,
@@ -79,6 +219,36 @@ table! {
     }
 }
 
+table! {
+    stock_tags (isin, tag_id) {
+        isin -> Text,
+        tag_id -> Int4,
+    }
+}
+
+table! {
+    tags (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+    }
+}
+
+table! {
+    tax_allowances (user_id, year) {
+        user_id -> Int4,
+        year -> Int4,
+        amount -> Int8,
+    }
+}
+
+table! {
+    transaction_tags (transaction_id, tag_id) {
+        transaction_id -> Int4,
+        tag_id -> Int4,
+    }
+}
+
 table! {
     transactions (id) {
         id -> Int4,
@@ -92,6 +262,21 @@ table! {
         comments -> Text,
         exchange -> Nullable<Text>,
         receipt_number -> Nullable<Int8>,
+        currency -> Nullable<Text>,
+        exchange_rate -> Nullable<Float8>,
+        savings_plan_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    transfers (id) {
+        id -> Int4,
+        from_account_id -> Int4,
+        to_account_id -> Int4,
+        isin -> Text,
+        date -> Timestamptz,
+        units -> Float8,
+        comments -> Text,
     }
 }
 
@@ -101,23 +286,56 @@ table! {
         name -> Text,
         full_name -> Text,
         hash -> Text,
+        reporting_currency -> Text,
     }
 }
 
 joinable!(accounts -> users (user_id));
+joinable!(allocation_targets -> tags (tag_id));
+joinable!(allocation_targets -> users (user_id));
+joinable!(cash_transactions -> accounts (account_id));
+joinable!(changes -> users (user_id));
+joinable!(csv_profiles -> users (user_id));
+joinable!(distributions -> transactions (transaction_id));
+joinable!(fee_items -> transactions (transaction_id));
 joinable!(historical_prices -> stock_exchanges (onvista_record_id));
 joinable!(push_subscriptions -> users (user_id));
 joinable!(realtime_prices -> stock_exchanges (onvista_record_id));
+joinable!(receipt_documents -> users (user_id));
+joinable!(savings_plans -> accounts (account_id));
 joinable!(stock_exchanges -> stock_infos (isin));
+joinable!(stock_holdings -> stock_infos (isin));
+joinable!(stock_tags -> tags (tag_id));
+joinable!(tags -> users (user_id));
+joinable!(tax_allowances -> users (user_id));
+joinable!(transaction_tags -> tags (tag_id));
+joinable!(transaction_tags -> transactions (transaction_id));
 joinable!(transactions -> accounts (account_id));
 
 allow_tables_to_appear_in_same_query!(
     accounts,
+    allocation_targets,
+    base_rates,
+    cash_transactions,
+    changes,
+    corporate_actions,
+    csv_profiles,
+    distributions,
+    exchange_rates,
+    fee_items,
     historical_prices,
     push_subscriptions,
     realtime_prices,
+    receipt_documents,
+    savings_plans,
     stock_exchanges,
+    stock_holdings,
     stock_infos,
+    stock_tags,
+    tags,
+    tax_allowances,
+    transaction_tags,
     transactions,
+    transfers,
     users,
 );
@@ -1,5 +1,7 @@
+use crate::history;
 use crate::models::*;
 use crate::schema::*;
+use crate::validation::Validator;
 
 use chrono::{DateTime, Utc};
 use diesel::prelude::*;
@@ -7,12 +9,38 @@ use itertools::Itertools;
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::error::Error;
 
+// backups written before a table existed lack its field
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NativeFormat {
     users: Vec<User>,
     accounts: Vec<Account>,
     transactions: Vec<Transaction>,
+    #[serde(default)]
+    tags: Vec<Tag>,
+    #[serde(default)]
+    transaction_tags: Vec<TransactionTag>,
+    #[serde(default)]
+    stock_tags: Vec<StockTag>,
+    #[serde(default)]
+    savings_plans: Vec<SavingsPlan>,
+    #[serde(default)]
+    fee_items: Vec<FeeItem>,
+    #[serde(default)]
+    distributions: Vec<Distribution>,
+    #[serde(default)]
+    cash_transactions: Vec<CashTransaction>,
+    #[serde(default)]
+    transfers: Vec<Transfer>,
+    #[serde(default)]
+    corporate_actions: Vec<CorporateAction>,
+    #[serde(default)]
+    csv_profiles: Vec<CsvProfile>,
+    #[serde(default)]
+    allocation_targets: Vec<AllocationTarget>,
+    #[serde(default)]
+    tax_allowances: Vec<TaxAllowance>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -102,45 +130,191 @@ impl NativeFormat {
             users: us,
             accounts: acs,
             transactions: ts,
+            tags: tags::table
+                .load::<Tag>(connection)
+                .expect("Error loading tags"),
+            transaction_tags: transaction_tags::table
+                .load::<TransactionTag>(connection)
+                .expect("Error loading transaction tags"),
+            stock_tags: stock_tags::table
+                .load::<StockTag>(connection)
+                .expect("Error loading stock tags"),
+            savings_plans: savings_plans::table
+                .load::<SavingsPlan>(connection)
+                .expect("Error loading savings plans"),
+            fee_items: fee_items::table
+                .load::<FeeItem>(connection)
+                .expect("Error loading fee items"),
+            distributions: distributions::table
+                .load::<Distribution>(connection)
+                .expect("Error loading distributions"),
+            cash_transactions: cash_transactions::table
+                .load::<CashTransaction>(connection)
+                .expect("Error loading cash transactions"),
+            transfers: transfers::table
+                .load::<Transfer>(connection)
+                .expect("Error loading transfers"),
+            corporate_actions: corporate_actions::table
+                .load::<CorporateAction>(connection)
+                .expect("Error loading corporate actions"),
+            csv_profiles: csv_profiles::table
+                .load::<CsvProfile>(connection)
+                .expect("Error loading csv profiles"),
+            allocation_targets: allocation_targets::table
+                .load::<AllocationTarget>(connection)
+                .expect("Error loading allocation targets"),
+            tax_allowances: tax_allowances::table
+                .load::<TaxAllowance>(connection)
+                .expect("Error loading tax allowances"),
         }
     }
 
+    // the imported accounts and transactions are recorded as changes of their users
     pub fn write_to(&self, connection: &PgConnection) {
-        let user_count = diesel::insert_into(users::table)
-            .values(&self.users)
+        connection
+            .transaction::<_, Box<dyn Error>, _>(|| {
+                let user_count = diesel::insert_into(users::table)
+                    .values(&self.users)
+                    .on_conflict_do_nothing()
+                    .execute(connection)
+                    .expect("Error writing users into the database");
+                info!(
+                    "imported {} of {} users into the database",
+                    user_count,
+                    self.users.len()
+                );
+
+                let accounts = diesel::insert_into(accounts::table)
+                    .values(&self.accounts)
+                    .on_conflict_do_nothing()
+                    .get_results::<Account>(connection)
+                    .expect("Error writing accounts into the database");
+                info!(
+                    "imported {} of {} accounts into the database",
+                    accounts.len(),
+                    self.accounts.len()
+                );
+
+                // before the transactions, which refer to them
+                let count = diesel::insert_into(savings_plans::table)
+                    .values(&self.savings_plans)
+                    .on_conflict_do_nothing()
+                    .execute(connection)?;
+                log_import(count, self.savings_plans.len(), "savings plans");
+
+                let ts = diesel::insert_into(transactions::table)
+                    .values(
+                        &self
+                            .transactions
+                            .iter()
+                            .map(TransactionRow::from)
+                            .collect::<Vec<_>>(),
+                    )
+                    .on_conflict_do_nothing()
+                    .get_results::<Transaction>(connection)
+                    .expect("Error writing transactions into the database");
+                info!(
+                    "imported {} of {} transactions into the database",
+                    ts.len(),
+                    self.transactions.len()
+                );
+
+                self.write_details(connection)?;
+                record_imports(connection, &accounts, &ts)
+            })
+            .expect("Error recording imported records");
+    }
+
+    // everything that refers to the users, accounts and transactions
+    fn write_details(&self, connection: &PgConnection) -> Result<(), Box<dyn Error>> {
+        let count = diesel::insert_into(tags::table)
+            .values(&self.tags)
             .on_conflict_do_nothing()
-            .execute(connection)
-            .expect("Error writing users into the database");
-        info!(
-            "imported {} of {} users into the database",
-            user_count,
-            self.users.len()
-        );
-
-        let account_count = diesel::insert_into(accounts::table)
-            .values(&self.accounts)
+            .execute(connection)?;
+        log_import(count, self.tags.len(), "tags");
+        let count = diesel::insert_into(transaction_tags::table)
+            .values(&self.transaction_tags)
             .on_conflict_do_nothing()
-            .execute(connection)
-            .expect("Error writing accounts into the database");
-        info!(
-            "imported {} of {} accounts into the database",
-            account_count,
-            self.accounts.len()
-        );
-
-        let transaction_count = diesel::insert_into(transactions::table)
-            .values(&self.transactions)
+            .execute(connection)?;
+        log_import(count, self.transaction_tags.len(), "transaction tags");
+        let count = diesel::insert_into(stock_tags::table)
+            .values(&self.stock_tags)
             .on_conflict_do_nothing()
-            .execute(connection)
-            .expect("Error writing transactions into the database");
-        info!(
-            "imported {} of {} transactions into the database",
-            transaction_count,
-            self.transactions.len()
-        );
+            .execute(connection)?;
+        log_import(count, self.stock_tags.len(), "stock tags");
+        let count = diesel::insert_into(fee_items::table)
+            .values(&self.fee_items)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.fee_items.len(), "fee items");
+        let count = diesel::insert_into(distributions::table)
+            .values(&self.distributions)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.distributions.len(), "distributions");
+        let count = diesel::insert_into(cash_transactions::table)
+            .values(&self.cash_transactions)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.cash_transactions.len(), "cash transactions");
+        let count = diesel::insert_into(transfers::table)
+            .values(&self.transfers)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.transfers.len(), "transfers");
+        let count = diesel::insert_into(corporate_actions::table)
+            .values(&self.corporate_actions)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.corporate_actions.len(), "corporate actions");
+        let count = diesel::insert_into(csv_profiles::table)
+            .values(&self.csv_profiles)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.csv_profiles.len(), "csv profiles");
+        let count = diesel::insert_into(allocation_targets::table)
+            .values(&self.allocation_targets)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.allocation_targets.len(), "allocation targets");
+        let count = diesel::insert_into(tax_allowances::table)
+            .values(&self.tax_allowances)
+            .on_conflict_do_nothing()
+            .execute(connection)?;
+        log_import(count, self.tax_allowances.len(), "tax allowances");
+
+        // the rows keep their ids, so the sequences have to continue after them
+        for table in [
+            "users",
+            "accounts",
+            "transactions",
+            "tags",
+            "savings_plans",
+            "fee_items",
+            "distributions",
+            "cash_transactions",
+            "transfers",
+            "corporate_actions",
+            "csv_profiles",
+            "allocation_targets",
+        ]
+        .iter()
+        {
+            diesel::sql_query(format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), COALESCE(MAX(id), 0) + 1, false) FROM {0}",
+                table
+            ))
+            .execute(connection)?;
+        }
+
+        Ok(())
     }
 }
 
+fn log_import(count: usize, total: usize, what: &str) {
+    info!("imported {} of {} {} into the database", count, total, what);
+}
+
 #[derive(Deserialize, Debug)]
 pub struct MoneyDBFormat {
     users: Vec<MoneyDBUser>,
@@ -152,7 +326,17 @@ pub struct MoneyDBFormat {
 }
 
 impl MoneyDBFormat {
+    // everything is imported at once; the accounts and transactions are recorded as changes of their users
     pub fn write_to(&self, connection: &PgConnection) {
+        connection
+            .transaction::<_, Box<dyn Error>, _>(|| {
+                self.import(connection);
+                Ok(())
+            })
+            .expect("Error importing data");
+    }
+
+    fn import(&self, connection: &PgConnection) {
         let users = diesel::insert_into(users::table)
             .values(self.users.iter().map(|u| u.convert()).collect::<Vec<_>>())
             .load::<User>(connection)
@@ -190,24 +374,62 @@ impl MoneyDBFormat {
             .load::<StockExchange>(connection)
             .expect("Error loading stock exchanges from the database");
 
-        let transaction_count = diesel::insert_into(transactions::table)
-            .values(
-                self.transactions
-                    .iter()
-                    .map(|t| t.convert(&account_map, &stock_map, &exchanges))
-                    .collect::<Vec<_>>(),
-            )
-            .execute(connection)
+        let ts = self
+            .transactions
+            .iter()
+            .map(|t| t.convert(&account_map, &stock_map, &exchanges))
+            .collect::<Vec<_>>();
+
+        let validator = Validator::new(connection, accounts.iter().map(|a| a.id).collect())
+            .expect("Error loading stock exchanges from the database");
+        for t in ts.iter() {
+            if let Err(e) = validator.check(t) {
+                panic!("{} ({} on {})", e, t.isin, t.date);
+            }
+        }
+
+        let ts = diesel::insert_into(transactions::table)
+            .values(ts)
+            .get_results::<Transaction>(connection)
             .expect("Error writing transactions into the database");
-        info!(
-            "imported {} transactions into the database",
-            transaction_count
-        );
+        info!("imported {} transactions into the database", ts.len());
+
+        record_imports(connection, &accounts, &ts).expect("Error recording imported records");
 
         warn!("Note that the passwords of all users have been reset!")
     }
 }
 
+fn record_imports(
+    connection: &PgConnection,
+    accounts: &[Account],
+    ts: &[Transaction],
+) -> Result<(), Box<dyn Error>> {
+    for a in accounts.iter() {
+        history::inserted(
+            connection,
+            a.user_id,
+            history::IMPORT,
+            std::slice::from_ref(a),
+        )?;
+    }
+
+    // transactions may belong to accounts that existed before
+    let owners = accounts::table
+        .select((accounts::id, accounts::user_id))
+        .load::<(i32, i32)>(connection)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    for t in ts.iter() {
+        let uid = owners
+            .get(&t.account_id)
+            .ok_or_else(|| format!("account {} does not exist", t.account_id))?;
+        history::inserted(connection, *uid, history::IMPORT, std::slice::from_ref(t))?;
+    }
+
+    Ok(())
+}
+
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct MoneyDBUser {
@@ -222,6 +444,7 @@ impl MoneyDBUser {
             name: self.name.to_string(),
             full_name: self.full_name.to_string(),
             hash: String::new(),
+            reporting_currency: String::from("EUR"),
         }
     }
 }
@@ -242,6 +465,10 @@ impl MoneyDBSecurityAccount {
             user_id: *user_map.get(&self.user_id).unwrap(),
             name: format!("{} ({})", self.title, self.broker),
             iban: Some(self.iban.to_string()),
+            depot_number: None,
+            clearing_account_number: None,
+            broker: Some(self.broker.to_string()),
+            currency: String::from("EUR"),
         }
     }
 }
@@ -284,6 +511,9 @@ impl MoneyDBTransaction {
             exchange: None,
             receipt_number: None,
             comments: String::new(),
+            currency: None,
+            exchange_rate: None,
+            savings_plan_id: None,
         };
 
         let ex = exchanges
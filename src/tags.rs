@@ -0,0 +1,200 @@
+use crate::models::*;
+use crate::schema::*;
+
+use diesel::prelude::*;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+// a tag together with everything that carries it
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TagInfo {
+    pub id: i32,
+    pub name: String,
+    pub transaction_ids: Vec<i32>, // only the ones that are tagged directly
+    pub isins: Vec<String>,
+}
+
+pub fn find(
+    connection: &PgConnection,
+    uid: i32,
+    tag_name: &str,
+) -> Result<Option<Tag>, Box<dyn Error>> {
+    Ok(tags::table
+        .filter(tags::user_id.eq(uid))
+        .filter(tags::name.eq(tag_name))
+        .first::<Tag>(connection)
+        .optional()?)
+}
+
+pub fn find_or_create(
+    connection: &PgConnection,
+    uid: i32,
+    tag_name: &str,
+) -> Result<Tag, Box<dyn Error>> {
+    if let Some(t) = find(connection, uid, tag_name)? {
+        return Ok(t);
+    }
+
+    let t: Tag = diesel::insert_into(tags::table)
+        .values(&NewTag {
+            user_id: uid,
+            name: tag_name.to_string(),
+        })
+        .get_result(connection)?;
+    info!("Created tag {:?}", t);
+
+    Ok(t)
+}
+
+pub fn list(connection: &PgConnection, uid: i32) -> Result<Vec<TagInfo>, Box<dyn Error>> {
+    let ts = tags::table
+        .filter(tags::user_id.eq(uid))
+        .order(tags::name.asc())
+        .load::<Tag>(connection)?;
+    let ids = ts.iter().map(|t| t.id).collect::<Vec<_>>();
+
+    let tts = transaction_tags::table
+        .filter(transaction_tags::tag_id.eq_any(&ids))
+        .load::<TransactionTag>(connection)?;
+    let sts = stock_tags::table
+        .filter(stock_tags::tag_id.eq_any(&ids))
+        .load::<StockTag>(connection)?;
+
+    Ok(ts
+        .into_iter()
+        .map(|t| TagInfo {
+            transaction_ids: tts
+                .iter()
+                .filter(|tt| tt.tag_id == t.id)
+                .map(|tt| tt.transaction_id)
+                .collect(),
+            isins: sts
+                .iter()
+                .filter(|st| st.tag_id == t.id)
+                .map(|st| st.isin.clone())
+                .collect(),
+            id: t.id,
+            name: t.name,
+        })
+        .collect())
+}
+
+// ids of the user's transactions that carry the tag, either directly or through their stock
+pub fn transaction_ids(
+    connection: &PgConnection,
+    uid: i32,
+    tag_name: &str,
+) -> Result<Vec<i32>, Box<dyn Error>> {
+    let tag = match find(connection, uid, tag_name)? {
+        Some(t) => t,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut ids = transaction_tags::table
+        .filter(transaction_tags::tag_id.eq(tag.id))
+        .select(transaction_tags::transaction_id)
+        .load::<i32>(connection)?;
+
+    let isins = stock_tags::table
+        .filter(stock_tags::tag_id.eq(tag.id))
+        .select(stock_tags::isin)
+        .load::<String>(connection)?;
+    ids.extend(
+        transactions::table
+            .inner_join(accounts::table)
+            .filter(accounts::user_id.eq(uid))
+            .filter(transactions::isin.eq_any(&isins))
+            .select(transactions::id)
+            .load::<i32>(connection)?,
+    );
+
+    ids.sort_unstable();
+    ids.dedup();
+    Ok(ids)
+}
+
+pub fn tag_transaction(
+    connection: &PgConnection,
+    uid: i32,
+    transaction_id: i32,
+    tag_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    transactions::table
+        .inner_join(accounts::table)
+        .filter(accounts::user_id.eq(uid))
+        .filter(transactions::id.eq(transaction_id))
+        .select(transactions::id)
+        .first::<i32>(connection)
+        .optional()?
+        .ok_or_else(|| format!("there is no transaction with id {}", transaction_id))?;
+    let tag = find_or_create(connection, uid, tag_name)?;
+
+    diesel::insert_into(transaction_tags::table)
+        .values(&TransactionTag {
+            transaction_id,
+            tag_id: tag.id,
+        })
+        .on_conflict_do_nothing()
+        .execute(connection)?;
+
+    Ok(())
+}
+
+pub fn untag_transaction(
+    connection: &PgConnection,
+    uid: i32,
+    transaction_id: i32,
+    tag_name: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let tag = match find(connection, uid, tag_name)? {
+        Some(t) => t,
+        None => return Ok(0),
+    };
+
+    Ok(diesel::delete(
+        transaction_tags::table
+            .filter(transaction_tags::transaction_id.eq(transaction_id))
+            .filter(transaction_tags::tag_id.eq(tag.id)),
+    )
+    .execute(connection)?)
+}
+
+pub fn tag_stock(
+    connection: &PgConnection,
+    uid: i32,
+    isin: &str,
+    tag_name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let tag = find_or_create(connection, uid, tag_name)?;
+
+    diesel::insert_into(stock_tags::table)
+        .values(&StockTag {
+            isin: isin.to_string(),
+            tag_id: tag.id,
+        })
+        .on_conflict_do_nothing()
+        .execute(connection)?;
+
+    Ok(())
+}
+
+pub fn untag_stock(
+    connection: &PgConnection,
+    uid: i32,
+    isin: &str,
+    tag_name: &str,
+) -> Result<usize, Box<dyn Error>> {
+    let tag = match find(connection, uid, tag_name)? {
+        Some(t) => t,
+        None => return Ok(0),
+    };
+
+    Ok(diesel::delete(
+        stock_tags::table
+            .filter(stock_tags::isin.eq(isin))
+            .filter(stock_tags::tag_id.eq(tag.id)),
+    )
+    .execute(connection)?)
+}
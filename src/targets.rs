@@ -0,0 +1,191 @@
+use crate::models::*;
+use crate::schema::{allocation_targets, stock_infos, stock_tags, tags};
+use crate::validation::{isin_is_valid, FieldError, ValidationErrors};
+
+use diesel::prelude::*;
+use log::info;
+use std::collections::HashMap;
+use std::error::Error;
+
+// weights may add up to a bit more than 100% because of rounding
+const TOLERANCE: f64 = 1e-6;
+
+pub fn load(connection: &PgConnection, uid: i32) -> Result<Vec<AllocationTarget>, Box<dyn Error>> {
+    Ok(allocation_targets::table
+        .filter(allocation_targets::user_id.eq(uid))
+        .order(allocation_targets::id.asc())
+        .load::<AllocationTarget>(connection)?)
+}
+
+// `tag_ids` are the tags of the user, `isins` the stocks that are known
+pub fn check(
+    targets: &[NewAllocationTarget],
+    tag_ids: &[i32],
+    isins: &[String],
+) -> Result<(), ValidationErrors> {
+    let mut errors = Vec::new();
+    let mut error = |field: String, message: &str| {
+        errors.push(FieldError {
+            field,
+            message: message.to_string(),
+        })
+    };
+
+    for (i, t) in targets.iter().enumerate() {
+        match (t.isin.as_ref(), t.tag_id) {
+            (Some(isin), None) => {
+                if !isin_is_valid(isin) {
+                    error(format!("[{}].isin", i), "not a valid ISIN");
+                } else if !isins.contains(isin) {
+                    error(format!("[{}].isin", i), "unknown stock");
+                } else if targets
+                    .iter()
+                    .filter(|o| o.isin.as_ref() == Some(isin))
+                    .count()
+                    > 1
+                {
+                    error(format!("[{}].isin", i), "has more than one target");
+                }
+            }
+            (None, Some(tag_id)) => {
+                if !tag_ids.contains(&tag_id) {
+                    error(format!("[{}].tagId", i), "unknown tag");
+                } else if targets.iter().filter(|o| o.tag_id == Some(tag_id)).count() > 1 {
+                    error(format!("[{}].tagId", i), "has more than one target");
+                }
+            }
+            _ => error(
+                format!("[{}].isin", i),
+                "either isin or tagId has to be set",
+            ),
+        }
+
+        if !t.weight.is_finite() || t.weight <= 0.0 || t.weight > 1.0 {
+            error(
+                format!("[{}].weight", i),
+                "must be greater than 0 and at most 1",
+            );
+        }
+    }
+
+    if targets.iter().map(|t| t.weight).sum::<f64>() > 1.0 + TOLERANCE {
+        error("weight".to_string(), "must not add up to more than 100%");
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors { errors })
+    }
+}
+
+// replace all targets of a user (the user ids of `targets` are ignored)
+pub fn replace(
+    connection: &PgConnection,
+    uid: i32,
+    targets: &[NewAllocationTarget],
+) -> Result<Vec<AllocationTarget>, Box<dyn Error>> {
+    let tag_ids = tags::table
+        .filter(tags::user_id.eq(uid))
+        .select(tags::id)
+        .load::<i32>(connection)?;
+    let isins = stock_infos::table
+        .filter(
+            stock_infos::isin.eq_any(
+                targets
+                    .iter()
+                    .filter_map(|t| t.isin.clone())
+                    .collect::<Vec<_>>(),
+            ),
+        )
+        .select(stock_infos::isin)
+        .load::<String>(connection)?;
+    check(targets, &tag_ids, &isins)?;
+
+    let targets = targets
+        .iter()
+        .map(|t| NewAllocationTarget {
+            user_id: uid,
+            ..t.clone()
+        })
+        .collect::<Vec<_>>();
+
+    connection.transaction::<_, Box<dyn Error>, _>(|| {
+        diesel::delete(allocation_targets::table.filter(allocation_targets::user_id.eq(uid)))
+            .execute(connection)?;
+        let result = diesel::insert_into(allocation_targets::table)
+            .values(&targets)
+            .load::<AllocationTarget>(connection)?;
+        info!("Set {} allocation target(s) of user {}", result.len(), uid);
+
+        Ok(result)
+    })
+}
+
+// stocks that belong to the group of a target with a tag
+pub fn members(
+    connection: &PgConnection,
+    targets: &[AllocationTarget],
+) -> Result<HashMap<i32, Vec<String>>, Box<dyn Error>> {
+    let tag_ids = targets.iter().filter_map(|t| t.tag_id).collect::<Vec<_>>();
+
+    let mut result: HashMap<i32, Vec<String>> = HashMap::new();
+    for st in stock_tags::table
+        .filter(stock_tags::tag_id.eq_any(&tag_ids))
+        .order(stock_tags::isin.asc())
+        .load::<StockTag>(connection)?
+    {
+        result.entry(st.tag_id).or_default().push(st.isin);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(isin: Option<&str>, tag_id: Option<i32>, weight: f64) -> NewAllocationTarget {
+        NewAllocationTarget {
+            user_id: 1,
+            isin: isin.map(|i| i.to_string()),
+            tag_id,
+            weight,
+        }
+    }
+
+    fn fields(r: Result<(), ValidationErrors>) -> Vec<String> {
+        r.err()
+            .map(|e| e.errors.into_iter().map(|e| e.field).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn targets_need_known_stocks_and_tags() {
+        let isins = vec!["IE00B4L5Y983".to_string()];
+        let ts = vec![
+            target(Some("IE00B4L5Y983"), None, 0.5),
+            target(Some("IE00B4L5Y984"), None, 0.1), // wrong check digit
+            target(Some("US0378331005"), None, 0.1), // valid, but unknown
+            target(None, Some(7), 0.1),
+            target(None, Some(8), 0.1),
+        ];
+
+        assert_eq!(
+            fields(check(&ts, &[7], &isins)),
+            vec!["[1].isin", "[2].isin", "[4].tagId"]
+        );
+    }
+
+    #[test]
+    fn weights_must_not_exceed_100_percent() {
+        let isins = vec!["IE00B4L5Y983".to_string()];
+        let ts = vec![
+            target(Some("IE00B4L5Y983"), None, 0.8),
+            target(None, Some(7), 0.3),
+        ];
+
+        assert_eq!(fields(check(&ts, &[7], &isins)), vec!["weight"]);
+        assert!(check(&ts[..1], &[7], &isins).is_ok());
+    }
+}
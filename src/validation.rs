@@ -0,0 +1,313 @@
+use crate::models::*;
+use crate::schema::{accounts, savings_plans, stock_exchanges};
+
+use chrono::Utc;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    pub field: String, // name of the field in the json representation
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let msgs = self
+            .errors
+            .iter()
+            .map(|e| format!("{}: {}", e.field, e.message))
+            .collect::<Vec<_>>();
+        write!(f, "invalid transaction ({})", msgs.join("; "))
+    }
+}
+
+impl Error for ValidationErrors {}
+
+// 2 letters (country), 9 alphanumeric characters and a check digit
+pub fn isin_is_valid(isin: &str) -> bool {
+    let chars = isin.chars().collect::<Vec<_>>();
+    if chars.len() != 12
+        || !chars[..2].iter().all(|c| c.is_ascii_uppercase())
+        || !chars[2..11]
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        || !chars[11].is_ascii_digit()
+    {
+        return false;
+    }
+
+    // letters count as two digits (A = 10, ..., Z = 35), then the Luhn algorithm is applied
+    let digits = chars[..11]
+        .iter()
+        .flat_map(|c| {
+            let v = c.to_digit(36).unwrap();
+            if v >= 10 {
+                vec![v / 10, v % 10]
+            } else {
+                vec![v]
+            }
+        })
+        .collect::<Vec<_>>();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| {
+            if i % 2 == 0 {
+                let d = d * 2;
+                d / 10 + d % 10
+            } else {
+                *d
+            }
+        })
+        .sum();
+
+    (10 - sum % 10) % 10 == chars[11].to_digit(10).unwrap()
+}
+
+// checks transactions before they are written, no matter where they come from
+pub struct Validator {
+    account_ids: Vec<i32>,          // the ones the transactions may belong to
+    exchange_ids: Vec<i32>,         // known onvista exchange ids
+    plans: Vec<(i32, i32, String)>, // id, account and isin of the savings plans of the accounts
+}
+
+impl Validator {
+    pub fn new(
+        connection: &PgConnection,
+        account_ids: Vec<i32>,
+    ) -> Result<Validator, Box<dyn Error>> {
+        let exchange_ids = stock_exchanges::table
+            .select(stock_exchanges::onvista_exchange_id)
+            .distinct()
+            .load::<Option<i32>>(connection)?
+            .into_iter()
+            .flatten()
+            .collect();
+        let plans = savings_plans::table
+            .filter(savings_plans::account_id.eq_any(&account_ids))
+            .select((
+                savings_plans::id,
+                savings_plans::account_id,
+                savings_plans::isin,
+            ))
+            .load::<(i32, i32, String)>(connection)?;
+
+        Ok(Validator {
+            account_ids,
+            exchange_ids,
+            plans,
+        })
+    }
+
+    // validator for transactions of the given user
+    pub fn load(connection: &PgConnection, uid: i32) -> Result<Validator, Box<dyn Error>> {
+        let account_ids = accounts::table
+            .filter(accounts::user_id.eq(uid))
+            .select(accounts::id)
+            .load::<i32>(connection)?;
+
+        Validator::new(connection, account_ids)
+    }
+
+    pub fn check(&self, t: &NewTransaction) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        let mut error = |field: &str, message: &str| {
+            errors.push(FieldError {
+                field: field.to_string(),
+                message: message.to_string(),
+            })
+        };
+
+        if !isin_is_valid(&t.isin) {
+            error("isin", "not a valid ISIN");
+        }
+        if !self.account_ids.contains(&t.account_id) {
+            error("accountId", "unknown account");
+        }
+        if let Some(id) = t.onvista_exchange_id {
+            if !self.exchange_ids.contains(&id) {
+                error("onvistaExchangeId", "unknown exchange");
+            }
+        }
+        if let Some(id) = t.savings_plan_id {
+            // the plan has to be one of the account's for the same stock
+            if !self
+                .plans
+                .iter()
+                .any(|(p, a, isin)| *p == id && *a == t.account_id && *isin == t.isin)
+            {
+                error("savingsPlanId", "unknown savings plan");
+            }
+        }
+        if t.date > Utc::now() {
+            error("date", "must not be in the future");
+        }
+
+        if !t.units.is_finite() {
+            error("units", "must be a number");
+        } else if t.units > 0.0 && t.amount > 0 {
+            error("amount", "must not be positive for purchases");
+        } else if t.units < 0.0 && t.amount < 0 {
+            error("amount", "must not be negative for sales");
+        }
+        if t.fees > 0 {
+            error("fees", "must not be positive");
+        }
+
+        if let Some(c) = &t.currency {
+            if c.len() != 3 || !c.chars().all(|c| c.is_ascii_uppercase()) {
+                error("currency", "must be a three-letter code (e.g. USD)");
+            }
+        }
+        if let Some(r) = t.exchange_rate {
+            if !r.is_finite() || r <= 0.0 {
+                error("exchangeRate", "must be positive");
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors { errors })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn validator() -> Validator {
+        Validator {
+            account_ids: vec![1],
+            exchange_ids: vec![2],
+            plans: vec![(3, 1, "US0378331005".to_string())],
+        }
+    }
+
+    fn purchase() -> NewTransaction {
+        NewTransaction {
+            account_id: 1,
+            isin: "US0378331005".to_string(),
+            date: Utc::now() - Duration::days(1),
+            units: 10.0,
+            amount: -100000,
+            fees: -100,
+            onvista_exchange_id: Some(2),
+            comments: String::new(),
+            exchange: None,
+            receipt_number: None,
+            currency: Some("USD".to_string()),
+            exchange_rate: Some(1.2),
+            savings_plan_id: Some(3),
+        }
+    }
+
+    fn fields(t: &NewTransaction) -> Vec<String> {
+        match validator().check(t) {
+            Ok(()) => Vec::new(),
+            Err(e) => e.errors.into_iter().map(|e| e.field).collect(),
+        }
+    }
+
+    #[test]
+    fn isins() {
+        assert!(isin_is_valid("US0378331005"));
+        assert!(isin_is_valid("IE00B4L5Y983"));
+        assert!(isin_is_valid("DE0007164600"));
+        assert!(!isin_is_valid("US0378331006"));
+        assert!(!isin_is_valid("IE00B4L5Y984"));
+        assert!(!isin_is_valid("us0378331005"));
+        assert!(!isin_is_valid("US037833100"));
+        assert!(!isin_is_valid("US037833100X"));
+    }
+
+    #[test]
+    fn valid_transactions() {
+        assert!(fields(&purchase()).is_empty());
+
+        let sale = NewTransaction {
+            units: -10.0,
+            amount: 100000,
+            ..purchase()
+        };
+        assert!(fields(&sale).is_empty());
+
+        // dividends have no units, their amount may have either sign
+        let dividend = NewTransaction {
+            units: 0.0,
+            amount: 1000,
+            savings_plan_id: None,
+            ..purchase()
+        };
+        assert!(fields(&dividend).is_empty());
+    }
+
+    #[test]
+    fn signs() {
+        let t = NewTransaction {
+            amount: 100000,
+            ..purchase()
+        };
+        assert_eq!(fields(&t), vec!["amount"]);
+
+        let t = NewTransaction {
+            units: -10.0,
+            ..purchase()
+        };
+        assert_eq!(fields(&t), vec!["amount"]);
+
+        let t = NewTransaction {
+            fees: 100,
+            ..purchase()
+        };
+        assert_eq!(fields(&t), vec!["fees"]);
+
+        let t = NewTransaction {
+            units: f64::NAN,
+            ..purchase()
+        };
+        assert_eq!(fields(&t), vec!["units"]);
+    }
+
+    #[test]
+    fn references_and_formats() {
+        let t = NewTransaction {
+            account_id: 4,
+            onvista_exchange_id: Some(5),
+            date: Utc::now() + Duration::days(1),
+            currency: Some("usd".to_string()),
+            exchange_rate: Some(0.0),
+            ..purchase()
+        };
+        assert_eq!(
+            fields(&t),
+            vec![
+                "accountId",
+                "onvistaExchangeId",
+                "savingsPlanId",
+                "date",
+                "currency",
+                "exchangeRate"
+            ]
+        );
+
+        // the savings plan is for another stock
+        let t = NewTransaction {
+            isin: "IE00B4L5Y983".to_string(),
+            ..purchase()
+        };
+        assert_eq!(fields(&t), vec!["savingsPlanId"]);
+    }
+}
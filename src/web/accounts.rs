@@ -1,3 +1,4 @@
+use crate::history;
 use crate::models::*;
 use crate::schema::*;
 use crate::web::user::UserId;
@@ -9,6 +10,7 @@ use log::info;
 use rocket::http::Status;
 use rocket_contrib::databases::diesel;
 use rocket_contrib::json::Json;
+use std::error::Error;
 
 #[get("/accounts?<offset>&<count>")]
 pub async fn list(
@@ -49,20 +51,22 @@ pub async fn get(uid: UserId, connection: DbConn, id: i32) -> Option<Json<Accoun
 pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
     connection
         .run(move |c| {
-            let row_count = diesel::delete(
-                accounts::table
-                    .filter(accounts::user_id.eq(*uid))
-                    .filter(accounts::id.eq(id)),
-            )
-            .execute(c)
-            .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-            if row_count == 0 {
-                Err(Status::NotFound)
-            } else {
-                info!("Deleted record {} from the account table", id);
+            let acc = accounts::table
+                .filter(accounts::user_id.eq(*uid))
+                .filter(accounts::id.eq(id))
+                .first::<Account>(c)
+                .optional()
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+            c.transaction::<_, Box<dyn Error>, _>(|| {
+                // recorded first, along with the transactions etc. that are deleted with it
+                history::deleted(c, *uid, history::WEB, &acc)?;
+                diesel::delete(accounts::table.filter(accounts::id.eq(id))).execute(c)?;
                 Ok(())
-            }
+            })
+            .map_err(log_error_and_500)?;
+            info!("Deleted record {} from the account table", id);
+            Ok(())
         })
         .await
 }
@@ -79,21 +83,24 @@ pub async fn update(
             let mut acc = account.0;
             acc.user_id = *uid;
 
-            let row_count = diesel::update(
-                accounts::table
-                    .filter(accounts::id.eq(id))
-                    .filter(accounts::user_id.eq(*uid)),
-            )
-            .set(acc)
-            .execute(c)
-            .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let before = accounts::table
+                .filter(accounts::id.eq(id))
+                .filter(accounts::user_id.eq(*uid))
+                .first::<Account>(c)
+                .optional()
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
 
-            if row_count == 0 {
-                Err(Status::NotFound)
-            } else {
-                info!("updated record {} from the account table", id);
+            c.transaction::<_, Box<dyn Error>, _>(|| {
+                let after = diesel::update(accounts::table.filter(accounts::id.eq(id)))
+                    .set(acc)
+                    .get_result::<Account>(c)?;
+                history::updated(c, *uid, history::WEB, &before, &after)?;
                 Ok(())
-            }
+            })
+            .map_err(log_error_and_500)?;
+            info!("updated record {} from the account table", id);
+            Ok(())
         })
         .await
 }
@@ -109,12 +116,14 @@ pub async fn create(
             let mut acc = account.0;
             acc.user_id = *uid;
 
-            let t: Account = diesel::insert_into(crate::schema::accounts::table)
-                .values(&acc)
-                .get_result(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-            Ok(Json(t))
+            c.transaction::<_, Box<dyn Error>, _>(|| {
+                let t: Account = diesel::insert_into(crate::schema::accounts::table)
+                    .values(&acc)
+                    .get_result(c)?;
+                history::inserted(c, *uid, history::WEB, std::slice::from_ref(&t))?;
+                Ok(Json(t))
+            })
+            .map_err(log_error_and_500)
         })
         .await
 }
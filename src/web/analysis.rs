@@ -1,9 +1,16 @@
+use crate::analysis::allocation;
+use crate::analysis::allocation::Allocation;
+use crate::analysis::overlap;
+use crate::analysis::overlap::OverlapReport;
 use crate::analysis::performance;
 use crate::analysis::performance::PortfolioPerformance;
 use crate::analysis::plots;
 use crate::analysis::plots::{DataSourceSelection, PortfolioPlot, StockPlot};
 use crate::analysis::portfolio;
 use crate::analysis::portfolio::Portfolio;
+use crate::analysis::risk;
+use crate::analysis::risk::RiskReport;
+use crate::analysis::Options;
 use crate::models::*;
 use crate::web::user::UserId;
 use crate::web::DbConn;
@@ -12,11 +19,14 @@ use chrono::offset::TimeZone;
 use chrono::{NaiveDate, Utc};
 use rocket_contrib::json::Json;
 
-#[get("/analysis/portfolio?<date>")]
+#[get("/analysis/portfolio?<date>&<include_cash>&<account>&<tag>")]
 pub async fn compute_historic_portfolio(
     uid: UserId,
     connection: DbConn,
     date: String, // DateTime<Utc> not possible
+    include_cash: Option<bool>,
+    account: Option<i32>,
+    tag: Option<String>,
 ) -> Option<Json<Portfolio<HistoricalPrice>>> {
     connection
         .run(move |c| {
@@ -24,44 +34,153 @@ pub async fn compute_historic_portfolio(
                 .datetime_from_str(&format!("{} 17:30:00", &date), "%Y-%m-%d %H:%M:%S")
                 .ok()?;
 
-            portfolio::compute(c, *uid, date).ok().map(Json)
+            let options = Options {
+                include_cash: include_cash.unwrap_or(false),
+                account_id: account,
+                tag,
+                ..Default::default()
+            };
+            portfolio::compute(c, *uid, date, &options).ok().map(Json)
         })
         .await
 }
 
-#[get("/analysis/portfolio")]
+#[get("/analysis/portfolio?<include_cash>&<account>&<tag>", rank = 2)]
 pub async fn compute_realtime_portfolio(
     uid: UserId,
     connection: DbConn,
+    include_cash: Option<bool>,
+    account: Option<i32>,
+    tag: Option<String>,
 ) -> Option<Json<Portfolio<RealtimePrice>>> {
     connection
         .run(move |c| {
             let now = Utc::now();
-            portfolio::compute(c, *uid, now).ok().map(Json)
+            let options = Options {
+                include_cash: include_cash.unwrap_or(false),
+                account_id: account,
+                tag,
+                ..Default::default()
+            };
+            portfolio::compute(c, *uid, now, &options).ok().map(Json)
         })
         .await
 }
 
-#[get("/analysis/performance")]
+#[get("/analysis/performance?<include_cash>&<account>&<tag>&<benchmark>")]
 pub async fn compute_performance(
     uid: UserId,
     connection: DbConn,
+    include_cash: Option<bool>,
+    account: Option<i32>,
+    tag: Option<String>,
+    benchmark: Option<String>,
 ) -> Option<Json<Vec<PortfolioPerformance>>> {
     connection
         .run(move |c| {
             let now = Utc::now();
-            performance::compute(c, *uid, now).ok().map(Json)
+            let options = Options {
+                include_cash: include_cash.unwrap_or(false),
+                account_id: account,
+                tag,
+                benchmark,
+            };
+            performance::compute(c, *uid, now, &options).ok().map(Json)
         })
         .await
 }
 
-#[get("/analysis/plots/portfolio?<start>&<end>&<source>")]
+#[get("/analysis/allocation?<include_cash>&<account>&<tag>")]
+pub async fn compute_allocation(
+    uid: UserId,
+    connection: DbConn,
+    include_cash: Option<bool>,
+    account: Option<i32>,
+    tag: Option<String>,
+) -> Option<Json<Allocation>> {
+    connection
+        .run(move |c| {
+            let options = Options {
+                include_cash: include_cash.unwrap_or(false),
+                account_id: account,
+                tag,
+                ..Default::default()
+            };
+            allocation::compute(c, *uid, Utc::now(), &options)
+                .ok()
+                .map(Json)
+        })
+        .await
+}
+
+#[get("/analysis/overlap?<account>&<tag>")]
+pub async fn compute_overlap(
+    uid: UserId,
+    connection: DbConn,
+    account: Option<i32>,
+    tag: Option<String>,
+) -> Option<Json<OverlapReport>> {
+    connection
+        .run(move |c| {
+            let options = Options {
+                account_id: account,
+                tag,
+                ..Default::default()
+            };
+            overlap::compute(c, *uid, Utc::now(), &options)
+                .ok()
+                .map(Json)
+        })
+        .await
+}
+
+#[get("/analysis/risk?<include_cash>&<account>&<tag>&<benchmark>&<start>&<risk_free_rate>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn compute_risk(
+    uid: UserId,
+    connection: DbConn,
+    include_cash: Option<bool>,
+    account: Option<i32>,
+    tag: Option<String>,
+    benchmark: Option<String>,
+    start: Option<String>,
+    risk_free_rate: Option<f64>,
+) -> Option<Json<RiskReport>> {
+    connection
+        .run(move |c| {
+            let start = match start {
+                Some(s) => Some(NaiveDate::parse_from_str(&s, "%Y-%m-%d").ok()?),
+                None => None,
+            };
+            let options = Options {
+                include_cash: include_cash.unwrap_or(false),
+                account_id: account,
+                tag,
+                benchmark,
+            };
+            risk::compute(
+                c,
+                *uid,
+                Utc::now(),
+                &options,
+                start,
+                risk_free_rate.unwrap_or(0.0),
+            )
+            .ok()
+            .map(Json)
+        })
+        .await
+}
+
+#[get("/analysis/plots/portfolio?<start>&<end>&<source>&<include_cash>&<benchmark>")]
 pub async fn compute_portfolio_plot(
     uid: UserId,
     connection: DbConn,
     start: String,
     end: String,
     source: Option<String>,
+    include_cash: Option<bool>,
+    benchmark: Option<String>,
 ) -> Option<Json<PortfolioPlot>> {
     connection
         .run(move |c| {
@@ -83,9 +202,17 @@ pub async fn compute_portfolio_plot(
                 Some(DataSourceSelection::Automatic)
             };
 
-            plots::compute_portfolio_plot(c, *uid, start, end, source?)
-                .ok()
-                .map(Json)
+            plots::compute_portfolio_plot(
+                c,
+                *uid,
+                start,
+                end,
+                source?,
+                include_cash.unwrap_or(false),
+                benchmark.as_deref(),
+            )
+            .ok()
+            .map(Json)
         })
         .await
 }
@@ -0,0 +1,93 @@
+use crate::analysis::cash::{self, BalanceEntry};
+use crate::models::*;
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::info;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/accounts/<id>/cash")]
+pub async fn balance(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+) -> Result<Json<Vec<BalanceEntry>>, Status> {
+    connection
+        .run(move |c| {
+            let acc = accounts::table
+                .find(id)
+                .filter(accounts::user_id.eq(*uid)) // return a 404 on other accounts
+                .first::<Account>(c)
+                .map_err(|_| Status::NotFound)?;
+
+            let cts = CashTransaction::belonging_to(&acc)
+                .load::<CashTransaction>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let ts = Transaction::belonging_to(&acc)
+                .load::<Transaction>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(cash::running_balance(&cts, &ts)))
+        })
+        .await
+}
+
+#[post("/cash_transactions", data = "<transaction>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    transaction: Json<NewCashTransaction>,
+) -> Result<Json<CashTransaction>, Status> {
+    if !cash::KINDS.contains(&transaction.kind.as_str()) {
+        return Err(Status::BadRequest);
+    }
+
+    connection
+        .run(move |c| {
+            accounts::table
+                .find(transaction.account_id)
+                .filter(accounts::user_id.eq(*uid))
+                .first::<Account>(c)
+                .map_err(|_| Status::NotFound)?;
+
+            let ct: CashTransaction = diesel::insert_into(cash_transactions::table)
+                .values(&transaction.0)
+                .get_result(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(ct))
+        })
+        .await
+}
+
+#[delete("/cash_transactions/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let account_ids = accounts::table
+                .filter(accounts::user_id.eq(*uid))
+                .select(accounts::id)
+                .load::<i32>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let row_count = diesel::delete(
+                cash_transactions::table
+                    .filter(cash_transactions::id.eq(id))
+                    .filter(cash_transactions::account_id.eq_any(account_ids)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if row_count == 0 {
+                Err(Status::NotFound)
+            } else {
+                info!("Deleted record {} from the cash_transactions table", id);
+                Ok(())
+            }
+        })
+        .await
+}
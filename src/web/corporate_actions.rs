@@ -0,0 +1,105 @@
+use crate::analysis::corporate_actions::{self, SplitCandidate};
+use crate::models::*;
+use crate::schema::corporate_actions as actions;
+use crate::web::user::{self, UserId};
+use crate::web::util::log_error_and_500;
+use crate::web::{Config, DbConn};
+
+use diesel::prelude::*;
+use log::info;
+use rocket::http::Status;
+use rocket::State;
+use rocket_contrib::json::Json;
+
+#[get("/corporate_actions?<isin>")]
+pub async fn list(
+    _uid: UserId,
+    connection: DbConn,
+    isin: Option<String>,
+) -> Result<Json<Vec<CorporateAction>>, Status> {
+    connection
+        .run(move |c| {
+            let mut query = actions::table.order(actions::date.asc()).into_boxed();
+            if let Some(isin) = isin {
+                query = query.filter(actions::isin.eq(isin));
+            }
+
+            query
+                .load::<CorporateAction>(c)
+                .map(Json)
+                .map_err(|e| log_error_and_500(Box::new(e)))
+        })
+        .await
+}
+
+// corporate actions apply to the portfolios of all users, so only admins may change them
+#[post("/corporate_actions", data = "<action>")]
+pub async fn create(
+    config: State<'_, Config>,
+    uid: UserId,
+    connection: DbConn,
+    action: Json<NewCorporateAction>,
+) -> Result<Json<CorporateAction>, Status> {
+    if !corporate_actions::KINDS.contains(&action.kind.as_str()) {
+        return Err(Status::BadRequest);
+    }
+
+    let admins = config.admins.clone();
+    connection
+        .run(move |c| {
+            if !user::is_admin(&admins, c, *uid).map_err(|e| log_error_and_500(Box::new(e)))? {
+                return Err(Status::Forbidden);
+            }
+
+            let a: CorporateAction = diesel::insert_into(actions::table)
+                .values(&action.0)
+                .get_result(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            info!("Created corporate action {:?}", a);
+            Ok(Json(a))
+        })
+        .await
+}
+
+#[delete("/corporate_actions/<id>")]
+pub async fn delete(
+    config: State<'_, Config>,
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+) -> Result<(), Status> {
+    let admins = config.admins.clone();
+    connection
+        .run(move |c| {
+            if !user::is_admin(&admins, c, *uid).map_err(|e| log_error_and_500(Box::new(e)))? {
+                return Err(Status::Forbidden);
+            }
+
+            let row_count = diesel::delete(actions::table.find(id))
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if row_count == 0 {
+                Err(Status::NotFound)
+            } else {
+                info!("Deleted record {} from the corporate_actions table", id);
+                Ok(())
+            }
+        })
+        .await
+}
+
+#[get("/corporate_actions/split_candidates")]
+pub async fn split_candidates(
+    _uid: UserId,
+    connection: DbConn,
+) -> Result<Json<Vec<SplitCandidate>>, Status> {
+    connection
+        .run(move |c| {
+            corporate_actions::find_split_candidates(c)
+                .map(Json)
+                .map_err(log_error_and_500)
+        })
+        .await
+}
@@ -0,0 +1,84 @@
+use crate::csv_import::{self, CsvMapping};
+use crate::models::*;
+use crate::schema::csv_profiles;
+use crate::web::receipts::{wrap_error, ErrString, FileContents};
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::info;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/csv_profiles")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<CsvProfile>>, Status> {
+    connection
+        .run(move |c| {
+            csv_profiles::table
+                .filter(csv_profiles::user_id.eq(*uid))
+                .order(csv_profiles::name.asc())
+                .load::<CsvProfile>(c)
+                .map(Json)
+                .map_err(|e| log_error_and_500(Box::new(e)))
+        })
+        .await
+}
+
+#[put("/csv_profiles/<name>", data = "<mapping>")]
+pub async fn save(
+    uid: UserId,
+    connection: DbConn,
+    name: String,
+    mapping: Json<CsvMapping>,
+) -> Result<Json<CsvProfile>, Status> {
+    connection
+        .run(move |c| {
+            csv_import::save_profile(c, *uid, &name, &mapping.0)
+                .map(Json)
+                .map_err(log_error_and_500)
+        })
+        .await
+}
+
+#[delete("/csv_profiles/<name>")]
+pub async fn delete(uid: UserId, connection: DbConn, name: String) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let row_count = diesel::delete(
+                csv_profiles::table
+                    .filter(csv_profiles::user_id.eq(*uid))
+                    .filter(csv_profiles::name.eq(&name)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if row_count == 0 {
+                Err(Status::NotFound)
+            } else {
+                info!("Deleted csv profile '{}'", name);
+                Ok(())
+            }
+        })
+        .await
+}
+
+#[post("/csv_profiles/<name>/import", data = "<file>")]
+pub async fn import(
+    uid: UserId,
+    connection: DbConn,
+    name: String,
+    file: Json<FileContents>,
+) -> Result<Json<Vec<Transaction>>, Json<ErrString>> {
+    connection
+        .run(move |c| {
+            let buf = base64::decode(&file.bytes).map_err(|e| {
+                wrap_error(format!("{}: error decoding base64: {}", file.name, e).into())
+            })?;
+
+            Ok(Json(
+                csv_import::import(c, *uid, &name, &buf).map_err(wrap_error)?,
+            ))
+        })
+        .await
+}
@@ -0,0 +1,102 @@
+use crate::distributions::{self, DistributionInfo, DistributionSummary};
+use crate::models::{Distribution, NewDistribution, Transaction};
+use crate::schema::distributions as distributions_table;
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_500, Rejection};
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/distributions?<isin>&<year>")]
+pub async fn list(
+    uid: UserId,
+    connection: DbConn,
+    isin: Option<String>,
+    year: Option<i32>,
+) -> Result<Json<Vec<DistributionInfo>>, Status> {
+    connection
+        .run(move |c| {
+            distributions::list(c, *uid, isin.as_deref(), year)
+                .map(Json)
+                .map_err(log_error_and_500)
+        })
+        .await
+}
+
+#[get("/analysis/distributions?<isin>&<year>")]
+pub async fn summary(
+    uid: UserId,
+    connection: DbConn,
+    isin: Option<String>,
+    year: Option<i32>,
+) -> Result<Json<Vec<DistributionSummary>>, Status> {
+    connection
+        .run(move |c| {
+            distributions::list(c, *uid, isin.as_deref(), year)
+                .map(|ds| Json(distributions::summarize(&ds)))
+                .map_err(log_error_and_500)
+        })
+        .await
+}
+
+#[get("/transactions/<id>/distribution")]
+pub async fn get(uid: UserId, connection: DbConn, id: i32) -> Result<Json<Distribution>, Status> {
+    connection
+        .run(move |c| {
+            Transaction::find_owned(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            distributions::find(c, id)
+                .map_err(log_error_and_500)?
+                .map(Json)
+                .ok_or(Status::NotFound)
+        })
+        .await
+}
+
+#[put("/transactions/<id>/distribution", data = "<distribution>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    distribution: Json<NewDistribution>,
+) -> Result<Json<Distribution>, Rejection> {
+    connection
+        .run(move |c| {
+            let t = Transaction::find_owned(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+            distributions::check(&t, &distribution)?;
+
+            Ok(distributions::save(c, &t, &distribution)
+                .map(Json)
+                .map_err(log_error_and_500)?)
+        })
+        .await
+}
+
+#[delete("/transactions/<id>/distribution")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            Transaction::find_owned(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            let row_count = diesel::delete(
+                distributions_table::table.filter(distributions_table::transaction_id.eq(id)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if row_count == 0 {
+                Err(Status::NotFound)
+            } else {
+                Ok(())
+            }
+        })
+        .await
+}
@@ -0,0 +1,59 @@
+use crate::analysis::fees::{self as fee_report, FeeReport};
+use crate::fees;
+use crate::models::{FeeItem, NewFeeItem, Transaction};
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_500, Rejection};
+use crate::web::DbConn;
+
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/transactions/<id>/fees")]
+pub async fn list(uid: UserId, connection: DbConn, id: i32) -> Result<Json<Vec<FeeItem>>, Status> {
+    connection
+        .run(move |c| {
+            Transaction::find_owned(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            fees::list(c, &[id]).map(Json).map_err(log_error_and_500)
+        })
+        .await
+}
+
+// replaces all fee items of the transaction
+#[put("/transactions/<id>/fees", data = "<items>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    items: Json<Vec<NewFeeItem>>,
+) -> Result<Json<Vec<FeeItem>>, Rejection> {
+    connection
+        .run(move |c| {
+            let t = Transaction::find_owned(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+            fees::check(&t, &items)?;
+
+            Ok(fees::replace(c, &t, &items)
+                .map(Json)
+                .map_err(log_error_and_500)?)
+        })
+        .await
+}
+
+#[get("/analysis/fees?<year>")]
+pub async fn report(
+    uid: UserId,
+    connection: DbConn,
+    year: Option<i32>,
+) -> Result<Json<FeeReport>, Status> {
+    connection
+        .run(move |c| {
+            fee_report::compute(c, *uid, year)
+                .map(Json)
+                .map_err(log_error_and_500)
+        })
+        .await
+}
@@ -0,0 +1,49 @@
+use crate::history;
+use crate::models::*;
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::warn;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/changes?<table>&<record>")]
+pub async fn list(
+    uid: UserId,
+    connection: DbConn,
+    table: Option<String>,
+    record: Option<i32>,
+) -> Result<Json<Vec<Change>>, Status> {
+    connection
+        .run(move |c| {
+            history::list(c, *uid, table.as_deref(), record)
+                .map(Json)
+                .map_err(log_error_and_500)
+        })
+        .await
+}
+
+#[post("/changes/<id>/revert")]
+pub async fn revert(uid: UserId, connection: DbConn, id: i32) -> Result<Json<Change>, Status> {
+    connection
+        .run(move |c| {
+            crate::schema::changes::table
+                .find(id)
+                .filter(crate::schema::changes::user_id.eq(*uid))
+                .first::<Change>(c)
+                .optional()
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            // e.g. already reverted, or the record has been changed or deleted in the meantime
+            history::revert(c, *uid, history::WEB, id)
+                .map(Json)
+                .map_err(|e| {
+                    warn!("Unable to revert change {}: {}", id, e);
+                    Status::Conflict
+                })
+        })
+        .await
+}
@@ -1,11 +1,22 @@
 pub mod accounts;
 pub mod analysis;
+pub mod cash;
+pub mod corporate_actions;
+pub mod csv_import;
+pub mod distributions;
+pub mod fees;
+pub mod history;
 pub mod prices;
 pub mod push;
 pub mod receipts;
+pub mod savings_plans;
 pub mod static_files;
 pub mod stocks;
+pub mod tags;
+pub mod targets;
+pub mod taxes;
 pub mod transactions;
+pub mod transfers;
 pub mod user;
 mod util;
 
@@ -21,6 +32,7 @@ pub struct Config {
     pub log_level: LogLevel,
     pub database_url: String,
     pub application_server_key: String,
+    pub admins: Vec<String>, // user names
 }
 
 pub async fn handle(config: Config) {
@@ -50,6 +62,7 @@ pub async fn handle(config: Config) {
                 user::login,
                 user::logout,
                 user::info,
+                user::set_reporting_currency,
                 accounts::list,
                 accounts::get,
                 accounts::delete,
@@ -60,17 +73,66 @@ pub async fn handle(config: Config) {
                 transactions::delete,
                 transactions::update,
                 transactions::create,
+                transactions::batch,
                 stocks::list,
                 stocks::get,
                 prices::list,
                 analysis::compute_historic_portfolio,
                 analysis::compute_realtime_portfolio,
                 analysis::compute_performance,
+                analysis::compute_risk,
+                analysis::compute_allocation,
+                analysis::compute_overlap,
                 analysis::compute_stock_plot,
                 analysis::compute_portfolio_plot,
                 push::subscribe,
                 push::unsubscribe,
-                receipts::upload
+                receipts::upload,
+                receipts::download,
+                csv_import::list,
+                csv_import::save,
+                csv_import::delete,
+                csv_import::import,
+                corporate_actions::list,
+                corporate_actions::create,
+                corporate_actions::delete,
+                corporate_actions::split_candidates,
+                cash::balance,
+                cash::create,
+                cash::delete,
+                transfers::list,
+                transfers::create,
+                transfers::delete,
+                tags::list,
+                tags::delete,
+                tags::tag_transaction,
+                tags::untag_transaction,
+                tags::tag_stock,
+                tags::untag_stock,
+                savings_plans::list,
+                savings_plans::status,
+                savings_plans::projection,
+                savings_plans::create,
+                savings_plans::update,
+                savings_plans::delete,
+                history::list,
+                history::revert,
+                taxes::report,
+                taxes::list_base_rates,
+                taxes::list_allowances,
+                taxes::set_allowance,
+                taxes::delete_allowance,
+                fees::list,
+                fees::update,
+                fees::report,
+                distributions::list,
+                distributions::summary,
+                distributions::get,
+                distributions::update,
+                distributions::delete,
+                targets::list,
+                targets::update,
+                targets::rebalance
             ],
         )
         .mount("/", routes![static_files::serve, static_files::index])
@@ -1,10 +1,14 @@
 use crate::models::*;
 use crate::receipts;
 use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
 use crate::web::DbConn;
 
+use rocket::http::{ContentType, Status};
+use rocket::response;
 use rocket_contrib::json::Json;
 use serde::{Deserialize, Serialize};
+use std::io::Cursor;
 
 #[derive(Deserialize)]
 pub struct FileContents {
@@ -13,19 +17,44 @@ pub struct FileContents {
 }
 
 #[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ErrString {
     pub error: String,
+    pub details: Vec<receipts::ReceiptError>, // one entry per file/page that could not be parsed
+    pub unknown_depots: Vec<String>, // upload again with create_accounts=true to create them
 }
 
 fn wrap_string(e: String) -> Json<ErrString> {
-    Json(ErrString { error: e })
+    Json(ErrString {
+        error: e,
+        details: Vec::new(),
+        unknown_depots: Vec::new(),
+    })
 }
 
-#[post("/receipts", data = "<receipts>")]
+pub fn wrap_error(e: Box<dyn std::error::Error>) -> Json<ErrString> {
+    let details = e
+        .downcast_ref::<receipts::ReceiptErrors>()
+        .map(|es| es.0.clone())
+        .unwrap_or_default();
+    let unknown_depots = e
+        .downcast_ref::<receipts::UnknownDepots>()
+        .map(|ds| ds.0.clone())
+        .unwrap_or_default();
+
+    Json(ErrString {
+        error: format!("{}", e),
+        details,
+        unknown_depots,
+    })
+}
+
+#[post("/receipts?<create_accounts>", data = "<receipts>")]
 pub async fn upload(
     uid: UserId,
     connection: DbConn,
     receipts: Json<Vec<FileContents>>,
+    create_accounts: Option<bool>,
 ) -> Result<Json<Vec<Transaction>>, Json<ErrString>> {
     connection
         .run(move |c| {
@@ -43,8 +72,30 @@ pub async fn upload(
                 .collect::<Result<Vec<_>, Json<ErrString>>>()?;
 
             Ok(Json(
-                receipts::parse_mem(c, *uid, &files).map_err(|e| wrap_string(format!("{}", e)))?,
+                receipts::parse_mem(c, *uid, &files, create_accounts.unwrap_or(false))
+                    .map_err(wrap_error)?,
             ))
         })
         .await
 }
+
+#[get("/receipts/<receipt_number>")]
+pub async fn download(
+    uid: UserId,
+    connection: DbConn,
+    receipt_number: i64,
+) -> response::Result<'static> {
+    let doc = connection
+        .run(move |c| receipts::find_document(c, *uid, receipt_number).map_err(log_error_and_500))
+        .await?
+        .ok_or(Status::NotFound)?;
+
+    response::Response::build()
+        .header(ContentType::PDF)
+        .raw_header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", doc.name.replace('"', "")),
+        )
+        .sized_body(None, Cursor::new(doc.content))
+        .ok()
+}
@@ -0,0 +1,131 @@
+use crate::models::*;
+use crate::savings_plans::{self, Contribution, PlanStatus};
+use crate::schema::accounts;
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use chrono::{Duration, Local};
+use diesel::prelude::*;
+use log::info;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+fn check_account(c: &PgConnection, uid: i32, account_id: i32) -> Result<(), Status> {
+    accounts::table
+        .find(account_id)
+        .filter(accounts::user_id.eq(uid))
+        .first::<Account>(c)
+        .map(|_| ())
+        .map_err(|_| Status::NotFound)
+}
+
+fn check_plan(c: &PgConnection, uid: i32, id: i32) -> Result<(), Status> {
+    savings_plans::load(c, uid)
+        .map_err(log_error_and_500)?
+        .iter()
+        .find(|p| p.id == id)
+        .map(|_| ())
+        .ok_or(Status::NotFound)
+}
+
+#[get("/savings_plans")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<SavingsPlan>>, Status> {
+    connection
+        .run(move |c| {
+            savings_plans::load(c, *uid)
+                .map(Json)
+                .map_err(log_error_and_500)
+        })
+        .await
+}
+
+#[get("/savings_plans/status")]
+pub async fn status(uid: UserId, connection: DbConn) -> Result<Json<Vec<PlanStatus>>, Status> {
+    connection
+        .run(move |c| {
+            savings_plans::check(c, *uid)
+                .map(Json)
+                .map_err(log_error_and_500)
+        })
+        .await
+}
+
+#[get("/savings_plans/projection?<months>")]
+pub async fn projection(
+    uid: UserId,
+    connection: DbConn,
+    months: Option<u32>,
+) -> Result<Json<Vec<Contribution>>, Status> {
+    connection
+        .run(move |c| {
+            let ps = savings_plans::load(c, *uid).map_err(log_error_and_500)?;
+            let today = Local::today().naive_local();
+            let until =
+                today + Duration::days((months.unwrap_or(12) as f64 * 30.44).round() as i64);
+
+            Ok(Json(savings_plans::projection(&ps, today, until)))
+        })
+        .await
+}
+
+#[post("/savings_plans", data = "<plan>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    plan: Json<NewSavingsPlan>,
+) -> Result<Json<SavingsPlan>, Status> {
+    connection
+        .run(move |c| {
+            check_account(c, *uid, plan.account_id)?;
+
+            let p: SavingsPlan = diesel::insert_into(crate::schema::savings_plans::table)
+                .values(&plan.0)
+                .get_result(c)
+                .map_err(|_| Status::BadRequest)?;
+
+            Ok(Json(p))
+        })
+        .await
+}
+
+#[put("/savings_plans/<id>", data = "<plan>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    plan: Json<SavingsPlan>,
+) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            check_plan(c, *uid, id)?;
+            check_account(c, *uid, plan.account_id)?;
+
+            let mut p = plan.0;
+            p.id = id;
+            diesel::update(crate::schema::savings_plans::table.find(id))
+                .set(p)
+                .execute(c)
+                .map_err(|_| Status::BadRequest)?;
+
+            info!("updated record {} from the savings_plans table", id);
+            Ok(())
+        })
+        .await
+}
+
+#[delete("/savings_plans/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            check_plan(c, *uid, id)?;
+
+            diesel::delete(crate::schema::savings_plans::table.find(id))
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            info!("Deleted record {} from the savings_plans table", id);
+            Ok(())
+        })
+        .await
+}
@@ -0,0 +1,94 @@
+use crate::tags::{self, TagInfo};
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::info;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/tags")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<TagInfo>>, Status> {
+    connection
+        .run(move |c| tags::list(c, *uid).map(Json).map_err(log_error_and_500))
+        .await
+}
+
+#[delete("/tags/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let row_count = diesel::delete(
+                crate::schema::tags::table
+                    .filter(crate::schema::tags::id.eq(id))
+                    .filter(crate::schema::tags::user_id.eq(*uid)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if row_count == 0 {
+                Err(Status::NotFound)
+            } else {
+                info!("Deleted record {} from the tags table", id);
+                Ok(())
+            }
+        })
+        .await
+}
+
+#[put("/transactions/<id>/tags/<name>")]
+pub async fn tag_transaction(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    name: String,
+) -> Result<(), Status> {
+    connection
+        .run(move |c| tags::tag_transaction(c, *uid, id, &name).map_err(|_| Status::NotFound))
+        .await
+}
+
+#[delete("/transactions/<id>/tags/<name>")]
+pub async fn untag_transaction(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    name: String,
+) -> Result<(), Status> {
+    connection
+        .run(move |c| match tags::untag_transaction(c, *uid, id, &name) {
+            Ok(0) => Err(Status::NotFound),
+            Ok(_) => Ok(()),
+            Err(e) => Err(log_error_and_500(e)),
+        })
+        .await
+}
+
+#[put("/stocks/<isin>/tags/<name>")]
+pub async fn tag_stock(
+    uid: UserId,
+    connection: DbConn,
+    isin: String,
+    name: String,
+) -> Result<(), Status> {
+    connection
+        .run(move |c| tags::tag_stock(c, *uid, &isin, &name).map_err(log_error_and_500))
+        .await
+}
+
+#[delete("/stocks/<isin>/tags/<name>")]
+pub async fn untag_stock(
+    uid: UserId,
+    connection: DbConn,
+    isin: String,
+    name: String,
+) -> Result<(), Status> {
+    connection
+        .run(move |c| match tags::untag_stock(c, *uid, &isin, &name) {
+            Ok(0) => Err(Status::NotFound),
+            Ok(_) => Ok(()),
+            Err(e) => Err(log_error_and_500(e)),
+        })
+        .await
+}
@@ -0,0 +1,77 @@
+use crate::analysis::rebalancing::{self, Parameters, Rebalancing};
+use crate::analysis::Options;
+use crate::models::{AllocationTarget, NewAllocationTarget};
+use crate::targets;
+use crate::validation::ValidationErrors;
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_500, Rejection};
+use crate::web::DbConn;
+
+use chrono::Utc;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/targets")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<AllocationTarget>>, Status> {
+    connection
+        .run(move |c| targets::load(c, *uid).map(Json).map_err(log_error_and_500))
+        .await
+}
+
+// replaces all allocation targets of the user
+#[put("/targets", data = "<new_targets>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    new_targets: Json<Vec<NewAllocationTarget>>,
+) -> Result<Json<Vec<AllocationTarget>>, Rejection> {
+    connection
+        .run(move |c| {
+            targets::replace(c, *uid, &new_targets)
+                .map(Json)
+                .map_err(|e| match e.downcast::<ValidationErrors>() {
+                    Ok(errors) => Rejection::from(*errors),
+                    Err(e) => Rejection::from(log_error_and_500(e)),
+                })
+        })
+        .await
+}
+
+#[get(
+    "/analysis/rebalancing?<cash>&<allow_sales>&<min_order>&<fixed_fee>&<fee_rate>&<account>&<tag>"
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn rebalance(
+    uid: UserId,
+    connection: DbConn,
+    cash: Option<f64>,
+    allow_sales: Option<bool>,
+    min_order: Option<f64>,
+    fixed_fee: Option<f64>,
+    fee_rate: Option<f64>,
+    account: Option<i32>,
+    tag: Option<String>,
+) -> Result<Json<Rebalancing>, Rejection> {
+    let params = Parameters {
+        cash: cash.unwrap_or(0.0),
+        allow_sales: allow_sales.unwrap_or(false),
+        min_order: min_order.unwrap_or(0.0),
+        fixed_fee: fixed_fee.unwrap_or(0.0),
+        fee_rate: fee_rate.unwrap_or(0.0),
+    };
+    params.check()?;
+
+    connection
+        .run(move |c| {
+            let options = Options {
+                account_id: account,
+                tag,
+                ..Default::default()
+            };
+
+            rebalancing::compute(c, *uid, Utc::now(), &options, &params)
+                .map(Json)
+                .map_err(|e| Rejection::from(log_error_and_500(e)))
+        })
+        .await
+}
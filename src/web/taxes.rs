@@ -0,0 +1,108 @@
+use crate::analysis::tax::{self, TaxReport};
+use crate::models::{BaseRate, TaxAllowance};
+use crate::schema::{base_rates, tax_allowances};
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::info;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/taxes")]
+pub async fn report(uid: UserId, connection: DbConn) -> Result<Json<TaxReport>, Status> {
+    connection
+        .run(move |c| tax::compute(c, *uid).map(Json).map_err(log_error_and_500))
+        .await
+}
+
+#[get("/taxes/base_rates")]
+pub async fn list_base_rates(
+    _uid: UserId,
+    connection: DbConn,
+) -> Result<Json<Vec<BaseRate>>, Status> {
+    connection
+        .run(move |c| {
+            base_rates::table
+                .order(base_rates::year.asc())
+                .load::<BaseRate>(c)
+                .map(Json)
+                .map_err(|e| log_error_and_500(Box::new(e)))
+        })
+        .await
+}
+
+#[get("/taxes/allowances")]
+pub async fn list_allowances(
+    uid: UserId,
+    connection: DbConn,
+) -> Result<Json<Vec<TaxAllowance>>, Status> {
+    connection
+        .run(move |c| {
+            tax_allowances::table
+                .filter(tax_allowances::user_id.eq(*uid))
+                .order(tax_allowances::year.asc())
+                .load::<TaxAllowance>(c)
+                .map(Json)
+                .map_err(|e| log_error_and_500(Box::new(e)))
+        })
+        .await
+}
+
+// amount in cents, applies from `year` on until the next entry
+#[put("/taxes/allowances/<year>", data = "<amount>")]
+pub async fn set_allowance(
+    uid: UserId,
+    connection: DbConn,
+    year: i32,
+    amount: Json<i64>,
+) -> Result<(), Status> {
+    let amount = amount.into_inner();
+    if amount < 0 {
+        return Err(Status::BadRequest);
+    }
+
+    connection
+        .run(move |c| {
+            diesel::insert_into(tax_allowances::table)
+                .values(&TaxAllowance {
+                    user_id: *uid,
+                    year,
+                    amount,
+                })
+                .on_conflict((tax_allowances::user_id, tax_allowances::year))
+                .do_update()
+                .set(tax_allowances::amount.eq(amount))
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            info!(
+                "Set allowance of user {} from {} on to {}",
+                *uid, year, amount
+            );
+            Ok(())
+        })
+        .await
+}
+
+#[delete("/taxes/allowances/<year>")]
+pub async fn delete_allowance(uid: UserId, connection: DbConn, year: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let row_count = diesel::delete(
+                tax_allowances::table
+                    .filter(tax_allowances::user_id.eq(*uid))
+                    .filter(tax_allowances::year.eq(year)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if row_count == 0 {
+                Err(Status::NotFound)
+            } else {
+                Ok(())
+            }
+        })
+        .await
+}
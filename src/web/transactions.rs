@@ -1,7 +1,10 @@
+use crate::batch::{self, Operation, OperationResult};
+use crate::history;
 use crate::models::*;
 use crate::schema::*;
+use crate::validation::{ValidationErrors, Validator};
 use crate::web::user::UserId;
-use crate::web::util::log_error_and_500;
+use crate::web::util::{log_error_and_500, Rejection};
 use crate::web::DbConn;
 
 use diesel::prelude::*;
@@ -9,19 +12,28 @@ use log::info;
 use rocket::http::Status;
 use rocket_contrib::databases::diesel;
 use rocket_contrib::json::Json;
+use std::error::Error;
 
-#[get("/transactions?<offset>&<count>")]
+#[get("/transactions?<offset>&<count>&<tag>")]
 pub async fn list(
     uid: UserId,
     connection: DbConn,
     offset: Option<i64>,
     count: Option<i64>,
+    tag: Option<String>,
 ) -> Option<Json<Vec<Transaction>>> {
     connection
         .run(move |c| {
-            transactions::table
+            let mut query = transactions::table
                 .inner_join(accounts::table)
                 .filter(accounts::user_id.eq(*uid))
+                .into_boxed();
+            if let Some(tag) = tag {
+                let ids = crate::tags::transaction_ids(c, *uid, &tag).ok()?;
+                query = query.filter(transactions::id.eq_any(ids));
+            }
+
+            query
                 .order(transactions::id.asc())
                 .limit(count.unwrap_or(i64::max_value()))
                 .offset(offset.unwrap_or(0))
@@ -59,15 +71,23 @@ pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Stat
                 .map(|a| a.id)
                 .collect::<Vec<_>>();
 
-            let row_count = diesel::delete(
-                transactions::table
-                    .filter(transactions::account_id.eq_any(accs))
-                    .filter(transactions::id.eq(id)),
-            )
-            .execute(c)
-            .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let deleted = c
+                .transaction::<_, Box<dyn Error>, _>(|| {
+                    let t = transactions::table
+                        .filter(transactions::account_id.eq_any(accs))
+                        .filter(transactions::id.eq(id))
+                        .first::<Transaction>(c)
+                        .optional()?;
+                    if let Some(t) = t.as_ref() {
+                        // recorded first, along with the rows that are deleted with it
+                        history::deleted(c, *uid, history::WEB, t)?;
+                        diesel::delete(transactions::table.find(id)).execute(c)?;
+                    }
+                    Ok(t)
+                })
+                .map_err(log_error_and_500)?;
 
-            if row_count == 0 {
+            if deleted.is_none() {
                 Err(Status::NotFound)
             } else {
                 info!("Deleted record {} from the transaction table", id);
@@ -83,32 +103,37 @@ pub async fn update(
     connection: DbConn,
     id: i32,
     transaction: Json<Transaction>,
-) -> Result<(), Status> {
+) -> Result<(), Rejection> {
     connection
         .run(move |c| {
             let accs = accounts::table
                 .filter(accounts::user_id.eq(*uid))
-                .load::<Account>(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?
-                .into_iter()
-                .map(|a| a.id)
-                .collect::<Vec<_>>();
+                .select(accounts::id)
+                .load::<i32>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
 
-            if !accs.contains(&transaction.account_id) {
-                return Err(Status::Unauthorized);
-            }
+            let before = transactions::table
+                .filter(transactions::account_id.eq_any(&accs))
+                .filter(transactions::id.eq(id))
+                .first::<Transaction>(c)
+                .optional()
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
 
-            let row_count = diesel::update(transactions::table.filter(transactions::id.eq(id)))
-                .set(transaction.0)
-                .execute(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            Validator::new(c, accs)
+                .map_err(log_error_and_500)?
+                .check(&NewTransaction::from(&transaction.0))?;
 
-            if row_count == 0 {
-                Err(Status::NotFound)
-            } else {
-                info!("updated record {} from the transaction table", id);
+            c.transaction::<_, Box<dyn Error>, _>(|| {
+                let after = diesel::update(transactions::table.filter(transactions::id.eq(id)))
+                    .set(transaction.0)
+                    .get_result::<Transaction>(c)?;
+                history::updated(c, *uid, history::WEB, &before, &after)?;
                 Ok(())
-            }
+            })
+            .map_err(log_error_and_500)?;
+            info!("updated record {} from the transaction table", id);
+            Ok(())
         })
         .await
 }
@@ -118,27 +143,43 @@ pub async fn create(
     uid: UserId,
     connection: DbConn,
     transaction: Json<NewTransaction>,
-) -> Result<Json<Transaction>, Status> {
+) -> Result<Json<Transaction>, Rejection> {
     connection
         .run(move |c| {
-            let accs = accounts::table
-                .filter(accounts::user_id.eq(*uid))
-                .load::<Account>(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?
-                .into_iter()
-                .map(|a| a.id)
-                .collect::<Vec<_>>();
-
-            if !accs.contains(&transaction.account_id) {
-                return Err(Status::Unauthorized);
-            }
+            Validator::load(c, *uid)
+                .map_err(log_error_and_500)?
+                .check(&transaction)?;
 
-            let t: Transaction = diesel::insert_into(crate::schema::transactions::table)
-                .values(&transaction.0)
-                .get_result(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let t = c
+                .transaction::<_, Box<dyn Error>, _>(|| {
+                    let t: Transaction = diesel::insert_into(crate::schema::transactions::table)
+                        .values(&transaction.0)
+                        .get_result(c)?;
+                    history::inserted(c, *uid, history::WEB, std::slice::from_ref(&t))?;
+                    Ok(t)
+                })
+                .map_err(log_error_and_500)?;
 
             Ok(Json(t))
         })
         .await
 }
+
+// create, update and delete transactions at once; nothing is changed if one of the operations is invalid
+#[post("/transactions/batch", data = "<operations>")]
+pub async fn batch(
+    uid: UserId,
+    connection: DbConn,
+    operations: Json<Vec<Operation>>,
+) -> Result<Json<Vec<OperationResult>>, Rejection> {
+    connection
+        .run(move |c| {
+            batch::apply(c, *uid, history::WEB, &operations)
+                .map(Json)
+                .map_err(|e| match e.downcast::<ValidationErrors>() {
+                    Ok(errors) => Rejection::from(*errors),
+                    Err(e) => Rejection::from(log_error_and_500(e)),
+                })
+        })
+        .await
+}
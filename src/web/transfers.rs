@@ -0,0 +1,91 @@
+use crate::models::*;
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::info;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+#[get("/transfers")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<Transfer>>, Status> {
+    connection
+        .run(move |c| {
+            let account_ids = accounts::table
+                .filter(accounts::user_id.eq(*uid))
+                .select(accounts::id)
+                .load::<i32>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let trs = transfers::table
+                .filter(transfers::from_account_id.eq_any(account_ids))
+                .order(transfers::date.asc())
+                .load::<Transfer>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(trs))
+        })
+        .await
+}
+
+#[post("/transfers", data = "<transfer>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    transfer: Json<NewTransfer>,
+) -> Result<Json<Transfer>, Status> {
+    if transfer.from_account_id == transfer.to_account_id || transfer.units <= 0.0 {
+        return Err(Status::BadRequest);
+    }
+
+    connection
+        .run(move |c| {
+            let count = accounts::table
+                .filter(accounts::id.eq_any(vec![transfer.from_account_id, transfer.to_account_id]))
+                .filter(accounts::user_id.eq(*uid))
+                .count()
+                .get_result::<i64>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            if count != 2 {
+                return Err(Status::NotFound);
+            }
+
+            let tr: Transfer = diesel::insert_into(transfers::table)
+                .values(&transfer.0)
+                .get_result(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(tr))
+        })
+        .await
+}
+
+#[delete("/transfers/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let account_ids = accounts::table
+                .filter(accounts::user_id.eq(*uid))
+                .select(accounts::id)
+                .load::<i32>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let row_count = diesel::delete(
+                transfers::table
+                    .filter(transfers::id.eq(id))
+                    .filter(transfers::from_account_id.eq_any(account_ids)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if row_count == 0 {
+                Err(Status::NotFound)
+            } else {
+                info!("Deleted record {} from the transfers table", id);
+                Ok(())
+            }
+        })
+        .await
+}
@@ -1,6 +1,7 @@
 use crate::models::*;
 use crate::schema::*;
 use crate::verify_password;
+use crate::web::util::log_error_and_500;
 use crate::web::{Config, DbConn};
 
 use diesel::prelude::*;
@@ -46,6 +47,7 @@ pub struct UserInfo {
     id: i32,
     name: String,
     full_name: String,
+    reporting_currency: String,
     application_server_key: String,
 }
 
@@ -62,11 +64,22 @@ impl UserInfo {
             id: user.id,
             name: user.name,
             full_name: user.full_name,
+            reporting_currency: user.reporting_currency,
             application_server_key,
         }
     }
 }
 
+// whether the user may change data that is shared by all users, like corporate actions
+pub fn is_admin(admins: &[String], connection: &PgConnection, uid: i32) -> QueryResult<bool> {
+    let user_name = users::table
+        .find(uid)
+        .select(users::name)
+        .first::<String>(connection)?;
+
+    Ok(admins.contains(&user_name))
+}
+
 #[get("/user")]
 pub async fn info(
     config: State<'_, Config>,
@@ -86,6 +99,28 @@ pub async fn info(
         .await
 }
 
+#[put("/user/reporting_currency", data = "<currency>")]
+pub async fn set_reporting_currency(
+    uid: UserId,
+    connection: DbConn,
+    currency: Json<String>,
+) -> Result<(), Status> {
+    let currency = currency.trim().to_uppercase();
+    if currency.len() != 3 {
+        return Err(Status::BadRequest);
+    }
+
+    connection
+        .run(move |c| {
+            diesel::update(users::table.find(*uid))
+                .set(users::reporting_currency.eq(currency))
+                .execute(c)
+                .map(|_| ())
+                .map_err(|e| log_error_and_500(Box::new(e)))
+        })
+        .await
+}
+
 #[get("/user/logout")]
 pub fn logout(cookies: &CookieJar) -> Status {
     cookies.remove_private(Cookie::named("user_id"));
@@ -1,8 +1,31 @@
+use crate::validation::ValidationErrors;
+
 use log::error;
 use rocket::http::Status;
+use rocket_contrib::json::Json;
 use std::error::Error;
 
 pub fn log_error_and_500(e: Box<dyn Error>) -> Status {
     error!("{}", e);
     Status::InternalServerError
 }
+
+// either field-level errors of invalid input or a plain status
+#[derive(Responder)]
+pub enum Rejection {
+    #[response(status = 422)]
+    Invalid(Json<ValidationErrors>),
+    Status(Status),
+}
+
+impl From<Status> for Rejection {
+    fn from(s: Status) -> Rejection {
+        Rejection::Status(s)
+    }
+}
+
+impl From<ValidationErrors> for Rejection {
+    fn from(e: ValidationErrors) -> Rejection {
+        Rejection::Invalid(Json(e))
+    }
+}
@@ -0,0 +1,28 @@
+// runs the receipt parser over the corpus in tests/receipts: every `<name>.txt` contains the
+// anonymised text of a receipt (see `stockdb transaction --extract`), `<name>.json` the expected result.
+use stockdb::receipts::{parse_text, ParsedReceipt};
+
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn receipt_corpus() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/receipts");
+    let mut entries = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().map(|e| e == "txt").unwrap_or(false))
+        .collect::<Vec<_>>();
+    entries.sort();
+    assert!(!entries.is_empty(), "receipt corpus is empty");
+
+    for path in entries.iter() {
+        let text = fs::read_to_string(path).unwrap();
+        let expected: Vec<ParsedReceipt> =
+            serde_json::from_str(&fs::read_to_string(path.with_extension("json")).unwrap())
+                .unwrap_or_else(|e| panic!("{}: invalid expected result: {}", path.display(), e));
+
+        let parsed = parse_text(&text).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        assert_eq!(parsed, expected, "{}", path.display());
+    }
+}
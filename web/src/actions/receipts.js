@@ -38,15 +38,29 @@ export const uploadReceipts = inputFiles => dispatch => {
         })
     )
   )
-    .then(urls =>
-      fetch(`/api/receipts`, {
-        method: 'POST',
-        body: JSON.stringify(urls),
-      })
-    )
-    .then(res => {
-      if (!res.ok) throw Error(`${res.status} ${res.statusText}`);
-      return res.json();
+    .then(urls => {
+      const post = createAccounts =>
+        fetch(`/api/receipts?create_accounts=${createAccounts}`, {
+          method: 'POST',
+          body: JSON.stringify(urls),
+        }).then(res => {
+          if (!res.ok) throw Error(`${res.status} ${res.statusText}`);
+          return res.json();
+        });
+
+      return post(false).then(res => {
+        if (
+          res.unknownDepots &&
+          res.unknownDepots.length > 0 &&
+          window.confirm(
+            `Für Depotnummer(n) ${res.unknownDepots.join(
+              ', '
+            )} existiert kein Depot. Jetzt anlegen?`
+          )
+        )
+          return post(true);
+        return res;
+      });
     })
     .then(res => {
       if (res.error) throw res.error;
@@ -48,6 +48,16 @@ export const modifyTransactionError = (id, method, error) => ({
   receivedAt: Date.now(),
 });
 
+// invalid transactions are rejected with a list of field errors
+const checkResponse = res => {
+  if (res.status === 422)
+    return res.json().then(json => {
+      throw Error(json.errors.map(e => `${e.field}: ${e.message}`).join(', '));
+    });
+  if (!res.ok) throw Error(`${res.status} ${res.statusText}`);
+  return res;
+};
+
 export const fetchTransactions = () => dispatch => {
   dispatch(fetchTransactionsRequest());
   return fetch('/api/transactions')
@@ -85,10 +95,8 @@ export const createTransaction = transaction => dispatch => {
     method: 'POST',
     body: JSON.stringify(transaction),
   })
-    .then(res => {
-      if (!res.ok) throw Error(`${res.status} ${res.statusText}`);
-      return res.json();
-    })
+    .then(checkResponse)
+    .then(res => res.json())
     .then(res => {
       if (res.error) throw res.error;
 
@@ -104,10 +112,7 @@ export const updateTransaction = transaction => dispatch => {
     method: 'PUT',
     body: JSON.stringify(transaction),
   })
-    .then(res => {
-      if (!res.ok) throw Error(`${res.status} ${res.statusText}`);
-      return res;
-    })
+    .then(checkResponse)
     .then(res => {
       if (res.error) throw res.error;
 
@@ -117,6 +122,25 @@ export const updateTransaction = transaction => dispatch => {
     .catch(e => dispatch(modifyTransactionError(transaction.id, 'PUT', e)));
 };
 
+// operations: [{ op: 'create', transaction }, { op: 'update', id, transaction }, { op: 'delete', id }],
+// either all of them are applied or none
+export const batchTransactions = operations => dispatch => {
+  dispatch(modifyTransactionRequest(null, 'BATCH'));
+  return fetch('/api/transactions/batch', {
+    method: 'POST',
+    body: JSON.stringify(operations),
+  })
+    .then(checkResponse)
+    .then(res => res.json())
+    .then(res => {
+      if (res.error) throw res.error;
+
+      dispatch(modifyTransactionSuccess(null, 'BATCH', res));
+      dispatch(invalidatePerformance());
+    })
+    .catch(e => dispatch(modifyTransactionError(null, 'BATCH', e)));
+};
+
 export const updateTransactions = () => (dispatch, getState) => {
   const { transactions } = getState();
 
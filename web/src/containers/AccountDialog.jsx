@@ -38,6 +38,8 @@ const AccountDialog = ({
     return null;
   };
 
+  const toOptional = s => (s.trim() !== '' ? s.trim() : null);
+
   const toAccount = t => {
     const iban = toIBAN(t.iban);
     if (t.name.trim() === '' || iban === null) return null;
@@ -47,12 +49,23 @@ const AccountDialog = ({
       id: t.id,
       name: t.name.trim(),
       iban: iban !== '' ? iban : null,
+      depotNumber: toOptional(t.depotNumber),
+      clearingAccountNumber: toOptional(t.clearingAccountNumber),
+      broker: toOptional(t.broker),
+      currency: t.currency.trim() !== '' ? t.currency.trim().toUpperCase() : 'EUR',
     };
 
     return ret;
   };
 
-  const toEditable = t => ({ ...t });
+  const toEditable = t => ({
+    ...t,
+    iban: t.iban || '',
+    depotNumber: t.depotNumber || '',
+    clearingAccountNumber: t.clearingAccountNumber || '',
+    broker: t.broker || '',
+    currency: t.currency || 'EUR',
+  });
 
   const theme = useTheme();
   const mdUp = useMediaQuery(theme.breakpoints.up('md'));
@@ -137,6 +150,62 @@ const AccountDialog = ({
                 error={toIBAN(account.iban) === null && triedToSave}
               />
             </Grid>
+            <Grid item xs={12} md={4}>
+              <TextField
+                margin="dense"
+                label="Depotnummer"
+                fullWidth
+                value={account.depotNumber}
+                onChange={v =>
+                  setAccount({
+                    ...account,
+                    depotNumber: v.target.value,
+                  })
+                }
+              />
+            </Grid>
+            <Grid item xs={12} md={4}>
+              <TextField
+                margin="dense"
+                label="Verrechnungskonto"
+                fullWidth
+                value={account.clearingAccountNumber}
+                onChange={v =>
+                  setAccount({
+                    ...account,
+                    clearingAccountNumber: v.target.value,
+                  })
+                }
+              />
+            </Grid>
+            <Grid item xs={12} md={4}>
+              <TextField
+                margin="dense"
+                label="Broker"
+                fullWidth
+                value={account.broker}
+                onChange={v =>
+                  setAccount({
+                    ...account,
+                    broker: v.target.value,
+                  })
+                }
+              />
+            </Grid>
+            <Grid item xs={12} md={4}>
+              <TextField
+                margin="dense"
+                label="Währung"
+                fullWidth
+                value={account.currency}
+                onChange={v =>
+                  setAccount({
+                    ...account,
+                    currency: v.target.value,
+                  })
+                }
+              />
+            </Grid>
           </Grid>
           {toAccount(account) === null && triedToSave && (
             <Alert severity="error">Depot ist unvollständig!</Alert>
@@ -44,6 +44,10 @@ const Accounts = ({ accounts, updateAccounts }) => {
     userId: -1,
     name: '',
     iban: '',
+    depotNumber: '',
+    clearingAccountNumber: '',
+    broker: '',
+    currency: 'EUR',
   };
   if (
     accounts.items !== null &&